@@ -0,0 +1,212 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+use crate::history::{self, Change};
+use crate::ignore;
+use crate::linker::platform;
+use crate::state;
+
+const BACKUPS_DIR: &str = "backups";
+
+/*
+ * Real files in the target that adopting moves into the source
+ * These are the files the source also provides (which would otherwise conflict), plus
+ * every real file under the extra target-relative paths, which the source may not have yet.
+ * Paths the source has as a directory cannot be replaced by a file and are left out.
+ */
+pub fn candidates(source: &Path, target: &Path, ignore: &[String], extra: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_provided(source, target, Path::new(""), ignore, &mut files);
+    for relative in extra {
+        collect_real_files(target, relative, ignore, &mut files);
+    }
+    files.retain(|relative| !source.join(relative).is_dir());
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn collect_provided(source: &Path, target: &Path, relative: &Path, ignore: &[String], files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(source.join(relative)) else { return };
+    for entry in entries.flatten() {
+        let relative = relative.join(entry.file_name());
+        if ignore::is_ignored(&relative, ignore) {
+            continue;
+        }
+        let source_path = source.join(&relative);
+        if source_path.is_dir() && !source_path.is_symlink() {
+            collect_provided(source, target, &relative, ignore, files);
+        } else if is_real_file(&target.join(&relative)) {
+            files.push(relative);
+        }
+    }
+}
+
+/// Real files at or under a target-relative path.
+pub fn real_files(target: &Path, relative: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_real_files(target, relative, &[], &mut files);
+    files
+}
+
+fn collect_real_files(target: &Path, relative: &Path, ignore: &[String], files: &mut Vec<PathBuf>) {
+    if ignore::is_ignored(relative, ignore) {
+        return;
+    }
+    let path = target.join(relative);
+    if is_real_file(&path) {
+        files.push(relative.to_path_buf());
+    } else if path.is_dir() && !path.is_symlink() {
+        let Ok(entries) = fs::read_dir(&path) else { return };
+        for entry in entries.flatten() {
+            collect_real_files(target, &relative.join(entry.file_name()), ignore, files);
+        }
+    }
+}
+
+fn is_real_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/*
+ * Move target files into the source, replacing the source's versions
+ * Source files about to be overwritten are first copied to a backup directory under the
+ * state directory, which is returned if anything was backed up. Linking the moved files
+ * back is left to the caller.
+ */
+pub fn adopt(source: &Path, target: &Path, files: &[PathBuf]) -> Result<Option<PathBuf>> {
+    let name = source.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let backup = backup_dir(&name)?;
+    let mut backed_up = false;
+
+    for relative in files {
+        let source_file = source.join(relative);
+        if source_file.is_file() {
+            let saved = backup.join(relative);
+            if let Some(parent) = saved.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&source_file, &saved)?;
+            history::record(Change::Copied { from: source_file.clone(), to: saved });
+            backed_up = true;
+        }
+        if let Some(parent) = source_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&target.join(relative), &source_file)?;
+        history::record(Change::Moved { from: target.join(relative), to: source_file });
+    }
+
+    Ok(backed_up.then_some(backup))
+}
+
+/// Directory holding every backup, in the state directory.
+pub fn backups_root() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(BACKUPS_DIR))
+}
+
+/// A fresh, timestamped directory under the state directory for this run's backups (not created yet).
+pub fn backup_dir(label: &str) -> Result<PathBuf> {
+    // Directories handed out but maybe not created yet, so `--jobs` workers get distinct ones
+    static HANDED_OUT: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let base = backups_root()?;
+    let mut handed_out = HANDED_OUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut dir = base.join(format!("{}-{}", stamp, label));
+    let mut n = 1;
+    while dir.exists() || handed_out.contains(&dir) {
+        n += 1;
+        dir = base.join(format!("{}-{}-{}", stamp, label, n));
+    }
+    handed_out.insert(dir.clone());
+    Ok(dir)
+}
+
+/// Move a file or directory, copying it when source and destination are on different filesystems.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => move_by_copy(from, to),
+        result => result,
+    }
+}
+
+/// Copy `from` to `to`, links as links, then remove `from`.
+fn move_by_copy(from: &Path, to: &Path) -> io::Result<()> {
+    fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(from)?;
+        if metadata.is_symlink() {
+            platform::symlink(&fs::read_link(from)?, to)
+        } else if metadata.is_dir() {
+            fs::create_dir(to)?;
+            fs::set_permissions(to, metadata.permissions())?;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+            }
+            Ok(())
+        } else {
+            fs::copy(from, to).map(|_| ())
+        }
+    }
+
+    copy_tree(from, to)?;
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_candidates() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("dotfiles");
+        let target = temp.path().join("home");
+        fs::create_dir_all(source.join(".config/nvim")).unwrap();
+        fs::create_dir_all(target.join(".config/nvim")).unwrap();
+        fs::create_dir_all(target.join(".ssh")).unwrap();
+        fs::write(source.join(".zshrc"), "").unwrap();
+        fs::write(source.join(".vimrc"), "").unwrap();
+        fs::write(target.join(".zshrc"), "").unwrap();
+        fs::write(target.join(".config/nvim/init.lua"), "").unwrap();
+        fs::write(target.join(".config/nvim/init.lua~"), "").unwrap();
+        fs::write(target.join(".ssh/config"), "").unwrap();
+
+        let ignore = vec!["*~".to_string()];
+        assert_eq!(candidates(&source, &target, &ignore, &[]), vec![PathBuf::from(".zshrc")]);
+        assert_eq!(
+            candidates(&source, &target, &ignore, &[PathBuf::from(".config")]),
+            vec![PathBuf::from(".config/nvim/init.lua"), PathBuf::from(".zshrc")]
+        );
+    }
+
+    #[test]
+    fn test_move_by_copy() {
+        let temp = TempDir::new().unwrap();
+        let from = temp.path().join("from");
+        fs::create_dir_all(from.join("nvim")).unwrap();
+        fs::write(from.join("nvim/init.lua"), "lua").unwrap();
+        platform::symlink(Path::new("init.lua"), &from.join("nvim/link")).unwrap();
+
+        let to = temp.path().join("to");
+        move_by_copy(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("nvim/init.lua")).unwrap(), "lua");
+        assert_eq!(fs::read_link(to.join("nvim/link")).unwrap(), PathBuf::from("init.lua"));
+
+        move_by_copy(&to.join("nvim/init.lua"), &temp.path().join("init.lua")).unwrap();
+        assert_eq!(fs::read_to_string(temp.path().join("init.lua")).unwrap(), "lua");
+        assert!(!to.join("nvim/init.lua").exists());
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{DotlinkError, Result};
+
+/*
+ * Scripts for `amu batch`
+ * One amu command per line; a leading `amu` is optional. Blank lines and comments (`#` at
+ * the start of a word) are skipped. Words are split the way a shell quotes them: single
+ * quotes are literal, double quotes allow `\"` and `\\`, and a backslash outside quotes
+ * escapes the next character. Nothing else is expanded; commands expand `~` themselves.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    /// Line number in the script, from 1
+    pub line: usize,
+    pub args: Vec<String>,
+}
+
+/// The steps of a script, or the first line that cannot be split into words.
+pub fn parse(content: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for (index, text) in content.lines().enumerate() {
+        let line = index + 1;
+        let mut args = split_words(text).map_err(|message| DotlinkError::BatchStep { line, message: message.to_string() })?;
+        if args.first().is_some_and(|word| word == "amu") {
+            args.remove(0);
+        }
+        if !args.is_empty() {
+            steps.push(Step { line, args });
+        }
+    }
+    Ok(steps)
+}
+
+fn split_words(line: &str) -> std::result::Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '#' if word.is_none() => break,
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote"),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("backslash at end of line"),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Mark whether a batch is running, so an early exit can roll its steps back.
+pub fn set_running(running: bool) {
+    RUNNING.store(running, Ordering::SeqCst);
+}
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("add ~/dotfiles/nvim ~/.config/nvim").unwrap(), ["add", "~/dotfiles/nvim", "~/.config/nvim"]);
+        assert_eq!(split_words(r#"add 'My Files' "a \"b\"" c\ d"#).unwrap(), ["add", "My Files", "a \"b\"", "c d"]);
+        assert_eq!(split_words("status # check").unwrap(), ["status"]);
+        assert_eq!(split_words("a#b ''").unwrap(), ["a#b", ""]);
+        assert!(split_words("add 'open").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let steps = parse("# provision\n\namu add a b\nupdate --all\n").unwrap();
+        assert_eq!(steps, [
+            Step { line: 3, args: vec!["add".to_string(), "a".to_string(), "b".to_string()] },
+            Step { line: 4, args: vec!["update".to_string(), "--all".to_string()] },
+        ]);
+        assert!(matches!(parse("ok\nbad \"quote"), Err(DotlinkError::BatchStep { line: 2, .. })));
+    }
+}
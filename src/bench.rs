@@ -0,0 +1,113 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::{abbreviate_path, Config};
+use crate::error::Result;
+use crate::ignore;
+use crate::linker::{self, Backend};
+use crate::status;
+use crate::stow;
+
+/*
+ * Self-benchmark (`amu bench`)
+ * Times the phases behind everyday commands without changing anything: loading the config,
+ * the status scan of each target, and planning its links (a dry-run restow of each source).
+ * Every phase runs several times and the median is kept, so one cold filesystem cache does
+ * not decide the result.
+ */
+#[derive(Debug, Serialize)]
+pub struct Bench {
+    pub backend: Backend,
+    pub runs: usize,
+    pub config_load_ms: f64,
+    pub targets: Vec<TargetBench>,
+    pub total_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetBench {
+    pub path: String,
+    pub sources: usize,
+    pub status_ms: f64,
+    /// None when the target does not exist, so nothing can be planned
+    pub plan_ms: Option<f64>,
+    pub links_planned: usize,
+}
+
+pub fn run(config: &Config, runs: usize) -> Result<Bench> {
+    let config_load = median(runs, Config::load)?.0;
+
+    let mut targets = Vec::new();
+    for (target, sources) in &config.targets {
+        let status = median(runs, || Ok(status::check_target(config, target)))?.0;
+        let plan = if target.is_dir() {
+            Some(median(runs, || plan(config, target, sources))?)
+        } else {
+            None
+        };
+        targets.push(TargetBench {
+            path: abbreviate_path(target),
+            sources: sources.len(),
+            status_ms: millis(status),
+            plan_ms: plan.as_ref().map(|(time, _)| millis(*time)),
+            links_planned: plan.map_or(0, |(_, links)| links),
+        });
+    }
+
+    let total = millis(config_load)
+        + targets.iter().map(|t| t.status_ms + t.plan_ms.unwrap_or(0.0)).sum::<f64>();
+    Ok(Bench {
+        backend: linker::backend(),
+        runs,
+        config_load_ms: millis(config_load),
+        targets,
+        total_ms: total,
+    })
+}
+
+/// Links a restow of every existing source of a target would create.
+fn plan(config: &Config, target: &Path, sources: &[std::path::PathBuf]) -> Result<usize> {
+    let mut links = 0;
+    for source in sources.iter().filter(|s| s.is_dir()) {
+        let ignore = ignore::patterns_for(config, target, source);
+        links += stow::dry_run_restow(config, source, target, &ignore)?.links();
+    }
+    Ok(links)
+}
+
+/// Run `f` `runs` times (at least once); the median time and the last result.
+fn median<T>(runs: usize, mut f: impl FnMut() -> Result<T>) -> Result<(Duration, T)> {
+    let started = Instant::now();
+    let mut result = f()?;
+    let mut times = vec![started.elapsed()];
+    for _ in 1..runs {
+        let started = Instant::now();
+        result = f()?;
+        times.push(started.elapsed());
+    }
+    times.sort();
+    Ok((times[times.len() / 2], result))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        let mut calls = 0;
+        let (_, last) = median(3, || {
+            calls += 1;
+            Ok(calls)
+        })
+        .unwrap();
+        assert_eq!(last, 3);
+        assert_eq!(millis(Duration::from_micros(1500)), 1.5);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{DotlinkError, Result};
+use crate::ownership;
+use crate::state;
+use crate::status::{self, SourceStatus};
+
+const CACHE_FILE: &str = "status-cache.json";
+
+/// How often a running daemon confirms the cache is still being maintained
+pub const HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// A cache whose daemon has been silent this long is no longer trusted
+const STALE_AFTER: Duration = Duration::from_secs(15);
+
+/*
+ * Source statuses kept warm by `amu daemon`
+ * The daemon rewrites the cache whenever a watched path changes and refreshes a heartbeat
+ * in between. Readers only use it while the heartbeat is recent and the config file is the
+ * one the statuses were computed from; a target whose sources differ from the cached ones
+ * is checked live.
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StatusCache {
+    /// When statuses were last computed (milliseconds since the Unix epoch)
+    pub updated_at: u64,
+    /// The daemon's last heartbeat (milliseconds since the Unix epoch)
+    pub alive_at: u64,
+    /// Modification time of the config file the statuses were computed from
+    pub config_mtime: u64,
+    pub targets: BTreeMap<PathBuf, Vec<(PathBuf, SourceStatus)>>,
+}
+
+impl StatusCache {
+    /// Statuses of every registered target, computed now.
+    pub fn compute(config: &Config) -> Self {
+        let mut cache = StatusCache::default();
+        let targets: Vec<PathBuf> = config.targets.keys().map(|t| t.to_path_buf()).collect();
+        cache.refresh(config, &targets);
+        cache
+    }
+
+    /// Recompute the given targets and drop targets no longer registered.
+    pub fn refresh(&mut self, config: &Config, targets: &[PathBuf]) {
+        self.targets.retain(|target, _| config.get_sources(target).is_some());
+        for target in targets.iter().filter(|t| config.get_sources(t).is_some()) {
+            self.targets.insert(target.clone(), status::check_target(config, target));
+        }
+        let now = now_ms();
+        self.updated_at = now;
+        self.alive_at = now;
+        self.config_mtime = config_mtime();
+    }
+
+    /// Cached statuses of a target, if they were computed for its current sources.
+    pub fn sources(&self, config: &Config, target: &Path) -> Option<&[(PathBuf, SourceStatus)]> {
+        let cached = self.targets.get(target)?;
+        let current = config.get_sources(target)?;
+        let matches = cached.len() == current.len() && cached.iter().zip(current).all(|((s, _), c)| s == c);
+        matches.then_some(cached.as_slice())
+    }
+
+    /// Time since statuses were last computed.
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.updated_at))
+    }
+
+    /// Record a heartbeat without recomputing anything.
+    pub fn touch(&mut self) -> Result<()> {
+        self.alive_at = now_ms();
+        self.save()
+    }
+
+    /// Write the cache; readers never see a partially written file.
+    pub fn save(&self) -> Result<()> {
+        let path = cache_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string(self).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, content)?;
+        fs::rename(&temp, &path)?;
+        ownership::fix_written(&path);
+        Ok(())
+    }
+
+    /// The cache, if a daemon is maintaining it for the current config file.
+    pub fn load_fresh() -> Option<Self> {
+        let content = fs::read_to_string(cache_path().ok()?).ok()?;
+        let cache: StatusCache = serde_json::from_str(&content).ok()?;
+        let silent = Duration::from_millis(now_ms().saturating_sub(cache.alive_at));
+        (silent < STALE_AFTER && cache.config_mtime == config_mtime()).then_some(cache)
+    }
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(CACHE_FILE))
+}
+
+/// Modification time of the config file in milliseconds, or 0 if it does not exist.
+pub fn config_mtime() -> u64 {
+    Config::config_path()
+        .ok()
+        .and_then(|path| fs::metadata(path).ok())
+        .and_then(|m| m.modified().ok())
+        .map(millis)
+        .unwrap_or(0)
+}
+
+fn now_ms() -> u64 {
+    millis(SystemTime::now())
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// "3s ago", "2m ago", "1h ago"
+pub fn describe_age(age: Duration) -> String {
+    match age.as_secs() {
+        s if s < 60 => format!("{}s ago", s),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s => format!("{}h ago", s / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_age() {
+        assert_eq!(describe_age(Duration::from_millis(2500)), "2s ago");
+        assert_eq!(describe_age(Duration::from_secs(125)), "2m ago");
+        assert_eq!(describe_age(Duration::from_secs(7300)), "2h ago");
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DotlinkError, Result};
+use crate::ownership;
+use crate::state;
+
+const CHECKPOINT_FILE: &str = "restore-checkpoint.yaml";
+
+/*
+ * Progress of an interrupted or chunked restore
+ * Each (target, source) pair is recorded as soon as it is linked, so the next restore
+ * skips it instead of re-walking the source. The file is removed once a restore
+ * finishes with nothing left to do.
+ */
+#[derive(Debug, Default)]
+pub struct Checkpoint {
+    completed: BTreeSet<(PathBuf, PathBuf)>,
+}
+
+fn checkpoint_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(CHECKPOINT_FILE))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    target: PathBuf,
+    source: PathBuf,
+}
+
+impl Checkpoint {
+    pub fn load() -> Result<Self> {
+        let path = checkpoint_path()?;
+        if !path.exists() {
+            return Ok(Checkpoint::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let entries: Vec<Entry> = serde_yaml::from_str(&content)
+            .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+        Ok(Checkpoint {
+            completed: entries.into_iter().map(|e| (e.target, e.source)).collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty()
+    }
+
+    pub fn contains(&self, target: &Path, source: &Path) -> bool {
+        self.completed.contains(&(target.to_path_buf(), source.to_path_buf()))
+    }
+
+    /// Record a linked pair and persist immediately.
+    pub fn mark(&mut self, target: &Path, source: &Path) -> Result<()> {
+        self.completed.insert((target.to_path_buf(), source.to_path_buf()));
+        self.save()
+    }
+
+    /// Forget a pair whose links were rolled back, and persist immediately.
+    pub fn unmark(&mut self, target: &Path, source: &Path) -> Result<()> {
+        self.completed.remove(&(target.to_path_buf(), source.to_path_buf()));
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = checkpoint_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let entries: Vec<Entry> = self.completed.iter()
+            .map(|(target, source)| Entry { target: target.clone(), source: source.clone() })
+            .collect();
+        let content = serde_yaml::to_string(&entries)
+            .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+        fs::write(&path, content)?;
+        ownership::fix_written(&path);
+        Ok(())
+    }
+
+    pub fn clear() -> Result<()> {
+        let path = checkpoint_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,20 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use crate::audit::Severity;
+use crate::conflict::OnConflict;
+use crate::export::MergeStrategy;
+use crate::linker::Backend;
+use crate::migrate::Manager;
+use crate::mkdir::{self, ParentsMode};
+use crate::ownership::{self, Owner};
+use crate::report::Format;
+use crate::shellenv::EnvShell;
+use crate::style::ColorChoice;
+use crate::theme::Theme;
 
 #[derive(Parser)]
 #[command(name = "amu")]
@@ -9,6 +23,76 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Treat DIR as the home directory (also AMU_HOME)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub home: Option<PathBuf>,
+
+    /// Emit NDJSON progress events to stderr, or to file descriptor FD
+    #[arg(long, global = true, value_name = "FD", num_args = 0..=1, require_equals = true, default_missing_value = "2")]
+    pub progress_json: Option<i32>,
+
+    /// Apply the mappings of profile NAME in addition to the shared ones (also AMU_PROFILE)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// How links are created (default: stow if installed, otherwise native)
+    #[arg(long, global = true, value_enum)]
+    pub backend: Option<Backend>,
+
+    /// Output format; json and yaml print one structured report instead of the usual text
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Separate columns of list and status output with a single space instead of aligning them
+    #[arg(long, global = true)]
+    pub no_align: bool,
+
+    /// Targets processed at once by update, restore and status with several targets (default: one per CPU)
+    #[arg(long, short = 'j', global = true, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+
+    /// Keep links created before a failure instead of removing them again
+    #[arg(long, global = true)]
+    pub no_rollback: bool,
+
+    /// Symbols marking results: unicode, ascii, nerd-font or words (also `theme:` in the config)
+    #[arg(long, global = true, value_enum)]
+    pub theme: Option<Theme>,
+
+    /// Color results and paths: auto (a terminal without NO_COLOR), always or never (also `color:` in the config)
+    #[arg(long, global = true, value_enum, value_name = "WHEN")]
+    pub color: Option<ColorChoice>,
+
+    /// Do not run the hooks configured for add, remove, update and restore
+    #[arg(long, global = true)]
+    pub no_hooks: bool,
+
+    /// Fail instead of waiting when another amu process (such as `amu watch`) is changing a target
+    #[arg(long, global = true)]
+    pub no_wait: bool,
+
+    /// Log more: -v also shows progress details (and `list` the symlinks), -vv every filesystem
+    /// change and external command
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only report errors, not warnings
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Append timestamped log records, down to debug level, to FILE
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Treat warnings as errors: check before changing anything and fail if there are any
+    /// (also `strict: true` in the config)
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Fail instead of warning when a scan skips a directory it is not allowed to read
+    #[arg(long, global = true)]
+    pub fail_on_unreadable: bool,
 }
 
 #[derive(Subcommand)]
@@ -21,22 +105,107 @@ pub enum Commands {
         /// Target directory to link to (defaults to current directory)
         target: Option<PathBuf>,
 
+        /// Link into several targets at once (comma-separated)
+        #[arg(long, value_delimiter = ',', conflicts_with = "target")]
+        targets: Vec<PathBuf>,
+
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
+        /// Link only paths matching PATTERN, now and on update and restore (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        only: Vec<String>,
+
+        /// Leave paths matching PATTERN unlinked, now and on update and restore (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Link directories missing from the target with one symlink each; kept in the config
+        #[arg(long, overrides_with = "no_fold")]
+        fold: bool,
+
+        /// Link file by file (the default); kept in the config
+        #[arg(long, overrides_with = "fold")]
+        no_fold: bool,
+
+        /// Copy files instead of linking them, for targets that cannot hold symlinks; kept in the config
+        #[arg(long, conflicts_with = "fold")]
+        copy: bool,
+
+        /// Hard-link files instead, for tools that refuse to follow symlinks (same filesystem only); kept in the config
+        #[arg(long, conflicts_with_all = ["fold", "copy"])]
+        hardlink: bool,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Output results in JSON format (with --dry-run, each source's plan)
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Register a source for a while: `amu gc` removes it once it expires
+    TmpAdd {
+        /// Source directory to link from
+        source: PathBuf,
+
+        /// Target directory to link to (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Remove after DURATION (e.g. 30m, 12h, 7d)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, required_unless_present = "session")]
+        ttl: Option<Duration>,
+
+        /// Remove once the machine has restarted
+        #[arg(long, conflicts_with = "ttl")]
+        session: bool,
+
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
     },
 
+    /// Remove temporary sources that have expired
+    Gc {
+        /// List what would be removed without removing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
     /// Remove symlinks and unregister a source directory
     Remove {
         /// Source directory to unlink
-        source: PathBuf,
+        #[arg(required_unless_present = "path")]
+        source: Option<PathBuf>,
 
         /// Target directory (defaults to current directory)
         target: Option<PathBuf>,
 
+        /// Unlink only the files under this path inside a target, whichever source provides them
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["source", "target"])]
+        path: Option<PathBuf>,
+
+        /// With --path, also record the path as excluded in config so updates do not relink it
+        #[arg(long, requires = "path")]
+        exclude: bool,
+
+        /// Unlink only the source files matching PATTERN and exclude them in config (repeatable)
+        #[arg(long, value_name = "PATTERN", conflicts_with = "path")]
+        only: Vec<String>,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
+
+        /// Output results in JSON format (with --dry-run, each source's plan)
+        #[arg(long)]
+        json: bool,
     },
 
     /// Reapply registered sources for a target
@@ -48,9 +217,33 @@ pub enum Commands {
         #[arg(long)]
         all: bool,
 
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
+        /// Replace real files identical to their source file with links
+        #[arg(long)]
+        adopt_identical: bool,
+
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
+        /// Link directories missing from the target with one symlink each; kept in the config
+        #[arg(long, overrides_with = "no_fold")]
+        fold: bool,
+
+        /// Link file by file (the default); kept in the config
+        #[arg(long, overrides_with = "fold")]
+        no_fold: bool,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
+
+        /// Output per-source results in JSON format
+        #[arg(long)]
+        json: bool,
     },
 
     /// Sync targets from a source directory (interactive selection)
@@ -58,11 +251,240 @@ pub enum Commands {
         /// Source directory (defaults to current directory)
         source: Option<PathBuf>,
 
+        /// Don't ask: sync every target, link missing files and back up files in the way
+        #[arg(short = 'y', long)]
+        assume_yes: bool,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
     },
 
+    /// Pull git-backed sources, update all targets, prune dead entries and report status
+    SyncAll {
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Pull the git repositories of registered sources, then update targets whose files were added or removed
+    Pull {
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
+        /// Fetch and show what would be done, without merging or linking
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Commit all changes in the git repositories of registered sources and push them
+    Push {
+        /// Commit message (default: "Update dotfiles from <hostname>")
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Fetch the git repositories of registered sources and show how far each is behind or ahead of its upstream
+    VerifyRemote {
+        /// Only the repositories of this target's sources (default: every registered source)
+        target: Option<PathBuf>,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run git in the repository of registered sources (e.g. `amu git status`)
+    #[command(disable_help_flag = true)]
+    Git {
+        /// Repository of this source (default: the one containing the current directory, or the only one)
+        #[arg(short, long)]
+        source: Option<PathBuf>,
+
+        /// Run in every repository containing a registered source
+        #[arg(long, conflicts_with = "source")]
+        all: bool,
+
+        /// Arguments passed to git
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
+
+    /// Move real files from a target into a source and link them back (backing up replaced files)
+    Adopt {
+        /// Source directory to move the files into
+        source: PathBuf,
+
+        /// Target directory (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Also adopt real files under PATH in the target that the source does not have yet (repeatable)
+        #[arg(long, value_name = "PATH")]
+        path: Vec<PathBuf>,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Create a dotfiles directory with one source per package, registered against the home directory
+    Init {
+        /// Dotfiles directory to create (defaults to ~/dotfiles)
+        dir: Option<PathBuf>,
+
+        /// Package directory to create and register (repeatable; defaults to "home")
+        #[arg(long = "package", short, value_name = "NAME")]
+        packages: Vec<String>,
+
+        /// Also run `git init` in the dotfiles directory
+        #[arg(long)]
+        git: bool,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Set up amu: where the config lives, the default target, git-backed sources, existing dotfiles
+    Setup {
+        /// Keep the config in DIR (as amu.yaml), linked from the default location
+        #[arg(long, value_name = "DIR")]
+        config_in: Option<PathBuf>,
+
+        /// Target commands use when none is given
+        #[arg(long, value_name = "DIR")]
+        default_target: Option<PathBuf>,
+
+        /// Keep sources in a git repository at DIR, created if missing
+        #[arg(long, value_name = "DIR")]
+        git: Option<PathBuf>,
+
+        /// Import the stow directories, chezmoi and yadm setups found in the home directory
+        #[arg(long)]
+        discover: bool,
+
+        /// Don't ask: questions no option answers take their defaults
+        #[arg(short = 'y', long)]
+        assume_yes: bool,
+    },
+
+    /// Write targets' configuration to a file or bundle that `amu import` can read
+    Export {
+        /// Target to export (defaults to current directory)
+        #[arg(long, short, conflicts_with = "all")]
+        target: Option<PathBuf>,
+
+        /// Export every registered target
+        #[arg(long)]
+        all: bool,
+
+        /// Write to FILE instead of stdout
+        #[arg(long, short, value_name = "FILE", conflicts_with = "bundle")]
+        output: Option<PathBuf>,
+
+        /// Write a .tar.gz bundle holding the configuration to FILE
+        #[arg(long, value_name = "FILE")]
+        bundle: Option<PathBuf>,
+
+        /// Include the sources under the home directory in the bundle
+        #[arg(long, requires = "bundle")]
+        with_sources: bool,
+    },
+
+    /// Merge targets from an exported file or bundle, or another manager's setup, into the config
+    Import {
+        /// File or bundle written by `amu export` (use - for stdin), or the setup to read with --from
+        #[arg(required_unless_present = "from")]
+        file: Option<PathBuf>,
+
+        /// Read an existing stow directory, chezmoi source directory or yadm repository
+        #[arg(long, value_enum, value_name = "MANAGER")]
+        from: Option<Manager>,
+
+        /// Target the imported setup is linked into (default: the stow target, or home)
+        #[arg(long, requires = "from")]
+        target: Option<PathBuf>,
+
+        /// Directory chezmoi and yadm files are converted into (default: ~/dotfiles/<manager>)
+        #[arg(long, value_name = "DIR", requires = "from")]
+        into: Option<PathBuf>,
+
+        /// What to do with targets already registered with other sources (asks when omitted)
+        #[arg(long, value_enum, value_name = "STRATEGY")]
+        on_conflict: Option<MergeStrategy>,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Run amu commands from a script, one per line, as a single operation
+    Batch {
+        /// Script to run (default or `-`: stdin)
+        file: Option<PathBuf>,
+
+        /// Run every step as a dry run
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Revert the most recent operation: links, moved files and config changes
+    Undo {
+        /// Operation to revert instead of the most recent one (see --list)
+        id: Option<u64>,
+
+        /// List recorded operations
+        #[arg(long, conflicts_with = "id")]
+        list: bool,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Pin a path in a target to one source, whatever the source order
+    Bless {
+        /// File or directory inside a target
+        path: PathBuf,
+
+        /// Source to provide the path (defaults to the one currently linked)
+        #[arg(long, short)]
+        source: Option<PathBuf>,
+    },
+
+    /// Remove the pin set with bless
+    Unbless {
+        /// File or directory inside a target
+        path: PathBuf,
+    },
+
+    /// Mark sources on removable or network media, so they are reported as not mounted
+    Media {
+        #[command(subcommand)]
+        action: MediaAction,
+    },
+
+    /// List the files `--on-conflict backup` moved aside, or put them back
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+
+    /// Show or change the order in which a target's sources win paths they both provide
+    Priority {
+        #[command(subcommand)]
+        action: PriorityAction,
+    },
+
     /// Restore links from configuration (for new machine setup)
     Restore {
         /// Target directory to restore (defaults to current directory)
@@ -72,6 +494,81 @@ pub enum Commands {
         #[arg(long)]
         all: bool,
 
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Show details and a suggested fix for each failure
+        #[arg(long)]
+        why_failed: bool,
+
+        /// Output in JSON format (always includes failure details)
+        #[arg(long)]
+        json: bool,
+
+        /// Link at most N sources, then stop; the next restore resumes from there
+        #[arg(long, value_name = "N")]
+        chunk: Option<usize>,
+
+        /// Ignore progress saved by an interrupted restore and start over
+        #[arg(long)]
+        restart: bool,
+
+        /// Stop at the first path already provided by an earlier source, or the first conflict
+        #[arg(long, conflicts_with = "continue_on_conflict")]
+        stop_on_conflict: bool,
+
+        /// Skip paths already provided by an earlier source and keep going (default)
+        #[arg(long = "continue")]
+        continue_on_conflict: bool,
+
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
+        /// Wait up to DURATION (e.g. 10s) for the media of sources marked with `amu media` to mount
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        mount_wait: Option<Duration>,
+
+        /// Which missing directories may be created for a target that does not exist
+        #[arg(long, value_enum, value_name = "MODE", default_value_t = ParentsMode::All)]
+        parents_mode: ParentsMode,
+
+        /// Permission bits for directories created for targets, in octal (e.g. 700)
+        #[arg(long, value_name = "MODE", value_parser = mkdir::parse_mode)]
+        dir_mode: Option<u32>,
+
+        /// Owner of directories created for targets, as USER[:GROUP]
+        #[arg(long, value_name = "OWNER", value_parser = ownership::parse_owner)]
+        dir_owner: Option<Owner>,
+
+        /// Link directories missing from the target with one symlink each; kept in the config
+        #[arg(long, overrides_with = "no_fold")]
+        fold: bool,
+
+        /// Link file by file (the default); kept in the config
+        #[arg(long, overrides_with = "fold")]
+        no_fold: bool,
+    },
+
+    /// Re-attempt only the entries that failed in the last restore or update
+    Retry {
+        /// Show the recorded failures without retrying them
+        #[arg(long, conflicts_with = "clear")]
+        list: bool,
+
+        /// Forget the recorded failures without retrying them
+        #[arg(long)]
+        clear: bool,
+
+        /// What to do with existing files in the way of links
+        #[arg(long, value_enum, value_name = "STRATEGY", default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
@@ -90,9 +587,9 @@ pub enum Commands {
         #[arg(short = 'f', long)]
         flat: bool,
 
-        /// Show actual symlinks
-        #[arg(short, long)]
-        verbose: bool,
+        /// Draw targets, their sources and (with --verbose) the links into each source as a tree
+        #[arg(long)]
+        tree: bool,
     },
 
     /// Show status of registered links
@@ -104,15 +601,106 @@ pub enum Commands {
         #[arg(long)]
         all: bool,
 
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
         /// Non-recursive mode (only check current target)
         #[arg(short = 'f', long)]
         flat: bool,
 
+        /// Show a content diff for real files that shadow expected links
+        #[arg(long)]
+        diff_content: bool,
+
+        /// Only report sources with files changed within DURATION (e.g. 30m, 12h, 7d, 2w)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        since: Option<Duration>,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+
+        /// Send the status JSON to the configured webhook or command when there are warnings or errors
+        #[arg(long)]
+        notify: bool,
+
+        /// Check the filesystem even when `amu daemon` has the status cached
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Also fetch the sources' git repositories and show how far each is from its upstream
+        #[arg(long)]
+        remote: bool,
+
+        /// Repair first: remove broken links and create missing ones
+        #[arg(long)]
+        fix: bool,
+
+        /// With --fix, back up or adopt real files in the way of links (needs --yes)
+        #[arg(long, value_enum, value_name = "ACTION", requires = "fix")]
+        real_files: Option<RealFilesFix>,
+
+        /// Carry out the destructive fixes chosen with --real-files
+        #[arg(short, long, requires = "fix")]
+        yes: bool,
+    },
+
+    /// Show file by file what `amu update` would change
+    Diff {
+        /// Target directory to compare (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Compare all targets
+        #[arg(long)]
+        all: bool,
+
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
+        /// Print only the paths that would change, one per line
+        #[arg(long)]
+        name_only: bool,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List files in managed directories that no source provides
+    Orphans {
+        /// Target directory to check (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Check all targets
+        #[arg(long)]
+        all: bool,
+
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
+        /// Do not report paths matching PATTERN (repeatable; also `orphan_ignore:` in the config)
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+
+        /// Move the orphans into the source managing their directory and link them
+        #[arg(long)]
+        adopt: bool,
+
+        /// With --adopt, show what would be adopted without moving anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
         /// Output in JSON format
         #[arg(long)]
         json: bool,
     },
 
+    /// Print a short status summary for shell prompts, from the daemon's cache
+    Prompt,
+
     /// Remove symlinks and clear configuration
     Clear {
         /// Target directory to clear (defaults to current directory)
@@ -122,9 +710,21 @@ pub enum Commands {
         #[arg(long)]
         all: bool,
 
+        /// With --all, only targets at or below DIR (e.g. ~/.config)
+        #[arg(long, value_name = "DIR", requires = "all")]
+        under: Option<PathBuf>,
+
+        /// Only delete links amu recorded creating, leaving any others in place
+        #[arg(long)]
+        recorded_only: bool,
+
         /// Show what would be done without making changes
         #[arg(short = 'n', long)]
         dry_run: bool,
+
+        /// Output results in JSON format (with --dry-run, each source's plan)
+        #[arg(long)]
+        json: bool,
     },
 
     /// Remove dangling symlinks from targets
@@ -144,4 +744,399 @@ pub enum Commands {
         #[arg(short = 'n', long)]
         dry_run: bool,
     },
+    /// Analyze any directory for symlinks without registering it
+    VerifyTarget {
+        /// Directory to analyze
+        dir: PathBuf,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Report the largest sources by file count, links and scan time
+    Stats {
+        /// Sort order for the report
+        #[arg(long, value_enum, default_value_t = StatsSort::Files)]
+        sort: StatsSort,
+
+        /// Only show the first N sources
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Time config loading, status scans and link planning without changing anything
+    Bench {
+        /// Times each phase is run; the median is reported
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+        runs: u64,
+    },
+
+    /// Report security and compliance issues in sources and targets
+    Audit {
+        /// Exit with code 1 if any finding is at least this severe
+        #[arg(long, value_enum, default_value_t = Severity::High)]
+        fail_on: Severity,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Compare the files of two sources (overlap and divergence)
+    DiffSource {
+        /// First source directory
+        a: PathBuf,
+
+        /// Second source directory
+        b: PathBuf,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Merge duplicate source entries (same canonical path or identical contents)
+    Dedupe {
+        /// Target directory to dedupe (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Dedupe all targets
+        #[arg(long)]
+        all: bool,
+
+        /// Keep the first entry of each group without prompting
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Show what would be done without making changes
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Edit the target/source mapping in the config without linking
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Turn existing files into templates
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
+
+    /// Manage per-machine template variables
+    Vars {
+        #[command(subcommand)]
+        action: VarsAction,
+    },
+
+    /// Relink sources automatically when files are added or removed
+    Watch {
+        /// Process pending changes once and exit
+        #[arg(long)]
+        once: bool,
+
+        /// Wait until changes have been quiet for this long before relinking
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        debounce_ms: u64,
+
+        /// How often to check sources when file notifications are unavailable
+        #[arg(long, value_name = "MS", default_value_t = 1000)]
+        interval_ms: u64,
+
+        /// Append decisions to FILE as NDJSON (default: watch.log in the state directory)
+        #[arg(long, value_name = "FILE")]
+        log: Option<PathBuf>,
+    },
+
+    /// Keep the status cache up to date as registered sources and targets change
+    Daemon {
+        /// Write the status cache once and exit
+        #[arg(long)]
+        once: bool,
+
+        /// Wait until changes have been quiet for this long before rechecking
+        #[arg(long, value_name = "MS", default_value_t = 200)]
+        debounce_ms: u64,
+
+        /// Send the status JSON to the configured webhook or command when drift appears or changes
+        #[arg(long)]
+        notify: bool,
+    },
+
+    /// Serve JSON-RPC requests for editor plugins and GUI frontends
+    Serve {
+        /// Read requests from stdin and write responses to stdout (one JSON object per line)
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+
+    /// Print shell code adding sources' bin/ directories to PATH (use with eval "$(amu shellenv)")
+    Shellenv {
+        /// Shell syntax to print (default: from $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<EnvShell>,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate the script for
+        shell: Shell,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Register entries (link them with `amu restore`)
+    AddEntry {
+        /// Source directory
+        #[arg(required_unless_present = "json")]
+        source: Option<PathBuf>,
+
+        /// Target directory (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Read entry objects ({"target": ..., "source": ...}) from stdin and report results as JSON
+        #[arg(long, conflicts_with_all = ["source", "target"])]
+        json: bool,
+    },
+
+    /// Unregister entries without touching their links (use `amu remove` to unlink)
+    RemoveEntry {
+        /// Source directory
+        #[arg(required_unless_present = "json")]
+        source: Option<PathBuf>,
+
+        /// Target directory (defaults to current directory)
+        target: Option<PathBuf>,
+
+        /// Read entry objects ({"target": ..., "source": ...}) from stdin and report results as JSON
+        #[arg(long, conflicts_with_all = ["source", "target"])]
+        json: bool,
+    },
+
+    /// Rewrite the config file in the current schema version
+    Migrate {
+        /// Show the rewritten file as a diff without saving it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+
+    /// Print a setting (dotted key: backend, ignore, hooks.post-restore, ...); exits 1 if unset
+    Get {
+        key: String,
+    },
+
+    /// Change a setting; VALUE is read as YAML, falling back to a plain string
+    Set {
+        key: String,
+
+        value: String,
+
+        /// Append VALUE to a list setting
+        #[arg(long, conflicts_with = "remove")]
+        add: bool,
+
+        /// Remove VALUE from a list setting
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Remove a setting, returning it to its default
+    Unset {
+        key: String,
+    },
+
+    /// Open the config file in $VISUAL or $EDITOR and check it before saving
+    Edit,
+
+    /// Print the config file's path
+    Path,
+
+    /// Print the effective config (profile entries merged in): YAML, JSON with --json, or
+    /// one key=value line per setting with --flat
+    Dump {
+        /// One key=value line per value, keyed as `amu config get` takes them
+        #[arg(long, conflicts_with = "json")]
+        flat: bool,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MediaAction {
+    /// Mark a source as living on removable or network media mounted at MOUNT_POINT
+    Mark {
+        /// Source directory
+        source: PathBuf,
+
+        /// Where the media is mounted; the source must be inside it
+        mount_point: PathBuf,
+    },
+
+    /// Remove the mark
+    Unmark {
+        /// Source directory
+        source: PathBuf,
+    },
+
+    /// List marked sources and whether their media is mounted
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// List backups, oldest first, with their target and files
+    List,
+
+    /// Move a backup's files back into their target, replacing the links made in their place
+    Restore {
+        /// Backup name, as listed, or its directory
+        name: PathBuf,
+
+        /// Show what would be put back without moving anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PriorityAction {
+    /// List a target's sources, highest priority first, with the paths each one loses
+    List {
+        /// Target directory (defaults to current directory)
+        target: Option<PathBuf>,
+    },
+
+    /// Move a source to a position (1 = highest) and relink the paths that change hands
+    Set {
+        /// Source directory
+        source: PathBuf,
+
+        /// New position, starting at 1
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        position: u64,
+
+        /// Target directory (defaults to current directory)
+        target: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TemplateAction {
+    /// Convert a file in a target into a template in a source, extracting variable values
+    Init {
+        /// Real file inside a target
+        file: PathBuf,
+
+        /// Source directory to store the template in
+        #[arg(long, short)]
+        source: PathBuf,
+
+        /// Target the file belongs to (defaults to the registered target containing it)
+        #[arg(long, short)]
+        target: Option<PathBuf>,
+
+        /// Extract VALUE as variable NAME and save it (repeatable); saved variables are always extracted
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
+
+        /// Show the template without writing anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VarsAction {
+    /// List all variables
+    List {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Set a variable
+    Set {
+        /// Variable name
+        name: String,
+
+        /// Variable value
+        value: String,
+    },
+
+    /// Remove a variable
+    Unset {
+        /// Variable name
+        name: String,
+    },
+}
+
+/// Sort key for `amu stats`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatsSort {
+    /// Number of files in the source
+    Files,
+    /// Number of links created in the target
+    Links,
+    /// Time spent scanning the source
+    Duration,
+}
+
+/// What `amu status --fix` does with real files in the way of links
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RealFilesFix {
+    /// Move them to a timestamped backup directory
+    Backup,
+    /// Move them into the source, backing up the source's versions
+    Adopt,
+}
+
+impl RealFilesFix {
+    pub fn strategy(self) -> OnConflict {
+        match self {
+            RealFilesFix::Backup => OnConflict::Backup,
+            RealFilesFix::Adopt => OnConflict::Adopt,
+        }
+    }
+}
+
+/// Parse a duration such as `45s`, `30m`, `12h`, `7d` or `2w`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration: {value}"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit in {value} (use s, m, h, d or w)")),
+    };
+    let total = number.checked_mul(seconds).ok_or_else(|| format!("duration too long: {value}"))?;
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert_eq!(parse_duration("18446744073709551615w").unwrap_err(), "duration too long: 18446744073709551615w");
+    }
 }
@@ -1,56 +1,496 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
 
 use serde::{Deserialize, Serialize};
 
 use crate::error::{DotlinkError, Result};
+use crate::history;
+use crate::hooks::Hook;
+use crate::linker::{Backend, Mode};
+use crate::style::ColorChoice;
+use crate::theme::{Symbols, Theme};
+use crate::ownership;
+use crate::schema::{self, EntryMeta};
+use crate::yaml;
+
+pub use crate::paths::{
+    abbreviate_path, absolute_path, absolute_path_in, canonicalize_existing, expand_path, normalize_lexically, resolve_existing,
+    resolve_link, resolve_path, resolve_path_in, same_path, symlink_destination, PathKey,
+};
 
 const CONFIG_DIR: &str = "amu";
 const CONFIG_FILE: &str = "config.yaml";
+/// Directory beside the config file holding fragments merged into it
+const FRAGMENT_DIR: &str = "conf.d";
+const HOME_ENV: &str = "AMU_HOME";
+const PROFILE_ENV: &str = "AMU_PROFILE";
+
+/*
+ * What to do with a target path that is itself a symlink (`symlinked_targets:`)
+ * Either way amu links into the directory the symlink points to, with links relative to
+ * where they really are, and looks entries up by that resolved location, so an entry
+ * written through the symlink and one stored resolved are the same target.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkedTargets {
+    /// Operate on the directory the symlink points to
+    #[default]
+    Resolve,
+    /// Fail for such targets, as the symlink may be repointed later
+    Refuse,
+}
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
-    pub targets: BTreeMap<PathBuf, Vec<PathBuf>>,
+    pub targets: BTreeMap<PathKey, Vec<PathBuf>>,
+
+    /// Skip editor temporary/backup files (`*~`, `*.swp`, ...) when linking (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ignores: Option<bool>,
+
+    /// Glob patterns never linked from any source, in addition to each source's `.amuignore`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+
+    /// Store sources inside the config file's directory as relative paths
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_sources: Option<bool>,
+
+    /// Replace real files that are byte-identical to their source file with links on update (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adopt_identical: Option<bool>,
+
+    /// Source-relative paths that are left unlinked, per target and source
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub excludes: BTreeMap<PathBuf, BTreeMap<PathBuf, Vec<String>>>,
+
+    /// Source-relative paths that alone are linked, per target and source (`amu add --only`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub only: BTreeMap<PathBuf, BTreeMap<PathBuf, Vec<String>>>,
+
+    /// Glob patterns `amu orphans` never reports (caches and the like)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub orphan_ignore: Vec<String>,
+
+    /// Target-relative paths that always come from one source, per target (`amu bless`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pins: BTreeMap<PathBuf, BTreeMap<PathBuf, PathBuf>>,
+
+    /// Sources whose directories are linked whole where possible, per target (`--fold`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fold: BTreeMap<PathBuf, Vec<PathBuf>>,
+
+    /// Template variables shared by every machine; `amu vars set` values override them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+
+    /// Sources on removable or network media, with the mount point each lives under (`amu media`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mounts: BTreeMap<PathBuf, PathBuf>,
+
+    /// Target used by commands given none (default: the current directory)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_target: Option<PathBuf>,
+
+    /// How links are created: auto (default), native or stow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+
+    /// Shell commands run before and after add, remove, update and restore (`post-restore: fc-cache -f`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hooks: BTreeMap<Hook, String>,
+
+    /// Targets that are symlinks: resolve (default) or refuse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlinked_targets: Option<SymlinkedTargets>,
+
+    /// Fail instead of warning, as `--strict` does (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+
+    /// Symbols marking results in text output: unicode (default), ascii, nerd-font or words
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<Theme>,
+
+    /// Replacements for single symbols of the theme (`ok`, `warning`, `error`, `rolled_back`)
+    #[serde(default, skip_serializing_if = "Symbols::is_empty")]
+    pub symbols: Symbols,
+
+    /// Colors in text output: auto (default), always or never
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorChoice>,
+
+    /// Where `status --notify` reports drift
+    #[serde(default, skip_serializing_if = "Notify::is_empty")]
+    pub notify: Notify,
+
+    /// Extra mappings applied only where the profile is active (`--profile`, `AMU_PROFILE` or the hostname)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// Registration date and backend per target and source, stored in the entries (see `schema`)
+    #[serde(skip)]
+    pub metadata: BTreeMap<PathBuf, BTreeMap<PathBuf, EntryMeta>>,
+
+    /// Where the entries of `targets` came from once the active profile is merged in
+    #[serde(skip)]
+    origin: Origin,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub targets: BTreeMap<PathKey, Vec<PathBuf>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Origin {
+    /// Name of the merged profile, and whether it was selected explicitly rather than by hostname
+    profile: Option<(String, bool)>,
+    shared: BTreeSet<(PathBuf, PathBuf)>,
+    from_profile: BTreeSet<(PathBuf, PathBuf)>,
+    /// Fragments merged from `conf.d/`, in load order
+    fragments: Vec<Fragment>,
+    /// The config file as loaded, when there are fragments
+    main: Option<MainFile>,
+}
+
+/// A file of `conf.d/`, as loaded
+#[derive(Debug, Clone)]
+struct Fragment {
+    path: PathBuf,
+    config: Config,
+    /// Entries it lists, saved back to it while they remain
+    entries: BTreeSet<(PathBuf, PathBuf)>,
+    /// Whether each of its entries has a priority, else it is rewritten with them on save
+    ranked: bool,
+}
+
+#[derive(Debug, Clone)]
+struct MainFile {
+    entries: BTreeSet<(PathBuf, PathBuf)>,
+    /// Its own settings
+    settings: serde_yaml::Value,
+    /// Settings with every fragment's merged in
+    merged: serde_yaml::Value,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Notify {
+    /// URL the status JSON is POSTed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+
+    /// Shell command the status JSON is piped to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+impl Notify {
+    pub fn is_empty(&self) -> bool {
+        self.webhook.is_none() && self.command.is_none()
+    }
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
-        let path = Self::config_path()?;
-        if !path.exists() {
-            return Ok(Config::default());
+        Self::load_from(&Self::config_path()?)
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        let mut config = match Self::read_file(path)? {
+            Some(content) => Self::stored_from(&content, path)?,
+            None => Config::default(),
+        };
+        config.merge_fragments(path)?;
+        config.forget_priorities();
+        config.apply_profile();
+        Ok(config)
+    }
+
+    /// Contents of a config file, or of its staged save; None if there is no such file.
+    fn read_file(path: &Path) -> Result<Option<String>> {
+        let staged = staged().as_ref().and_then(|files| files.get(path).cloned());
+        let content = match staged {
+            Some(content) => content,
+            None => {
+                if !path.exists() {
+                    return Ok(None);
+                }
+                if path.is_dir() {
+                    return Err(DotlinkError::ConfigIsDirectory(path.to_path_buf()));
+                }
+                fs::read_to_string(path).map_err(|e| {
+                    let reason = match e.kind() {
+                        std::io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+                        std::io::ErrorKind::InvalidData => "not valid UTF-8".to_string(),
+                        _ => e.to_string(),
+                    };
+                    DotlinkError::ConfigUnreadable { path: path.to_path_buf(), reason }
+                })?
+            }
+        };
+        // Editors on Windows may prepend a byte order mark, which YAML does not expect
+        Ok(Some(content.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(content)))
+    }
+
+    /// The config as loaded from `content`, the contents of the file at `path`.
+    fn from_content(content: &str, path: &Path) -> Result<Self> {
+        let mut config = Self::stored_from(content, path)?;
+        config.forget_priorities();
+        config.apply_profile();
+        Ok(config)
+    }
+
+    /// The file at `path` as stored (no profile merged in), with its sources resolved.
+    fn stored_from(content: &str, path: &Path) -> Result<Self> {
+        if let Some(line) = merge_conflict_line(content) {
+            return Err(DotlinkError::ConfigMergeConflict { path: path.to_path_buf(), line });
+        }
+
+        let mut config = Self::parse_stored(content)?;
+        config.resolve_relative_sources(&Self::config_root(path))?;
+        Ok(config)
+    }
+
+    /*
+     * Merge in the fragments of `conf.d/` beside the config file, in file name order
+     * Each file's sources come after those of the files before it, then all are ordered by
+     * their priorities; settings are merged key by key, a later file's value winning and
+     * lists adding up.
+     */
+    fn merge_fragments(&mut self, path: &Path) -> Result<()> {
+        let paths = fragment_paths(path);
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let settings = settings_of(self)?;
+        let entries = entry_keys(self);
+        for fragment_path in paths {
+            let content = Self::read_file(&fragment_path)?.unwrap_or_default();
+            let fragment = Self::stored_from(&content, &fragment_path).map_err(|e| match e {
+                DotlinkError::ConfigParseError(message) => {
+                    DotlinkError::ConfigParseError(format!("{}: {}", fragment_path.display(), message))
+                }
+                e => e,
+            })?;
+            self.merge(&fragment)?;
+            let entries = entry_keys(&fragment);
+            let ranked = entries.iter().all(|(target, source)| {
+                fragment.metadata.get(target).and_then(|m| m.get(source)).is_some_and(|meta| meta.priority.is_some())
+            });
+            self.origin.fragments.push(Fragment { path: fragment_path, config: fragment, entries, ranked });
+        }
+        self.order_by_priority();
+        self.origin.main = Some(MainFile { entries, settings, merged: settings_of(self)? });
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Config) -> Result<()> {
+        let mut document = to_value(&*self)?;
+        merge_value(&mut document, to_value(other)?);
+        let mut merged: Config = from_value(document)?;
+        merged.metadata = std::mem::take(&mut self.metadata);
+        for (target, metadata) in &other.metadata {
+            let merged_metadata = merged.metadata.entry(target.clone()).or_default();
+            for (source, meta) in metadata {
+                merged_metadata.entry(source.clone()).or_insert_with(|| meta.clone());
+            }
         }
+        merged.origin = std::mem::take(&mut self.origin);
+        *self = merged;
+        Ok(())
+    }
 
-        let content = fs::read_to_string(&path)?;
-        let config: Config = serde_yaml::from_str(&content)
-            .map_err(|e| DotlinkError::ConfigParseError(e.to_string()))?;
+    /// Sort each target's sources by their stored priorities; those without one keep their place.
+    fn order_by_priority(&mut self) {
+        let metadata = &self.metadata;
+        let profiles = self.profiles.values_mut().map(|profile| &mut profile.targets);
+        for targets in std::iter::once(&mut self.targets).chain(profiles) {
+            for (target, sources) in targets.iter_mut() {
+                let priority = |position: usize, source: &PathBuf| {
+                    metadata.get(target.as_path()).and_then(|m| m.get(source)).and_then(|meta| meta.priority).unwrap_or(position + 1)
+                };
+                let ranks: BTreeMap<PathBuf, usize> = sources.iter().enumerate()
+                    .map(|(position, source)| (source.clone(), priority(position, source)))
+                    .collect();
+                sources.sort_by_key(|source| ranks[source]);
+            }
+        }
+    }
+
+    /// Drop the priorities read from the files; from now on the order of the lists is what counts.
+    fn forget_priorities(&mut self) {
+        for metadata in self.metadata.values_mut() {
+            for meta in metadata.values_mut() {
+                meta.priority = None;
+            }
+            metadata.retain(|_, meta| !meta.is_empty());
+        }
+        self.metadata.retain(|_, metadata| !metadata.is_empty());
+    }
+
+    /// Check file contents written outside amu (`amu config edit`) as `load` would read them.
+    pub fn validate(content: &str) -> Result<Self> {
+        Self::from_content(content.strip_prefix('\u{feff}').unwrap_or(content), &Self::config_path()?)
+    }
+
+    /// Replace the config file with `content` as given, after checking that it loads.
+    pub fn save_content(content: String) -> Result<()> {
+        Self::validate(&content)?;
+        Self::write(Self::config_path()?, content)
+    }
+
+    /// Parse config file contents of any schema version, as stored (sources not resolved).
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut config = Self::parse_stored(content)?;
+        config.forget_priorities();
         Ok(config)
     }
 
+    /// As `parse`, keeping the entries' priorities for merging with other files.
+    fn parse_stored(content: &str) -> Result<Self> {
+        yaml::check(content).map_err(DotlinkError::ConfigParseError)?;
+        let document = match serde_yaml::from_str(content).map_err(|e| DotlinkError::ConfigParseError(e.to_string()))? {
+            // An empty file
+            serde_yaml::Value::Null => serde_yaml::Value::Mapping(Default::default()),
+            document => document,
+        };
+        schema::parse(document).map_err(DotlinkError::ConfigParseError)
+    }
+
+    /// The config file contents `save` would write (fragments aside).
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(self.files(&Self::config_path()?)?.swap_remove(0).1)
+    }
+
     pub fn save(&self) -> Result<()> {
-        let path = Self::config_path()?;
+        let files = self.files(&Self::config_path()?)?;
+        if let Some(staged) = staged().as_mut() {
+            staged.extend(files);
+            return Ok(());
+        }
+        for (path, content) in files {
+            Self::write(path, content)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * The files `save` writes: the config file, then each fragment whose entries changed
+     * Entries are saved to the files they were loaded from and new ones to the config file, as
+     * are changed settings; a fragment's own settings are left as they are. Every entry is
+     * written with its priority among all of its target's entries, so that the files merge
+     * back in the same order.
+     */
+    fn files(&self, path: &Path) -> Result<Vec<(PathBuf, String)>> {
+        let mut stored = self.unmerged();
+        let Some(loaded) = &self.origin.main else {
+            return Ok(vec![(path.to_path_buf(), stored.file_content(path)?)]);
+        };
+        schema::rank(&mut stored);
+
+        let fragments = &self.origin.fragments;
+        let own = |target: &Path, source: &Path| {
+            let key = (target.to_path_buf(), source.to_path_buf());
+            loaded.entries.contains(&key) || !fragments.iter().any(|fragment| fragment.entries.contains(&key))
+        };
+        let settings = own_settings(&settings_of(&stored)?, &loaded.merged, Some(&loaded.settings));
+        let main = stored.entries_where(own).with_settings(settings)?;
+        let mut files = vec![(path.to_path_buf(), main.file_content(path)?)];
+        for fragment in fragments {
+            let keep = |t: &Path, s: &Path| fragment.entries.contains(&(t.to_path_buf(), s.to_path_buf()));
+            let part = stored.entries_where(keep).with_settings(Some(settings_of(&fragment.config)?))?;
+            let content = part.file_content(&fragment.path)?;
+            if !fragment.ranked || content != fragment.config.file_content(&fragment.path)? {
+                files.push((fragment.path.clone(), content));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Contents of the file at `path` storing this config (profile entries not merged in).
+    fn file_content(&self, path: &Path) -> Result<String> {
+        let stored = if self.relative_sources.unwrap_or(false) {
+            self.relativized(&Self::config_root(path))
+        } else {
+            self.clone()
+        };
+        schema::to_document(&stored)
+            .and_then(|document| serde_yaml::to_string(&document).map_err(|e| e.to_string()))
+            .map_err(DotlinkError::ConfigSaveError)
+    }
+
+    /// Copy holding only the entries `keep` accepts, with their fold modes, filters and metadata.
+    fn entries_where(&self, keep: impl Fn(&Path, &Path) -> bool) -> Config {
+        fn sources<K: Ord + Clone + std::ops::Deref<Target = Path>>(
+            targets: &BTreeMap<K, Vec<PathBuf>>,
+            keep: &impl Fn(&Path, &Path) -> bool,
+        ) -> BTreeMap<K, Vec<PathBuf>> {
+            targets.iter()
+                .map(|(target, sources)| (target.clone(), sources.iter().filter(|s| keep(target, s)).cloned().collect::<Vec<_>>()))
+                .filter(|(_, sources)| !sources.is_empty())
+                .collect()
+        }
+        let profiles = self.profiles.iter()
+            .map(|(name, profile)| (name.clone(), Profile { targets: sources(&profile.targets, &keep) }))
+            .filter(|(_, profile)| !profile.targets.is_empty())
+            .collect();
+        Config {
+            targets: sources(&self.targets, &keep),
+            fold: sources(&self.fold, &keep),
+            profiles,
+            excludes: per_source(&self.excludes, &keep),
+            only: per_source(&self.only, &keep),
+            metadata: per_source(&self.metadata, &keep),
+            ..Config::default()
+        }
+    }
+
+    /// This config's entries with `settings` (a mapping of everything else).
+    fn with_settings(self, settings: Option<serde_yaml::Value>) -> Result<Config> {
+        let mut document = to_value(&self)?;
+        if let (Some(document), Some(serde_yaml::Value::Mapping(settings))) = (document.as_mapping_mut(), settings) {
+            document.extend(settings);
+        }
+        let mut config: Config = from_value(document)?;
+        config.metadata = self.metadata;
+        Ok(config)
+    }
+
+    fn write(path: PathBuf, content: String) -> Result<()> {
+        if path.is_dir() {
+            return Err(DotlinkError::ConfigIsDirectory(path));
+        }
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let content = serde_yaml::to_string(self)
-            .map_err(|e| DotlinkError::ConfigSaveError(e.to_string()))?;
-        fs::write(&path, content)?;
+        let before = fs::read_to_string(&path).ok();
+        fs::write(&path, &content).map_err(|e| DotlinkError::ConfigSaveError(format!("{}: {}", path.display(), e)))?;
+        ownership::fix_written(&path);
+        history::record(history::Change::Config { path, before, after: content });
         Ok(())
     }
 
     pub fn add_source(&mut self, target: PathBuf, source: PathBuf) -> Result<()> {
-        let sources = self.targets.entry(target.clone()).or_default();
+        let sources = self.targets.entry(PathKey::new(&target)).or_default();
         if sources.contains(&source) {
             return Err(DotlinkError::AlreadyRegistered { src: source, dest: target });
         }
-        sources.push(source);
+        sources.push(source.clone());
+        // Settings made for the pair before it is added (`--copy`) stay
+        self.metadata.entry(target).or_default().entry(source).or_default().added = Some(crate::log::today());
         Ok(())
     }
 
     pub fn remove_source(&mut self, target: &Path, source: &Path) -> Result<()> {
-        let sources = self.targets.get_mut(target).ok_or_else(|| {
+        let key = PathKey::new(target);
+        let sources = self.targets.get_mut(&key).ok_or_else(|| {
             DotlinkError::NotRegistered {
                 src: source.to_path_buf(),
                 dest: target.to_path_buf(),
@@ -67,40 +507,630 @@ impl Config {
         sources.remove(pos);
 
         if sources.is_empty() {
-            self.targets.remove(target);
+            self.targets.remove(&key);
+        }
+        self.clear_filters(target, source);
+        self.clear_pins(target, source);
+        self.set_fold(target, source, false);
+        if let Some(metadata) = self.metadata.get_mut(target) {
+            metadata.remove(source);
+            if metadata.is_empty() {
+                self.metadata.remove(target);
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Move a source to `position` (0 = highest priority) in its target's list
+     * Positions past the end move it last. The active profile's sources are stored after
+     * the shared ones, so an order mixing the two is only kept within each group.
+     */
+    pub fn move_source(&mut self, target: &Path, source: &Path, position: usize) -> Result<()> {
+        let not_registered = || DotlinkError::NotRegistered { src: source.to_path_buf(), dest: target.to_path_buf() };
+        let sources = self.targets.get_mut(&PathKey::new(target)).ok_or_else(not_registered)?;
+        let current = sources.iter().position(|s| s == source).ok_or_else(not_registered)?;
+        let moved = sources.remove(current);
+        sources.insert(position.min(sources.len()), moved);
+        Ok(())
+    }
+
+    /// The registered target entry referring to `target`, in whichever form it was stored.
+    pub fn find_target(&self, target: &Path) -> Option<PathBuf> {
+        self.targets.get_key_value(&PathKey::new(target)).map(|(stored, _)| stored.to_path_buf())
+    }
+
+    /*
+     * The target a command was given (`default_target:`, else the current directory, if
+     * none), as registered
+     * The stored entry is returned when one refers to the same location, whether it was
+     * written through a symlink or resolved; otherwise the path resolved as usual.
+     */
+    pub fn lookup_target(&self, target: Option<PathBuf>) -> Result<PathBuf> {
+        let target = target.or_else(|| self.default_target.clone());
+        if let Some(given) = &target {
+            self.check_symlinked(&expand_path(given))?;
+        }
+        let resolved = resolve_target(target)?;
+        let target = self.find_target(&resolved).unwrap_or(resolved);
+        self.check_symlinked(&target)?;
+        Ok(target)
+    }
+
+    /// Fail for a target that is a symlink when `symlinked_targets: refuse` is set.
+    pub fn check_symlinked(&self, target: &Path) -> Result<()> {
+        if self.symlinked_targets.unwrap_or_default() != SymlinkedTargets::Refuse {
+            return Ok(());
+        }
+        match symlink_destination(&absolute_path(target)) {
+            Some(resolved) => Err(DotlinkError::SymlinkedTarget { target: target.to_path_buf(), resolved }),
+            None => Ok(()),
+        }
+    }
+
+    /// Registered targets at or below `dir`, in whichever form they were stored.
+    pub fn targets_under(&self, dir: &Path) -> Vec<PathBuf> {
+        let dir = PathKey::new(dir);
+        self.targets.keys().filter(|t| t.starts_with(&dir)).map(|t| t.to_path_buf()).collect()
+    }
+
+    /// The source entry registered for `target` that refers to `source`.
+    pub fn find_source(&self, target: &Path, source: &Path) -> Option<PathBuf> {
+        let sources = self.get_sources(target)?;
+        if sources.iter().any(|s| s == source) {
+            return Some(source.to_path_buf());
+        }
+        let key = PathKey::new(source);
+        sources.iter().find(|s| PathKey::new(s) == key).cloned()
+    }
+
+    pub fn excludes_for(&self, target: &Path, source: &Path) -> &[String] {
+        self.excludes
+            .get(target)
+            .and_then(|sources| sources.get(source))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Patterns limiting what a (target, source) pair links; empty if everything is linked.
+    pub fn only_for(&self, target: &Path, source: &Path) -> &[String] {
+        self.only
+            .get(target)
+            .and_then(|sources| sources.get(source))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Record a pattern to leave unlinked for a (target, source) pair. Returns false if already present.
+    pub fn add_exclude(&mut self, target: &Path, source: &Path, pattern: String) -> bool {
+        add_pattern(&mut self.excludes, target, source, pattern)
+    }
+
+    /// Record a pattern a (target, source) pair is limited to. Returns false if already present.
+    pub fn add_only(&mut self, target: &Path, source: &Path, pattern: String) -> bool {
+        add_pattern(&mut self.only, target, source, pattern)
+    }
+
+    fn clear_filters(&mut self, target: &Path, source: &Path) {
+        for filters in [&mut self.excludes, &mut self.only] {
+            if let Some(sources) = filters.get_mut(target) {
+                sources.remove(source);
+                if sources.is_empty() {
+                    filters.remove(target);
+                }
+            }
+        }
+    }
+
+    /// Pin a target-relative path to a source. Returns the source it was pinned to before.
+    pub fn pin(&mut self, target: &Path, relative: PathBuf, source: PathBuf) -> Option<PathBuf> {
+        self.pins.entry(target.to_path_buf()).or_default().insert(relative, source)
+    }
+
+    /// Remove the pin for a target-relative path. Returns the source it was pinned to.
+    pub fn unpin(&mut self, target: &Path, relative: &Path) -> Option<PathBuf> {
+        let pins = self.pins.get_mut(target)?;
+        let source = pins.remove(relative);
+        if pins.is_empty() {
+            self.pins.remove(target);
+        }
+        source
+    }
+
+    /// Whether a (target, source) pair links directories whole where possible.
+    pub fn folds(&self, target: &Path, source: &Path) -> bool {
+        self.fold.get(target).is_some_and(|sources| sources.iter().any(|s| s == source))
+    }
+
+    /// Link a (target, source) pair's directories whole (`--fold`) or file by file from now on.
+    pub fn set_fold(&mut self, target: &Path, source: &Path, fold: bool) {
+        let sources = self.fold.entry(target.to_path_buf()).or_default();
+        sources.retain(|s| s != source);
+        if fold {
+            sources.push(source.to_path_buf());
+        }
+        if sources.is_empty() {
+            self.fold.remove(target);
+        }
+    }
+
+    /// Copy or hard-link a (target, source) pair's files rather than link them from now on
+    /// (`--copy`, `--hardlink`), or link them again.
+    pub fn set_mode(&mut self, target: &Path, source: &Path, mode: Mode) {
+        let meta = self.metadata.entry(target.to_path_buf()).or_default().entry(source.to_path_buf()).or_default();
+        meta.mode = (mode != Mode::Link).then_some(mode);
+    }
+
+    /// Mark a (target, source) pair temporary: removed by `amu gc` after `expires` or once
+    /// the machine has restarted since boot `session`.
+    pub fn set_lifetime(&mut self, target: &Path, source: &Path, expires: Option<String>, session: Option<String>) {
+        let meta = self.metadata.entry(target.to_path_buf()).or_default().entry(source.to_path_buf()).or_default();
+        meta.expires = expires;
+        meta.session = session;
+    }
+
+    /// How the entry's files get into the target, if not linked.
+    pub fn mode_of(&self, target: &Path, source: &Path) -> Option<Mode> {
+        self.metadata.get(target)?.get(source)?.mode
+    }
+
+    /// The backend the entry asks for in place of `backend:`, if any.
+    pub fn backend_of(&self, target: &Path, source: &Path) -> Option<Backend> {
+        self.metadata.get(target)?.get(source)?.backend
+    }
+
+    fn clear_pins(&mut self, target: &Path, source: &Path) {
+        if let Some(pins) = self.pins.get_mut(target) {
+            pins.retain(|_, pinned| pinned != source);
+            if pins.is_empty() {
+                self.pins.remove(target);
+            }
+        }
+    }
+
+    /*
+     * Merge the active profile's mappings into `targets`
+     * The profile is the one selected with --profile or AMU_PROFILE, or else the one named
+     * after this machine's hostname. Its sources come after the shared ones of each target.
+     */
+    fn apply_profile(&mut self) {
+        self.origin.shared = entry_set(&self.targets);
+        let (name, explicit) = match selected_profile() {
+            Some(name) => (name, true),
+            None => match hostname().filter(|h| self.profiles.contains_key(h)) {
+                Some(name) => (name, false),
+                None => return,
+            },
+        };
+
+        if let Some(profile) = self.profiles.get(&name) {
+            for (target, sources) in &profile.targets {
+                let merged = self.targets.entry(target.clone()).or_default();
+                for source in sources {
+                    if !merged.contains(source) {
+                        merged.push(source.clone());
+                    }
+                }
+            }
+            self.origin.from_profile = entry_set(&profile.targets);
         }
+        self.origin.profile = Some((name, explicit));
+    }
 
+    /// Fail for a profile name (`--profile`) the config has no entries for.
+    pub fn check_profile(&self, name: &str) -> Result<()> {
+        if self.profiles.contains_key(name) {
+            return Ok(());
+        }
+        let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        Err(DotlinkError::UnknownProfile {
+            name: name.to_string(),
+            known: if known.is_empty() { "none".to_string() } else { known.join(", ") },
+        })
+    }
+
+    /// Name of the profile merged into `targets`, if any.
+    pub fn active_profile(&self) -> Option<&str> {
+        self.origin.profile.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Name of the active profile if the entry comes from it rather than the shared mappings.
+    pub fn profile_of(&self, target: &Path, source: &Path) -> Option<&str> {
+        let key = (target.to_path_buf(), source.to_path_buf());
+        if self.origin.shared.contains(&key) || !self.origin.from_profile.contains(&key) {
+            return None;
+        }
+        self.active_profile()
+    }
+
+    /*
+     * Copy of the config as stored, with the active profile's entries moved back into it
+     * Entries registered since loading go to the profile when it was selected explicitly,
+     * and to the shared mappings otherwise.
+     */
+    fn unmerged(&self) -> Config {
+        let Some((name, explicit)) = &self.origin.profile else {
+            return self.clone();
+        };
+
+        let mut config = self.clone();
+        config.targets = BTreeMap::new();
+        let mut profile_targets: BTreeMap<PathKey, Vec<PathBuf>> = BTreeMap::new();
+        for (target, sources) in &self.targets {
+            for source in sources {
+                let key = (target.to_path_buf(), source.clone());
+                let shared = self.origin.shared.contains(&key);
+                let from_profile = self.origin.from_profile.contains(&key);
+                let added = !shared && !from_profile;
+                if shared || (added && !explicit) {
+                    config.targets.entry(target.clone()).or_default().push(source.clone());
+                }
+                if from_profile || (added && *explicit) {
+                    profile_targets.entry(target.clone()).or_default().push(source.clone());
+                }
+            }
+        }
+        if !profile_targets.is_empty() || config.profiles.contains_key(name) {
+            config.profiles.entry(name.clone()).or_default().targets = profile_targets;
+        }
+        config
+    }
+
+    /*
+     * Resolve relative source entries against the config file's directory
+     * Entries that climb out of that directory are rejected rather than silently
+     * pointing somewhere unexpected after the repository is cloned elsewhere.
+     */
+    fn resolve_relative_sources(&mut self, root: &Path) -> Result<()> {
+        let profile_sources = self.profiles.values_mut().flat_map(|profile| profile.targets.values_mut());
+        for sources in self.targets.values_mut().chain(self.fold.values_mut()).chain(profile_sources) {
+            for source in sources.iter_mut() {
+                *source = Self::resolve_source(source, root)?;
+            }
+        }
+        for sources in self.excludes.values_mut().chain(self.only.values_mut()) {
+            let resolved = std::mem::take(sources)
+                .into_iter()
+                .map(|(source, patterns)| Ok((Self::resolve_source(&source, root)?, patterns)))
+                .collect::<Result<_>>()?;
+            *sources = resolved;
+        }
+        for metadata in self.metadata.values_mut() {
+            let resolved = std::mem::take(metadata)
+                .into_iter()
+                .map(|(source, meta)| Ok((Self::resolve_source(&source, root)?, meta)))
+                .collect::<Result<_>>()?;
+            *metadata = resolved;
+        }
+        for pins in self.pins.values_mut() {
+            for source in pins.values_mut() {
+                *source = Self::resolve_source(source, root)?;
+            }
+        }
         Ok(())
     }
 
+    fn resolve_source(source: &Path, root: &Path) -> Result<PathBuf> {
+        if source.is_absolute() || source.starts_with("~") {
+            return Ok(source.to_path_buf());
+        }
+        let resolved = normalize_lexically(&root.join(source));
+        if !resolved.starts_with(root) {
+            return Err(DotlinkError::RelativeSourceEscapesRoot {
+                path: source.to_path_buf(),
+                root: root.to_path_buf(),
+            });
+        }
+        Ok(canonicalize_existing(&resolved))
+    }
+
+    /// Copy of the config with sources under `root` stored relative to it.
+    fn relativized(&self, root: &Path) -> Config {
+        let relative = |source: &Path| source.strip_prefix(root).unwrap_or(source).to_path_buf();
+
+        let mut config = self.clone();
+        let profile_sources = config.profiles.values_mut().flat_map(|profile| profile.targets.values_mut());
+        for sources in config.targets.values_mut().chain(config.fold.values_mut()).chain(profile_sources) {
+            for source in sources.iter_mut() {
+                *source = relative(source);
+            }
+        }
+        for sources in config.excludes.values_mut().chain(config.only.values_mut()) {
+            *sources = std::mem::take(sources)
+                .into_iter()
+                .map(|(source, patterns)| (relative(&source), patterns))
+                .collect();
+        }
+        for metadata in config.metadata.values_mut() {
+            *metadata = std::mem::take(metadata)
+                .into_iter()
+                .map(|(source, meta)| (relative(&source), meta))
+                .collect();
+        }
+        for pins in config.pins.values_mut() {
+            for source in pins.values_mut() {
+                *source = relative(source);
+            }
+        }
+        config
+    }
+
+    /// Directory relative source entries are resolved against.
+    fn config_root(config_path: &Path) -> PathBuf {
+        resolve_path(config_path.parent().unwrap_or(Path::new(".")))
+    }
+
     pub fn get_sources(&self, target: &Path) -> Option<&Vec<PathBuf>> {
-        self.targets.get(target)
+        self.targets.get(&PathKey::new(target))
+    }
+
+    /// Sources of a target, highest priority first; none if it is not registered.
+    pub fn sources_of(&self, target: &Path) -> &[PathBuf] {
+        self.get_sources(target).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn uses_default_ignores(&self) -> bool {
+        self.default_ignores.unwrap_or(true)
     }
 
-    fn config_path() -> Result<PathBuf> {
+    pub fn adopts_identical(&self) -> bool {
+        self.adopt_identical.unwrap_or(false)
+    }
+
+    pub fn config_path() -> Result<PathBuf> {
         if let Ok(path) = std::env::var("AMU_CONFIG") {
             return Ok(PathBuf::from(path));
         }
-        let home = dirs::home_dir()
-            .ok_or_else(|| DotlinkError::IoError(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Could not find home directory",
-            )))?;
+        let home = home_dir().ok_or(DotlinkError::HomeNotFound)?;
         Ok(home.join(".config").join(CONFIG_DIR).join(CONFIG_FILE))
     }
 }
 
-pub fn expand_path(path: &Path) -> PathBuf {
-    let path_str = path.to_string_lossy();
-    let expanded = shellexpand::tilde(&path_str);
-    PathBuf::from(expanded.as_ref())
+/// Line number (1-based) of the first git merge conflict marker, if any.
+fn merge_conflict_line(content: &str) -> Option<usize> {
+    content.lines()
+        .position(|line| {
+            line.starts_with("<<<<<<< ") || line == "=======" || line.starts_with(">>>>>>> ")
+        })
+        .map(|index| index + 1)
+}
+
+fn entry_set(targets: &BTreeMap<PathKey, Vec<PathBuf>>) -> BTreeSet<(PathBuf, PathBuf)> {
+    targets.iter()
+        .flat_map(|(target, sources)| sources.iter().map(move |source| (target.to_path_buf(), source.clone())))
+        .collect()
+}
+
+/// The fragments beside the config file at `path` (`conf.d/*.yaml`), by file name.
+fn fragment_paths(path: &Path) -> Vec<PathBuf> {
+    let Some(dir) = path.parent().map(|parent| parent.join(FRAGMENT_DIR)) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+            !name.starts_with('.') && path.extension().is_some_and(|ext| ext == "yaml") && !path.is_dir()
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Every (target, source) entry of the config, profiles included.
+fn entry_keys(config: &Config) -> BTreeSet<(PathBuf, PathBuf)> {
+    let mut keys = entry_set(&config.targets);
+    for profile in config.profiles.values() {
+        keys.extend(entry_set(&profile.targets));
+    }
+    keys
+}
+
+fn to_value(config: &Config) -> Result<serde_yaml::Value> {
+    serde_yaml::to_value(config).map_err(|e| DotlinkError::ConfigSaveError(e.to_string()))
+}
+
+fn from_value(value: serde_yaml::Value) -> Result<Config> {
+    serde_yaml::from_value(value).map_err(|e| DotlinkError::ConfigParseError(e.to_string()))
+}
+
+/// Everything but the entries: a mapping of the config's settings.
+fn settings_of(config: &Config) -> Result<serde_yaml::Value> {
+    let mut value = to_value(config)?;
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.retain(|key, _| !key.as_str().is_some_and(|key| schema::ENTRY_TABLES.contains(&key)));
+    }
+    Ok(value)
+}
+
+/// Merge `other` into `value`: mappings key by key, lists adding items not yet there, other values replaced.
+fn merge_value(value: &mut serde_yaml::Value, other: serde_yaml::Value) {
+    use serde_yaml::Value;
+    match (value, other) {
+        (Value::Mapping(mapping), Value::Mapping(other)) => {
+            for (key, item) in other {
+                match mapping.get_mut(&key) {
+                    Some(existing) => merge_value(existing, item),
+                    None => {
+                        mapping.insert(key, item);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(items), Value::Sequence(other)) => {
+            for item in other {
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+        }
+        (value, other) => *value = other,
+    }
+}
+
+/*
+ * The config file's settings after a save, given the `current` settings, those `merged` at load
+ * time and the file's `own`
+ * What is unchanged since loading keeps the file's own value (so a fragment's settings are not
+ * copied into it); what changed is taken from `current`. Lists keep the file's items that are
+ * still there and add the new ones.
+ */
+fn own_settings(current: &serde_yaml::Value, merged: &serde_yaml::Value, own: Option<&serde_yaml::Value>) -> Option<serde_yaml::Value> {
+    use serde_yaml::Value;
+    if current == merged {
+        return own.cloned();
+    }
+    match (current, merged) {
+        (Value::Mapping(current), Value::Mapping(merged)) => {
+            let own = own.and_then(Value::as_mapping);
+            let mut result = serde_yaml::Mapping::new();
+            for (key, value) in current {
+                let own_value = own.and_then(|own| own.get(key));
+                let setting = match merged.get(key) {
+                    Some(merged) => own_settings(value, merged, own_value),
+                    None => Some(value.clone()),
+                };
+                if let Some(setting) = setting {
+                    result.insert(key.clone(), setting);
+                }
+            }
+            Some(Value::Mapping(result))
+        }
+        (Value::Sequence(current), Value::Sequence(merged)) => {
+            let own = own.and_then(Value::as_sequence);
+            let kept = own.into_iter().flatten().filter(|item| current.contains(item));
+            let added = current.iter().filter(|item| !merged.contains(item));
+            Some(Value::Sequence(kept.chain(added).cloned().collect()))
+        }
+        _ => Some(current.clone()),
+    }
+}
+
+/// Copy of a per-(target, source) table holding only the entries `keep` accepts.
+fn per_source<T: Clone>(
+    table: &BTreeMap<PathBuf, BTreeMap<PathBuf, T>>,
+    keep: impl Fn(&Path, &Path) -> bool,
+) -> BTreeMap<PathBuf, BTreeMap<PathBuf, T>> {
+    table.iter()
+        .map(|(target, sources)| {
+            let kept = sources.iter().filter(|(source, _)| keep(target, source)).map(|(s, v)| (s.clone(), v.clone()));
+            (target.clone(), kept.collect::<BTreeMap<_, _>>())
+        })
+        .filter(|(_, sources)| !sources.is_empty())
+        .collect()
+}
+
+/// Add a pattern to a per-(target, source) list in `excludes` or `only`; false if already there.
+fn add_pattern(
+    filters: &mut BTreeMap<PathBuf, BTreeMap<PathBuf, Vec<String>>>,
+    target: &Path,
+    source: &Path,
+    pattern: String,
+) -> bool {
+    let patterns = filters.entry(target.to_path_buf()).or_default().entry(source.to_path_buf()).or_default();
+    if patterns.contains(&pattern) {
+        return false;
+    }
+    patterns.push(pattern);
+    true
+}
+
+/*
+ * Staged saves (`amu batch`)
+ * While staging, `save` keeps the config in memory and `load` reads it back from there, so
+ * each step sees what the ones before it saved. Each file is written once, on commit.
+ */
+static STAGED: Mutex<Option<BTreeMap<PathBuf, String>>> = Mutex::new(None);
+
+fn staged() -> MutexGuard<'static, Option<BTreeMap<PathBuf, String>>> {
+    STAGED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keep config saves in memory until `commit_staged` or `discard_staged`.
+pub fn stage_saves() {
+    *staged() = Some(BTreeMap::new());
+}
+
+/// Write the config files saved while staging, if any were, and stop staging.
+pub fn commit_staged() -> Result<()> {
+    let files = staged().take().unwrap_or_default();
+    for (path, content) in files {
+        Config::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// Forget the config saved while staging and stop staging.
+pub fn discard_staged() {
+    staged().take();
+}
+
+static PROFILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Select the profile for this process (`--profile`), taking precedence over `AMU_PROFILE`.
+pub fn set_profile(name: String) {
+    let _ = PROFILE_OVERRIDE.set(name);
+}
+
+/// The profile selected with `--profile` or `AMU_PROFILE`, if any.
+fn selected_profile() -> Option<String> {
+    PROFILE_OVERRIDE.get().cloned()
+        .or_else(|| std::env::var(PROFILE_ENV).ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// The user amu acts for: under sudo the invoking user, as for the home directory.
+pub fn user_name() -> Option<String> {
+    std::env::var("SUDO_USER").ok().filter(|u| !u.is_empty() && u != "root")
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+}
+
+/// This machine's short hostname (without the domain).
+#[cfg(unix)]
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..end]);
+    name.split('.').next().filter(|n| !n.is_empty()).map(String::from)
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok().filter(|n| !n.is_empty())
+}
+
+static HOME_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Override the home directory for this process (`--home`).
+pub fn set_home(home: PathBuf) {
+    let _ = HOME_OVERRIDE.set(home);
+}
+
+/// Home directory used for tilde expansion, abbreviation and the default config path.
+/// Precedence: `--home`, then `AMU_HOME`, then the invoking user's home under sudo,
+/// then the current user's home.
+pub fn home_dir() -> Option<PathBuf> {
+    if let Some(home) = HOME_OVERRIDE.get() {
+        return Some(home.clone());
+    }
+    if let Some(home) = std::env::var_os(HOME_ENV).filter(|h| !h.is_empty()) {
+        return Some(PathBuf::from(home));
+    }
+    ownership::sudo_user_home().or_else(dirs::home_dir)
 }
 
 pub fn normalize_path(path: &Path) -> Result<PathBuf> {
-    let expanded = expand_path(path);
-    expanded.canonicalize().map_err(|e| {
+    resolve_existing(path).map_err(|e| {
         if e.kind() == std::io::ErrorKind::NotFound {
-            DotlinkError::SourceNotFound(expanded)
+            DotlinkError::SourceNotFound(expand_path(path))
         } else {
             DotlinkError::IoError(e)
         }
@@ -110,10 +1140,9 @@ pub fn normalize_path(path: &Path) -> Result<PathBuf> {
 pub fn resolve_target(target: Option<PathBuf>) -> Result<PathBuf> {
     match target {
         Some(t) => {
-            let expanded = expand_path(&t);
-            expanded.canonicalize().map_err(|e| {
+            resolve_existing(&t).map_err(|e| {
                 if e.kind() == std::io::ErrorKind::NotFound {
-                    DotlinkError::TargetNotFound(expanded)
+                    DotlinkError::TargetNotFound(expand_path(&t))
                 } else {
                     DotlinkError::IoError(e)
                 }
@@ -129,18 +1158,50 @@ mod tests {
     use tempfile::TempDir;
 
     #[test]
-    fn test_expand_path_with_tilde() {
-        let home = dirs::home_dir().unwrap();
-        let path = Path::new("~/.config");
-        let expanded = expand_path(path);
-        assert_eq!(expanded, home.join(".config"));
+    fn test_load_rejects_unusable_config() {
+        let temp = TempDir::new().unwrap();
+
+        assert!(matches!(
+            Config::load_from(temp.path()),
+            Err(DotlinkError::ConfigIsDirectory(_))
+        ));
+
+        let conflicted = temp.path().join("conflicted.yaml");
+        fs::write(&conflicted, "targets:\n<<<<<<< HEAD\n  /a: [/b]\n=======\n  /a: [/c]\n>>>>>>> other\n").unwrap();
+        assert!(matches!(
+            Config::load_from(&conflicted),
+            Err(DotlinkError::ConfigMergeConflict { line: 2, .. })
+        ));
+
+        let bom = temp.path().join("bom.yaml");
+        fs::write(&bom, "\u{feff}targets:\n  /a:\n    - /b\n").unwrap();
+        assert_eq!(Config::load_from(&bom).unwrap().targets.len(), 1);
+
+        // Duplicate keys would otherwise drop the earlier entry without a word
+        let duplicate = temp.path().join("duplicate.yaml");
+        fs::write(&duplicate, "targets:\n  /a: [/b]\n  /a: [/c]\n").unwrap();
+        assert!(matches!(
+            Config::load_from(&duplicate),
+            Err(DotlinkError::ConfigParseError(message)) if message.contains("duplicate key /a")
+        ));
     }
 
     #[test]
-    fn test_expand_path_without_tilde() {
-        let path = Path::new("/usr/local/bin");
-        let expanded = expand_path(path);
-        assert_eq!(expanded, PathBuf::from("/usr/local/bin"));
+    fn test_find_source_after_deletion() {
+        let temp = TempDir::new().unwrap();
+        let real = temp.path().join("real");
+        fs::create_dir_all(real.join("dotfiles")).unwrap();
+        crate::linker::platform::symlink(&real, &temp.path().join("link")).unwrap();
+
+        let mut config = Config::default();
+        let target = PathBuf::from("/home/user");
+        let stored = real.canonicalize().unwrap().join("dotfiles");
+        config.add_source(target.clone(), stored.clone()).unwrap();
+        fs::remove_dir(real.join("dotfiles")).unwrap();
+
+        let given = temp.path().join("link/./dotfiles");
+        assert_eq!(config.find_source(&target, &given), Some(stored));
+        assert_eq!(config.find_source(&target, &temp.path().join("link/other")), None);
     }
 
     #[test]
@@ -150,10 +1211,37 @@ mod tests {
         let source = PathBuf::from("/home/user/dotfiles/config");
 
         config.add_source(target.clone(), source.clone()).unwrap();
-        assert_eq!(config.targets.get(&target).unwrap(), &vec![source.clone()]);
+        assert_eq!(config.get_sources(&target).unwrap(), &vec![source.clone()]);
 
         config.remove_source(&target, &source).unwrap();
-        assert!(config.targets.get(&target).is_none());
+        assert!(config.get_sources(&target).is_none());
+    }
+
+    #[test]
+    fn test_move_source() {
+        let mut config = Config::default();
+        let target = PathBuf::from("/home/user/.config");
+        for source in ["/dotfiles/a", "/dotfiles/b", "/dotfiles/c"] {
+            config.add_source(target.clone(), PathBuf::from(source)).unwrap();
+        }
+
+        config.move_source(&target, Path::new("/dotfiles/c"), 0).unwrap();
+        config.move_source(&target, Path::new("/dotfiles/a"), 9).unwrap();
+        let order: Vec<&str> = config.sources_of(&target).iter().map(|s| s.to_str().unwrap()).collect();
+        assert_eq!(order, ["/dotfiles/c", "/dotfiles/b", "/dotfiles/a"]);
+        assert!(config.move_source(&target, Path::new("/dotfiles/d"), 0).is_err());
+    }
+
+    #[test]
+    fn test_targets_under() {
+        let mut config = Config::default();
+        for target in ["/nonexistent/home/.config/nvim", "/nonexistent/home/.configs", "/nonexistent/srv/app"] {
+            config.add_source(PathBuf::from(target), PathBuf::from("/nonexistent/dotfiles")).unwrap();
+        }
+
+        assert_eq!(config.targets_under(Path::new("/nonexistent/home/.config")), vec![PathBuf::from("/nonexistent/home/.config/nvim")]);
+        assert_eq!(config.targets_under(Path::new("/nonexistent/home/./")).len(), 2);
+        assert!(config.targets_under(Path::new("/nonexistent/etc")).is_empty());
     }
 
     #[test]
@@ -167,6 +1255,148 @@ mod tests {
         assert!(matches!(result, Err(DotlinkError::AlreadyRegistered { .. })));
     }
 
+    #[test]
+    fn test_resolve_relative_sources() {
+        let root = PathBuf::from("/repo/dotfiles");
+        let mut config = Config::default();
+        config.targets.insert(
+            PathKey::new(Path::new("/home/user/.config/nvim")),
+            vec![PathBuf::from("nvim"), PathBuf::from("/abs/source")],
+        );
+
+        config.resolve_relative_sources(&root).unwrap();
+        let sources = config.targets.values().next().unwrap();
+        assert_eq!(sources[0], PathBuf::from("/repo/dotfiles/nvim"));
+        assert_eq!(sources[1], PathBuf::from("/abs/source"));
+
+        let relativized = config.relativized(&root);
+        let sources = relativized.targets.values().next().unwrap();
+        assert_eq!(sources[0], PathBuf::from("nvim"));
+        assert_eq!(sources[1], PathBuf::from("/abs/source"));
+    }
+
+    #[test]
+    fn test_profile_entries_saved_back_to_profile() {
+        let mut config: Config = serde_yaml::from_str(
+            "targets:\n  /home/.config:\n    - /dotfiles/shared\nprofiles:\n  work:\n    targets:\n      /home/.config:\n        - /dotfiles/work\n",
+        ).unwrap();
+        config.origin.shared = entry_set(&config.targets);
+        config.origin.from_profile = entry_set(&config.profiles["work"].targets);
+        config.targets.get_mut(&PathKey::new(Path::new("/home/.config"))).unwrap().push(PathBuf::from("/dotfiles/work"));
+        config.origin.profile = Some(("work".to_string(), true));
+
+        assert_eq!(config.profile_of(Path::new("/home/.config"), Path::new("/dotfiles/work")), Some("work"));
+        assert_eq!(config.profile_of(Path::new("/home/.config"), Path::new("/dotfiles/shared")), None);
+
+        // Entries added under an explicit profile are stored in it
+        config.add_source(PathBuf::from("/home/.ssh"), PathBuf::from("/dotfiles/ssh")).unwrap();
+        let stored = config.unmerged();
+        assert_eq!(stored.sources_of(Path::new("/home/.config")), vec![PathBuf::from("/dotfiles/shared")]);
+        assert!(stored.get_sources(Path::new("/home/.ssh")).is_none());
+        let work = &stored.profiles["work"].targets;
+        assert_eq!(work[&PathKey::new(Path::new("/home/.config"))], vec![PathBuf::from("/dotfiles/work")]);
+        assert_eq!(work[&PathKey::new(Path::new("/home/.ssh"))], vec![PathBuf::from("/dotfiles/ssh")]);
+
+        // Removing a profile entry removes it from the profile
+        config.remove_source(Path::new("/home/.config"), Path::new("/dotfiles/work")).unwrap();
+        assert!(!config.unmerged().profiles["work"].targets.contains_key(&PathKey::new(Path::new("/home/.config"))));
+    }
+
+    #[test]
+    fn test_relative_source_escaping_root() {
+        let mut config = Config::default();
+        config.targets.insert(
+            PathKey::new(Path::new("/home/user/.config/nvim")),
+            vec![PathBuf::from("../outside")],
+        );
+
+        let result = config.resolve_relative_sources(Path::new("/repo/dotfiles"));
+        assert!(matches!(result, Err(DotlinkError::RelativeSourceEscapesRoot { .. })));
+    }
+
+    #[test]
+    fn test_fragments_merged_and_saved_back() {
+        let temp = TempDir::new().unwrap();
+        let config_path = temp.path().join("config.yaml");
+        let fragments = temp.path().join(FRAGMENT_DIR);
+        fs::create_dir(&fragments).unwrap();
+        fs::write(&config_path, "targets:\n  /home: [/dotfiles/shared]\nignore: ['*.bak']\nstrict: false\n").unwrap();
+        fs::write(fragments.join("20-work.yaml"), "targets:\n  /home: [/dotfiles/work]\nstrict: true\n").unwrap();
+        fs::write(fragments.join("10-laptop.yaml"), "targets:\n  /home: [/dotfiles/shared, /dotfiles/laptop]\nignore: ['*.tmp']\n").unwrap();
+        fs::write(fragments.join(".hidden.yaml"), "targets:\n  /home: [/dotfiles/hidden]\n").unwrap();
+
+        let config = Config::load_from(&config_path).unwrap();
+        let sources: Vec<PathBuf> = ["/dotfiles/shared", "/dotfiles/laptop", "/dotfiles/work"].iter().map(PathBuf::from).collect();
+        assert_eq!(config.sources_of(Path::new("/home")), sources);
+        assert_eq!(config.ignore, vec!["*.bak", "*.tmp"]);
+        assert_eq!(config.strict, Some(true));
+
+        // Fragments whose entries have no priorities are given them
+        let files = config.files(&config_path).unwrap();
+        assert_eq!(files.len(), 3);
+        for (path, content) in &files {
+            fs::write(path, content).unwrap();
+        }
+        let work = Config::parse_stored(&files[2].1).unwrap();
+        assert_eq!(work.metadata[Path::new("/home")][Path::new("/dotfiles/work")].priority, Some(3));
+
+        // Unchanged, only the config file is written, with its own settings
+        let mut config = Config::load_from(&config_path).unwrap();
+        assert_eq!(config.sources_of(Path::new("/home")), sources);
+        let files = config.files(&config_path).unwrap();
+        assert_eq!(files.len(), 1);
+        let main = Config::parse(&files[0].1).unwrap();
+        assert_eq!(main.sources_of(Path::new("/home")), vec![PathBuf::from("/dotfiles/shared")]);
+        assert_eq!(main.ignore, vec!["*.bak"]);
+        assert_eq!(main.strict, Some(false));
+
+        // A fragment's entry is removed from the fragment; a new entry goes to the config file
+        config.remove_source(Path::new("/home"), Path::new("/dotfiles/work")).unwrap();
+        config.add_source(PathBuf::from("/etc"), PathBuf::from("/dotfiles/etc")).unwrap();
+        config.ignore.push("*.orig".to_string());
+        let files = config.files(&config_path).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].0, fragments.join("20-work.yaml"));
+        let work = Config::parse(&files[1].1).unwrap();
+        assert!(work.targets.is_empty());
+        assert_eq!(work.strict, Some(true));
+        let main = Config::parse(&files[0].1).unwrap();
+        assert_eq!(main.sources_of(Path::new("/etc")), vec![PathBuf::from("/dotfiles/etc")]);
+        assert_eq!(main.ignore, vec!["*.bak", "*.orig"]);
+    }
+
+    #[test]
+    fn test_fragments_keep_priorities() {
+        let temp = TempDir::new().unwrap();
+        let config_path = temp.path().join("config.yaml");
+        let fragments = temp.path().join(FRAGMENT_DIR);
+        fs::create_dir(&fragments).unwrap();
+        fs::write(&config_path, "targets:\n  /home: [/dotfiles/shared]\n").unwrap();
+        fs::write(fragments.join("work.yaml"), "targets:\n  /home: [/dotfiles/work]\n").unwrap();
+        let home = Path::new("/home");
+        let save = |config: &Config| {
+            for (path, content) in config.files(&config_path).unwrap() {
+                fs::write(path, content).unwrap();
+            }
+        };
+
+        // A fragment's source moved ahead of the config file's stays there
+        let mut config = Config::load_from(&config_path).unwrap();
+        config.move_source(home, Path::new("/dotfiles/work"), 0).unwrap();
+        save(&config);
+        let config = Config::load_from(&config_path).unwrap();
+        let sources: Vec<PathBuf> = ["/dotfiles/work", "/dotfiles/shared"].iter().map(PathBuf::from).collect();
+        assert_eq!(config.sources_of(home), sources);
+
+        // A new source ranks last, though it is written to the config file
+        let mut config = config;
+        config.add_source(home.to_path_buf(), PathBuf::from("/dotfiles/new")).unwrap();
+        save(&config);
+        let config = Config::load_from(&config_path).unwrap();
+        assert_eq!(config.sources_of(home).last(), Some(&PathBuf::from("/dotfiles/new")));
+        assert!(config.metadata.values().flat_map(|m| m.values()).all(|meta| meta.priority.is_none()));
+    }
+
     #[test]
     fn test_config_save_and_load() {
         let temp_dir = TempDir::new().unwrap();
@@ -174,7 +1404,7 @@ mod tests {
 
         let mut config = Config::default();
         config.targets.insert(
-            PathBuf::from("/home/user/.config"),
+            PathKey::new(Path::new("/home/user/.config")),
             vec![PathBuf::from("/home/user/dotfiles/config")],
         );
 
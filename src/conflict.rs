@@ -0,0 +1,332 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::adopt;
+use crate::error::{DotlinkError, Result};
+use crate::history::{self, Change};
+use crate::ignore;
+use crate::linker::platform;
+use crate::links;
+
+/// Record written next to backed-up files so they can be put back
+const BACKUP_RECORD: &str = "backup.yaml";
+
+/// What to do with target paths a source would link over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OnConflict {
+    /// Leave the existing file in place and link the rest of the source
+    Skip,
+    /// Move the existing file to a timestamped backup directory, then link
+    Backup,
+    /// Delete the existing file, then link
+    Overwrite,
+    /// Move the existing file into the source (backing up the source's version), then link
+    Adopt,
+    /// Stop with an error
+    #[default]
+    Fail,
+}
+
+/// A conflicting target path and what was done about it
+pub struct Resolution {
+    pub path: PathBuf,
+    pub action: &'static str,
+}
+
+/// Files moved out of a target by `--on-conflict backup`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub target: PathBuf,
+    /// Target-relative paths, stored under the same relative paths in the backup directory
+    pub files: Vec<PathBuf>,
+}
+
+/*
+ * Target paths the source provides that are occupied by something other than a link
+ * into one of `sources` (the target's registered sources, including this one)
+ * Links into other registered sources are left to source precedence, not treated as conflicts.
+ */
+pub fn find(source: &Path, target: &Path, ignore: &[String], sources: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    collect(source, target, Path::new(""), ignore, sources, &mut found);
+    found.sort();
+    found
+}
+
+fn collect(source: &Path, target: &Path, relative: &Path, ignore: &[String], sources: &[PathBuf], found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(source.join(relative)) else { return };
+    for entry in entries.flatten() {
+        let relative = relative.join(entry.file_name());
+        if ignore::is_ignored(&relative, ignore) {
+            continue;
+        }
+        let target_path = target.join(&relative);
+        let Ok(metadata) = fs::symlink_metadata(&target_path) else { continue };
+        let source_path = source.join(&relative);
+        let source_is_dir = source_path.is_dir() && !source_path.is_symlink();
+
+        if metadata.file_type().is_symlink() {
+            let managed = links::link_destination(&target_path)
+                .is_some_and(|destination| sources.iter().any(|s| destination.starts_with(s)));
+            if !managed {
+                found.push(relative);
+            }
+        } else if metadata.is_dir() && source_is_dir {
+            collect(source, target, &relative, ignore, sources, found);
+        } else {
+            found.push(relative);
+        }
+    }
+}
+
+/// Paths the source provides that do not exist in the target at all, outermost first.
+pub fn missing(source: &Path, target: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    fn walk(source: &Path, target: &Path, relative: &Path, ignore: &[String], found: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(source.join(relative)) else { return };
+        for entry in entries.flatten() {
+            let relative = relative.join(entry.file_name());
+            if ignore::is_ignored(&relative, ignore) {
+                continue;
+            }
+            let target_path = target.join(&relative);
+            let source_path = source.join(&relative);
+            match fs::symlink_metadata(&target_path) {
+                Err(_) => found.push(relative),
+                Ok(metadata) if metadata.is_dir() && source_path.is_dir() && !source_path.is_symlink() => {
+                    walk(source, target, &relative, ignore, found);
+                }
+                Ok(_) => {}
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    walk(source, target, Path::new(""), ignore, &mut found);
+    found.sort();
+    found
+}
+
+/*
+ * Clear conflicts for a source according to the strategy, before it is linked
+ * Skipped paths (and, in dry-run mode, every conflicting path) are added to `ignore`
+ * so linking proceeds without them. Returns what was, or would be, done per path.
+ */
+pub fn resolve(
+    strategy: OnConflict,
+    source: &Path,
+    target: &Path,
+    ignore: &mut Vec<String>,
+    sources: &[PathBuf],
+    dry_run: bool,
+) -> Result<Vec<Resolution>> {
+    if strategy == OnConflict::Fail {
+        return Ok(Vec::new());
+    }
+    let conflicts = find(source, target, ignore, sources);
+    apply(strategy, source, target, conflicts, ignore, dry_run)
+}
+
+/// Apply a strategy to the given conflicting paths, as `resolve` does for all of them.
+pub fn apply(
+    strategy: OnConflict,
+    source: &Path,
+    target: &Path,
+    conflicts: Vec<PathBuf>,
+    ignore: &mut Vec<String>,
+    dry_run: bool,
+) -> Result<Vec<Resolution>> {
+    if conflicts.is_empty() || strategy == OnConflict::Fail {
+        return Ok(Vec::new());
+    }
+
+    let action = match strategy {
+        OnConflict::Skip => "skipped",
+        OnConflict::Backup => "backed up",
+        OnConflict::Overwrite => "overwritten",
+        OnConflict::Adopt => "adopted",
+        OnConflict::Fail => unreachable!(),
+    };
+
+    if dry_run || strategy == OnConflict::Skip {
+        ignore.extend(conflicts.iter().map(|path| format!("/{}", path.display())));
+    } else {
+        match strategy {
+            OnConflict::Backup => {
+                backup(target, &conflicts)?;
+            }
+            OnConflict::Overwrite => {
+                for path in &conflicts {
+                    remove(&target.join(path))?;
+                }
+            }
+            OnConflict::Adopt => {
+                adopt::adopt(source, target, &conflicts)?;
+            }
+            OnConflict::Skip | OnConflict::Fail => {}
+        }
+    }
+
+    Ok(conflicts.into_iter().map(|path| Resolution { path, action }).collect())
+}
+
+/// Move target paths into a new backup directory with a record of where they came from.
+pub fn backup(target: &Path, paths: &[PathBuf]) -> Result<PathBuf> {
+    let name = target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let dir = adopt::backup_dir(&name)?;
+    fs::create_dir_all(&dir)?;
+
+    let record = BackupRecord { target: target.to_path_buf(), files: paths.to_vec() };
+    let content = serde_yaml::to_string(&record).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(dir.join(BACKUP_RECORD), content)?;
+
+    for path in paths {
+        let saved = dir.join(path);
+        if let Some(parent) = saved.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        adopt::move_path(&target.join(path), &saved)?;
+        history::record(Change::Moved { from: target.join(path), to: saved });
+    }
+    Ok(dir)
+}
+
+/// A backup directory made by `--on-conflict backup`, with its record
+pub struct Backup {
+    pub dir: PathBuf,
+    pub record: BackupRecord,
+}
+
+/// Backups in the state directory, oldest first; directories without a record are left out.
+pub fn backups() -> Result<Vec<Backup>> {
+    let Ok(entries) = fs::read_dir(adopt::backups_root()?) else { return Ok(Vec::new()) };
+    let mut dirs: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    dirs.sort();
+    let mut found = Vec::new();
+    for dir in dirs.into_iter().filter(|dir| dir.join(BACKUP_RECORD).is_file()) {
+        found.push(load_backup(&dir)?);
+    }
+    Ok(found)
+}
+
+/// The backup named `name` (a directory under `backups/`), or at that path.
+pub fn find_backup(name: &Path) -> Result<Backup> {
+    let dir = if name.join(BACKUP_RECORD).is_file() { name.to_path_buf() } else { adopt::backups_root()?.join(name) };
+    if !dir.join(BACKUP_RECORD).is_file() {
+        return Err(DotlinkError::BackupNotFound(name.to_path_buf()));
+    }
+    load_backup(&dir)
+}
+
+fn load_backup(dir: &Path) -> Result<Backup> {
+    let path = dir.join(BACKUP_RECORD);
+    let content = fs::read_to_string(&path)?;
+    let record = serde_yaml::from_str(&content)
+        .map_err(|e| DotlinkError::StateError(format!("{}: {}", path.display(), e)))?;
+    Ok(Backup { dir: dir.to_path_buf(), record })
+}
+
+/// A backed-up path put back into its target, or why it was not
+pub struct Restored {
+    pub path: PathBuf,
+    pub skipped: Option<&'static str>,
+}
+
+/*
+ * Move a backup's files back to where they were taken from
+ * A link in the way (what replaced the file) is removed first; anything else there is left
+ * alone and the file stays in the backup. The record keeps what was not put back, and the
+ * backup directory is removed once it is empty.
+ */
+pub fn restore_backup(backup: &Backup, dry_run: bool) -> Result<Vec<Restored>> {
+    let mut restored = Vec::new();
+    let mut left = Vec::new();
+    for relative in &backup.record.files {
+        let saved = backup.dir.join(relative);
+        let at = backup.record.target.join(relative);
+        let skipped = if fs::symlink_metadata(&saved).is_err() {
+            Some("missing from the backup")
+        } else if fs::symlink_metadata(&at).is_ok() && !at.is_symlink() {
+            Some("occupied")
+        } else {
+            None
+        };
+        if skipped.is_none() && !dry_run {
+            if let Ok(destination) = fs::read_link(&at) {
+                platform::remove_link(&at)?;
+                history::record(Change::LinkRemoved { link: at.clone(), destination });
+            }
+            if let Some(parent) = at.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            adopt::move_path(&saved, &at)?;
+            history::record(Change::Moved { from: saved, to: at });
+        } else if skipped == Some("occupied") {
+            left.push(relative.clone());
+        }
+        restored.push(Restored { path: relative.clone(), skipped });
+    }
+
+    if !dry_run {
+        if left.is_empty() {
+            fs::remove_dir_all(&backup.dir)?;
+        } else {
+            let record = BackupRecord { target: backup.record.target.clone(), files: left };
+            let content = serde_yaml::to_string(&record).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+            fs::write(backup.dir.join(BACKUP_RECORD), content)?;
+        }
+    }
+    Ok(restored)
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)?;
+        crate::debug!("deleted directory {}", path.display());
+    } else if path.is_symlink() {
+        platform::remove_link(path)?;
+    } else {
+        fs::remove_file(path)?;
+        crate::debug!("deleted {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_conflicts() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("dotfiles");
+        let other = root.join("other");
+        let target = root.join("home");
+        fs::create_dir_all(source.join(".config/nvim")).unwrap();
+        fs::create_dir_all(target.join(".config/nvim")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        for name in [".zshrc", ".vimrc", ".bashrc", ".profile", ".config/nvim/init.lua"] {
+            fs::write(source.join(name), "").unwrap();
+        }
+        fs::write(other.join(".vimrc"), "").unwrap();
+
+        fs::write(target.join(".zshrc"), "mine").unwrap();
+        fs::write(target.join(".config/nvim/init.lua"), "mine").unwrap();
+        crate::linker::platform::symlink(&other.join(".vimrc"), &target.join(".vimrc")).unwrap();
+        crate::linker::platform::symlink(&source.join(".bashrc"), &target.join(".bashrc")).unwrap();
+
+        let sources = vec![source.clone(), other.clone()];
+        assert_eq!(
+            find(&source, &target, &[], &sources),
+            vec![PathBuf::from(".config/nvim/init.lua"), PathBuf::from(".zshrc")]
+        );
+        // A link into an unregistered directory is a conflict
+        assert_eq!(find(&source, &target, &[], std::slice::from_ref(&source)).len(), 3);
+
+        assert_eq!(missing(&source, &target, &[]), vec![PathBuf::from(".profile")]);
+    }
+}
@@ -0,0 +1,306 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::cache::{self, StatusCache};
+use crate::config::{abbreviate_path, Config};
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::locks;
+use crate::report::{self, Environment, SourceStatusReport, StatusReport, StatusSummary, TargetReport};
+use crate::temporary;
+
+type Events = notify::Result<Event>;
+
+/// How often expired temporary sources are looked for
+const EXPIRY_CHECK: Duration = Duration::from_secs(60);
+
+/*
+ * Background status daemon (`amu daemon`)
+ * Sources are watched recursively. In targets only the directories mirroring a source
+ * directory are watched, one level each, since nothing else can change a source's status;
+ * watching a whole home directory would exhaust inotify watches. Changes are collected
+ * until quiet for the debounce window, then only the affected targets are rechecked;
+ * where notifications are unavailable, every target is rechecked on each heartbeat.
+ * Temporary sources are removed as they expire; the config change that makes is picked up
+ * like any other. A target another amu process is changing is rechecked once it is done,
+ * so the cache never records a half-applied update. With `notify`, drift is reported to
+ * the configured webhook or command when it appears or changes, not on every recheck.
+ */
+pub fn run(debounce: Duration, once: bool, notify: bool) -> Result<()> {
+    remove_expired();
+    let mut expiry_checked = Instant::now();
+    let mut config = Config::load()?;
+    let mut cache = StatusCache::compute(&config);
+    cache.save()?;
+    println!("Checked {} target(s)", cache.targets.len());
+    let mut notified = None;
+    if notify {
+        notify_drift(&config, &cache, &mut notified);
+    }
+    if once {
+        return Ok(());
+    }
+
+    let config_path = Config::config_path()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut polling = false;
+    let mut watcher = watch_or_poll(&config, &config_path, &sender, &mut polling);
+    println!("Watching registered targets (Ctrl-C to stop)...");
+    let mut deferred: Vec<PathBuf> = Vec::new();
+
+    loop {
+        let first = match receiver.recv_timeout(cache::HEARTBEAT) {
+            Ok(events) => events,
+            Err(RecvTimeoutError::Timeout) => {
+                cache.touch()?;
+                if expiry_checked.elapsed() >= EXPIRY_CHECK {
+                    remove_expired();
+                    expiry_checked = Instant::now();
+                }
+                // Without notifications every target is rechecked on each heartbeat
+                if watcher.is_none() {
+                    reload(&mut config);
+                    watcher = watch_or_poll(&config, &config_path, &sender, &mut polling);
+                    let all = config.targets.keys().map(|t| t.to_path_buf()).collect();
+                    recheck(&config, &mut cache, all, &mut deferred)?;
+                    if notify {
+                        notify_drift(&config, &cache, &mut notified);
+                    }
+                    continue;
+                }
+                let done: Vec<PathBuf> = deferred.iter().filter(|target| !locks::is_busy(target)).cloned().collect();
+                if !done.is_empty() {
+                    recheck(&config, &mut cache, done, &mut deferred)?;
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        let Some(paths) = settle(first, &receiver, debounce) else {
+            return Ok(());
+        };
+
+        let dirty = if paths.contains(&config_path) {
+            reload(&mut config);
+            config.targets.keys().map(|t| t.to_path_buf()).collect()
+        } else {
+            affected(&config, &paths)
+        };
+        if dirty.is_empty() {
+            continue;
+        }
+
+        // Rewatch first so changes made while checking are not missed
+        drop(watcher);
+        watcher = watch_or_poll(&config, &config_path, &sender, &mut polling);
+        recheck(&config, &mut cache, dirty, &mut deferred)?;
+        if notify {
+            notify_drift(&config, &cache, &mut notified);
+        }
+    }
+}
+
+/*
+ * Send the cached statuses to `notify:` when they show warnings or errors
+ * `notified` holds the problems last sent, so the same drift is reported once; it is
+ * cleared when everything is fine again. A failed send is retried after the next recheck.
+ */
+fn notify_drift(config: &Config, cache: &StatusCache, notified: &mut Option<String>) {
+    let report = status_report(cache);
+    let StatusSummary { warning, error, .. } = report.summary;
+    if warning == 0 && error == 0 {
+        *notified = None;
+        return;
+    }
+    let Ok(problems) = serde_json::to_string(&report.targets) else { return };
+    if notified.as_ref() == Some(&problems) {
+        return;
+    }
+    match report::to_json(&report).and_then(|json| crate::notify::send(&config.notify, &json)) {
+        Ok(()) => {
+            println!("Notified: {} warning, {} error", warning, error);
+            *notified = Some(problems);
+        }
+        Err(e) => crate::warn!("could not send the drift notification: {}", e),
+    }
+}
+
+/// The cached statuses as `amu status --json` reports them.
+fn status_report(cache: &StatusCache) -> StatusReport {
+    let mut summary = StatusSummary::default();
+    let targets = cache.targets.iter()
+        .map(|(target, statuses)| {
+            let mut target_report = TargetReport::new(abbreviate_path(target));
+            for (source, status) in statuses {
+                match status.class() {
+                    "ok" => summary.ok += 1,
+                    "warning" => summary.warning += 1,
+                    _ => summary.error += 1,
+                }
+                target_report.sources.push(SourceStatusReport::from_status(abbreviate_path(source), status));
+            }
+            target_report
+        })
+        .collect();
+    StatusReport {
+        targets,
+        summary,
+        cached_at: Some(cache.updated_at),
+        repairs: None,
+        unreadable: Vec::new(),
+        remotes: None,
+        environment: Environment::current(),
+    }
+}
+
+/// Load the config again; if it no longer loads, the previous one is kept.
+fn reload(config: &mut Config) {
+    match Config::load() {
+        Ok(reloaded) => *config = reloaded,
+        Err(e) => crate::warn!("keeping the previous config: {}", e),
+    }
+}
+
+/// Recheck targets, leaving those another process is changing in `deferred` for later.
+fn recheck(config: &Config, cache: &mut StatusCache, targets: Vec<PathBuf>, deferred: &mut Vec<PathBuf>) -> Result<()> {
+    let (busy, ready): (Vec<PathBuf>, Vec<PathBuf>) = targets.into_iter().partition(|target| locks::is_busy(target));
+    for target in busy {
+        if !deferred.contains(&target) {
+            println!("Busy: {} (rechecking once it is done)", abbreviate_path(&target));
+            deferred.push(target);
+        }
+    }
+    if ready.is_empty() {
+        return Ok(());
+    }
+    deferred.retain(|target| !ready.contains(target));
+    cache.refresh(config, &ready);
+    cache.save()?;
+    for target in &ready {
+        println!("Rechecked: {}", abbreviate_path(target));
+    }
+    Ok(())
+}
+
+/// Remove temporary sources that have expired; a failure is logged and retried later.
+fn remove_expired() {
+    match temporary::collect(false) {
+        Ok(expired) => {
+            for (target, source) in expired {
+                println!("Expired: {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
+            }
+        }
+        Err(e) => crate::warn!("could not remove expired sources: {}", e),
+    }
+}
+
+/// Paths changed by a burst of events; None once the watcher has gone away.
+fn settle(first: Events, receiver: &Receiver<Events>, debounce: Duration) -> Option<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut add = |events: Events| match events {
+        Ok(event) => paths.extend(event.paths),
+        Err(e) => crate::warn!("{}", e),
+    };
+    add(first);
+    loop {
+        match receiver.recv_timeout(debounce) {
+            Ok(events) => add(events),
+            Err(RecvTimeoutError::Timeout) => return Some(paths),
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+/// Registered targets whose status can be changed by the given paths.
+fn affected(config: &Config, paths: &[PathBuf]) -> Vec<PathBuf> {
+    config.targets.iter()
+        .filter(|(target, sources)| {
+            paths.iter().any(|p| p.starts_with(target) || sources.iter().any(|s| p.starts_with(s)))
+        })
+        .map(|(target, _)| target.to_path_buf())
+        .collect()
+}
+
+/*
+ * Watch the config and registered targets, or None where notifications are unavailable
+ * (e.g. inotify watches exhausted); the daemon then rechecks every target on each
+ * heartbeat. The first failure is warned about.
+ */
+fn watch_or_poll(config: &Config, config_path: &Path, sender: &Sender<Events>, polling: &mut bool) -> Option<RecommendedWatcher> {
+    match watch(config, config_path, sender) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            if !*polling {
+                crate::warn!("file notifications unavailable, rechecking every {}s: {}", cache::HEARTBEAT.as_secs(), e);
+                *polling = true;
+            }
+            None
+        }
+    }
+}
+
+fn watch(config: &Config, config_path: &Path, sender: &Sender<Events>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(sender.clone()).map_err(watch_error)?;
+    if let Some(dir) = config_path.parent().filter(|d| d.is_dir()) {
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+    }
+    for (target, sources) in &config.targets {
+        for source in sources.iter().filter(|s| s.is_dir()) {
+            watcher.watch(source, RecursiveMode::Recursive).map_err(watch_error)?;
+            let ignore = ignore::patterns_for(config, target, source);
+            for dir in mirrored_dirs(source, &ignore) {
+                let dir = target.join(dir);
+                // Real directories only; a folded directory link is watched in its parent
+                if dir.is_dir() && !dir.is_symlink() {
+                    watcher.watch(&dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+                }
+            }
+        }
+    }
+    Ok(watcher)
+}
+
+/// The source's directories relative to it, starting with the source itself (an empty path).
+fn mirrored_dirs(source: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    fn collect(base: &Path, current: &Path, ignore: &[String], dirs: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(current) else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let relative = path.strip_prefix(base).unwrap_or(&path);
+            if path.is_dir() && !path.is_symlink() && !ignore::is_ignored(relative, ignore) {
+                dirs.push(relative.to_path_buf());
+                collect(base, &path, ignore, dirs);
+            }
+        }
+    }
+
+    let mut dirs = vec![PathBuf::new()];
+    collect(source, source, ignore, &mut dirs);
+    dirs
+}
+
+fn watch_error(e: notify::Error) -> DotlinkError {
+    DotlinkError::IoError(io::Error::other(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mirrored_dirs() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join(".config/nvim")).unwrap();
+        std::fs::create_dir_all(temp.path().join(".git/objects")).unwrap();
+        std::fs::write(temp.path().join(".zshrc"), "").unwrap();
+
+        let mut dirs = mirrored_dirs(temp.path(), &[".git".to_string()]);
+        dirs.sort();
+        assert_eq!(dirs, vec![PathBuf::new(), PathBuf::from(".config"), PathBuf::from(".config/nvim")]);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use crate::config::{resolve_path, Config};
+use crate::error::Result;
+use crate::ignore;
+use crate::links::SymlinkEntry;
+use crate::precedence;
+use crate::stow;
+use crate::template::{self, Outcome};
+
+/// Files larger than this are not diffed
+const MAX_DIFF_BYTES: u64 = 1024 * 1024;
+
+/// Result of comparing a real file in the target with its source counterpart
+pub enum ContentDiff {
+    Identical,
+    Binary,
+    TooLarge,
+    Unreadable,
+    /// Unified diff from the target file to the source file
+    Text(String),
+}
+
+impl ContentDiff {
+    /// Short description for non-text outcomes
+    pub fn summary(&self) -> &'static str {
+        match self {
+            ContentDiff::Identical => "identical to source",
+            ContentDiff::Binary => "binary files differ",
+            ContentDiff::TooLarge => "too large to diff",
+            ContentDiff::Unreadable => "could not be read",
+            ContentDiff::Text(_) => "content differs",
+        }
+    }
+}
+
+pub fn compare_files(target_file: &Path, source_file: &Path) -> ContentDiff {
+    let too_large = |p: &Path| fs::metadata(p).map(|m| m.len() > MAX_DIFF_BYTES).unwrap_or(false);
+    if too_large(target_file) || too_large(source_file) {
+        return ContentDiff::TooLarge;
+    }
+
+    let (Ok(target_bytes), Ok(source_bytes)) = (fs::read(target_file), fs::read(source_file)) else {
+        return ContentDiff::Unreadable;
+    };
+    if target_bytes == source_bytes {
+        return ContentDiff::Identical;
+    }
+
+    let (Ok(target_text), Ok(source_text)) =
+        (String::from_utf8(target_bytes), String::from_utf8(source_bytes))
+    else {
+        return ContentDiff::Binary;
+    };
+
+    ContentDiff::Text(unified(&target_text, &source_text, "target", "source"))
+}
+
+/// Unified diff of two texts with three lines of context.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/*
+ * What `amu update` would change for one source in a target, file by file (`amu diff`)
+ * Added: links stow would create, and template files rendered for the first time.
+ * Removed: links into the source whose file is gone or is no longer linked (excluded, or
+ * left to a higher-priority source). Changed: rendered files update would rewrite.
+ * Conflicts stop the update; they are listed rather than treated as changes.
+ */
+#[derive(Default)]
+pub struct Pending {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<(PathBuf, ContentDiff)>,
+    pub conflicts: Vec<String>,
+}
+
+impl Pending {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// Pending changes of `source` in `target`, given the symlinks found in the target.
+pub fn pending(config: &Config, target: &Path, source: &Path, symlinks: &[SymlinkEntry]) -> Result<Pending> {
+    let mut ignore = ignore::patterns_for(config, target, source);
+    ignore.extend(precedence::skip_patterns(&precedence::contested_in(config, target, source)));
+    let mut pending = Pending::default();
+
+    let plan = stow::dry_run(config, source, target, &ignore)?;
+    pending.added.extend(plan.created().cloned());
+    pending.conflicts.extend(plan.conflicts().map(String::from));
+
+    let canonical = resolve_path(source);
+    for entry in symlinks {
+        let Ok(relative) = entry.destination.strip_prefix(&canonical) else { continue };
+        if entry.dangling || ignore::is_ignored(relative, &ignore) {
+            pending.removed.push(entry.relative.clone());
+        }
+    }
+
+    for rendering in template::apply(config, source, target, &ignore, true)? {
+        match (rendering.outcome, rendering.content) {
+            (Outcome::Rendered, Some(content)) => match fs::read(target.join(&rendering.path)) {
+                Ok(current) => pending.changed.push((rendering.path, compare_rendered(current, &content))),
+                Err(_) => pending.added.push(rendering.path),
+            },
+            (Outcome::Failed(e), _) => {
+                pending.conflicts.push(format!("{}{}: {}", rendering.path.display(), template::SUFFIX, e));
+            }
+            _ => {}
+        }
+    }
+
+    pending.added.sort();
+    pending.removed.sort();
+    Ok(pending)
+}
+
+/// Diff from a file's current content to what a template renders.
+fn compare_rendered(current: Vec<u8>, rendered: &str) -> ContentDiff {
+    match String::from_utf8(current) {
+        Ok(current) => ContentDiff::Text(unified(&current, rendered, "target", "rendered")),
+        Err(_) => ContentDiff::Binary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_compare_identical_and_changed() {
+        let temp = TempDir::new().unwrap();
+        let a = temp.path().join("a");
+        let b = temp.path().join("b");
+        fs::write(&a, "one\ntwo\n").unwrap();
+        fs::write(&b, "one\ntwo\n").unwrap();
+        assert!(matches!(compare_files(&a, &b), ContentDiff::Identical));
+
+        fs::write(&b, "one\nthree\n").unwrap();
+        match compare_files(&a, &b) {
+            ContentDiff::Text(diff) => {
+                assert!(diff.contains("-two"));
+                assert!(diff.contains("+three"));
+            }
+            _ => panic!("expected a text diff"),
+        }
+    }
+
+    #[test]
+    fn test_compare_binary() {
+        let temp = TempDir::new().unwrap();
+        let a = temp.path().join("a");
+        let b = temp.path().join("b");
+        fs::write(&a, [0xff, 0xfe, 0x00]).unwrap();
+        fs::write(&b, [0xff, 0x00]).unwrap();
+        assert!(matches!(compare_files(&a, &b), ContentDiff::Binary));
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config;
+use crate::error::{DotlinkError, Result};
+
+/// A target/source pair as read by `amu config add-entry` and `remove-entry`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub target: PathBuf,
+    pub source: PathBuf,
+}
+
+/*
+ * Parse entries from JSON
+ * Accepts a single object, an array of objects, or a stream of either (such as NDJSON),
+ * so callers can pipe whatever their tooling produces.
+ */
+pub fn parse(input: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for value in serde_json::Deserializer::from_str(input).into_iter::<Value>() {
+        let value = value.map_err(|e| DotlinkError::InvalidEntries(e.to_string()))?;
+        let items = match value {
+            Value::Array(items) => items,
+            other => vec![other],
+        };
+        for item in items {
+            let entry = serde_json::from_value(item).map_err(|e| DotlinkError::InvalidEntries(e.to_string()))?;
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Expand `~` and resolve a path for storing in config; paths that do not exist yet are
+/// made absolute and resolved as far as they exist.
+pub fn resolve(path: &Path) -> PathBuf {
+    config::resolve_path(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = |target: &str, source: &str| Entry { target: target.into(), source: source.into() };
+
+        assert_eq!(parse(r#"{"target": "~/.zsh", "source": "~/dotfiles/zsh"}"#).unwrap(), vec![entry("~/.zsh", "~/dotfiles/zsh")]);
+        assert_eq!(
+            parse("[{\"target\": \"/a\", \"source\": \"/b\"}]\n{\"target\": \"/c\", \"source\": \"/d\"}\n").unwrap(),
+            vec![entry("/a", "/b"), entry("/c", "/d")]
+        );
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse(r#"{"target": "/a"}"#).is_err());
+        assert!(parse(r#"{"target": "/a", "source": "/b", "priority": 1}"#).is_err());
+        assert!(parse("{").is_err());
+    }
+}
@@ -5,30 +5,186 @@ pub type Result<T> = std::result::Result<T, DotlinkError>;
 
 #[derive(Error, Debug)]
 pub enum DotlinkError {
-    #[error("stow is not installed\n\nInstall with:\n  macOS:  brew install stow\n  Ubuntu: sudo apt install stow\n  Arch:   sudo pacman -S stow")]
+    #[error("stow is not installed\n\nInstall with:\n  macOS:  brew install stow\n  Ubuntu: sudo apt install stow\n  Arch:   sudo pacman -S stow\n\nOr use the built-in linker with --backend native")]
     StowNotFound,
 
+    #[error("Could not find home directory\n\nSet HOME, or give the directory with --home")]
+    HomeNotFound,
+
     #[error("Source directory does not exist: {0}")]
     SourceNotFound(PathBuf),
 
+    #[error("Source is no longer available: {0}\n\nIt may be on a drive that has been unmounted")]
+    SourceUnavailable(PathBuf),
+
+    #[error("Source is not under the mount point {mount}: {src}")]
+    SourceOutsideMount { src: PathBuf, mount: PathBuf },
+
     #[error("Target directory does not exist: {0}")]
     TargetNotFound(PathBuf),
 
+    #[error("Target directory is read-only: {0}\n\nCheck the directory permissions or whether its filesystem is mounted read-only")]
+    TargetReadOnly(PathBuf),
+
+    #[error("Target is a symlink: {target} -> {resolved}\n\nUse the directory it points to, or set `symlinked_targets: resolve` in the config")]
+    SymlinkedTarget { target: PathBuf, resolved: PathBuf },
+
     #[error("Already registered: {src} -> {dest}")]
     AlreadyRegistered { src: PathBuf, dest: PathBuf },
 
     #[error("Not registered: {src} -> {dest}")]
     NotRegistered { src: PathBuf, dest: PathBuf },
 
+    #[error("No registered source provides: {0}")]
+    PathNotManaged(PathBuf),
+
+    #[error("Source does not provide {path}: {src}")]
+    NotProvided { path: PathBuf, src: PathBuf },
+
+    #[error("Not inside target {target}: {path}")]
+    NotInTarget { path: PathBuf, target: PathBuf },
+
+    #[error("{0} needs a terminal to ask what to do\n\nRe-run with --assume-yes to accept the defaults")]
+    NeedsTerminal(&'static str),
+
+    #[error("Cannot tell this machine's boot session\n\nUse --ttl to give the link a lifetime instead")]
+    SessionUnknown,
+
+    #[error("Lifetime too long: {0} seconds\n\nGive a --ttl that ends before the year 10000")]
+    LifetimeOutOfRange(u64),
+
+    #[error("Target is being changed by {holder}: {target}\n\nRe-run without --no-wait to wait for it")]
+    TargetBusy { target: PathBuf, holder: String },
+
+    #[error("Target not registered: {0}")]
+    TargetNotRegistered(PathBuf),
+
+    #[error("Imported target is already registered with other sources: {0}\n\nRe-run with --on-conflict keep, replace or merge")]
+    ImportConflict(PathBuf),
+
+    #[error("Nothing to undo")]
+    NothingToUndo,
+
+    #[error("No recorded operation with id {0}\n\nSee the recorded operations with: amu undo --list")]
+    OperationNotFound(u64),
+
+    #[error("Operation {0} has already been undone")]
+    AlreadyUndone(u64),
+
+    #[error("Not marked as removable media: {0}")]
+    NotMarked(PathBuf),
+
+    #[error("Invalid package name: {0:?}\n\nA package is one directory name, without `/`")]
+    InvalidPackageName(String),
+
+    #[error("Unknown profile: {name}\n\nProfiles in the config: {known}")]
+    UnknownProfile { name: String, known: String },
+
+    #[error("Not pinned: {0}")]
+    NotPinned(PathBuf),
+
+    #[error("No backup recorded at: {0}\n\nRun `amu backup list` to see the backups")]
+    BackupNotFound(PathBuf),
+
+    #[error("Config path is a directory: {0}\n\nMove it aside, or point AMU_CONFIG at a file")]
+    ConfigIsDirectory(PathBuf),
+
+    #[error("Cannot read config file: {path} ({reason})\n\nCheck the file's owner and permissions with: ls -l {path}")]
+    ConfigUnreadable { path: PathBuf, reason: String },
+
+    #[error("Config file contains merge conflict markers: {path} (line {line})\n\nResolve the conflict and remove the <<<<<<< / ======= / >>>>>>> lines")]
+    ConfigMergeConflict { path: PathBuf, line: usize },
+
+    #[error("Invalid entry JSON: {0}\n\nExpected objects like {{\"target\": \"~/.config/nvim\", \"source\": \"~/dotfiles/nvim\"}}")]
+    InvalidEntries(String),
+
     #[error("Failed to parse config file: {0}")]
     ConfigParseError(String),
 
+    #[error("Relative source escapes the config directory: {path} (relative to {root})")]
+    RelativeSourceEscapesRoot { path: PathBuf, root: PathBuf },
+
     #[error("Failed to save config file: {0}")]
     ConfigSaveError(String),
 
+    #[error("Unknown setting: {0}")]
+    UnknownSetting(String),
+
+    #[error("Not a setting: {0}\n\nChange the target/source mapping with amu add and amu remove, or amu config add-entry and remove-entry")]
+    MappingKey(String),
+
+    #[error("Invalid value for {key}: {reason}")]
+    InvalidSetting { key: String, reason: String },
+
+    #[error("Failed to write state: {0}")]
+    StateError(String),
+
+    #[error("Invalid variable name: {0} (use letters, digits and underscores)")]
+    InvalidVariableName(String),
+
+    #[error("Not creating {target}: {missing} does not exist and --parents-mode is {mode}")]
+    ParentsModeRefused { target: PathBuf, missing: PathBuf, mode: &'static str },
+
+    #[error("Not a regular file: {0}\n\nOnly a file (not a link or directory) can become a template")]
+    NotARegularFile(PathBuf),
+
+    #[error("Cannot read file as text: {path} ({reason})")]
+    FileNotText { path: PathBuf, reason: String },
+
+    #[error("Expected NAME=VALUE: {0}")]
+    InvalidAssignment(String),
+
+    #[error("Template would not render back to the file: {0}")]
+    TemplateMismatch(PathBuf),
+
+    #[error("Template already exists: {0}")]
+    TemplateExists(PathBuf),
+
+    #[error("Variable not set: {0}")]
+    VariableNotSet(String),
+
     #[error("stow command failed: {0}")]
     StowError(String),
 
+    #[error("Linking failed: {0}")]
+    LinkError(String),
+
+    #[error("Failed to send notification: {0}")]
+    NotifyError(String),
+
+    #[error("git command failed: {0}")]
+    GitError(String),
+
+    #[error("No registered source is in a git repository")]
+    NoRepository,
+
+    #[error("Not in a git repository: {0}")]
+    NotARepository(PathBuf),
+
+    #[error("Sources are in several git repositories: {0}\n\nPick one with --source, or use --all")]
+    AmbiguousRepository(String),
+
+    #[error("tar command failed: {0}")]
+    ArchiveError(String),
+
+    #[error("Import destination is not empty: {0}\n\nChoose another directory with --into")]
+    ImportDestinationNotEmpty(PathBuf),
+
+    #[error("{0} has no default location; give the directory to import")]
+    ImportPathRequired(&'static str),
+
+    #[error("Hook failed: {0}\n\nNothing was changed; skip hooks with --no-hooks")]
+    HookFailed(String),
+
+    #[error("Line {line}: {message}")]
+    BatchStep { line: usize, message: String },
+
+    #[error("Strict mode: {count} warning(s), nothing was changed:\n  {details}")]
+    StrictWarnings { count: usize, details: String },
+
+    #[error("Unreadable directories skipped ({count}, --fail-on-unreadable):\n  {paths}")]
+    Unreadable { count: usize, paths: String },
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
@@ -0,0 +1,49 @@
+use crate::strict;
+
+/*
+ * Exit codes shared by every command
+ * 0 when all is well, 1 when the result has warnings (a source with broken links, a
+ * repository behind its upstream, a source `update` skipped), 2 when something failed, 3
+ * when the command line itself is wrong. Other warnings printed while a command changes
+ * things (a missing target) leave the exit code alone unless strict mode is on, which
+ * makes every warning an error. `amu git` passes on git's own exit code instead, and `amu config get`
+ * exits 1 for a key that is not set, as `git config` does.
+ * Commands return their exit rather than exiting, so a batch can go on after a step ending
+ * with 1 and exit with the worst of its steps; a step ending with 2 rolls the batch back.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exit {
+    Ok = 0,
+    Warnings = 1,
+    Errors = 2,
+    Usage = 3,
+}
+
+impl Exit {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// The exit for a result with this many warnings and errors; strict mode fails on warnings.
+    pub fn from_counts(warnings: usize, errors: usize) -> Exit {
+        if errors > 0 || (warnings > 0 && strict::is_strict()) {
+            Exit::Errors
+        } else if warnings > 0 {
+            Exit::Warnings
+        } else {
+            Exit::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_counts() {
+        assert_eq!(Exit::from_counts(0, 0), Exit::Ok);
+        assert_eq!(Exit::from_counts(3, 1), Exit::Errors);
+        assert_eq!(Exit::Usage.code(), 3);
+    }
+}
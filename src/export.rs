@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::ValueEnum;
+
+use crate::config::{self, Config, PathKey};
+use crate::error::{DotlinkError, Result};
+
+/// How `amu import` treats a target the destination config already maps to other sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeStrategy {
+    /// Keep the destination's entry and skip the imported one
+    Keep,
+    /// Replace the destination's sources, filters and pins with the imported ones
+    Replace,
+    /// Append imported sources the destination does not have yet (they rank last)
+    Merge,
+}
+
+/// Portable form of a path: inside the home directory it is written as `~/...`.
+fn portable(path: &Path) -> PathBuf {
+    PathBuf::from(config::abbreviate_path(path))
+}
+
+/*
+ * The given targets' part of the config, as a config file of its own
+ * Sources, filters (excludes and `only` patterns) and pins are kept; paths under the home directory are written
+ * with `~` so the file can be imported on a machine with a different home.
+ */
+pub fn export(config: &Config, targets: &[PathBuf]) -> Result<String> {
+    let mut exported = Config::default();
+    for target in targets {
+        let sources = config.get_sources(target)
+            .ok_or_else(|| DotlinkError::TargetNotRegistered(target.to_path_buf()))?;
+        exported.targets.insert(PathKey::from(portable(target)), sources.iter().map(|s| portable(s)).collect());
+        for (filters, exported) in [(&config.excludes, &mut exported.excludes), (&config.only, &mut exported.only)] {
+            if let Some(filters) = filters.get(target) {
+                let filters = filters.iter().map(|(source, patterns)| (portable(source), patterns.clone())).collect();
+                exported.insert(portable(target), filters);
+            }
+        }
+        if let Some(pins) = config.pins.get(target) {
+            let pins = pins.iter().map(|(relative, source)| (relative.clone(), portable(source))).collect();
+            exported.pins.insert(portable(target), pins);
+        }
+        if let Some(metadata) = config.metadata.get(target) {
+            let metadata = metadata.iter().map(|(source, meta)| (portable(source), meta.clone())).collect();
+            exported.metadata.insert(portable(target), metadata);
+        }
+    }
+
+    exported.to_yaml()
+}
+
+/// Parse an exported file, resolving `~` and the paths for this machine.
+pub fn parse(content: &str) -> Result<Config> {
+    let exported = Config::parse(content)?;
+    let resolve = |path: &PathBuf| config::resolve_path(path);
+
+    let mut imported = Config::default();
+    for (target, sources) in &exported.targets {
+        imported.targets.insert(PathKey::new(target.resolved()), sources.iter().map(resolve).collect());
+    }
+    for (filters, imported) in [(&exported.excludes, &mut imported.excludes), (&exported.only, &mut imported.only)] {
+        for (target, filters) in filters {
+            let filters = filters.iter().map(|(source, patterns)| (resolve(source), patterns.clone())).collect();
+            imported.insert(resolve(target), filters);
+        }
+    }
+    for (target, pins) in &exported.pins {
+        let pins = pins.iter().map(|(relative, source)| (relative.clone(), resolve(source))).collect();
+        imported.pins.insert(resolve(target), pins);
+    }
+    for (target, metadata) in &exported.metadata {
+        let metadata = metadata.iter().map(|(source, meta)| (resolve(source), meta.clone())).collect();
+        imported.metadata.insert(resolve(target), metadata);
+    }
+    Ok(imported)
+}
+
+/*
+ * Bundles: a .tar.gz holding an exported config as `amu.yaml` and, optionally, the sources
+ * it maps, stored by their path relative to the home directory. Sources outside the home
+ * directory cannot be placed on another machine and are left out. The archive is made and
+ * read with the system's `tar`.
+ */
+const BUNDLE_CONFIG: &str = "amu.yaml";
+
+/// Whether `path` names a bundle rather than a plain exported config.
+pub fn is_bundle(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Write a bundle of `content` and the given sources; returns the sources left out.
+pub fn write_bundle(path: &Path, content: &str, sources: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let home = config::home_dir().map(|h| config::resolve_path(&h)).unwrap_or_default();
+    let (inside, outside): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        sources.iter().partition(|s| s.starts_with(&home) && s.as_path() != home && s.is_dir());
+
+    // Removed when dropped
+    let staging = tempfile::Builder::new().prefix("amu-bundle-").tempdir()?;
+    fs::write(staging.path().join(BUNDLE_CONFIG), content)?;
+    let mut tar = Command::new("tar");
+    tar.arg("-czf").arg(config::absolute_path(path)).arg("-C").arg(staging.path()).arg(BUNDLE_CONFIG);
+    if !inside.is_empty() {
+        tar.arg("-C").arg(&home);
+        tar.args(inside.iter().filter_map(|s| s.strip_prefix(&home).ok()));
+    }
+    run_tar(&mut tar)?;
+    Ok(outside.into_iter().cloned().collect())
+}
+
+/// The exported config inside a bundle.
+pub fn read_bundle(path: &Path) -> Result<String> {
+    let output = run_tar(Command::new("tar").arg("-xzOf").arg(path).arg(BUNDLE_CONFIG))?;
+    String::from_utf8(output).map_err(|e| DotlinkError::ArchiveError(e.to_string()))
+}
+
+/// Sources among `sources` that the bundle holds and this machine does not have yet.
+pub fn bundled_sources(path: &Path, sources: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let Some(home) = config::home_dir().map(|h| config::resolve_path(&h)) else { return Ok(Vec::new()) };
+    let listing = String::from_utf8_lossy(&run_tar(Command::new("tar").arg("-tzf").arg(path))?).into_owned();
+    let members: Vec<PathBuf> = listing.lines().map(|line| PathBuf::from(line.trim_end_matches('/'))).collect();
+    Ok(sources.iter()
+        .filter(|source| !source.exists())
+        .filter(|source| source.strip_prefix(&home).is_ok_and(|relative| members.iter().any(|m| m == relative)))
+        .cloned()
+        .collect())
+}
+
+/// Unpack the given sources from a bundle into the home directory.
+pub fn extract_sources(path: &Path, sources: &[PathBuf]) -> Result<()> {
+    let Some(home) = config::home_dir().map(|h| config::resolve_path(&h)) else { return Ok(()) };
+    if sources.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(&home)?;
+    let mut tar = Command::new("tar");
+    tar.arg("-xzf").arg(config::absolute_path(path)).arg("-C").arg(&home);
+    tar.args(sources.iter().filter_map(|s| s.strip_prefix(&home).ok()));
+    run_tar(&mut tar).map(|_| ())
+}
+
+fn run_tar(command: &mut Command) -> Result<Vec<u8>> {
+    crate::debug!("run: {}", crate::log::command_line(command));
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(DotlinkError::ArchiveError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(output.stdout)
+}
+
+/// Imported targets the config already maps to a different list of sources.
+pub fn conflicts(config: &Config, imported: &Config) -> Vec<PathBuf> {
+    imported.targets.iter()
+        .filter(|(target, sources)| config.get_sources(target).is_some_and(|existing| existing != *sources))
+        .map(|(target, _)| target.to_path_buf())
+        .collect()
+}
+
+/*
+ * Merge imported targets into the config
+ * `decide` is asked for every conflicting target; targets that are new or already
+ * identical are taken as they are, with their filters and pins added. Returns the
+ * targets that changed.
+ */
+pub fn merge(
+    config: &mut Config,
+    imported: Config,
+    mut decide: impl FnMut(&Path) -> Result<MergeStrategy>,
+) -> Result<Vec<PathBuf>> {
+    let conflicting = conflicts(config, &imported);
+    let mut changed = Vec::new();
+
+    for (key, sources) in imported.targets {
+        let target = key.to_path_buf();
+        let excludes = imported.excludes.get(&target).cloned().unwrap_or_default();
+        let only = imported.only.get(&target).cloned().unwrap_or_default();
+        let pins = imported.pins.get(&target).cloned().unwrap_or_default();
+        let metadata = imported.metadata.get(&target).cloned().unwrap_or_default();
+        let strategy = if conflicting.contains(&target) { decide(&target)? } else { MergeStrategy::Merge };
+
+        let before = target_entries(config, &target);
+        match strategy {
+            MergeStrategy::Keep => continue,
+            MergeStrategy::Replace => {
+                config.targets.insert(key, sources);
+                set_or_remove(&mut config.excludes, &target, excludes);
+                set_or_remove(&mut config.only, &target, only);
+                set_or_remove(&mut config.pins, &target, pins);
+                set_or_remove(&mut config.metadata, &target, metadata);
+            }
+            MergeStrategy::Merge => {
+                let existing = config.targets.entry(key).or_default();
+                for source in sources {
+                    if !existing.contains(&source) {
+                        existing.push(source);
+                    }
+                }
+                for (filters, imported) in [(&mut config.excludes, excludes), (&mut config.only, only)] {
+                    let existing = filters.entry(target.clone()).or_default();
+                    for (source, patterns) in imported {
+                        let existing = existing.entry(source).or_default();
+                        for pattern in patterns {
+                            if !existing.contains(&pattern) {
+                                existing.push(pattern);
+                            }
+                        }
+                    }
+                    filters.retain(|_, f| !f.is_empty());
+                }
+                let existing = config.pins.entry(target.clone()).or_default();
+                for (relative, source) in pins {
+                    existing.entry(relative).or_insert(source);
+                }
+                config.pins.retain(|_, p| !p.is_empty());
+                let existing = config.metadata.entry(target.clone()).or_default();
+                for (source, meta) in metadata {
+                    existing.entry(source).or_insert(meta);
+                }
+                config.metadata.retain(|_, m| !m.is_empty());
+            }
+        }
+
+        if target_entries(config, &target) != before {
+            changed.push(target);
+        }
+    }
+    Ok(changed)
+}
+
+/// Everything the config records for a target, to tell whether a merge changed it.
+fn target_entries(config: &Config, target: &Path) -> impl PartialEq {
+    (
+        config.get_sources(target).cloned(),
+        config.excludes.get(target).cloned(),
+        config.only.get(target).cloned(),
+        config.pins.get(target).cloned(),
+    )
+}
+
+fn set_or_remove<V>(map: &mut BTreeMap<PathBuf, BTreeMap<PathBuf, V>>, target: &Path, value: BTreeMap<PathBuf, V>) {
+    if value.is_empty() {
+        map.remove(target);
+    } else {
+        map.insert(target.to_path_buf(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with(target: &str, sources: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.targets.insert(PathKey::new(Path::new(target)), sources.iter().map(PathBuf::from).collect());
+        config
+    }
+
+    #[test]
+    fn test_export_parse_roundtrip() {
+        let mut config = config_with("/t", &["/a", "/b"]);
+        config.excludes.entry("/t".into()).or_default().insert("/a".into(), vec!["*.bak".to_string()]);
+        config.only.entry("/t".into()).or_default().insert("/b".into(), vec![".config/nvim".to_string()]);
+        config.targets.insert(PathKey::new(Path::new("/other")), vec!["/c".into()]);
+
+        let imported = parse(&export(&config, &[PathBuf::from("/t")]).unwrap()).unwrap();
+        assert_eq!(imported.targets.len(), 1);
+        assert_eq!(imported.sources_of(Path::new("/t")), vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+        assert_eq!(imported.excludes[Path::new("/t")][Path::new("/a")], vec!["*.bak".to_string()]);
+        assert_eq!(imported.only[Path::new("/t")][Path::new("/b")], vec![".config/nvim".to_string()]);
+        assert!(export(&config, &[PathBuf::from("/missing")]).is_err());
+
+        let both = parse(&export(&config, &[PathBuf::from("/t"), PathBuf::from("/other")]).unwrap()).unwrap();
+        assert_eq!(both.targets.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let imported = || config_with("/t", &["/b", "/c"]);
+
+        let mut config = config_with("/t", &["/a", "/b"]);
+        let changed = merge(&mut config, imported(), |_| Ok(MergeStrategy::Merge)).unwrap();
+        assert_eq!(changed, vec![PathBuf::from("/t")]);
+        assert_eq!(config.sources_of(Path::new("/t")), vec![PathBuf::from("/a"), PathBuf::from("/b"), PathBuf::from("/c")]);
+
+        let mut config = config_with("/t", &["/a", "/b"]);
+        merge(&mut config, imported(), |_| Ok(MergeStrategy::Replace)).unwrap();
+        assert_eq!(config.sources_of(Path::new("/t")), vec![PathBuf::from("/b"), PathBuf::from("/c")]);
+
+        let mut config = config_with("/t", &["/a", "/b"]);
+        assert!(merge(&mut config, imported(), |_| Ok(MergeStrategy::Keep)).unwrap().is_empty());
+        assert_eq!(config.sources_of(Path::new("/t")), vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+
+        // New targets never ask
+        let mut config = Config::default();
+        merge(&mut config, imported(), |_| panic!("no conflict expected")).unwrap();
+        assert_eq!(config.targets.len(), 1);
+    }
+}
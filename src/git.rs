@@ -0,0 +1,349 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use serde::Serialize;
+
+use crate::error::{DotlinkError, Result};
+use crate::log;
+
+/*
+ * Thin wrapper around the git CLI for sources kept in dotfiles repositories
+ */
+
+fn git(repo: &Path, args: &[&str]) -> std::io::Result<Output> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo).args(args);
+    crate::debug!("run: {}", log::command_line(&command));
+    command.output()
+}
+
+/// Root of the git work tree containing `path`, if it is inside one.
+pub fn repo_root(path: &Path) -> Option<PathBuf> {
+    let output = git(path, &["rev-parse", "--show-toplevel"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(PathBuf::from(root))
+}
+
+/// Distinct repositories containing the given sources, sorted; missing sources are skipped.
+pub fn repos<'a>(sources: impl IntoIterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+    let mut repos: Vec<PathBuf> = sources.into_iter()
+        .filter(|source| source.exists())
+        .filter_map(|source| repo_root(source))
+        .collect();
+    repos.sort();
+    repos.dedup();
+    repos
+}
+
+/// Run git in `repo` on the current terminal; returns its exit code.
+pub fn passthrough(repo: &Path, args: &[String]) -> Result<i32> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo).args(args);
+    crate::debug!("run: {}", log::command_line(&command));
+    let status = command.status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/*
+ * Uncommitted changes in a repository and how far it is from its upstream branch
+ * Ahead and behind are counted against the remote-tracking branch as last fetched;
+ * nothing is fetched.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RepoState {
+    pub uncommitted: usize,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+impl RepoState {
+    pub fn is_clean(&self) -> bool {
+        *self == RepoState::default()
+    }
+
+    /// "2 uncommitted change(s), 1 commit(s) behind upstream"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.uncommitted > 0 {
+            parts.push(format!("{} uncommitted change(s)", self.uncommitted));
+        }
+        if self.ahead > 0 {
+            parts.push(format!("{} commit(s) ahead of upstream", self.ahead));
+        }
+        if self.behind > 0 {
+            parts.push(format!("{} commit(s) behind upstream", self.behind));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Whether `path` may be inside a work tree; looking for `.git` spares a git process per
+/// source outside any repository.
+fn in_work_tree(path: &Path) -> bool {
+    path.ancestors().any(|dir| dir.join(".git").exists())
+}
+
+/// State of the repository containing `path`; None outside a work tree.
+pub fn state(path: &Path) -> Option<RepoState> {
+    if !in_work_tree(path) {
+        return None;
+    }
+    let output = git(path, &["status", "--porcelain"]).ok().filter(|o| o.status.success())?;
+    let mut state = RepoState {
+        uncommitted: String::from_utf8_lossy(&output.stdout).lines().count(),
+        ..RepoState::default()
+    };
+    if has_upstream(path) {
+        let counts = git(path, &["rev-list", "--left-right", "--count", "HEAD...@{u}"]).ok()?;
+        let counts = String::from_utf8_lossy(&counts.stdout).into_owned();
+        if let Some((ahead, behind)) = parse_counts(&counts) {
+            state.ahead = ahead;
+            state.behind = behind;
+        }
+    }
+    Some(state)
+}
+
+/*
+ * How a repository compares with its upstream branch once fetched (`amu verify-remote`)
+ * Nothing is merged: the remote-tracking branch is updated and the commits on either side
+ * counted. When fetching fails, the counts are as of the last successful fetch.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RemoteState {
+    /// The branch tracked, such as `origin/main`; None when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_error: Option<String>,
+}
+
+impl RemoteState {
+    /// "up_to_date", "behind", "ahead", "diverged", "no_upstream" or "fetch_failed"
+    pub fn result(&self) -> &'static str {
+        match self {
+            RemoteState { upstream: None, .. } => "no_upstream",
+            RemoteState { fetch_error: Some(_), .. } => "fetch_failed",
+            RemoteState { ahead: 0, behind: 0, .. } => "up_to_date",
+            RemoteState { ahead: 0, .. } => "behind",
+            RemoteState { behind: 0, .. } => "ahead",
+            _ => "diverged",
+        }
+    }
+
+    /// Severity reported in summaries: "ok", "warning" or "error"
+    pub fn class(&self) -> &'static str {
+        match self.result() {
+            "up_to_date" | "no_upstream" => "ok",
+            "fetch_failed" => "error",
+            _ => "warning",
+        }
+    }
+
+    /// "12 commit(s) behind origin/main"
+    pub fn describe(&self) -> String {
+        let Some(upstream) = &self.upstream else { return "no upstream branch".to_string() };
+        if let Some(error) = &self.fetch_error {
+            return format!("could not fetch {}: {}", upstream, error);
+        }
+        match (self.ahead, self.behind) {
+            (0, 0) => format!("up to date with {}", upstream),
+            (0, behind) => format!("{} commit(s) behind {}", behind, upstream),
+            (ahead, 0) => format!("{} commit(s) ahead of {}", ahead, upstream),
+            (ahead, behind) => format!("{} commit(s) ahead of {}, {} behind", ahead, upstream, behind),
+        }
+    }
+}
+
+/// Fetch the repository containing `path` and compare its current branch with its upstream.
+pub fn remote_state(path: &Path) -> RemoteState {
+    let Some(upstream) = upstream(path) else { return RemoteState::default() };
+    let fetch_error = match fetch(path) {
+        Err(DotlinkError::GitError(message)) => Some(message),
+        Err(e) => Some(e.to_string()),
+        Ok(()) => None,
+    };
+    let (ahead, behind) = git(path, &["rev-list", "--left-right", "--count", "HEAD...@{u}"])
+        .ok()
+        .and_then(|output| parse_counts(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default();
+    RemoteState { upstream: Some(upstream), ahead, behind, fetch_error }
+}
+
+/// The branch the current one tracks (`origin/main`), if any.
+pub fn upstream(repo: &Path) -> Option<String> {
+    let output = git(repo, &["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"]).ok().filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/*
+ * Tracked files under `dir` left out of the working tree by sparse-checkout, relative to it
+ * Empty when `dir` is not in a repository using sparse-checkout.
+ */
+pub fn sparse_excluded(dir: &Path) -> Vec<PathBuf> {
+    if !in_work_tree(dir) {
+        return Vec::new();
+    }
+    let sparse = git(dir, &["config", "--bool", "core.sparseCheckout"])
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "true");
+    if !sparse {
+        return Vec::new();
+    }
+    match git(dir, &["ls-files", "-t", "-z", "--", "."]) {
+        Ok(output) if output.status.success() => parse_skipped(&String::from_utf8_lossy(&output.stdout)),
+        _ => Vec::new(),
+    }
+}
+
+/// Files tracked by a repository whose work tree lives elsewhere (yadm's), relative to `work_tree`.
+pub fn tracked_files(git_dir: &Path, work_tree: &Path) -> Result<Vec<PathBuf>> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(work_tree).arg("--git-dir").arg(git_dir).arg("--work-tree").arg(work_tree).args(["ls-files", "-z"]);
+    crate::debug!("run: {}", log::command_line(&command));
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(DotlinkError::GitError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).split('\0').filter(|p| !p.is_empty()).map(PathBuf::from).collect())
+}
+
+/// Paths tagged `S` (skip-worktree) in `ls-files -t -z` output
+fn parse_skipped(output: &str) -> Vec<PathBuf> {
+    output.split('\0').filter_map(|entry| entry.strip_prefix("S ")).map(PathBuf::from).collect()
+}
+
+/// "3\t1" from `rev-list --left-right --count` as (ahead, behind)
+fn parse_counts(output: &str) -> Option<(usize, usize)> {
+    let mut counts = output.split_whitespace().map(str::parse);
+    Some((counts.next()?.ok()?, counts.next()?.ok()?))
+}
+
+/// Create an empty repository in `dir`, or reinitialize an existing one.
+pub fn init(dir: &Path) -> Result<()> {
+    run(dir, &["init", "--quiet"])
+}
+
+/// Whether the current branch tracks a remote branch.
+pub fn has_upstream(repo: &Path) -> bool {
+    git(repo, &["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"])
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Update the remote-tracking branches, leaving the work tree alone.
+pub fn fetch(repo: &Path) -> Result<()> {
+    run(repo, &["fetch", "--quiet"])
+}
+
+/// Fast-forward the current branch from its upstream. Returns git's last line of output.
+pub fn pull(repo: &Path) -> Result<String> {
+    let output = git(repo, &["pull", "--ff-only"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DotlinkError::GitError(stderr.trim().to_string()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().last().unwrap_or_default().trim().to_string())
+}
+
+/// Commit the current branch points at.
+pub fn head(repo: &Path) -> Option<String> {
+    let output = git(repo, &["rev-parse", "HEAD"]).ok().filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Files changed between two commits, as (status letter, path from the repository root);
+/// a rename shows up as a deletion and an addition.
+pub fn changed_files(repo: &Path, from: &str, to: &str) -> Result<Vec<(char, PathBuf)>> {
+    let output = git(repo, &["diff", "--name-status", "--no-renames", "-z", from, to])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DotlinkError::GitError(stderr.trim().to_string()));
+    }
+    Ok(parse_name_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// `diff --name-status -z` output: status and path, each terminated by NUL
+fn parse_name_status(output: &str) -> Vec<(char, PathBuf)> {
+    let fields: Vec<&str> = output.split('\0').collect();
+    fields.chunks(2)
+        .filter_map(|pair| match pair {
+            [status, path] if !path.is_empty() => Some((status.chars().next()?, PathBuf::from(path))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Stage everything and commit it; false when there was nothing to commit.
+pub fn commit_all(repo: &Path, message: &str) -> Result<bool> {
+    run(repo, &["add", "--all"])?;
+    if git(repo, &["diff", "--cached", "--quiet"]).is_ok_and(|output| output.status.success()) {
+        return Ok(false);
+    }
+    run(repo, &["commit", "--quiet", "-m", message])?;
+    Ok(true)
+}
+
+/// Push the current branch to its upstream.
+pub fn push(repo: &Path) -> Result<()> {
+    run(repo, &["push", "--quiet"])
+}
+
+fn run(repo: &Path, args: &[&str]) -> Result<()> {
+    let output = git(repo, args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(DotlinkError::GitError(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_state() {
+        assert_eq!(parse_counts("3\t1\n"), Some((3, 1)));
+        assert_eq!(parse_counts(""), None);
+
+        let state = RepoState { uncommitted: 2, ahead: 0, behind: 1 };
+        assert!(!state.is_clean());
+        assert_eq!(state.describe(), "2 uncommitted change(s), 1 commit(s) behind upstream");
+        assert!(RepoState::default().is_clean());
+    }
+
+    #[test]
+    fn test_remote_state() {
+        let upstream = Some("origin/main".to_string());
+        let diverged = RemoteState { upstream: upstream.clone(), ahead: 1, behind: 12, fetch_error: None };
+        assert_eq!((diverged.result(), diverged.class()), ("diverged", "warning"));
+        assert_eq!(diverged.describe(), "1 commit(s) ahead of origin/main, 12 behind");
+        let offline = RemoteState { upstream, fetch_error: Some("no network".to_string()), ..RemoteState::default() };
+        assert_eq!((offline.result(), offline.class()), ("fetch_failed", "error"));
+        assert_eq!(RemoteState::default().describe(), "no upstream branch");
+        assert_eq!(RemoteState::default().class(), "ok");
+    }
+
+    #[test]
+    fn test_parse_skipped() {
+        let output = "H a/x\0S b/y\0H top\0S b/z w\0";
+        assert_eq!(parse_skipped(output), vec![PathBuf::from("b/y"), PathBuf::from("b/z w")]);
+        assert!(parse_skipped("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_name_status() {
+        let output = "A\0zsh/.zshrc\0D\0nvim/init.lua\0M\0git/.gitconfig.tmpl\0";
+        assert_eq!(parse_name_status(output), vec![
+            ('A', PathBuf::from("zsh/.zshrc")),
+            ('D', PathBuf::from("nvim/init.lua")),
+            ('M', PathBuf::from("git/.gitconfig.tmpl")),
+        ]);
+        assert!(parse_name_status("").is_empty());
+    }
+}
@@ -0,0 +1,405 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::adopt;
+use crate::error::{DotlinkError, Result};
+use crate::linker::{copy, platform};
+use crate::ownership;
+use crate::state;
+
+const HISTORY_DIR: &str = "history";
+
+/// Operations kept for `amu undo`; older ones are deleted
+const KEEP: usize = 100;
+
+/*
+ * Operation history for `amu undo`
+ * While a command runs, every link it creates or removes, every file it moves or copies
+ * aside and every config save is appended to one operation, saved under the state
+ * directory when the command ends. Undoing replays the changes backwards, skipping any
+ * whose result has since been changed by something else.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: u64,
+    /// The command line, without the program name
+    pub command: String,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub undone: bool,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    LinkCreated { link: PathBuf },
+    LinkRemoved { link: PathBuf, destination: PathBuf },
+    /// A missing directory created to hold links or copies
+    DirCreated { path: PathBuf },
+    /// A file moved aside (a backup) or into a source (adopt)
+    Moved { from: PathBuf, to: PathBuf },
+    /// A file copied aside before being replaced
+    Copied { from: PathBuf, to: PathBuf },
+    /// A file copied or hard-linked into a target (`mode: copy`, `mode: hardlink`)
+    Written { path: PathBuf, fingerprint: String },
+    Config { path: PathBuf, before: Option<String>, after: String },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Change::LinkCreated { link } => write!(f, "created link {}", link.display()),
+            Change::LinkRemoved { link, destination } => {
+                write!(f, "removed link {} -> {}", link.display(), destination.display())
+            }
+            Change::DirCreated { path } => write!(f, "created directory {}", path.display()),
+            Change::Moved { from, to } => write!(f, "moved {} to {}", from.display(), to.display()),
+            Change::Copied { from, to } => write!(f, "copied {} to {}", from.display(), to.display()),
+            Change::Written { path, .. } => write!(f, "wrote {}", path.display()),
+            Change::Config { path, .. } => write!(f, "wrote config {}", path.display()),
+        }
+    }
+}
+
+impl Operation {
+    /// "2 links created, 1 link removed, config changed"
+    pub fn summary(&self) -> String {
+        let count = |f: fn(&Change) -> bool| self.changes.iter().filter(|c| f(c)).count();
+        let mut parts = Vec::new();
+        let created = count(|c| matches!(c, Change::LinkCreated { .. }));
+        let removed = count(|c| matches!(c, Change::LinkRemoved { .. }));
+        let moved = count(|c| matches!(c, Change::Moved { .. }));
+        let written = count(|c| matches!(c, Change::Written { .. }));
+        if created > 0 {
+            parts.push(format!("{} link(s) created", created));
+        }
+        if removed > 0 {
+            parts.push(format!("{} link(s) removed", removed));
+        }
+        if moved > 0 {
+            parts.push(format!("{} file(s) moved", moved));
+        }
+        if written > 0 {
+            parts.push(format!("{} file(s) copied", written));
+        }
+        if count(|c| matches!(c, Change::Config { .. })) > 0 {
+            parts.push("config changed".to_string());
+        }
+        parts.join(", ")
+    }
+}
+
+static RECORDING: Mutex<Option<Vec<Change>>> = Mutex::new(None);
+
+fn recording() -> std::sync::MutexGuard<'static, Option<Vec<Change>>> {
+    RECORDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Start recording changes made by this process.
+pub fn begin() {
+    *recording() = Some(Vec::new());
+}
+
+pub fn is_recording() -> bool {
+    recording().is_some()
+}
+
+/// Append a change to the operation being recorded, if any.
+pub fn record(change: Change) {
+    crate::debug!("{}", change);
+    let mut recording = recording();
+    let Some(changes) = recording.as_mut() else { return };
+    // One config entry per file: its state before the first save and after the last
+    if let Change::Config { path, after, .. } = &change {
+        let earlier = changes.iter_mut().find_map(|c| match c {
+            Change::Config { path: p, after: a, .. } if p == path => Some(a),
+            _ => None,
+        });
+        if let Some(previous) = earlier {
+            *previous = after.clone();
+            return;
+        }
+    }
+    changes.push(change);
+}
+
+/// Stop recording and save the operation if it changed anything.
+pub fn finish() -> Result<()> {
+    let Some(changes) = recording().take() else { return Ok(()) };
+    crate::debug!("{} change(s) made", changes.len());
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let _lock = state::lock();
+    let existing = list()?;
+    let operation = Operation {
+        id: existing.last().map_or(1, |op| op.id + 1),
+        command: std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+        started_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        undone: false,
+        changes,
+    };
+    save(&operation)?;
+    for old in existing.iter().take((existing.len() + 1).saturating_sub(KEEP)) {
+        let _ = fs::remove_file(operation_path(old.id)?);
+    }
+    Ok(())
+}
+
+fn history_dir() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(HISTORY_DIR))
+}
+
+fn operation_path(id: u64) -> Result<PathBuf> {
+    Ok(history_dir()?.join(format!("{:06}.yaml", id)))
+}
+
+fn save(operation: &Operation) -> Result<()> {
+    let path = operation_path(operation.id)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml::to_string(operation).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+/// Recorded operations, oldest first. Unreadable records are skipped.
+pub fn list() -> Result<Vec<Operation>> {
+    let Ok(entries) = fs::read_dir(history_dir()?) else { return Ok(Vec::new()) };
+    let mut operations: Vec<Operation> = entries
+        .flatten()
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_yaml::from_str(&content).ok())
+        .collect();
+    operations.sort_by_key(|op| op.id);
+    Ok(operations)
+}
+
+/// The operation `undo` would revert: the given one, or the latest not yet undone.
+pub fn find(id: Option<u64>) -> Result<Operation> {
+    let operations = list()?;
+    let operation = match id {
+        Some(id) => operations.into_iter().find(|op| op.id == id).ok_or(DotlinkError::OperationNotFound(id))?,
+        None => operations.into_iter().rev().find(|op| !op.undone).ok_or(DotlinkError::NothingToUndo)?,
+    };
+    if operation.undone {
+        return Err(DotlinkError::AlreadyUndone(operation.id));
+    }
+    Ok(operation)
+}
+
+/// Changes of an undo: reverted, or skipped with the reason
+pub struct Reverted {
+    pub reverted: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/*
+ * Revert an operation's changes, newest first, and mark it undone
+ * A change is only reverted while its result is still in place (a link still a link,
+ * a moved file still where it was moved, the config still as saved); otherwise it is
+ * skipped and reported.
+ */
+pub fn undo(mut operation: Operation, dry_run: bool) -> Result<Reverted> {
+    let result = revert_all(&operation.changes, dry_run)?;
+    if !dry_run {
+        operation.undone = true;
+        save(&operation)?;
+    }
+    Ok(result)
+}
+
+/// Revert the changes recorded so far by this process, newest first, and forget them.
+pub fn revert_recorded() -> Result<Reverted> {
+    let changes = recording().as_mut().map(std::mem::take).unwrap_or_default();
+    revert_all(&changes, false)
+}
+
+/// Revert changes newest first.
+fn revert_all(changes: &[Change], dry_run: bool) -> Result<Reverted> {
+    let mut result = Reverted { reverted: Vec::new(), skipped: Vec::new() };
+    // Paths removed so far; on a dry run they are still there
+    let mut gone = Vec::new();
+    for change in changes.iter().rev() {
+        match revert(change, dry_run, &gone)? {
+            Ok(done) => {
+                if let Change::LinkCreated { link: path } | Change::DirCreated { path } | Change::Written { path, .. } = change {
+                    gone.push(path.clone());
+                }
+                result.reverted.push(done);
+            }
+            Err(reason) => result.skipped.push(reason),
+        }
+    }
+    Ok(result)
+}
+
+/*
+ * Revert one change; the inner result describes what was (or would be) done, or why not
+ * `gone` lists the paths already reverted in this run, so a dry run knows a created
+ * directory would be empty by the time it is reached.
+ */
+fn revert(change: &Change, dry_run: bool, gone: &[PathBuf]) -> Result<std::result::Result<String, String>> {
+    let occupied = |path: &Path| fs::symlink_metadata(path).is_ok();
+    Ok(match change {
+        Change::LinkCreated { link } => {
+            if !link.is_symlink() {
+                Err(format!("{} (no longer a link)", link.display()))
+            } else {
+                if !dry_run {
+                    platform::remove_link(link)?;
+                }
+                Ok(format!("removed link {}", link.display()))
+            }
+        }
+        Change::LinkRemoved { link, destination } => {
+            if occupied(link) {
+                Err(format!("{} (path is in use)", link.display()))
+            } else {
+                if !dry_run {
+                    create_parent(link)?;
+                    platform::symlink(destination, link)?;
+                }
+                Ok(format!("recreated link {}", link.display()))
+            }
+        }
+        Change::DirCreated { path } => {
+            let entries = fs::read_dir(path).ok().filter(|_| !path.is_symlink());
+            match entries.map(|entries| entries.flatten().any(|entry| !gone.contains(&entry.path()))) {
+                None => Err(format!("{} (no longer a directory)", path.display())),
+                Some(true) => Err(format!("{} (not empty)", path.display())),
+                Some(false) => {
+                    if !dry_run {
+                        fs::remove_dir(path)?;
+                    }
+                    Ok(format!("removed directory {}", path.display()))
+                }
+            }
+        }
+        Change::Moved { from, to } => {
+            if !occupied(to) || occupied(from) {
+                Err(format!("{} (cannot move back from {})", from.display(), to.display()))
+            } else {
+                if !dry_run {
+                    create_parent(from)?;
+                    adopt::move_path(to, from)?;
+                }
+                Ok(format!("moved back {}", from.display()))
+            }
+        }
+        Change::Copied { from, to } => {
+            if !to.is_file() || occupied(from) {
+                Err(format!("{} (not restored from {})", from.display(), to.display()))
+            } else {
+                if !dry_run {
+                    create_parent(from)?;
+                    fs::copy(to, from)?;
+                }
+                Ok(format!("restored {}", from.display()))
+            }
+        }
+        Change::Written { path, fingerprint } => {
+            if copy::file_fingerprint(path).as_ref() != Some(fingerprint) || path.is_symlink() {
+                Err(format!("{} (changed since)", path.display()))
+            } else {
+                if !dry_run {
+                    fs::remove_file(path)?;
+                    copy::forget(path)?;
+                }
+                Ok(format!("removed {}", path.display()))
+            }
+        }
+        Change::Config { path, before, after } => {
+            if fs::read_to_string(path).ok().as_ref() != Some(after) {
+                Err(format!("{} (changed since)", path.display()))
+            } else {
+                if !dry_run {
+                    match before {
+                        Some(content) => fs::write(path, content)?,
+                        None => fs::remove_file(path)?,
+                    }
+                }
+                let verb = if before.is_some() { "restored" } else { "removed" };
+                Ok(format!("{} config {}", verb, path.display()))
+            }
+        }
+    })
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_revert_changes() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        let link = root.join("link");
+        let file = root.join("file");
+        let config = root.join("config.yaml");
+        let backup = root.join("backups/app");
+        fs::write(&file, "x").unwrap();
+        fs::write(&config, "after").unwrap();
+        // A directory moved aside whole
+        fs::create_dir_all(backup.join("nested")).unwrap();
+        fs::write(backup.join("nested/conf"), "conf").unwrap();
+        // Directories an add created to hold a link, and one that has gained a file since
+        fs::create_dir_all(root.join("made/deeper")).unwrap();
+        std::os::unix::fs::symlink(&file, root.join("made/deeper/linked")).unwrap();
+        fs::create_dir(root.join("kept")).unwrap();
+        fs::write(root.join("kept/mine"), "x").unwrap();
+
+        let operation = Operation {
+            id: 1,
+            command: "add".to_string(),
+            started_at: 0,
+            undone: false,
+            changes: vec![
+                Change::Config { path: config.clone(), before: Some("before".to_string()), after: "after".to_string() },
+                Change::LinkRemoved { link: link.clone(), destination: file.clone() },
+                Change::Moved { from: root.join("app"), to: backup.clone() },
+                Change::LinkCreated { link: root.join("gone") },
+                Change::DirCreated { path: root.join("kept") },
+                Change::DirCreated { path: root.join("made") },
+                Change::DirCreated { path: root.join("made/deeper") },
+                Change::LinkCreated { link: root.join("made/deeper/linked") },
+            ],
+        };
+        assert_eq!(operation.summary(), "2 link(s) created, 1 link(s) removed, 1 file(s) moved, config changed");
+
+        // A dry run counts the link as gone before it reaches the directories holding it
+        let planned = revert_all(&operation.changes, true).unwrap();
+        assert_eq!(planned.reverted.len(), 6);
+        assert!(root.join("made/deeper/linked").is_symlink());
+
+        let reverted = revert_all(&operation.changes, false).unwrap();
+        assert_eq!(reverted.reverted, planned.reverted);
+        assert!(reverted.skipped[0].contains("not empty"));
+        assert!(reverted.skipped[1].contains("no longer a link"));
+        assert!(!root.join("made").exists());
+        assert!(root.join("kept/mine").exists());
+        assert_eq!(fs::read_to_string(root.join("app/nested/conf")).unwrap(), "conf");
+        assert!(!backup.exists());
+        assert_eq!(fs::read_link(&link).unwrap(), file);
+        assert_eq!(fs::read_to_string(&config).unwrap(), "before");
+
+        // The config no longer matches what was saved, so it is left alone
+        assert!(revert(&operation.changes[0], false, &[]).unwrap().is_err());
+    }
+}
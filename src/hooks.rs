@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{abbreviate_path, Config};
+use crate::error::{DotlinkError, Result};
+use crate::log;
+use crate::report;
+use crate::say;
+use crate::strict;
+
+/// A source's own amu files; never linked once it holds hook scripts
+pub const SOURCE_DIR: &str = ".amu";
+
+/// Where in a source its hook scripts are kept
+pub const HOOKS_DIR: &str = ".amu/hooks";
+
+/*
+ * Hooks run before and after add, remove, update and restore
+ * A command from `hooks:` in the config runs once per target with `sh -c`; a script named
+ * after the hook in a source's `.amu/hooks/` runs for that source. Both see AMU_HOOK,
+ * AMU_COMMAND, AMU_TARGET and AMU_SOURCES (one per line), source scripts AMU_SOURCE too.
+ * A failing pre hook stops the command before it changes the target; a failing post hook
+ * is only a warning, since the links are already in place.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Hook {
+    PreAdd,
+    PostAdd,
+    PreRemove,
+    PostRemove,
+    PreUpdate,
+    PostUpdate,
+    PreRestore,
+    PostRestore,
+}
+
+impl Hook {
+    /// "post-restore"
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreAdd => "pre-add",
+            Hook::PostAdd => "post-add",
+            Hook::PreRemove => "pre-remove",
+            Hook::PostRemove => "post-remove",
+            Hook::PreUpdate => "pre-update",
+            Hook::PostUpdate => "post-update",
+            Hook::PreRestore => "pre-restore",
+            Hook::PostRestore => "post-restore",
+        }
+    }
+
+    /// The command the hook runs around
+    fn command(self) -> &'static str {
+        self.name().split_once('-').map_or("", |(_, command)| command)
+    }
+
+    fn is_pre(self) -> bool {
+        self.name().starts_with("pre-")
+    }
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Turn hooks off for this process (`--no-hooks`).
+pub fn set_enabled(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(true)
+}
+
+/// A source's script for the hook, if it has one.
+pub fn source_script(source: &Path, hook: Hook) -> Option<PathBuf> {
+    Some(source.join(HOOKS_DIR).join(hook.name())).filter(|path| path.is_file())
+}
+
+/*
+ * Run the config's command and the sources' scripts for a hook on one target
+ * In a dry run the hooks are only listed. Returns an error only for a failing pre hook.
+ */
+pub fn run(config: &Config, hook: Hook, target: &Path, sources: &[PathBuf], dry_run: bool) -> Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    let listed = sources.iter().map(|s| s.display().to_string()).collect::<Vec<_>>().join("\n");
+    let mut commands = Vec::new();
+    if let Some(line) = config.hooks.get(&hook) {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(line).current_dir(target);
+        commands.push((line.clone(), command));
+    }
+    for source in sources {
+        let Some(script) = source_script(source, hook) else { continue };
+        let mut command = if is_executable(&script) { Command::new(&script) } else {
+            let mut command = Command::new("sh");
+            command.arg(&script);
+            command
+        };
+        command.current_dir(source).env("AMU_SOURCE", source);
+        commands.push((abbreviate_path(&script), command));
+    }
+
+    for (label, mut command) in commands {
+        if dry_run {
+            say!("  Would run {} hook: {}", hook.name(), label);
+            continue;
+        }
+        command
+            .env("AMU_HOOK", hook.name())
+            .env("AMU_COMMAND", hook.command())
+            .env("AMU_TARGET", target)
+            .env("AMU_SOURCES", &listed);
+        crate::debug!("run {} hook: {}", hook.name(), log::command_line(&command));
+        // Keep structured output on stdout parseable
+        if !report::is_text() {
+            command.stdout(Stdio::from(std::io::stderr()));
+        }
+        let failure = match command.status() {
+            Ok(status) if status.success() => continue,
+            Ok(status) => status.code().map_or("killed by a signal".to_string(), |c| format!("exit status {}", c)),
+            Err(e) => e.to_string(),
+        };
+        let message = format!("{}: {} ({})", hook.name(), label, failure);
+        if hook.is_pre() {
+            return Err(DotlinkError::HookFailed(message));
+        }
+        strict::warn(format!("hook failed: {}", message));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_hooks() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("source");
+        std::fs::create_dir_all(source.join(HOOKS_DIR)).unwrap();
+        std::fs::write(source.join(HOOKS_DIR).join("post-add"), "echo \"$AMU_COMMAND $AMU_SOURCE\" > added\n").unwrap();
+
+        let mut config = Config::default();
+        config.hooks.insert(Hook::PreAdd, "exit 3".to_string());
+        let sources = vec![source.clone()];
+
+        let failed = run(&config, Hook::PreAdd, temp.path(), &sources, false).unwrap_err();
+        assert!(failed.to_string().contains("pre-add: exit 3 (exit status 3)"));
+
+        run(&config, Hook::PostAdd, temp.path(), &sources, false).unwrap();
+        let written = std::fs::read_to_string(source.join("added")).unwrap();
+        assert_eq!(written.trim(), format!("add {}", source.display()));
+    }
+}
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::hooks;
+
+/// Editor temporary and backup files that are never linked by default
+pub const DEFAULT_PATTERNS: &[&str] = &["*~", "*.swp", ".#*", "4913"];
+
+/// Template sources, which are rendered rather than linked
+pub const TEMPLATE_PATTERN: &str = "*.tmpl";
+
+/// Per-source ignore file, read from the source root
+pub const IGNORE_FILE: &str = ".amuignore";
+
+/// Collect the ignore patterns that apply to a source wherever it is linked:
+/// the defaults, the config's `ignore:` list, the source's `.amuignore` and its hooks.
+/// Templates are still part of the source's content; see `link_patterns`.
+pub fn source_patterns(config: &Config, source: &Path) -> Vec<String> {
+    let mut patterns: Vec<String> = if config.uses_default_ignores() {
+        DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect()
+    } else {
+        Vec::new()
+    };
+    patterns.extend(config.ignore.iter().cloned());
+    patterns.extend(ignore_file_patterns(source));
+    if source.join(hooks::HOOKS_DIR).is_dir() {
+        patterns.push(format!("/{}", hooks::SOURCE_DIR));
+    }
+    patterns
+}
+
+/// Patterns listed in a source's `.amuignore`, which is itself never linked.
+/// One pattern per line; blank lines and lines starting with `#` are skipped.
+fn ignore_file_patterns(source: &Path) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(source.join(IGNORE_FILE)) else {
+        return Vec::new();
+    };
+    let mut patterns = vec![format!("/{}", IGNORE_FILE)];
+    patterns.extend(
+        content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from),
+    );
+    patterns
+}
+
+/// The source's patterns plus its templates, which are rendered rather than linked.
+pub fn link_patterns(config: &Config, source: &Path) -> Vec<String> {
+    let mut patterns = source_patterns(config, source);
+    patterns.push(TEMPLATE_PATTERN.to_string());
+    patterns
+}
+
+/// Collect the ignore patterns for a source linked into a target, including exclusions recorded
+/// for the pair and paths the target pins to other sources.
+pub fn patterns_for(config: &Config, target: &Path, source: &Path) -> Vec<String> {
+    let mut patterns = link_patterns(config, source);
+    patterns.extend(filter_patterns(config, target, source));
+    if let Some(pins) = config.pins.get(target) {
+        patterns.extend(
+            pins.iter()
+                .filter(|(_, pinned)| pinned.as_path() != source)
+                .map(|(path, _)| format!("/{}", path.display())),
+        );
+    }
+    patterns
+}
+
+/// Ignore patterns from the filters recorded for a pair: its excludes, and everything its
+/// `only` patterns leave out.
+pub fn filter_patterns(config: &Config, target: &Path, source: &Path) -> Vec<String> {
+    pair_patterns(source, config.excludes_for(target, source), config.only_for(target, source))
+}
+
+/// Ignore patterns for a source linked with the given excludes and `only` patterns.
+pub fn pair_patterns(source: &Path, excludes: &[String], only: &[String]) -> Vec<String> {
+    let mut patterns = excludes.to_vec();
+    if !only.is_empty() {
+        patterns.extend(only_patterns(source, only));
+    }
+    patterns
+}
+
+/*
+ * Ignore patterns leaving only the paths matching `only` linked
+ * A matching entry is kept whole and a directory is kept while anything below it matches;
+ * everything else in the source is ignored by its anchored path.
+ */
+pub fn only_patterns(source: &Path, only: &[String]) -> Vec<String> {
+    fn collect(base: &Path, current: &Path, only: &[String], patterns: &mut Vec<String>) -> bool {
+        let Ok(entries) = fs::read_dir(current) else { return false };
+        let mut kept = false;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(base) else { continue };
+            if is_ignored(relative, only) {
+                kept = true;
+                continue;
+            }
+            // A directory with nothing kept is ignored as a whole rather than entry by entry
+            let before = patterns.len();
+            if path.is_dir() && !path.is_symlink() && collect(base, &path, only, patterns) {
+                kept = true;
+            } else {
+                patterns.truncate(before);
+                patterns.push(format!("/{}", relative.display()));
+            }
+        }
+        kept
+    }
+
+    let mut patterns = Vec::new();
+    collect(source, source, only, &mut patterns);
+    patterns.sort();
+    patterns
+}
+
+/*
+ * Check whether a source-relative path matches any of the given patterns
+ * Patterns containing a `/` are anchored at the source root and also cover everything
+ * below a matching directory; other patterns match the file name at any depth.
+ */
+pub fn is_ignored(relative: &Path, patterns: &[String]) -> bool {
+    let name = relative.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+
+    patterns.iter().any(|p| match anchored(p) {
+        Some(anchored) => {
+            // The path itself or any of its parent directories
+            relative.ancestors().any(|a| !a.as_os_str().is_empty() && glob_match(anchored, &a.to_string_lossy()))
+        }
+        None => glob_match(p, &name),
+    })
+}
+
+/// The pattern without its leading `/` if it is anchored at the source root.
+fn anchored(pattern: &str) -> Option<&str> {
+    let trimmed = pattern.trim_end_matches('/');
+    if trimmed.contains('/') {
+        Some(trimmed.trim_start_matches('/'))
+    } else {
+        None
+    }
+}
+
+/*
+ * Match a name or relative path against a glob pattern
+ * Supports `*` (any run of characters except `/`), `**` (any run of characters) and `?`
+ */
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    glob_match_from(&p, &n)
+}
+
+fn glob_match_from(p: &[char], n: &[char]) -> bool {
+    match p.first() {
+        None => n.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            (0..=n.len()).any(|i| glob_match_from(rest, &n[i..]))
+        }
+        Some('*') => {
+            let rest = &p[1..];
+            // A single star stops at the next path separator
+            let limit = n.iter().position(|&c| c == '/').unwrap_or(n.len());
+            (0..=limit).any(|i| glob_match_from(rest, &n[i..]))
+        }
+        Some('?') => !n.is_empty() && n[0] != '/' && glob_match_from(&p[1..], &n[1..]),
+        Some(&c) => n.first() == Some(&c) && glob_match_from(&p[1..], &n[1..]),
+    }
+}
+
+/*
+ * Convert a glob pattern into a regex for stow's --ignore option
+ * stow anchors the regex at the end of the path. Name patterns may match after any `/`;
+ * anchored patterns must match from the package root and cover everything below it.
+ */
+pub fn to_stow_regex(pattern: &str) -> String {
+    let (mut regex, glob) = match anchored(pattern) {
+        Some(anchored) => (String::from("^/?"), anchored),
+        None => (String::from("(^|/)"), pattern),
+    };
+
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '.' | '+' | '(' | ')' | '|' | '^' | '$' | '[' | ']' | '{' | '}' | '\\' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            _ => regex.push(c),
+        }
+    }
+
+    if anchored(pattern).is_some() {
+        regex.push_str("(/.*)?");
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.swp", ".init.lua.swp"));
+        assert!(glob_match("*~", "init.lua~"));
+        assert!(glob_match(".#*", ".#init.lua"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("*.swp", "init.lua"));
+        assert!(!glob_match("4913", "49130"));
+    }
+
+    #[test]
+    fn test_glob_match_paths() {
+        assert!(glob_match("nvim/*.lua", "nvim/init.lua"));
+        assert!(!glob_match("nvim/*.lua", "nvim/lua/plugins.lua"));
+        assert!(glob_match("nvim/**", "nvim/lua/plugins.lua"));
+    }
+
+    #[test]
+    fn test_default_patterns() {
+        let patterns = defaults();
+        assert!(is_ignored(Path::new("4913"), &patterns));
+        assert!(is_ignored(Path::new("nvim/.init.lua.swp"), &patterns));
+        assert!(!is_ignored(Path::new(".zshrc"), &patterns));
+    }
+
+    #[test]
+    fn test_only_patterns() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let source = temp.path();
+        fs::create_dir_all(source.join(".config/nvim")).unwrap();
+        fs::create_dir_all(source.join(".config/fish")).unwrap();
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join(".config/nvim/init.lua"), "").unwrap();
+        fs::write(source.join(".config/fish/config.fish"), "").unwrap();
+        fs::write(source.join(".config/fish/theme.lua"), "").unwrap();
+        fs::write(source.join("bin/tool"), "").unwrap();
+
+        let patterns = only_patterns(source, &[".config/nvim".to_string(), "*.lua".to_string()]);
+        assert_eq!(patterns, ["/.config/fish/config.fish", "/bin"]);
+        assert!(!is_ignored(Path::new(".config/nvim/init.lua"), &patterns));
+        assert!(is_ignored(Path::new("bin/tool"), &patterns));
+    }
+
+    #[test]
+    fn test_anchored_patterns() {
+        let patterns = vec!["nvim/lua".to_string()];
+        assert!(is_ignored(Path::new("nvim/lua"), &patterns));
+        assert!(is_ignored(Path::new("nvim/lua/plugins.lua"), &patterns));
+        assert!(!is_ignored(Path::new("nvim/init.lua"), &patterns));
+        assert!(!is_ignored(Path::new("other/nvim/lua"), &patterns));
+    }
+
+    #[test]
+    fn test_ignore_file_patterns() {
+        let temp = tempfile::TempDir::new().unwrap();
+        fs::write(temp.path().join(IGNORE_FILE), "# editor files\n*.swp\n\n  README.md  \n").unwrap();
+
+        let patterns = ignore_file_patterns(temp.path());
+        assert_eq!(patterns, vec!["/.amuignore", "*.swp", "README.md"]);
+        assert!(is_ignored(Path::new(".amuignore"), &patterns));
+        assert!(!is_ignored(Path::new("nvim/.amuignore"), &patterns));
+        assert!(ignore_file_patterns(&temp.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_to_stow_regex() {
+        assert_eq!(to_stow_regex("*.swp"), "(^|/)[^/]*\\.swp");
+        assert_eq!(to_stow_regex(".#*"), "(^|/)\\.#[^/]*");
+        assert_eq!(to_stow_regex("4913"), "(^|/)4913");
+        assert_eq!(to_stow_regex("nvim/lua"), "^/?nvim/lua(/.*)?");
+        assert_eq!(to_stow_regex("/nvim/**"), "^/?nvim/.*(/.*)?");
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::links;
+use crate::ownership;
+use crate::state;
+use crate::unreadable;
+use crate::yaml;
+
+const JOURNAL_FILE: &str = "links.yaml";
+
+/*
+ * Links amu created, per target and source
+ * Paths are target-relative. A link is recorded when a stow/restow makes it appear and
+ * dropped once it no longer points into its source, so links that were already there,
+ * or that another tool owns, are never claimed.
+ */
+type Journal = BTreeMap<PathBuf, BTreeMap<PathBuf, BTreeSet<PathBuf>>>;
+
+fn journal_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(JOURNAL_FILE))
+}
+
+/// The journal, empty if there is none yet; an unreadable one is an error rather than
+/// being taken as empty, so it is never overwritten with what one run recorded.
+fn load() -> Result<Journal> {
+    let path = journal_path()?;
+    if !path.exists() {
+        return Ok(Journal::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    yaml::from_str(&content).map_err(|e| DotlinkError::StateError(format!("{}: {}", path.display(), e)))
+}
+
+fn save(journal: &Journal) -> Result<()> {
+    let path = journal_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml::to_string(journal)
+        .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+/// Files of the source currently linked into the target, target-relative.
+pub fn linked_files(source: &Path, target: &Path, ignore: &[String]) -> BTreeSet<PathBuf> {
+    fn collect(base: &Path, current: &Path, target: &Path, ignore: &[String], linked: &mut BTreeSet<PathBuf>) {
+        let Some(entries) = unreadable::read_dir(current) else { return };
+        for entry in entries {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(base) else { continue };
+            if ignore::is_ignored(relative, ignore) {
+                continue;
+            }
+            // A folded directory is one link standing for everything below it
+            if points_to(&target.join(relative), &path) {
+                linked.insert(relative.to_path_buf());
+            } else if path.is_dir() && !path.is_symlink() {
+                collect(base, &path, target, ignore, linked);
+            }
+        }
+    }
+
+    let mut linked = BTreeSet::new();
+    collect(source, source, target, ignore, &mut linked);
+    linked
+}
+
+fn points_to(link: &Path, file: &Path) -> bool {
+    link.is_symlink()
+        && links::link_destination(link).is_some_and(|d| d == config::canonicalize_existing(file))
+}
+
+/*
+ * Bring the record for a pair up to date after a link operation
+ * `before` is what `linked_files` returned before the operation; links that appeared
+ * since are amu's. Recorded links that stopped pointing into the source are dropped.
+ */
+pub fn update(source: &Path, target: &Path, before: &BTreeSet<PathBuf>, ignore: &[String]) -> Result<()> {
+    let _lock = state::lock();
+    let mut journal = load()?;
+    let pairs = journal.entry(target.to_path_buf()).or_default();
+    let mut recorded = pairs.remove(source).unwrap_or_default();
+
+    recorded.retain(|relative| points_to(&target.join(relative), &source.join(relative)));
+    recorded.extend(linked_files(source, target, ignore).into_iter().filter(|r| !before.contains(r)));
+
+    if !recorded.is_empty() {
+        pairs.insert(source.to_path_buf(), recorded);
+    }
+    if pairs.is_empty() {
+        journal.remove(target);
+    }
+    save(&journal)
+}
+
+/// Links recorded for a pair, target-relative; `None` if amu never recorded linking it.
+pub fn recorded(source: &Path, target: &Path) -> Result<Option<BTreeSet<PathBuf>>> {
+    Ok(load()?.get(target).and_then(|pairs| pairs.get(source)).cloned())
+}
+
+/// Drop the record for a pair.
+pub fn forget(source: &Path, target: &Path) -> Result<()> {
+    let _lock = state::lock();
+    let mut journal = load()?;
+    let Some(pairs) = journal.get_mut(target) else { return Ok(()) };
+    if pairs.remove(source).is_none() {
+        return Ok(());
+    }
+    if pairs.is_empty() {
+        journal.remove(target);
+    }
+    save(&journal)
+}
@@ -0,0 +1,68 @@
+/*
+ * amu as a library
+ * Other tools can load a `Config`, check `status`, and add, remove or relink single pairs
+ * (`link`) the same way the `amu` binary does. Whole commands (hooks, conflict handling,
+ * checkpoints, text output) stay in the binary.
+ */
+
+pub mod adopt;
+pub mod audit;
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod checkpoint;
+pub mod config;
+pub mod conflict;
+pub mod daemon;
+pub mod diff;
+pub mod entries;
+pub mod error;
+pub mod exit;
+pub mod export;
+pub mod git;
+pub mod history;
+pub mod hooks;
+pub mod ignore;
+pub mod journal;
+pub mod link;
+pub mod linker;
+pub mod links;
+pub mod locks;
+pub mod log;
+pub mod migrate;
+pub mod mkdir;
+pub mod mounts;
+pub mod notify;
+pub mod orphans;
+pub mod ownership;
+pub mod parallel;
+pub mod paths;
+pub mod plan;
+pub mod precedence;
+pub mod progress;
+pub mod renames;
+pub mod report;
+pub mod retry;
+pub mod schema;
+pub mod serve;
+pub mod settings;
+pub mod setup;
+pub mod shellenv;
+pub mod state;
+pub mod status;
+pub mod stow;
+pub mod strict;
+pub mod style;
+pub mod table;
+pub mod template;
+pub mod temporary;
+pub mod theme;
+pub mod transaction;
+pub mod tree;
+pub mod unreadable;
+pub mod vars;
+pub mod watch;
+pub mod yaml;
+
+pub use config::Config;
+pub use error::{DotlinkError, Result};
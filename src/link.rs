@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, resolve_target, Config};
+use crate::diff;
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::journal;
+use crate::linker::Mode;
+use crate::links;
+use crate::renames;
+use crate::status;
+use crate::stow;
+use crate::strict;
+use crate::template;
+use crate::unreadable;
+
+/*
+ * Register and link (or copy, as `mode` says) a source into one target, directories whole if `fold`
+ * The config entry is rolled back if linking fails, so the caller can save regardless.
+ */
+pub fn add_to_target(config: &mut Config, source: &Path, target: &Path, mode: Mode, fold: bool, ignore: &[String]) -> Result<()> {
+    if !target.is_dir() {
+        return Err(DotlinkError::TargetNotFound(target.to_path_buf()));
+    }
+    if !links::is_writable(target) {
+        return Err(DotlinkError::TargetReadOnly(target.to_path_buf()));
+    }
+
+    config.add_source(target.to_path_buf(), source.to_path_buf())?;
+    config.set_mode(target, source, mode);
+    config.set_fold(target, source, fold);
+    if let Err(e) = stow::stow(config, source, target, ignore) {
+        let _ = config.remove_source(target, source);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// What unregistering a source from a target removed
+#[derive(Debug, Default)]
+pub struct Removed {
+    /// Whether the source's links (or copies) were removed; false when the source is gone
+    pub unlinked: bool,
+    /// Files rendered from the source's templates, deleted as unchanged
+    pub rendered: Vec<PathBuf>,
+}
+
+/*
+ * Unlink a source from one target and unregister it
+ * A source that no longer exists is only unregistered. The caller saves the config.
+ */
+pub fn remove_from_target(config: &mut Config, source: &Path, target: &Path) -> Result<Removed> {
+    if !config.get_sources(target).is_some_and(|sources| sources.iter().any(|s| s == source)) {
+        return Err(DotlinkError::NotRegistered { src: source.to_path_buf(), dest: target.to_path_buf() });
+    }
+    let unlinked = source.exists() && target.exists();
+    if unlinked {
+        stow::unstow(config, source, target, &ignore::patterns_for(config, target, source))?;
+    }
+    let rendered = template::remove(source, target, false)?;
+    config.remove_source(target, source)?;
+    if let Err(e) = journal::forget(source, target) {
+        strict::warn(format_args!("recorded links not forgotten: {}", e));
+    }
+    Ok(Removed { unlinked, rendered })
+}
+
+/*
+ * Resolve a (source, target) pair to the entry stored in config
+ * Stored paths may be canonical, tilde-expanded or written by hand, and the source or
+ * target may no longer exist. Any form of the given path matching any form of a stored
+ * path selects that entry; otherwise the given paths are returned resolved as usual.
+ * Without a target, `default_target:` is used, then the current directory.
+ */
+pub fn lookup_pair(config: &Config, source: &Path, target: Option<PathBuf>) -> Result<(PathBuf, PathBuf)> {
+    let target = target.or_else(|| config.default_target.clone());
+    let target = match resolve_target(target.clone()) {
+        Ok(t) => config.find_target(&t).unwrap_or(t),
+        Err(e) => target.as_deref().and_then(|t| config.find_target(t)).ok_or(e)?,
+    };
+    config.check_symlinked(&target)?;
+    let source = config.find_source(&target, source).unwrap_or_else(|| config::resolve_path(source));
+    Ok((source, target))
+}
+
+/*
+ * Restow a source after clearing links left dangling by files renamed or deleted in it
+ * Returns the renames detected, so both names are handled in a single pass
+ */
+pub fn restow_source(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<Vec<renames::Rename>> {
+    let stale = renames::find_stale_links(target, source);
+    let detected = renames::detect(source, &stale, ignore);
+
+    let relative: Vec<PathBuf> = stale.into_iter().map(|entry| entry.relative).collect();
+    links::cleanup_dangling_links(target, &relative);
+
+    stow::restow(config, source, target, ignore)?;
+    Ok(detected)
+}
+
+/*
+ * Remove real files in the target that are byte-identical to their source file
+ * so the following restow replaces them with links. Returns the adopted relative paths.
+ */
+pub fn adopt_identical_files(source: &Path, target: &Path, ignore: &[String], dry_run: bool) -> Result<Vec<String>> {
+    let mut adopted = Vec::new();
+    for file in status::scan_pair(source, target, ignore).real_files {
+        let target_file = target.join(&file);
+        if !matches!(diff::compare_files(&target_file, &source.join(&file)), diff::ContentDiff::Identical) {
+            continue;
+        }
+        if !dry_run {
+            std::fs::remove_file(&target_file)?;
+        }
+        adopted.push(file);
+    }
+    Ok(adopted)
+}
+
+/*
+ * Count linkable files in a source, honoring ignore patterns
+ */
+pub fn count_source_files(source: &Path, ignore: &[String]) -> usize {
+    list_source_files(source, ignore).len()
+}
+
+/*
+ * Files a source would link, relative to the source, in sorted order
+ */
+pub fn list_source_files(source: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    fn collect(base: &Path, current: &Path, ignore: &[String], files: &mut Vec<PathBuf>) {
+        if let Some(entries) = unreadable::read_dir(current) {
+            for entry in entries {
+                let path = entry.path();
+                let relative = path.strip_prefix(base).unwrap_or(&path);
+                if ignore::is_ignored(relative, ignore) {
+                    continue;
+                }
+                if path.is_dir() && !path.is_symlink() {
+                    collect(base, &path, ignore, files);
+                } else {
+                    files.push(relative.to_path_buf());
+                }
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    collect(source, source, ignore, &mut files);
+    files.sort();
+    files
+}
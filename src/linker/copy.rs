@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{DotlinkError, Result};
+use crate::links;
+use crate::ownership;
+use crate::plan::{Plan, PlanAction};
+use crate::state::{self, Action};
+use crate::template::fingerprint;
+use crate::yaml;
+
+use super::{native, platform, Mode};
+
+/// Record of the files copied or hard-linked into targets, in the state directory
+const RECORD_FILE: &str = "copies.yaml";
+
+/*
+ * Copy mode (`mode: copy`) and hard-link mode (`mode: hardlink`)
+ * The source's files are copied, or hard-linked, into the target instead of symlinked. A
+ * fingerprint of every file written is kept per target and source, so an update rewrites
+ * only the files whose source changed, a file edited or replaced in the target is reported
+ * rather than overwritten, and removing the source deletes only the files still as amu wrote
+ * them. A hard link is current while it is the source's file (same inode); an editor that
+ * saves by replacing the file on either side leaves two files, which `status` reports.
+ */
+type Record = BTreeMap<PathBuf, BTreeMap<PathBuf, BTreeMap<PathBuf, String>>>;
+
+#[derive(Default)]
+struct Operations {
+    write: Vec<PathBuf>,
+    delete: Vec<PathBuf>,
+    /// Fingerprints of the files that are the source's once applied
+    copies: BTreeMap<PathBuf, String>,
+    conflicts: Vec<String>,
+}
+
+fn record_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(RECORD_FILE))
+}
+
+fn load() -> Result<Record> {
+    match fs::read_to_string(record_path()?) {
+        Ok(content) => yaml::from_str(&content).map_err(DotlinkError::StateError),
+        Err(_) => Ok(Record::default()),
+    }
+}
+
+fn save(record: &Record) -> Result<()> {
+    let path = record_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml::to_string(record).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+/// Copies recorded for a pair: target-relative path and fingerprint.
+fn recorded(record: &Record, source: &Path, target: &Path) -> BTreeMap<PathBuf, String> {
+    record.get(target).and_then(|pairs| pairs.get(source)).cloned().unwrap_or_default()
+}
+
+pub fn file_fingerprint(path: &Path) -> Option<String> {
+    fs::read(path).ok().map(|content| fingerprint(&content))
+}
+
+/// Whether two paths are one file, hard-linked (on Windows: have the same content).
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    file_fingerprint(a).is_some_and(|found| Some(found) == file_fingerprint(b))
+}
+
+/// How many names the file has, where the platform tells.
+#[cfg(unix)]
+fn link_count(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|metadata| metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn link_count(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether hard links can join the two directories; true when it cannot be told.
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_filesystem(_a: &Path, _b: &Path) -> bool {
+    true
+}
+
+/// The directory above `relative` in the target that is a symlink: writing there would write elsewhere.
+fn linked_ancestor(target: &Path, relative: &Path) -> Option<PathBuf> {
+    relative.ancestors()
+        .skip(1)
+        .filter(|a| !a.as_os_str().is_empty())
+        .find(|a| target.join(a).is_symlink())
+        .map(Path::to_path_buf)
+}
+
+fn plan(mode: Mode, action: Action, source: &Path, target: &Path, ignore: &[String], copied: &BTreeMap<PathBuf, String>) -> Operations {
+    let mut plan = Operations::default();
+    let files = if action == Action::Unstow { Vec::new() } else { native::package_files(source, ignore) };
+    if mode == Mode::Hardlink && !files.is_empty() && !same_filesystem(source, target) {
+        plan.conflicts.push(format!("hard links need the source and target on one filesystem: {}", target.display()));
+        return plan;
+    }
+
+    for relative in &files {
+        let Some(wanted) = file_fingerprint(&source.join(relative)) else { continue };
+        if let Some(dir) = linked_ancestor(target, relative) {
+            plan.conflicts.push(format!("existing target is a link to a directory: {}", dir.display()));
+            continue;
+        }
+        let at = target.join(relative);
+        let conflict = match fs::symlink_metadata(&at) {
+            Err(_) => native::conflict_at(target, relative),
+            Ok(metadata) if metadata.is_file() => {
+                let found = file_fingerprint(&at);
+                let current = match mode {
+                    Mode::Hardlink => same_file(&at, &source.join(relative)),
+                    _ => found.as_ref() == Some(&wanted),
+                };
+                if current {
+                    plan.copies.insert(relative.clone(), wanted);
+                    continue;
+                }
+                match copied.get(relative) {
+                    // A hard link would change nothing there is to see
+                    _ if found.as_ref() == Some(&wanted) => None,
+                    // As amu wrote it: the source has changed since
+                    Some(written) if found.as_ref() == Some(written) => None,
+                    Some(_) if mode == Mode::Hardlink => Some(format!("hard link was replaced in the target: {}", relative.display())),
+                    Some(_) => Some(format!("copy was changed in the target: {}", relative.display())),
+                    None => native::conflict_at(target, relative),
+                }
+            }
+            // Linked before the pair switched to copying
+            Ok(_) if native::is_link_to(&at, &source.join(relative)) => None,
+            Ok(_) => native::conflict_at(target, relative),
+        };
+        match conflict {
+            Some(conflict) => plan.conflicts.push(conflict),
+            None => {
+                plan.write.push(relative.clone());
+                plan.copies.insert(relative.clone(), wanted);
+            }
+        }
+    }
+
+    // Files the source no longer provides go, unless edited since
+    for (relative, written) in copied {
+        if files.contains(relative) {
+            continue;
+        }
+        let at = target.join(relative);
+        let ours = same_file(&at, &source.join(relative)) || file_fingerprint(&at).as_ref() == Some(written);
+        if !at.is_symlink() && ours {
+            plan.delete.push(relative.clone());
+        } else {
+            crate::debug!("kept {}: changed since it was written", at.display());
+        }
+    }
+    plan
+}
+
+/// Copy or hard-link a source's files into the target again, or delete them, as the action asks.
+pub fn apply(mode: Mode, action: Action, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    let _lock = state::lock();
+    let mut record = load()?;
+    let plan = plan(mode, action, source, target, ignore, &recorded(&record, source, target));
+    if !plan.conflicts.is_empty() {
+        return Err(DotlinkError::LinkError(native::conflict_report(action, source, &plan.conflicts)));
+    }
+
+    for relative in &plan.delete {
+        let path = target.join(relative);
+        fs::remove_file(&path)?;
+        crate::debug!("deleted {}", path.display());
+        if let Some(parent) = path.parent() {
+            links::remove_empty_dirs_up_to(parent, target);
+        }
+    }
+    for relative in &plan.write {
+        let path = target.join(relative);
+        if path.is_symlink() {
+            platform::remove_link(&path)?;
+        } else if mode == Mode::Hardlink && path.is_file() {
+            fs::remove_file(&path)?;
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.is_dir()) {
+            platform::create_dirs(parent)?;
+            crate::debug!("created directory {}", parent.display());
+        }
+        if mode == Mode::Hardlink {
+            // The source's own file: its owner stays as it is
+            fs::hard_link(source.join(relative), &path)?;
+            crate::debug!("hard-linked {}", path.display());
+        } else {
+            fs::copy(source.join(relative), &path)?;
+            ownership::fix_written(&path);
+            crate::debug!("copied {}", path.display());
+        }
+    }
+
+    let pairs = record.entry(target.to_path_buf()).or_default();
+    if plan.copies.is_empty() {
+        pairs.remove(source);
+    } else {
+        pairs.insert(source.to_path_buf(), plan.copies);
+    }
+    if pairs.is_empty() {
+        record.remove(target);
+    }
+    save(&record)
+}
+
+/// Drop a file from the record once it is deleted other than by `apply` (`amu undo`, a rollback).
+pub fn forget(path: &Path) -> Result<()> {
+    let _lock = state::lock();
+    let mut record = load()?;
+    let before = record.clone();
+    for (target, pairs) in record.iter_mut() {
+        for copies in pairs.values_mut() {
+            copies.retain(|relative, _| target.join(relative) != path);
+        }
+        pairs.retain(|_, copies| !copies.is_empty());
+    }
+    record.retain(|_, pairs| !pairs.is_empty());
+    if record == before { Ok(()) } else { save(&record) }
+}
+
+/// Describe what `apply` would do; with a conflict, only the conflicts (nothing would be done).
+pub fn dry_run(mode: Mode, action: Action, source: &Path, target: &Path, ignore: &[String]) -> Plan {
+    let copied = load().map(|record| recorded(&record, source, target)).unwrap_or_default();
+    let operations = plan(mode, action, source, target, ignore, &copied);
+    let mut plan = Plan::default();
+    if !operations.conflicts.is_empty() {
+        for conflict in operations.conflicts {
+            plan.conflict(conflict);
+        }
+        return plan;
+    }
+    for path in operations.delete {
+        plan.actions.push(PlanAction::Delete { path });
+    }
+    for path in operations.write {
+        plan.actions.push(match mode {
+            Mode::Hardlink => PlanAction::HardLink { path },
+            _ => PlanAction::Copy { path },
+        });
+    }
+    plan
+}
+
+/// How the copies or hard links of a source in a target compare with it
+#[derive(Debug, Default)]
+pub struct Drift {
+    /// Files that are as the source's
+    pub copied: usize,
+    /// The others, each with what is wrong ("nvim/init.lua (source changed)")
+    pub paths: Vec<String>,
+}
+
+/// Compare every file of the source with its copy, or hard link, in the target.
+pub fn drift(mode: Mode, source: &Path, target: &Path, ignore: &[String]) -> Drift {
+    let copied = load().map(|record| recorded(&record, source, target)).unwrap_or_default();
+    compare(mode, source, target, ignore, &copied)
+}
+
+fn compare(mode: Mode, source: &Path, target: &Path, ignore: &[String], copied: &BTreeMap<PathBuf, String>) -> Drift {
+    let mut drift = Drift::default();
+    for relative in native::package_files(source, ignore) {
+        let Some(wanted) = file_fingerprint(&source.join(&relative)) else { continue };
+        let at = target.join(&relative);
+        let found = if at.is_symlink() { None } else { file_fingerprint(&at) };
+        let problem = match (found, copied.get(&relative)) {
+            _ if mode == Mode::Hardlink && same_file(&at, &source.join(&relative)) => {
+                drift.copied += 1;
+                continue;
+            }
+            (Some(found), _) if mode == Mode::Copy && found == wanted => {
+                drift.copied += 1;
+                continue;
+            }
+            (None, _) if at.is_symlink() && mode == Mode::Hardlink => "symlink, expected a hard link".to_string(),
+            (None, _) if at.is_symlink() => "link, expected a copy".to_string(),
+            (None, _) => "missing".to_string(),
+            // Saved by replacing the file on one side: the other keeps the old one
+            (Some(found), Some(written)) if mode == Mode::Hardlink => {
+                let side = if &found == written { "replaced in the source" } else { "replaced in the target" };
+                match link_count(&at) {
+                    Some(count) => format!("link count dropped to {}, {}", count, side),
+                    None => format!("no longer hard-linked, {}", side),
+                }
+            }
+            (Some(found), Some(written)) if &found == written => "source changed".to_string(),
+            (Some(_), Some(_)) => "changed in target".to_string(),
+            (Some(_), None) if mode == Mode::Hardlink => "not a hard link to the source".to_string(),
+            (Some(_), None) => "differs from source".to_string(),
+        };
+        drift.paths.push(format!("{} ({})", relative.display(), problem));
+    }
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy_update_and_remove() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("dotfiles");
+        let target = temp.path().join("share");
+        fs::create_dir_all(source.join("app")).unwrap();
+        fs::create_dir(&target).unwrap();
+        fs::write(source.join("app/settings.json"), "{}").unwrap();
+        fs::write(source.join("notes.txt"), "v1").unwrap();
+
+        let operations = plan(Mode::Copy, Action::Stow, &source, &target, &[], &BTreeMap::new());
+        assert_eq!(operations.write, vec![PathBuf::from("app/settings.json"), PathBuf::from("notes.txt")]);
+        fs::create_dir(target.join("app")).unwrap();
+        for relative in &operations.write {
+            fs::copy(source.join(relative), target.join(relative)).unwrap();
+        }
+        let copied = operations.copies;
+        assert_eq!(compare(Mode::Copy, &source, &target, &[], &copied).copied, 2);
+
+        // Only the changed file is recopied
+        fs::write(source.join("notes.txt"), "v2").unwrap();
+        let operations = plan(Mode::Copy, Action::Restow, &source, &target, &[], &copied);
+        assert_eq!(operations.write, vec![PathBuf::from("notes.txt")]);
+        assert_eq!(compare(Mode::Copy, &source, &target, &[], &copied).paths, vec!["notes.txt (source changed)"]);
+
+        // An edited copy is a conflict, and is kept on removal
+        fs::write(target.join("app/settings.json"), "{\"mine\": true}").unwrap();
+        let operations = plan(Mode::Copy, Action::Restow, &source, &target, &[], &copied);
+        assert_eq!(operations.conflicts, vec!["copy was changed in the target: app/settings.json"]);
+        let operations = plan(Mode::Copy, Action::Unstow, &source, &target, &[], &copied);
+        assert_eq!(operations.delete, vec![PathBuf::from("notes.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlink_replaced() {
+        let temp = TempDir::new().unwrap();
+        let source = temp.path().join("dotfiles");
+        let target = temp.path().join("home");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir(&target).unwrap();
+        fs::write(source.join("rc"), "v1").unwrap();
+        fs::write(source.join("other"), "v1").unwrap();
+
+        let operations = plan(Mode::Hardlink, Action::Stow, &source, &target, &[], &BTreeMap::new());
+        assert_eq!(operations.write, vec![PathBuf::from("other"), PathBuf::from("rc")]);
+        for relative in &operations.write {
+            fs::hard_link(source.join(relative), target.join(relative)).unwrap();
+        }
+        let linked = operations.copies;
+        assert_eq!(compare(Mode::Hardlink, &source, &target, &[], &linked).copied, 2);
+
+        // Edited in place, both names see the change
+        fs::write(target.join("other"), "v2").unwrap();
+        assert_eq!(compare(Mode::Hardlink, &source, &target, &[], &linked).copied, 2);
+
+        // Saved by replacing the source's file: the target keeps the old one
+        fs::remove_file(source.join("rc")).unwrap();
+        fs::write(source.join("rc"), "v2").unwrap();
+        let drift = compare(Mode::Hardlink, &source, &target, &[], &linked);
+        assert_eq!(drift.paths, vec!["rc (link count dropped to 1, replaced in the source)"]);
+        let operations = plan(Mode::Hardlink, Action::Restow, &source, &target, &[], &linked);
+        assert_eq!(operations.write, vec![PathBuf::from("rc")]);
+
+        // Replaced in the target: a conflict
+        fs::remove_file(target.join("rc")).unwrap();
+        fs::write(target.join("rc"), "mine").unwrap();
+        let operations = plan(Mode::Hardlink, Action::Restow, &source, &target, &[], &linked);
+        assert_eq!(operations.conflicts, vec!["hard link was replaced in the target: rc"]);
+    }
+}
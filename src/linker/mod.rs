@@ -0,0 +1,101 @@
+pub mod copy;
+pub mod native;
+pub mod platform;
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::stow;
+
+/*
+ * Link backends
+ * GNU stow is used when it is installed; otherwise the built-in linker creates the same
+ * links (per file, as with `--no-folding`, unless the pair folds), so amu also works where
+ * stow is unavailable, Windows included (see `platform`).
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// GNU stow if installed, otherwise the built-in linker
+    Auto,
+    /// Built-in linker
+    Native,
+    /// GNU stow
+    Stow,
+}
+
+static REQUESTED: OnceLock<Backend> = OnceLock::new();
+static RESOLVED: OnceLock<Backend> = OnceLock::new();
+
+/// Select the backend for this process (`--backend`), taking precedence over the config.
+pub fn set_backend(backend: Backend) {
+    let _ = REQUESTED.set(backend);
+}
+
+/// The backend in use: `--backend`, then `backend:` in the config, then auto-detection.
+/// Never returns `Auto`.
+pub fn backend() -> Backend {
+    *RESOLVED.get_or_init(|| {
+        let requested = REQUESTED.get().copied()
+            .or_else(|| Config::load().ok().and_then(|config| config.backend))
+            .unwrap_or(Backend::Auto);
+        detect(requested)
+    })
+}
+
+fn detect(requested: Backend) -> Backend {
+    match requested {
+        // stow needs perl and a Unix-like shell; on Windows the built-in linker is the default
+        Backend::Auto if cfg!(windows) => Backend::Native,
+        Backend::Auto if stow::check_installed().is_ok() => Backend::Stow,
+        Backend::Auto => Backend::Native,
+        explicit => explicit,
+    }
+}
+
+/// The backend for one pair: `--backend`, then the entry's own `backend:`, then as `backend()`.
+pub fn backend_for(config: &Config, source: &Path, target: &Path) -> Backend {
+    if REQUESTED.get().is_some() {
+        return backend();
+    }
+    match config.backend_of(target, source) {
+        Some(entry) => detect(entry),
+        None => backend(),
+    }
+}
+
+/*
+ * How a source's files get into the target
+ * Linked by default; `mode: copy` on an entry copies them instead, for targets that cannot
+ * hold symlinks (network shares, Android storage, synced folders), and `mode: hardlink`
+ * hard-links them, for tools that refuse to follow symlinks. See `copy`.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Symlinks into the source
+    #[default]
+    Link,
+    /// Copies of the source's files
+    Copy,
+    /// Hard links to the source's files (same filesystem only)
+    Hardlink,
+}
+
+/// Whether the pair's files are linked or copied, as the entry's `mode:` says.
+pub fn mode_for(config: &Config, source: &Path, target: &Path) -> Mode {
+    config.mode_of(target, source).unwrap_or_default()
+}
+
+/*
+ * Folding: linking a directory whole rather than file by file
+ * Off unless `fold:` in the config lists the (target, source) pair; `--fold` and `--no-fold`
+ * change the config for the pairs a command links.
+ */
+pub fn folds(config: &Config, source: &Path, target: &Path) -> bool {
+    config.folds(target, source)
+}
@@ -0,0 +1,374 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config;
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::links;
+use crate::plan::{Plan, PlanAction};
+use crate::state::Action;
+use crate::unreadable;
+
+use super::platform;
+
+/// Names GNU stow never links by default, skipped here too so both backends link the same files
+const STOW_DEFAULT_IGNORES: &[&str] = &[
+    "RCS", "*,v", "CVS", ".#*", ".cvsignore", ".svn", "_darcs", ".hg", ".git", ".gitignore", ".gitmodules",
+    "*~", "#*#", "/README*", "/LICENSE*", "/COPYING",
+];
+
+/*
+ * Operations needed to apply an action, computed before anything is touched
+ * Mirrors `stow --no-folding`: every file gets its own link and intermediate
+ * directories are real directories. With folding, a directory missing from the target is
+ * linked whole instead, as stow does without `--no-folding`. Any conflict aborts the whole
+ * action.
+ */
+#[derive(Default)]
+struct Operations {
+    unlink: Vec<PathBuf>,
+    link: Vec<(PathBuf, PathBuf)>,
+    conflicts: Vec<String>,
+}
+
+pub fn apply(action: Action, source: &Path, target: &Path, ignore: &[String], fold: bool) -> Result<()> {
+    let plan = plan(action, source, target, ignore, fold);
+    if !plan.conflicts.is_empty() {
+        return Err(DotlinkError::LinkError(conflict_report(action, source, &plan.conflicts)));
+    }
+
+    let unlink: Vec<PathBuf> = plan.unlink.iter().map(|relative| target.join(relative)).collect();
+    links::remove_links(&unlink, target);
+
+    for (relative, destination) in &plan.link {
+        let link = target.join(relative);
+        if let Some(parent) = link.parent().filter(|p| !p.is_dir()) {
+            platform::create_dirs(parent)?;
+            crate::debug!("created directory {}", parent.display());
+        }
+        platform::symlink(destination, &link)?;
+    }
+    Ok(())
+}
+
+/// Describe what `apply` would do; with a conflict, only the conflicts (nothing would be done).
+pub fn dry_run(action: Action, source: &Path, target: &Path, ignore: &[String], fold: bool) -> Plan {
+    let operations = plan(action, source, target, ignore, fold);
+    let mut plan = Plan::default();
+    if !operations.conflicts.is_empty() {
+        for conflict in operations.conflicts {
+            plan.conflict(conflict);
+        }
+        return plan;
+    }
+
+    for path in operations.unlink {
+        plan.actions.push(PlanAction::LinkRemove { path });
+    }
+    for (path, destination) in operations.link {
+        plan.actions.push(PlanAction::LinkCreate { path, destination });
+    }
+    plan
+}
+
+fn plan(action: Action, source: &Path, target: &Path, ignore: &[String], fold: bool) -> Operations {
+    let mut plan = Operations::default();
+    let folds = if fold && action != Action::Unstow { fold_points(action, source, target, ignore) } else { Vec::new() };
+    for dir in &folds {
+        let destination = relative_path(&config::canonicalize_existing(target.join(dir).parent().unwrap_or(target)), &source.join(dir));
+        plan.link.push((dir.clone(), destination));
+    }
+
+    for relative in package_files(source, ignore) {
+        let link = target.join(&relative);
+        let file = source.join(&relative);
+        // Provided by a directory link from now on; a restow drops the file's own link first
+        if folds.iter().any(|dir| relative.starts_with(dir)) {
+            if is_link_to(&link, &file) {
+                plan.unlink.push(relative);
+            }
+            continue;
+        }
+        // A directory linked whole into the source (stow's folding) already provides the file
+        let folded = folded_ancestor(source, target, &relative);
+        let owned = folded.is_some() || is_link_to(&link, &file);
+
+        match action {
+            Action::Unstow => match folded {
+                Some(dir) if !plan.unlink.contains(&dir) => plan.unlink.push(dir),
+                Some(_) => {}
+                None if owned => plan.unlink.push(relative),
+                None => {}
+            },
+            // Links already in place are left alone; a restow keeps them as they are
+            Action::Stow | Action::Restow if owned => {}
+            Action::Stow | Action::Restow => {
+                if let Some(conflict) = conflict_at(target, &relative) {
+                    plan.conflicts.push(conflict);
+                } else {
+                    let destination = relative_path(&config::canonicalize_existing(link.parent().unwrap_or(target)), &file);
+                    plan.link.push((relative, destination));
+                }
+            }
+        }
+    }
+    plan
+}
+
+/// Files to link, relative to the source, honoring ignore patterns and stow's defaults.
+pub(super) fn package_files(source: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    fn collect(base: &Path, current: &Path, ignore: &[String], files: &mut Vec<PathBuf>) {
+        let Some(entries) = unreadable::read_dir(current) else { return };
+        for entry in entries {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(base) else { continue };
+            if ignore::is_ignored(relative, ignore) || ignore::is_ignored(relative, &stow_default_ignores()) {
+                continue;
+            }
+            if path.is_dir() && !path.is_symlink() {
+                collect(base, &path, ignore, files);
+            } else {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    collect(source, source, ignore, &mut files);
+    files.sort();
+    files
+}
+
+/*
+ * Directories to link whole, source-relative and outermost only
+ * A directory qualifies when it is missing from the target, or for a restow when it holds
+ * nothing but the source's own links (so switching a pair to folding refolds it), and
+ * nothing under it is ignored: a directory link would expose ignored files.
+ */
+fn fold_points(action: Action, source: &Path, target: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    fn walk(action: Action, source: &Path, target: &Path, relative: &Path, ignore: &[String], points: &mut Vec<PathBuf>) {
+        let Some(entries) = unreadable::read_dir(&source.join(relative)) else { return };
+        for entry in entries {
+            let path = entry.path();
+            let child = relative.join(entry.file_name());
+            if !path.is_dir() || path.is_symlink() || is_skipped(&child, ignore) {
+                continue;
+            }
+            let at = target.join(&child);
+            if is_link_to(&at, &path) {
+                continue;
+            }
+            let missing = fs::symlink_metadata(&at).is_err();
+            let refold = action == Action::Restow && at.is_dir() && !at.is_symlink() && only_links_into(&at, &path);
+            if (missing || refold) && nothing_skipped(source, &child, ignore) {
+                points.push(child);
+            } else if at.is_dir() {
+                walk(action, source, target, &child, ignore, points);
+            }
+        }
+    }
+
+    let mut points = Vec::new();
+    walk(action, source, target, Path::new(""), ignore, &mut points);
+    points.sort();
+    points
+}
+
+fn is_skipped(relative: &Path, ignore: &[String]) -> bool {
+    ignore::is_ignored(relative, ignore) || ignore::is_ignored(relative, &stow_default_ignores())
+}
+
+/// Whether no entry under the source directory is ignored.
+fn nothing_skipped(source: &Path, relative: &Path, ignore: &[String]) -> bool {
+    let Ok(entries) = fs::read_dir(source.join(relative)) else { return false };
+    entries.flatten().all(|entry| {
+        let child = relative.join(entry.file_name());
+        let path = entry.path();
+        !is_skipped(&child, ignore) && (!path.is_dir() || path.is_symlink() || nothing_skipped(source, &child, ignore))
+    })
+}
+
+/// Whether a target directory holds only links to the matching files of `dir`, at any depth.
+fn only_links_into(at: &Path, dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(at) else { return false };
+    entries.flatten().all(|entry| {
+        let path = entry.path();
+        let counterpart = dir.join(entry.file_name());
+        if path.is_symlink() {
+            is_link_to(&path, &counterpart)
+        } else {
+            path.is_dir() && only_links_into(&path, &counterpart)
+        }
+    })
+}
+
+fn stow_default_ignores() -> Vec<String> {
+    STOW_DEFAULT_IGNORES.iter().map(|p| p.to_string()).collect()
+}
+
+/// Whether `link` is a symlink resolving to `file`.
+pub(super) fn is_link_to(link: &Path, file: &Path) -> bool {
+    link.is_symlink()
+        && links::link_destination(link).is_some_and(|destination| {
+            destination == config::canonicalize_existing(file)
+        })
+}
+
+/// The directory above `relative` that is a link to the same directory in the source, if any.
+fn folded_ancestor(source: &Path, target: &Path, relative: &Path) -> Option<PathBuf> {
+    relative.ancestors()
+        .skip(1)
+        .filter(|a| !a.as_os_str().is_empty())
+        .find(|a| is_link_to(&target.join(a), &source.join(a)))
+        .map(Path::to_path_buf)
+}
+
+/// Why a link cannot be created at `relative`, in stow's wording.
+pub(super) fn conflict_at(target: &Path, relative: &Path) -> Option<String> {
+    // A file (or a link to one) where a parent directory is needed
+    for ancestor in relative.ancestors().skip(1).filter(|a| !a.as_os_str().is_empty()) {
+        let path = target.join(ancestor);
+        if fs::symlink_metadata(&path).is_ok() && !path.is_dir() {
+            return Some(format!("existing target is neither a link nor a directory: {}", ancestor.display()));
+        }
+    }
+
+    let path = target.join(relative);
+    let metadata = fs::symlink_metadata(&path).ok()?;
+    Some(if metadata.file_type().is_symlink() {
+        format!("existing target is not owned by stow: {}", relative.display())
+    } else if metadata.is_dir() {
+        format!("existing target is a directory: {}", relative.display())
+    } else {
+        format!("existing target is neither a link nor a directory: {}", relative.display())
+    })
+}
+
+pub(super) fn conflict_report(action: Action, source: &Path, conflicts: &[String]) -> String {
+    let verb = if action == Action::Unstow { "unstowing" } else { "stowing" };
+    let name = source.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let mut report = format!("WARNING! {} {} would cause conflicts:\n", verb, name);
+    for conflict in conflicts {
+        report.push_str(&format!("  * {}\n", conflict));
+    }
+    report.push_str("All operations aborted.\n");
+    report
+}
+
+/// Path to `to` relative to the directory `from` (both absolute).
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    // Different drives on Windows: there is no relative path
+    if common == 0 {
+        return to.iter().collect();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/home/user/.config/nvim"), Path::new("/home/user/dotfiles/nvim/init.lua")),
+            PathBuf::from("../../dotfiles/nvim/init.lua")
+        );
+        assert_eq!(relative_path(Path::new("/a"), Path::new("/a/b")), PathBuf::from("b"));
+    }
+
+    #[test]
+    fn test_stow_and_unstow() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("dotfiles");
+        let target = root.join("home");
+        fs::create_dir_all(source.join(".config/nvim")).unwrap();
+        fs::create_dir_all(source.join(".git")).unwrap();
+        fs::create_dir(&target).unwrap();
+        fs::write(source.join(".config/nvim/init.lua"), "").unwrap();
+        fs::write(source.join(".git/HEAD"), "").unwrap();
+        fs::write(source.join("README.md"), "").unwrap();
+
+        apply(Action::Stow, &source, &target, &[], false).unwrap();
+        let link = target.join(".config/nvim/init.lua");
+        assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("../../../dotfiles/.config/nvim/init.lua"));
+        assert!(!target.join(".config").is_symlink());
+        assert!(!target.join(".git").exists());
+        assert!(!target.join("README.md").exists());
+
+        // Stowing again is a no-op, and a foreign file is a conflict
+        assert!(dry_run(Action::Stow, &source, &target, &[], false).is_empty());
+        fs::write(source.join(".zshrc"), "").unwrap();
+        fs::write(target.join(".zshrc"), "mine").unwrap();
+        let error = apply(Action::Restow, &source, &target, &[], false).unwrap_err().to_string();
+        assert!(error.contains("existing target is neither a link nor a directory: .zshrc"));
+
+        apply(Action::Unstow, &source, &target, &[], false).unwrap();
+        assert!(!target.join(".config").exists());
+        assert!(target.join(".zshrc").exists());
+    }
+
+    #[test]
+    fn test_folded_directory() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("dotfiles");
+        let target = root.join("home");
+        fs::create_dir_all(source.join("nvim/lua")).unwrap();
+        fs::create_dir(&target).unwrap();
+        fs::write(source.join("nvim/init.lua"), "").unwrap();
+        fs::write(source.join("nvim/lua/plugins.lua"), "").unwrap();
+        platform::symlink(&source.join("nvim"), &target.join("nvim")).unwrap();
+
+        // The directory link provides both files; unstowing removes it once
+        assert!(dry_run(Action::Stow, &source, &target, &[], false).is_empty());
+        assert_eq!(dry_run(Action::Unstow, &source, &target, &[], false).actions, vec![PlanAction::LinkRemove { path: "nvim".into() }]);
+        apply(Action::Unstow, &source, &target, &[], false).unwrap();
+        assert!(!target.join("nvim").exists());
+    }
+
+    #[test]
+    fn test_fold() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("dotfiles");
+        let target = root.join("home");
+        fs::create_dir_all(source.join(".config/nvim/lua")).unwrap();
+        fs::create_dir_all(source.join(".config/git")).unwrap();
+        fs::create_dir_all(target.join(".config")).unwrap();
+        fs::write(source.join(".config/nvim/init.lua"), "").unwrap();
+        fs::write(source.join(".config/nvim/lua/plugins.lua"), "").unwrap();
+        fs::write(source.join(".config/git/config"), "").unwrap();
+        fs::write(source.join(".config/git/secrets"), "").unwrap();
+        let ignore = [".config/git/secrets".to_string()];
+
+        // `.config` exists, so its subdirectories are linked whole, unless something in them is ignored
+        apply(Action::Stow, &source, &target, &ignore, true).unwrap();
+        assert_eq!(fs::read_link(target.join(".config/nvim")).unwrap(), PathBuf::from("../../dotfiles/.config/nvim"));
+        assert!(!target.join(".config/git").is_symlink());
+        assert!(target.join(".config/git/config").is_symlink());
+        assert!(dry_run(Action::Stow, &source, &target, &ignore, true).is_empty());
+
+        // A restow with folding refolds a directory linked file by file
+        apply(Action::Unstow, &source, &target, &ignore, true).unwrap();
+        apply(Action::Stow, &source, &target, &ignore, false).unwrap();
+        assert!(target.join(".config/nvim/init.lua").is_symlink());
+        apply(Action::Restow, &source, &target, &ignore, true).unwrap();
+        assert!(target.join(".config/nvim").is_symlink());
+        assert!(target.join(".config/nvim/lua/plugins.lua").exists());
+    }
+}
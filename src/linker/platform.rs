@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::history::{self, Change};
+
+/*
+ * Creating and removing links on each platform
+ * Unix has one kind of symlink. Windows distinguishes file and directory symlinks and
+ * only lets privileged processes (or Developer Mode) create them; directories fall back
+ * to junctions, which any user can create but which must point at an absolute path.
+ */
+
+/// Create `link` pointing at `destination` (absolute, or relative to the link's directory).
+pub fn symlink(destination: &Path, link: &Path) -> io::Result<()> {
+    create_link(destination, link)?;
+    history::record(Change::LinkCreated { link: link.to_path_buf() });
+    Ok(())
+}
+
+/// Create `dir` with any missing parents, recording each directory created.
+pub fn create_dirs(dir: &Path) -> io::Result<()> {
+    let missing: Vec<&Path> = dir.ancestors().take_while(|d| !d.is_dir()).collect();
+    fs::create_dir_all(dir)?;
+    for created in missing.into_iter().rev() {
+        history::record(Change::DirCreated { path: created.to_path_buf() });
+    }
+    Ok(())
+}
+
+/// Remove a link without touching what it points to.
+pub fn remove_link(link: &Path) -> io::Result<()> {
+    let destination = if history::is_recording() { fs::read_link(link).ok() } else { None };
+    delete_link(link)?;
+    if let Some(destination) = destination {
+        history::record(Change::LinkRemoved { link: link.to_path_buf(), destination });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_link(destination: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(destination, link)
+}
+
+#[cfg(windows)]
+fn create_link(destination: &Path, link: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let resolved = match link.parent() {
+        Some(parent) if destination.is_relative() => parent.join(destination),
+        _ => destination.to_path_buf(),
+    };
+    if resolved.is_dir() {
+        match symlink_dir(destination, link) {
+            Err(e) if is_privilege_error(&e) => junction(&resolved, link),
+            result => result,
+        }
+    } else {
+        symlink_file(destination, link).map_err(|e| {
+            if is_privilege_error(&e) {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "creating symbolic links requires Developer Mode or an elevated prompt on Windows",
+                )
+            } else {
+                e
+            }
+        })
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_link(_destination: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported on this platform"))
+}
+
+/// ERROR_PRIVILEGE_NOT_HELD: the process lacks SeCreateSymbolicLinkPrivilege
+#[cfg(windows)]
+fn is_privilege_error(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(1314)
+}
+
+/// Directory junction via `mklink /J`, which needs no privilege.
+#[cfg(windows)]
+fn junction(destination: &Path, link: &Path) -> io::Result<()> {
+    let output = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(destination)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(io::Error::other(format!("mklink /J failed: {}", stderr.trim())))
+    }
+}
+
+#[cfg(not(windows))]
+fn delete_link(link: &Path) -> io::Result<()> {
+    fs::remove_file(link)
+}
+
+/// Directory symlinks and junctions are removed as directories, even when dangling.
+#[cfg(windows)]
+fn delete_link(link: &Path) -> io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+    if fs::symlink_metadata(link)?.file_attributes() & FILE_ATTRIBUTE_DIRECTORY != 0 {
+        fs::remove_dir(link)
+    } else {
+        fs::remove_file(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_symlink_and_remove_link() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), "x").unwrap();
+
+        symlink(Path::new("dir/file"), &temp.path().join("file-link")).unwrap();
+        symlink(&dir, &temp.path().join("dir-link")).unwrap();
+        assert_eq!(fs::read_to_string(temp.path().join("file-link")).unwrap(), "x");
+        assert!(temp.path().join("dir-link").join("file").exists());
+
+        remove_link(&temp.path().join("file-link")).unwrap();
+        remove_link(&temp.path().join("dir-link")).unwrap();
+        assert!(!temp.path().join("dir-link").exists());
+        assert!(dir.join("file").exists());
+    }
+}
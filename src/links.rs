@@ -1,6 +1,10 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::config;
+use crate::linker::platform;
+use crate::unreadable;
+
 /// Scan target directory for all dangling symlinks.
 /// Returns target-relative paths of dangling links.
 pub fn find_dangling_links(target: &Path) -> Vec<PathBuf> {
@@ -11,12 +15,9 @@ pub fn find_dangling_links(target: &Path) -> Vec<PathBuf> {
 }
 
 fn find_dangling_recursive(target_base: &Path, current: &Path, dangling: &mut Vec<PathBuf>) {
-    let entries = match fs::read_dir(current) {
-        Ok(e) => e,
-        Err(_) => return,
-    };
+    let Some(entries) = unreadable::read_dir(current) else { return };
 
-    for entry in entries.flatten() {
+    for entry in entries {
         let path = entry.path();
         if path.is_symlink() {
             // Dangling: symlink exists but target does not
@@ -39,7 +40,7 @@ pub fn cleanup_dangling_links(target: &Path, recorded: &[PathBuf]) -> usize {
     for relative in recorded {
         let full_path = target.join(relative);
         if full_path.is_symlink() && !full_path.exists()
-            && fs::remove_file(&full_path).is_ok()
+            && platform::remove_link(&full_path).is_ok()
         {
             removed += 1;
             if let Some(parent) = full_path.parent() {
@@ -61,7 +62,8 @@ pub fn cleanup_dangling_links(target: &Path, recorded: &[PathBuf]) -> usize {
     removed
 }
 
-fn remove_empty_dirs_up_to(dir: &Path, stop_at: &Path) {
+/// Remove `dir` and its ancestors below `stop_at` while they are empty.
+pub fn remove_empty_dirs_up_to(dir: &Path, stop_at: &Path) {
     let mut current = dir.to_path_buf();
     while current != stop_at && current.starts_with(stop_at) {
         match fs::read_dir(&current) {
@@ -81,3 +83,101 @@ fn remove_empty_dirs_up_to(dir: &Path, stop_at: &Path) {
         }
     }
 }
+
+/// Check whether entries can be created in a directory.
+/// Catches both permission bits and read-only mounts.
+#[cfg(unix)]
+pub fn is_writable(dir: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(c_path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_writable(dir: &Path) -> bool {
+    fs::metadata(dir).map(|m| !m.permissions().readonly()).unwrap_or(false)
+}
+
+/// A symlink found while scanning a directory.
+pub struct SymlinkEntry {
+    /// Path relative to the scanned directory
+    pub relative: PathBuf,
+    /// Absolute destination the link points to
+    pub destination: PathBuf,
+    /// True if the destination does not exist
+    pub dangling: bool,
+}
+
+/// Scan a directory for every symlink, regardless of where it points.
+/// Symlinked directories are reported but not descended into.
+pub fn find_all_symlinks(dir: &Path) -> Vec<SymlinkEntry> {
+    let mut found = Vec::new();
+    find_all_symlinks_recursive(dir, dir, &mut found);
+    found.sort_by(|a, b| a.relative.cmp(&b.relative));
+    found
+}
+
+fn find_all_symlinks_recursive(base: &Path, current: &Path, found: &mut Vec<SymlinkEntry>) {
+    let Some(entries) = unreadable::read_dir(current) else { return };
+
+    for entry in entries {
+        let path = entry.path();
+        if path.is_symlink() {
+            let Some(destination) = link_destination(&path) else { continue };
+            let dangling = !path.exists();
+            if let Ok(relative) = path.strip_prefix(base) {
+                found.push(SymlinkEntry {
+                    relative: relative.to_path_buf(),
+                    destination,
+                    dangling,
+                });
+            }
+        } else if path.is_dir() {
+            find_all_symlinks_recursive(base, &path, found);
+        }
+    }
+}
+
+/// Absolute destination of a symlink, resolved as far as it exists.
+pub fn link_destination(link: &Path) -> Option<PathBuf> {
+    let link_target = fs::read_link(link).ok()?;
+    let joined = if link_target.is_absolute() {
+        link_target
+    } else {
+        link.parent()?.join(link_target)
+    };
+    Some(config::resolve_path(&joined))
+}
+
+/// Symlinks at or below `path` that point into `source`.
+pub fn links_into(path: &Path, source: &Path) -> Vec<PathBuf> {
+    if path.is_symlink() {
+        return match link_destination(path) {
+            Some(destination) if destination.starts_with(source) => vec![path.to_path_buf()],
+            _ => Vec::new(),
+        };
+    }
+    find_all_symlinks(path)
+        .into_iter()
+        .filter(|entry| entry.destination.starts_with(source))
+        .map(|entry| path.join(entry.relative))
+        .collect()
+}
+
+/// Remove the given symlinks and any directories left empty, up to `stop_at`. Returns count removed.
+pub fn remove_links(links: &[PathBuf], stop_at: &Path) -> usize {
+    let mut removed = 0;
+    for link in links {
+        if link.is_symlink() && platform::remove_link(link).is_ok() {
+            removed += 1;
+            if let Some(parent) = link.parent() {
+                remove_empty_dirs_up_to(parent, stop_at);
+            }
+        }
+    }
+    removed
+}
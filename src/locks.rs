@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+use crate::config::abbreviate_path;
+use crate::error::{DotlinkError, Result};
+use crate::state;
+use crate::template;
+
+const LOCKS_DIR: &str = "locks";
+
+/*
+ * Per-target locks shared between amu processes
+ * `amu watch`, the daemon and commands run by hand can change the same target at once;
+ * whoever changes a target holds its lock file in the state directory, so a restow the
+ * watcher starts waits for a running `amu update` and the other way round. Files are named
+ * by a hash of the target, as a path can be longer than a file name may be; the file holds
+ * the target's path, and while it is held the holder's pid and command line, so a waiting
+ * process can say whom it waits for. Locks are advisory (`flock`) and released by the
+ * kernel when a process dies, so a crash never leaves a target locked.
+ * Within a process, threads take turns on a target too; the thread holding it may take it
+ * again, so a command holding a target for its whole run can stow into it without waiting
+ * on itself.
+ */
+static HELD: Mutex<BTreeMap<PathBuf, Held>> = Mutex::new(BTreeMap::new());
+static RELEASED: Condvar = Condvar::new();
+static NO_WAIT: AtomicBool = AtomicBool::new(false);
+
+struct Held {
+    owner: ThreadId,
+    count: usize,
+    /// Keeps the lock; closing it releases the lock. None while the owner waits for it.
+    file: Option<File>,
+}
+
+fn held() -> MutexGuard<'static, BTreeMap<PathBuf, Held>> {
+    HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Fail instead of waiting when another process holds a target (`--no-wait`).
+pub fn set_no_wait(no_wait: bool) {
+    NO_WAIT.store(no_wait, Ordering::Relaxed);
+}
+
+/// A target held by this thread until dropped
+#[derive(Debug)]
+pub struct TargetLock {
+    target: PathBuf,
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        let mut held = held();
+        let Some(entry) = held.get_mut(&self.target) else { return };
+        entry.count -= 1;
+        if entry.count > 0 {
+            return;
+        }
+        // Cleared while still held, so nobody later reads a holder that has gone
+        if let Some(file) = entry.file.as_mut() {
+            let _ = write_record(file, &self.target, None);
+        }
+        held.remove(&self.target);
+        RELEASED.notify_all();
+    }
+}
+
+/// Hold `target` against other amu processes and threads, waiting for the one holding it to finish.
+pub fn acquire(target: &Path) -> Result<TargetLock> {
+    let target = target.to_path_buf();
+    let me = thread::current().id();
+    {
+        let mut held = held();
+        loop {
+            match held.get_mut(&target) {
+                Some(entry) if entry.owner == me => {
+                    entry.count += 1;
+                    return Ok(TargetLock { target });
+                }
+                Some(_) => held = RELEASED.wait(held).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                None => break,
+            }
+        }
+        // Reserved for this thread while it waits for other processes
+        held.insert(target.clone(), Held { owner: me, count: 1, file: None });
+    }
+
+    match lock_file(&target) {
+        Ok(file) => {
+            if let Some(entry) = held().get_mut(&target) {
+                entry.file = Some(file);
+            }
+            Ok(TargetLock { target })
+        }
+        Err(e) => {
+            held().remove(&target);
+            RELEASED.notify_all();
+            Err(e)
+        }
+    }
+}
+
+/// Open and lock the target's file, then record this process as its holder.
+fn lock_file(target: &Path) -> Result<File> {
+    let path = lock_path(target)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+    if !try_lock(&file)? {
+        let holder = holder(&path).unwrap_or_else(|| "another amu process".to_string());
+        if NO_WAIT.load(Ordering::Relaxed) {
+            return Err(DotlinkError::TargetBusy { target: target.to_path_buf(), holder });
+        }
+        crate::warn!("{} is being changed by {}; waiting for it to finish", abbreviate_path(target), holder);
+        lock(&file)?;
+    }
+    let me = format!("`{}` (pid {})", command_line(), std::process::id());
+    write_record(&mut file, target, Some(&me))?;
+    Ok(file)
+}
+
+/*
+ * Whether another process or thread holds `target` right now
+ * Decided from the holder recorded in the file, without touching the lock itself, so
+ * asking never makes a `--no-wait` caller find the target busy.
+ */
+pub fn is_busy(target: &Path) -> bool {
+    if let Some(entry) = held().get(target) {
+        return entry.owner != thread::current().id();
+    }
+    let Ok(path) = lock_path(target) else { return false };
+    let Ok(text) = fs::read_to_string(path) else { return false };
+    text.lines().nth(1).and_then(holder_pid).is_some_and(is_running)
+}
+
+fn lock_path(target: &Path) -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(LOCKS_DIR).join(file_name(target)))
+}
+
+/// Lock file name for a target: a hash of its path, which fits any file name limit.
+fn file_name(target: &Path) -> String {
+    format!("{}.lock", template::fingerprint(target.as_os_str().as_encoded_bytes()))
+}
+
+/// The target's path, then the holder (if any) on a line of its own.
+fn write_record(file: &mut File, target: &Path, holder: Option<&str>) -> Result<()> {
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", target.display())?;
+    if let Some(holder) = holder {
+        writeln!(file, "{}", holder)?;
+    }
+    Ok(())
+}
+
+/// The holder recorded in a lock file, if it names one.
+fn holder(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    text.lines().nth(1).map(str::trim).filter(|holder| !holder.is_empty()).map(String::from)
+}
+
+/// The pid in "`amu update` (pid 4242)".
+fn holder_pid(holder: &str) -> Option<u32> {
+    holder.rsplit_once("(pid ")?.1.strip_suffix(')')?.parse().ok()
+}
+
+fn command_line() -> String {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    format!("amu {}", args.join(" ")).trim_end().to_string()
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) { Ok(false) } else { Err(e.into()) }
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e.into());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Without flock only the threads of this process are coordinated
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let deep = PathBuf::from(format!("/{}", "nested/".repeat(60)));
+        assert_eq!(file_name(&deep).len(), 21);
+        assert_ne!(file_name(Path::new("/a/b")), file_name(Path::new("/a/c")));
+    }
+
+    #[test]
+    fn test_holder_pid() {
+        assert_eq!(holder_pid("`amu update --all` (pid 4242)"), Some(4242));
+        assert_eq!(holder_pid("another amu process"), None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+ * Diagnostics on stderr and in a log file (`-v`, `-vv`, `-q`, `--log-file`)
+ * Warnings and errors print as before ("Warning: ..."); info and debug records are only
+ * shown with -v and -vv, prefixed with a UTC timestamp. Debug records cover every
+ * filesystem change and every external command a run makes. A log file receives every
+ * record down to debug, whatever the verbosity, so a failed run can be examined later.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    /// The level for `-q` and the number of `-v` flags.
+    pub fn from_flags(verbose: u8, quiet: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Level::Error,
+            (false, 0) => Level::Warn,
+            (false, 1) => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Set the stderr level and start appending records to `file`, if given.
+pub fn init(level: Level, file: Option<&Path>) -> std::io::Result<()> {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    if let Some(path) = file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        *LOG_FILE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(file);
+    }
+    Ok(())
+}
+
+/// Whether records at `level` go anywhere: to stderr, or to a log file.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed) || LOG_FILE.lock().is_ok_and(|file| file.is_some())
+}
+
+/// Whether -v was given.
+pub fn is_verbose() -> bool {
+    LEVEL.load(Ordering::Relaxed) >= Level::Info as u8
+}
+
+pub fn write(level: Level, args: fmt::Arguments) {
+    let time = timestamp(SystemTime::now());
+    if level as u8 <= LEVEL.load(Ordering::Relaxed) {
+        match level {
+            Level::Error => eprintln!("Error: {}", args),
+            Level::Warn => eprintln!("Warning: {}", args),
+            _ => eprintln!("{} {} {}", time, level.label(), args),
+        }
+    }
+    let mut file = LOG_FILE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(file) = file.as_mut() {
+        let _ = writeln!(file, "{} {} {}", time, level.label(), args);
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+        }
+    };
+}
+
+/// A command line as it would be typed: program and arguments, quoted where needed.
+pub fn command_line(command: &std::process::Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c)) {
+                arg.into_owned()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// "2026-10-17T08:30:05.123Z"
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, rem / 60 % 60, rem % 60, since.subsec_millis()
+    )
+}
+
+/// Today's date in UTC, "2026-10-17".
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Year, month and day of a count of days since 1970-01-01 (proleptic Gregorian calendar).
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Count of days since 1970-01-01 of a date; the inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(timestamp(time), "2024-02-29T12:34:56.789Z");
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        for days in [-719_468, -1, 0, 59, 19_782, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut command = std::process::Command::new("stow");
+        command.args(["--ignore=\\.git", "-t", "/home/me/my dir"]);
+        assert_eq!(command_line(&command), "stow '--ignore=\\.git' -t '/home/me/my dir'");
+    }
+}
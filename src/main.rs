@@ -1,892 +1,5218 @@
 mod cli;
-mod config;
-mod error;
-mod links;
-mod stow;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches};
 
-use cli::{Cli, Commands};
-use config::{normalize_path, resolve_target, Config};
-use error::{DotlinkError, Result};
+use amu::checkpoint::Checkpoint;
+use amu::config::{abbreviate_path, normalize_path, resolve_target, Config};
+use amu::conflict::OnConflict;
+use amu::error::{DotlinkError, Result};
+use amu::hooks::Hook;
+use amu::link::{add_to_target, adopt_identical_files, count_source_files, list_source_files, lookup_pair, remove_from_target, restow_source};
+use amu::exit::Exit;
+use amu::linker::Mode;
+use amu::temporary::Lifetime;
+use amu::report::Format;
+use amu::{debug, error, say, warn};
+use amu::plan::{Plan, PlanAction};
+use amu::table::Table;
+use amu::status::{check_source_status, scan_pair, source_health, SourceStatus};
+use amu::{
+    adopt, audit, batch, bench, cache, config, conflict, daemon, diff, entries, export, git, history, hooks, ignore, journal,
+    linker, links, locks, log, migrate, mkdir, mounts, notify, orphans, ownership, parallel, precedence, progress, renames, report, retry, schema,
+    serve, settings, setup, shellenv, state, status, stow, strict, style, table, template, temporary, theme, transaction, tree, unreadable, vars,
+    watch,
+};
+use cli::{BackupAction, Cli, Commands, ConfigAction, MediaAction, PriorityAction, StatsSort, TemplateAction, VarsAction};
 
 fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {e}");
-        std::process::exit(1);
+    let exit = run().and_then(|exit| unreadable::report().map(|()| exit)).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit_as(Exit::Errors)
+    });
+    if exit != Exit::Ok {
+        exit_as(exit);
     }
+    if strict::warnings() > 0 && strict::is_strict() {
+        error!("{} warning(s) in strict mode", strict::warnings());
+        exit_as(Exit::Errors);
+    }
+    save_history();
+    progress::emit(progress::Event::Finished { success: true });
 }
 
-fn run() -> Result<()> {
-    stow::check_installed()?;
+/// Exit with a code of the contract (see `exit`), closing the progress stream first.
+fn exit_as(exit: Exit) -> ! {
+    exit_with(exit.code(), exit >= Exit::Errors)
+}
 
-    let cli = Cli::parse();
+/// Exit with `code`; a batch ending here is rolled back if `failed`, and kept otherwise.
+fn exit_with(mut code: i32, mut failed: bool) -> ! {
+    // Commands that exit early still report the directories their scans skipped
+    if let Err(e) = unreadable::report() {
+        error!("{}", e);
+        code = code.max(Exit::Errors.code());
+        failed = true;
+    }
+    if batch::is_running() {
+        if failed {
+            roll_back_batch();
+        } else {
+            keep_batch();
+        }
+    }
+    save_history();
+    progress::emit(progress::Event::Finished { success: false });
+    std::process::exit(code);
+}
 
-    match cli.command {
-        Commands::Add { source, target, dry_run } => cmd_add(source, target, dry_run),
-        Commands::Remove { source, target, dry_run } => cmd_remove(source, target, dry_run),
-        Commands::Update { target, all, dry_run } => cmd_update(target, all, dry_run),
-        Commands::Sync { source, dry_run } => cmd_sync(source, dry_run),
-        Commands::Restore { target, all, dry_run } => cmd_restore(target, all, dry_run),
-        Commands::List { target, all, flat, verbose } => cmd_list(target, all, !flat, verbose),
-        Commands::Status { target, all, flat, json } => cmd_status(target, all, !flat, json),
-        Commands::Clear { target, all, dry_run } => cmd_clear(target, all, dry_run),
-        Commands::Clean { target, all, flat, dry_run } => cmd_clean(target, all, !flat, dry_run),
+/// Store what this run changed for `amu undo`.
+fn save_history() {
+    if let Err(e) = history::finish() {
+        warn!("could not record this operation for undo: {}", e);
     }
 }
 
-fn cmd_add(source: PathBuf, target: Option<PathBuf>, dry_run: bool) -> Result<()> {
-    let source = normalize_path(&source)?;
-    let target = resolve_target(target)?;
+fn run() -> Result<Exit> {
+    let matches = Cli::command().try_get_matches().unwrap_or_else(|e| {
+        // --help and --version are not errors
+        if !e.use_stderr() {
+            e.exit();
+        }
+        let _ = e.print();
+        std::process::exit(Exit::Usage.code());
+    });
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(Exit::Usage.code());
+    });
+    log::init(log::Level::from_flags(cli.verbose, cli.quiet), cli.log_file.as_deref())?;
+    debug!("amu {}", std::env::args().skip(1).collect::<Vec<_>>().join(" "));
 
-    if !source.is_dir() {
-        return Err(DotlinkError::SourceNotFound(source));
+    if let Some(fd) = cli.progress_json {
+        progress::enable(fd)?;
+        progress::emit(progress::Event::Started { command: matches.subcommand_name().unwrap_or_default() });
     }
-    if !target.is_dir() {
-        return Err(DotlinkError::TargetNotFound(target));
+
+    if let Some(home) = cli.home {
+        config::set_home(config::resolve_path(&home));
     }
 
-    // dry-run mode: preview only
-    if dry_run {
-        println!("[dry-run] add {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
-        let output = stow::dry_run(&source, &target)?;
-        let links = stow::parse_dry_run_output(&output);
-        if links.is_empty() {
-            println!("  No changes would be made.");
-        } else {
-            for link in links {
-                println!("  {}", link);
-            }
+    if let Some(profile) = cli.profile {
+        // `add` and `config add-entry` store entries in the profile, so they may start a new one
+        if !matches!(cli.command, Commands::Add { .. } | Commands::Config { action: ConfigAction::AddEntry { .. } }) {
+            Config::load()?.check_profile(&profile)?;
         }
-        return Ok(());
+        config::set_profile(profile);
     }
 
-    let mut config = Config::load()?;
-    config.add_source(target.clone(), source.clone())?;
+    if let Some(backend) = cli.backend {
+        linker::set_backend(backend);
+    }
 
-    stow::stow(&source, &target)?;
-    config.save()?;
+    // A command's own --json flag is shorthand for --format json
+    let json_flag = matches.subcommand()
+        .and_then(|(_, m)| m.try_get_one::<bool>("json").ok().flatten().copied())
+        .unwrap_or(false);
+    report::set_format(if json_flag && cli.format == Format::Text { Format::Json } else { cli.format });
+    let structured = !report::is_text();
+    table::set_align(!cli.no_align);
+    transaction::set_rollback(!cli.no_rollback);
+    hooks::set_enabled(!cli.no_hooks);
+    locks::set_no_wait(cli.no_wait);
+    if let Some(theme) = cli.theme {
+        theme::set_theme(theme);
+    }
+    if let Some(color) = cli.color {
+        style::set_color(color);
+    }
+    strict::set_strict(cli.strict);
+    unreadable::set_fail(cli.fail_on_unreadable);
+    if let Some(jobs) = cli.jobs {
+        parallel::set_jobs(jobs.into());
+    }
 
-    println!("Added: {} -> {}", source.display(), target.display());
-    Ok(())
-}
+    // Long-running commands would only save their history on exit
+    if !matches!(cli.command, Commands::Undo { .. } | Commands::Watch { .. } | Commands::Daemon { .. } | Commands::Serve { .. }) {
+        history::begin();
+    }
 
-fn cmd_remove(source: PathBuf, target: Option<PathBuf>, dry_run: bool) -> Result<()> {
-    let source = config::expand_path(&source);
-    let target = resolve_target(target)?;
+    recover_interrupted()?;
 
-    let source = if source.exists() {
-        source.canonicalize()?
-    } else {
-        source
-    };
+    if matches!(cli.command, Commands::Add { dry_run: false, .. }) && !structured {
+        offer_setup()?;
+    }
 
-    // dry-run mode: preview only
-    if dry_run {
-        println!("[dry-run] remove {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
-        if source.exists() {
-            let output = stow::dry_run_unstow(&source, &target)?;
-            let links = stow::parse_dry_run_output(&output);
-            if links.is_empty() {
-                println!("  No changes would be made.");
+    dispatch(cli.command, structured)
+}
+
+/// Run a command; those whose result has warnings or failures say so in the exit returned.
+fn dispatch(command: Commands, structured: bool) -> Result<Exit> {
+    let done = match command {
+        Commands::Add { source, target, targets, on_conflict, only, exclude, fold, no_fold, copy, hardlink, dry_run, .. } => {
+            let mode = if copy {
+                Mode::Copy
+            } else if hardlink {
+                Mode::Hardlink
             } else {
-                for link in links {
-                    println!("  {}", link);
-                }
-            }
-        } else {
-            println!("  Source not found, would only remove from config.");
+                Mode::Link
+            };
+            let fold = requested_folding(fold, no_fold).unwrap_or(false);
+            let options = AddOptions { on_conflict, filters: Filters { only, exclude }, mode, fold, lifetime: None, dry_run };
+            return cmd_add(source, target, targets, options);
         }
-        return Ok(());
-    }
+        Commands::TmpAdd { source, target, ttl, session, on_conflict, dry_run } => {
+            let lifetime = match ttl {
+                Some(ttl) if !session => Lifetime::For(ttl),
+                _ => Lifetime::Session,
+            };
+            let options = AddOptions {
+                on_conflict, filters: Filters::default(), mode: Mode::Link, fold: false, lifetime: Some(lifetime), dry_run,
+            };
+            return cmd_add(source, target, Vec::new(), options);
+        }
+        Commands::Gc { dry_run } => cmd_gc(dry_run),
+        Commands::Remove { source, target, path, exclude, only, dry_run, .. } => match (source, path) {
+            (_, Some(path)) => cmd_remove_path(path, exclude, dry_run),
+            (Some(source), None) if !only.is_empty() => cmd_remove_only(source, target, only, dry_run),
+            (Some(source), None) => cmd_remove(source, target, dry_run),
+            (None, None) => unreachable!("clap requires a source or --path"),
+        },
+        Commands::Update { target, all, under, adopt_identical, on_conflict, fold, no_fold, dry_run, .. } => {
+            return cmd_update(target, all, under, adopt_identical, on_conflict, requested_folding(fold, no_fold), dry_run);
+        }
+        Commands::Sync { source, assume_yes, dry_run } => cmd_sync(source, assume_yes, dry_run),
+        Commands::SyncAll { dry_run, .. } => return cmd_sync_all(dry_run, structured),
+        Commands::Pull { on_conflict, dry_run } => return cmd_pull(on_conflict, dry_run),
+        Commands::Push { message, dry_run } => return cmd_push(message, dry_run),
+        Commands::VerifyRemote { target, .. } => return cmd_verify_remote(target, structured),
+        Commands::Git { source, all, args } => cmd_git(source, all, args),
+        Commands::Adopt { source, target, path, dry_run } => cmd_adopt(source, target, path, dry_run),
+        Commands::Init { dir, packages, git, dry_run } => cmd_init(dir, packages, git, dry_run),
+        Commands::Setup { config_in, default_target, git, discover, assume_yes } => {
+            cmd_setup(SetupOptions { config_in, default_target, git, discover, assume_yes })
+        }
+        Commands::Export { target, all, output, bundle, with_sources } => {
+            cmd_export(target, all, output, bundle, with_sources)
+        }
+        Commands::Import { file, from, target, into, on_conflict, dry_run } => {
+            let options = ImportOptions { from, target, into, on_conflict, dry_run };
+            cmd_import(file, options)
+        }
+        Commands::Undo { id, list, dry_run } => cmd_undo(id, list, dry_run),
+        Commands::Bless { path, source } => cmd_bless(path, source),
+        Commands::Unbless { path } => cmd_unbless(path),
+        Commands::Priority { action } => cmd_priority(action),
+        Commands::Media { action } => cmd_media(action),
+        Commands::Backup { action } => cmd_backup(action),
+        Commands::DiffSource { a, b, .. } => cmd_diff_source(a, b, structured),
+        Commands::Restore {
+            target, all, under, dry_run, why_failed, chunk, restart, stop_on_conflict, on_conflict, mount_wait,
+            parents_mode, dir_mode, dir_owner, fold, no_fold, ..
+        } => {
+            let dirs = mkdir::DirOptions { parents: parents_mode, mode: dir_mode, owner: dir_owner };
+            let fold = requested_folding(fold, no_fold);
+            let options = RestoreOptions {
+                dry_run, why_failed, json: structured, chunk, restart, stop_on_conflict, on_conflict, mount_wait, dirs, fold,
+            };
+            return cmd_restore(target, all, under, options);
+        }
+        Commands::Retry { list, clear, on_conflict, dry_run } => return cmd_retry(list, clear, on_conflict, dry_run),
+        Commands::List { target, all, flat, tree } => cmd_list(target, all, !flat, log::is_verbose(), tree),
+        Commands::Status { target, all, under, flat, diff_content, since, notify, no_cache, remote, fix, real_files, yes, .. } => {
+            let fix = fix.then(|| Fix { real_files: real_files.map(|r| r.strategy()), yes });
+            let options = StatusOptions { recursive: !flat, diff_content, since, json: structured, notify, no_cache, remote, fix };
+            return cmd_status(target, all, under, options);
+        }
+        Commands::Diff { target, all, under, name_only, .. } => cmd_diff(target, all, under, name_only, structured),
+        Commands::Orphans { target, all, under, ignore, adopt, dry_run, .. } => {
+            cmd_orphans(target, all, under, ignore, adopt, dry_run)
+        }
+        Commands::Prompt => cmd_prompt(),
+        Commands::Clear { target, all, under, recorded_only, dry_run, .. } => {
+            cmd_clear(target, all, under, recorded_only, dry_run)
+        }
+        Commands::Clean { target, all, flat, dry_run } => cmd_clean(target, all, !flat, dry_run),
+        Commands::VerifyTarget { dir, .. } => cmd_verify_target(dir, structured),
+        Commands::Stats { sort, limit, .. } => cmd_stats(sort, limit, structured),
+        Commands::Audit { fail_on, .. } => return cmd_audit(fail_on, structured),
+        Commands::Bench { runs } => cmd_bench(runs as usize),
+        Commands::Dedupe { target, all, yes, dry_run } => cmd_dedupe(target, all, yes, dry_run),
+        Commands::Config { action: ConfigAction::Get { key } } => return cmd_config_get(&key),
+        Commands::Config { action } => cmd_config(action),
+        Commands::Template { action } => cmd_template(action),
+        Commands::Vars { action } => cmd_vars(action),
+        Commands::Watch { once, debounce_ms, interval_ms, log } => {
+            cmd_watch(once, debounce_ms, interval_ms, log)
+        }
+        Commands::Daemon { once, debounce_ms, notify } => daemon::run(std::time::Duration::from_millis(debounce_ms), once, notify),
+        Commands::Serve { .. } => serve::run_stdio(),
+        Commands::Shellenv { shell } => {
+            let config = Config::load()?;
+            let shell = shell.unwrap_or_else(shellenv::EnvShell::detect);
+            print!("{}", shellenv::render(shell, &shellenv::exposed(&config)));
+            Ok(())
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "amu", &mut std::io::stdout());
+            Ok(())
+        }
+        Commands::Batch { file, dry_run } => return cmd_batch(file, dry_run, structured),
+    };
+    done.map(|()| Exit::Ok)
+}
 
-    let mut config = Config::load()?;
+/*
+ * Detect operations interrupted by a crash and let the user resume or roll them back
+ */
+fn recover_interrupted() -> Result<()> {
+    use dialoguer::Select;
+    use std::io::IsTerminal;
 
-    if source.exists() {
-        stow::unstow(&source, &target)?;
+    let pending = state::pending_intents()?;
+    if pending.is_empty() {
+        return Ok(());
     }
 
-    config.remove_source(&target, &source)?;
-    config.save()?;
-
-    println!("Removed: {} -> {}", source.display(), target.display());
-    Ok(())
-}
+    // Structured output holds only the command's report, so there is no asking either
+    if !std::io::stdin().is_terminal() || !report::is_text() {
+        let message = format!("{} interrupted operation(s) found; run amu interactively to resume or roll back", pending.len());
+        strict::check(std::slice::from_ref(&message))?;
+        strict::warn(message);
+        return Ok(());
+    }
 
-fn cmd_update(target: Option<PathBuf>, all: bool, dry_run: bool) -> Result<()> {
     let config = Config::load()?;
+    for (record, intent) in pending {
+        say!(
+            "Interrupted operation: {} {} -> {}",
+            intent.action,
+            abbreviate_path(&intent.source),
+            abbreviate_path(&intent.target)
+        );
 
-    // Determine targets
-    let targets: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
-    } else {
-        let t = resolve_target(target)?;
-        if !config.targets.contains_key(&t) {
-            println!("Target not registered: {}", abbreviate_path(&t));
-            return Ok(());
-        }
-        vec![t]
-    };
+        let choices = ["Resume", "Roll back", "Discard record"];
+        let selection = Select::new()
+            .with_prompt("How should it be resolved? (Esc to decide later)")
+            .items(&choices)
+            .default(0)
+            .interact_opt()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
 
-    if targets.is_empty() {
-        println!("No targets registered.");
-        return Ok(());
-    }
+        let action = match selection {
+            None => continue,
+            Some(0) => Some(intent.action),
+            Some(1) => Some(intent.action.inverse()),
+            Some(_) => None,
+        };
 
-    let prefix = if dry_run { "[dry-run] " } else { "" };
-    for target in targets {
-        if let Some(sources) = config.get_sources(&target) {
-            println!("{}Updating {}:", prefix, abbreviate_path(&target));
-            for source in sources {
-                if source.exists() {
-                    if dry_run {
-                        let output = stow::dry_run_restow(source, &target)?;
-                        let links = stow::parse_dry_run_output(&output);
-                        if links.is_empty() {
-                            println!("  Would restow: {} (no changes)", abbreviate_path(source));
-                        } else {
-                            println!("  Would restow: {} ({} links)", abbreviate_path(source), links.len());
-                        }
-                    } else {
-                        stow::restow(source, &target)?;
-                        println!("  Restowed: {}", abbreviate_path(source));
-                    }
-                } else {
-                    println!("  Skipped (not found): {}", abbreviate_path(source));
+        if let Some(action) = action {
+            if !intent.source.exists() || !intent.target.exists() {
+                say!("  Skipped (source or target not found)");
+            } else {
+                let ignore = ignore::patterns_for(&config, &intent.target, &intent.source);
+                if let Err(e) = stow::apply(&config, action, &intent.source, &intent.target, &ignore) {
+                    say!("  {} {} failed: {}", style::error(), action, e);
+                    continue;
                 }
+                say!("  {} {} {}", style::ok(), action, abbreviate_path(&intent.target));
             }
         }
+
+        state::discard_intent(&record)?;
     }
+    say!();
 
     Ok(())
 }
 
-fn cmd_sync(source: Option<PathBuf>, dry_run: bool) -> Result<()> {
-    let config = Config::load()?;
+/// `--fold` (true) or `--no-fold` (false), if given.
+fn requested_folding(fold: bool, no_fold: bool) -> Option<bool> {
+    (fold || no_fold).then_some(fold)
+}
 
-    // Resolve source (defaults to current directory if omitted)
-    let source = match source {
-        Some(s) => normalize_path(&s)?,
-        None => normalize_path(&std::env::current_dir()?)?,
-    };
+/// Apply `--fold` / `--no-fold` to every pair of the targets, in `config` only.
+fn set_folding(config: &mut Config, targets: &[PathBuf], fold: bool) {
+    for target in targets {
+        for source in config.get_sources(target).cloned().unwrap_or_default() {
+            config.set_fold(target, &source, fold);
+        }
+    }
+}
 
-    // Find targets that reference this source
-    let targets: Vec<PathBuf> = config.targets.iter()
-        .filter(|(_, sources)| sources.contains(&source))
-        .map(|(target, _)| target.clone())
-        .collect();
+/// How `amu add` and `amu tmp-add` link a source
+struct AddOptions {
+    on_conflict: OnConflict,
+    filters: Filters,
+    /// Linked, copied (`--copy`) or hard-linked (`--hardlink`)
+    mode: Mode,
+    /// Directories linked whole (`--fold`)
+    fold: bool,
+    /// Set for `tmp-add`
+    lifetime: Option<Lifetime>,
+    dry_run: bool,
+}
 
-    if targets.is_empty() {
-        println!("No targets found for source: {}", abbreviate_path(&source));
-        println!("(This directory is not registered as a source)");
-        return Ok(());
+fn cmd_add(source: PathBuf, target: Option<PathBuf>, targets: Vec<PathBuf>, options: AddOptions) -> Result<Exit> {
+    let AddOptions { on_conflict, filters, mode, fold, lifetime, dry_run } = options;
+    if !targets.is_empty() {
+        return cmd_add_multi(source, targets, on_conflict, filters, mode, fold, dry_run);
     }
+    // Stamped up front: a session that cannot be told fails before anything is linked
+    let lifetime = lifetime.map(temporary::stamp).transpose()?;
 
-    println!("Syncing from source: {}\n", abbreviate_path(&source));
+    let source = normalize_path(&source)?;
+    let mut config = Config::load()?;
+    let target = config.lookup_target(target)?;
 
-    // Interactive selection
-    let selected = select_targets_interactive(&targets)?;
+    if !source.is_dir() {
+        return Err(DotlinkError::SourceNotFound(source));
+    }
+    if !target.is_dir() {
+        return Err(DotlinkError::TargetNotFound(target));
+    }
+    if !links::is_writable(&target) {
+        return Err(DotlinkError::TargetReadOnly(target));
+    }
 
-    if selected.is_empty() {
-        return Ok(());
+    let mut ignore = ignore::link_patterns(&config, &source);
+    ignore.extend(filters.patterns(&config, &target, &source));
+    let mut report = report::ChangeReport::new("add", dry_run);
+    let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "added");
+
+    // The new source ranks last: paths the target's other sources provide stay theirs
+    let contested = precedence::contested_in(&config, &target, &source);
+    ignore.extend(precedence::skip_patterns(&contested));
+    change.skipped = skipped_paths(&contested);
+
+    // dry-run mode: preview only
+    if dry_run {
+        config.set_mode(&target, &source, mode);
+        config.set_fold(&target, &source, fold);
+        say!("[dry-run] add {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
+        let resolved = resolve_conflicts(&config, on_conflict, &source, &target, &mut ignore, true)?;
+        print_resolutions(&resolved, "  ", true);
+        print_contested(&contested, "  ");
+        change.conflicts = conflict_reports(&resolved);
+        change.plan = preview_add(&config, &source, &target, &ignore)?;
+        change.templates = render_templates(&config, &source, &target, &ignore, "  ", true)?;
+        hooks::run(&config, Hook::PreAdd, &target, std::slice::from_ref(&source), true)?;
+        hooks::run(&config, Hook::PostAdd, &target, std::slice::from_ref(&source), true)?;
+        report.changes.push(change);
+        return report::emit_structured(&report).map(|()| Exit::Ok);
     }
 
-    // Update selected targets
-    let prefix = if dry_run { "[dry-run] " } else { "" };
-    for target in selected {
-        if dry_run {
-            let output = stow::dry_run_restow(&source, &target)?;
-            let links = stow::parse_dry_run_output(&output);
-            println!("{}Would restow: {} ({} links)", prefix, abbreviate_path(&target), links.len());
-        } else {
-            stow::restow(&source, &target)?;
-            println!("✓ {}", abbreviate_path(&target));
-        }
+    strict_preflight(&config, &[(target.clone(), source.clone())], on_conflict, false)?;
+    hooks::run(&config, Hook::PreAdd, &target, std::slice::from_ref(&source), false)?;
+    let resolved = resolve_conflicts(&config, on_conflict, &source, &target, &mut ignore, false)?;
+    print_resolutions(&resolved, "  ", false);
+    add_to_target(&mut config, &source, &target, mode, fold, &ignore)?;
+    filters.record(&mut config, &target, &source);
+    if let Some((expires, session)) = lifetime.clone() {
+        config.set_lifetime(&target, &source, expires, session);
     }
+    config.save()?;
 
-    Ok(())
+    say!("Added: {} -> {}", source.display(), target.display());
+    match lifetime {
+        Some((Some(expires), _)) => say!("  Removed by `amu gc` after {}", expires),
+        Some((None, _)) => say!("  Removed by `amu gc` once the machine restarts"),
+        None => {}
+    }
+    print_contested(&contested, "  ");
+    change.templates = render_templates(&config, &source, &target, &ignore, "  ", false)?;
+    change.conflicts = conflict_reports(&resolved);
+    hooks::run(&config, Hook::PostAdd, &target, std::slice::from_ref(&source), false)?;
+    report.changes.push(change);
+    report::emit_structured(&report)?;
+    Ok(Exit::Ok)
 }
 
 /*
- * Interactively select targets
+ * Register one source against several targets, reporting a result per target
  */
-fn select_targets_interactive(targets: &[PathBuf]) -> Result<Vec<PathBuf>> {
-    use dialoguer::MultiSelect;
-
-    // "All" option + each target
-    let mut items: Vec<String> = vec!["All".to_string()];
-    items.extend(targets.iter().map(|t| abbreviate_path(t)));
+fn cmd_add_multi(
+    source: PathBuf,
+    targets: Vec<PathBuf>,
+    on_conflict: OnConflict,
+    filters: Filters,
+    mode: Mode,
+    fold: bool,
+    dry_run: bool,
+) -> Result<Exit> {
+    let source = normalize_path(&source)?;
+    if !source.is_dir() {
+        return Err(DotlinkError::SourceNotFound(source));
+    }
 
-    // Default is all unselected (Esc to cancel)
-    let selections = MultiSelect::new()
-        .with_prompt("Select targets to update (Esc to cancel)")
-        .items(&items)
-        .interact_opt()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+    let mut config = Config::load()?;
+    let ignore = ignore::link_patterns(&config, &source);
+    let mut report = report::ChangeReport::new("add", dry_run);
 
-    let selections = match selections {
-        None => {
-            println!("Cancelled.");
-            return Ok(vec![]);
+    if dry_run {
+        for target in targets {
+            let target = config.lookup_target(Some(target))?;
+            config.set_mode(&target, &source, mode);
+            config.set_fold(&target, &source, fold);
+            let mut ignore = ignore.clone();
+            ignore.extend(filters.patterns(&config, &target, &source));
+            let contested = precedence::contested_in(&config, &target, &source);
+            ignore.extend(precedence::skip_patterns(&contested));
+            say!("[dry-run] add {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
+            let resolved = resolve_conflicts(&config, on_conflict, &source, &target, &mut ignore, true)?;
+            print_resolutions(&resolved, "  ", true);
+            print_contested(&contested, "  ");
+            let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "added");
+            change.conflicts = conflict_reports(&resolved);
+            change.skipped = skipped_paths(&contested);
+            change.plan = preview_add(&config, &source, &target, &ignore)?;
+            change.templates = render_templates(&config, &source, &target, &ignore, "  ", true)?;
+            hooks::run(&config, Hook::PreAdd, &target, std::slice::from_ref(&source), true)?;
+            hooks::run(&config, Hook::PostAdd, &target, std::slice::from_ref(&source), true)?;
+            report.changes.push(change);
         }
-        Some(s) if s.is_empty() => {
-            println!("No targets selected.");
-            return Ok(vec![]);
+        return report::emit_structured(&report).map(|()| Exit::Ok);
+    }
+
+    let pairs: Vec<(PathBuf, PathBuf)> = targets.iter()
+        .map(|target| (config.lookup_target(Some(target.clone())).unwrap_or_else(|_| config::resolve_path(target)), source.clone()))
+        .collect();
+    strict_preflight(&config, &pairs, on_conflict, false)?;
+
+    say!("Adding {} to {} target(s):", abbreviate_path(&source), targets.len());
+
+    let mut added = 0;
+    let mut failed = 0;
+    for target in targets {
+        let mut ignore = ignore.clone();
+        let result = config.lookup_target(Some(target.clone())).and_then(|t| {
+            ignore.extend(filters.patterns(&config, &t, &source));
+            let contested = precedence::contested_in(&config, &t, &source);
+            ignore.extend(precedence::skip_patterns(&contested));
+            hooks::run(&config, Hook::PreAdd, &t, std::slice::from_ref(&source), false)?;
+            let resolved = resolve_conflicts(&config, on_conflict, &source, &t, &mut ignore, false)?;
+            add_to_target(&mut config, &source, &t, mode, fold, &ignore)?;
+            filters.record(&mut config, &t, &source);
+            Ok((t, resolved, contested))
+        });
+        match result {
+            Ok((t, resolved, contested)) => {
+                say!("  {} {}", style::ok(), abbreviate_path(&t));
+                print_resolutions(&resolved, "    ", false);
+                print_contested(&contested, "    ");
+                let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&t), "added");
+                change.templates = render_templates(&config, &source, &t, &ignore, "    ", false)?;
+                change.conflicts = conflict_reports(&resolved);
+                change.skipped = skipped_paths(&contested);
+                hooks::run(&config, Hook::PostAdd, &t, std::slice::from_ref(&source), false)?;
+                report.changes.push(change);
+                added += 1;
+            }
+            Err(e) => {
+                say!("  {} {} ({})", style::error(), abbreviate_path(&target), e);
+                let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "failed");
+                change.message = Some(e.to_string());
+                report.changes.push(change);
+                failed += 1;
+            }
         }
-        Some(s) => s,
-    };
+    }
 
-    // If "All" is selected
-    if selections.contains(&0) {
-        Ok(targets.to_vec())
-    } else {
-        // Return selected targets (index 0 is "All", so subtract 1)
-        let result: Vec<PathBuf> = selections.iter()
-            .filter(|&&i| i > 0)
-            .map(|&i| targets[i - 1].clone())
-            .collect();
-        Ok(result)
+    config.save()?;
+
+    say!();
+    say!("Done: {} added, {} failed", added, failed);
+    report::emit_structured(&report)?;
+
+    if failed > 0 {
+        return Ok(Exit::Errors);
     }
+
+    Ok(Exit::Ok)
 }
 
-fn cmd_list(target: Option<PathBuf>, all: bool, recursive: bool, verbose: bool) -> Result<()> {
-    let config = Config::load()?;
+/// Patterns given to `amu add` with --only and --exclude
+#[derive(Default)]
+struct Filters {
+    only: Vec<String>,
+    exclude: Vec<String>,
+}
 
-    // Determine targets
-    let target_list: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
-    } else {
-        let t = resolve_target(target)?;
-        if recursive {
-            // Recursive mode: collect the specified target and registered targets in its subdirectories
-            let mut targets: Vec<PathBuf> = config.targets.keys()
-                .filter(|registered| registered.starts_with(&t))
-                .cloned()
-                .collect();
-            // Sort by path (parent directories come first)
-            targets.sort();
-            if targets.is_empty() {
-                println!("Target not registered: {}", abbreviate_path(&t));
-                return Ok(());
-            }
-            targets
-        } else if config.targets.contains_key(&t) {
-            vec![t]
-        } else {
-            println!("Target not registered: {}", abbreviate_path(&t));
-            return Ok(());
+impl Filters {
+    /// Ignore patterns for the pair with these patterns added to those already recorded.
+    fn patterns(&self, config: &Config, target: &Path, source: &Path) -> Vec<String> {
+        let excludes = [config.excludes_for(target, source), &self.exclude].concat();
+        let only = [config.only_for(target, source), &self.only].concat();
+        ignore::pair_patterns(source, &excludes, &only)
+    }
+
+    /// Record the patterns for the pair, so update and restore link the same subset.
+    fn record(&self, config: &mut Config, target: &Path, source: &Path) {
+        for pattern in &self.only {
+            config.add_only(target, source, pattern.clone());
         }
-    };
+        for pattern in &self.exclude {
+            config.add_exclude(target, source, pattern.clone());
+        }
+    }
+}
 
-    if target_list.is_empty() {
-        println!("No targets registered.");
-        return Ok(());
+/// Print the conflicts of a plan summarized by its link count.
+fn print_plan_conflicts(plan: &Plan, indent: &str) {
+    for conflict in plan.conflicts() {
+        say!("{}CONFLICT: {}", indent, conflict);
     }
+}
 
-    for target in &target_list {
-        println!("{}:", abbreviate_path(target));
-        if let Some(sources) = config.get_sources(target) {
-            if verbose {
-                println!("  sources:");
-                for source in sources {
-                    println!("    - {}", abbreviate_path(source));
-                }
-                let links = collect_symlinks(target, sources);
-                if !links.is_empty() {
-                    println!("  links:");
-                    for (link_path, link_target) in links {
-                        println!("    {} -> {}", abbreviate_path(&link_path), abbreviate_path(&link_target));
-                    }
-                }
-            } else {
-                for source in sources {
-                    println!("  - {}", abbreviate_path(source));
-                }
-            }
-        }
-        println!();
+/// Print what adding a source would do, and return the plan.
+fn preview_add(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
+    let plan = stow::dry_run(config, source, target, ignore)?;
+    plan.print("  ");
+    Ok(plan)
+}
+
+/*
+ * Apply an --on-conflict strategy to a source about to be linked into a target
+ * Conflicts are judged against every source registered for the target plus this one,
+ * so links from sibling sources are not mistaken for foreign files.
+ */
+fn resolve_conflicts(
+    config: &Config,
+    strategy: OnConflict,
+    source: &Path,
+    target: &Path,
+    ignore: &mut Vec<String>,
+    dry_run: bool,
+) -> Result<Vec<conflict::Resolution>> {
+    let mut sources = config.get_sources(target).cloned().unwrap_or_default();
+    if !sources.iter().any(|s| s == source) {
+        sources.push(source.to_path_buf());
     }
+    conflict::resolve(strategy, source, target, ignore, &sources, dry_run)
+}
 
-    Ok(())
+fn conflict_reports(resolved: &[conflict::Resolution]) -> Vec<report::ConflictReport> {
+    resolved.iter()
+        .map(|r| report::ConflictReport { path: r.path.display().to_string(), action: r.action.to_string() })
+        .collect()
 }
 
-fn collect_symlinks(target: &Path, sources: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
-    let mut links = Vec::new();
-    collect_symlinks_recursive(target, sources, target, &mut links);
-    links
+/// Report entries for paths left to a higher-priority source.
+fn skipped_paths(contested: &[precedence::Contested]) -> Vec<report::SkippedPath> {
+    contested.iter()
+        .map(|c| report::SkippedPath { path: c.path.display().to_string(), provided_by: abbreviate_path(&c.winner) })
+        .collect()
 }
 
-fn collect_symlinks_recursive(base_target: &Path, sources: &[PathBuf], current: &Path, links: &mut Vec<(PathBuf, PathBuf)>) {
-    if let Ok(entries) = std::fs::read_dir(current) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_symlink() {
-                if let Ok(link_target) = std::fs::read_link(&path) {
-                    // Check if this symlink points to one of our sources
-                    let joined = if link_target.is_absolute() {
-                        link_target.clone()
-                    } else {
-                        path.parent().unwrap_or(current).join(&link_target)
-                    };
-                    // Resolve relative paths (../) and symlinks (/tmp -> /private/tmp) with canonicalize
-                    let abs_target = joined.canonicalize().unwrap_or(joined);
-                    for source in sources {
-                        if abs_target.starts_with(source) {
-                            links.push((path.clone(), abs_target));
-                            break;
-                        }
-                    }
-                }
-            } else if path.is_dir() {
-                collect_symlinks_recursive(base_target, sources, &path, links);
+fn print_contested(contested: &[precedence::Contested], indent: &str) {
+    for c in contested {
+        say!("{}~ {} (provided by {})", indent, c.path.display(), abbreviate_path(&c.winner));
+    }
+}
+
+/// Render a source's templates into a target, print what changed and return it for the report.
+fn render_templates(
+    config: &Config,
+    source: &Path,
+    target: &Path,
+    ignore: &[String],
+    indent: &str,
+    dry_run: bool,
+) -> Result<Vec<report::TemplateReport>> {
+    let renderings = template::apply(config, source, target, ignore, dry_run)?;
+    Ok(print_renderings(renderings, indent, dry_run))
+}
+
+fn print_renderings(renderings: Vec<template::Rendering>, indent: &str, dry_run: bool) -> Vec<report::TemplateReport> {
+    for rendering in &renderings {
+        let path = rendering.path.display();
+        match &rendering.outcome {
+            template::Outcome::Rendered if dry_run => say!("{}Would render: {}", indent, path),
+            template::Outcome::Rendered => say!("{}Rendered: {}", indent, path),
+            template::Outcome::Unchanged => {}
+            template::Outcome::Modified => {
+                say!("{}{} {} (edited since rendered; left alone)", indent, style::warning(), path)
+            }
+            template::Outcome::Occupied => {
+                say!("{}{} {} (not rendered: another file is in the way)", indent, style::warning(), path)
             }
+            template::Outcome::Failed(e) => say!("{}{} {}{} ({})", indent, style::error(), path, template::SUFFIX, e),
         }
     }
+    renderings.into_iter()
+        .map(|r| report::TemplateReport {
+            path: r.path.display().to_string(),
+            result: r.outcome.label(),
+            message: match r.outcome {
+                template::Outcome::Failed(e) => Some(e),
+                _ => None,
+            },
+        })
+        .collect()
 }
 
-fn cmd_status(target: Option<PathBuf>, all: bool, recursive: bool, json: bool) -> Result<()> {
-    let config = Config::load()?;
-
+/// Delete the files a source's templates rendered into a target and print them.
+fn remove_rendered(source: &Path, target: &Path, indent: &str, dry_run: bool) -> Result<()> {
+    for path in template::remove(source, target, dry_run)? {
+        let verb = if dry_run { "Would delete rendered" } else { "Deleted rendered" };
+        say!("{}{}: {}", indent, verb, abbreviate_path(&path));
+    }
+    Ok(())
+}
+
+fn print_resolutions(resolved: &[conflict::Resolution], indent: &str, dry_run: bool) {
+    for resolution in resolved {
+        let action = if dry_run { format!("would be {}", resolution.action) } else { resolution.action.to_string() };
+        say!("{}{} {} ({})", indent, style::warning(), resolution.path.display(), action);
+    }
+}
+
+/// Remove the temporary sources whose time is up or whose session has ended.
+fn cmd_gc(dry_run: bool) -> Result<()> {
+    let expired = temporary::collect(dry_run)?;
+    if expired.is_empty() {
+        say!("No expired temporary sources");
+    }
+    let prefix = if dry_run { "[dry-run] Would remove" } else { "Removed" };
+    for (target, source) in expired {
+        say!("{}: {} -> {}", prefix, abbreviate_path(&source), abbreviate_path(&target));
+    }
+    Ok(())
+}
+
+fn cmd_remove(source: PathBuf, target: Option<PathBuf>, dry_run: bool) -> Result<()> {
+    let mut config = Config::load()?;
+    let (source, target) = lookup_pair(&config, &source, target)?;
+    let ignore = ignore::patterns_for(&config, &target, &source);
+    let mut report = report::ChangeReport::new("remove", dry_run);
+    let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "removed");
+
+    // dry-run mode: preview only
+    if dry_run {
+        say!("[dry-run] remove {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
+        if source.exists() {
+            change.plan = stow::dry_run_unstow(&config, &source, &target, &ignore)?;
+            change.plan.print("  ");
+        } else {
+            say!("  Source not found, would only remove from config.");
+            change.message = Some("source not found".to_string());
+        }
+        remove_rendered(&source, &target, "  ", true)?;
+        hooks::run(&config, Hook::PreRemove, &target, std::slice::from_ref(&source), true)?;
+        hooks::run(&config, Hook::PostRemove, &target, std::slice::from_ref(&source), true)?;
+        report.changes.push(change);
+        return report::emit_structured(&report);
+    }
+
+    hooks::run(&config, Hook::PreRemove, &target, std::slice::from_ref(&source), false)?;
+    let removed = remove_from_target(&mut config, &source, &target)?;
+    if !source.exists() {
+        change.message = Some("source not found".to_string());
+    }
+    for path in &removed.rendered {
+        say!("  Deleted rendered: {}", abbreviate_path(path));
+    }
+    config.save()?;
+
+    say!("Removed: {} -> {}", source.display(), target.display());
+    hooks::run(&config, Hook::PostRemove, &target, std::slice::from_ref(&source), false)?;
+    report.changes.push(change);
+    report::emit_structured(&report)
+}
+
+/*
+ * Unlink the part of a source matching the given patterns and keep it excluded
+ * The source stays registered; the patterns are recorded so update/restore skip them.
+ */
+fn cmd_remove_only(source: PathBuf, target: Option<PathBuf>, only: Vec<String>, dry_run: bool) -> Result<()> {
+    let mut config = Config::load()?;
+    let (source, target) = lookup_pair(&config, &source, target)?;
+    if config.find_source(&target, &source).is_none() {
+        return Err(DotlinkError::NotRegistered { src: source, dest: target });
+    }
+
+    let matching: Vec<PathBuf> = links::find_all_symlinks(&target)
+        .into_iter()
+        .filter(|entry| {
+            entry.destination.strip_prefix(&source)
+                .is_ok_and(|relative| ignore::is_ignored(relative, &only))
+        })
+        .map(|entry| target.join(entry.relative))
+        .collect();
+
+    let mut report = report::ChangeReport::new("remove", dry_run);
+    let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "unlinked");
+    change.links = matching.iter().map(|link| abbreviate_path(link)).collect();
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    if dry_run {
+        say!("{}Would unlink {} link(s) from {}", prefix, matching.len(), abbreviate_path(&source));
+        for link in &matching {
+            say!("  {}", abbreviate_path(link));
+        }
+    } else {
+        let removed = links::remove_links(&matching, &target);
+        say!("Unlinked {} link(s) from {}", removed, abbreviate_path(&source));
+    }
+
+    for pattern in only {
+        if config.add_exclude(&target, &source, pattern.clone()) {
+            say!("{}Excluded {} for {}", prefix, pattern, abbreviate_path(&source));
+        }
+    }
+    if !dry_run {
+        config.save()?;
+    }
+    report.changes.push(change);
+    report::emit_structured(&report)
+}
+
+/*
+ * Move real files from a target into a source and link them back
+ * Like `stow --adopt`, but the source versions that get replaced are backed up first.
+ * The pair is registered if it is not already. If moving, linking or saving the config
+ * fails, the files are moved back (unless --no-rollback, or inside a batch, which rolls
+ * back as a whole).
+ */
+fn cmd_adopt(source: PathBuf, target: Option<PathBuf>, paths: Vec<PathBuf>, dry_run: bool) -> Result<()> {
+    let source = normalize_path(&source)?;
+    if !source.is_dir() {
+        return Err(DotlinkError::SourceNotFound(source));
+    }
+    let mut config = Config::load()?;
+    let target = config.lookup_target(target)?;
+    if !target.is_dir() {
+        return Err(DotlinkError::TargetNotFound(target));
+    }
+    if !links::is_writable(&target) {
+        return Err(DotlinkError::TargetReadOnly(target));
+    }
+
+    let ignore = ignore::patterns_for(&config, &target, &source);
+
+    let mut extra = Vec::new();
+    for path in &paths {
+        let path = config::resolve_link(path);
+        match path.strip_prefix(&target) {
+            Ok(relative) if !relative.as_os_str().is_empty() => extra.push(relative.to_path_buf()),
+            _ => return Err(DotlinkError::NotInTarget { path, target }),
+        }
+    }
+
+    let files = adopt::candidates(&source, &target, &ignore, &extra);
+    let mut report = report::AdoptReport {
+        source: abbreviate_path(&source),
+        target: abbreviate_path(&target),
+        dry_run,
+        files: Vec::new(),
+        backup: None,
+    };
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    say!("{}Adopting {} into {}:", prefix, abbreviate_path(&target), abbreviate_path(&source));
+    if files.is_empty() {
+        say!("  No real files to adopt.");
+    }
+    for file in &files {
+        let replaces_source = source.join(file).exists();
+        say!("  {}{}", file.display(), if replaces_source { " (replaces source version)" } else { "" });
+        report.files.push(report::AdoptedFile { path: file.display().to_string(), replaces_source });
+    }
+    if dry_run {
+        return report::emit_structured(&report);
+    }
+
+    let adopted = adopt::adopt(&source, &target, &files).and_then(|backup| {
+        if config.find_source(&target, &source).is_some() {
+            stow::restow(&config, &source, &target, &ignore)?;
+        } else {
+            add_to_target(&mut config, &source, &target, Mode::Link, false, &ignore)?;
+            config.save()?;
+        }
+        Ok(backup)
+    });
+    let backup = match adopted {
+        Ok(backup) => backup,
+        Err(e) => {
+            if transaction::rollback_enabled() && !batch::is_running() {
+                revert_recorded("by adopt");
+            }
+            return Err(e);
+        }
+    };
+
+    if let Some(backup) = backup {
+        say!("Backed up replaced source files to {}", abbreviate_path(&backup));
+        report.backup = Some(abbreviate_path(&backup));
+    }
+    say!("Linked: {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
+    report::emit_structured(&report)
+}
+
+/// The deepest registered target containing `path`, and the path relative to it.
+fn containing_target(config: &Config, path: &Path) -> Result<(PathBuf, PathBuf)> {
+    // The path itself may be one of amu's links, so only its directory is resolved
+    let path = config::resolve_link(path);
+    let parent = config::PathKey::new(path.parent().unwrap_or(&path));
+    let target = config.targets.keys()
+        .filter(|t| parent.starts_with(t))
+        .max_by_key(|t| t.resolved().components().count())
+        .ok_or_else(|| DotlinkError::PathNotManaged(path.clone()))?;
+    let relative = path.strip_prefix(target.resolved()).unwrap_or(&path).to_path_buf();
+    Ok((target.to_path_buf(), relative))
+}
+
+/*
+ * Unlink a subtree of a target without naming its source
+ * The registered target containing the path is looked up, and every source that
+ * provides the subtree has its links under it removed.
+ */
+fn cmd_remove_path(path: PathBuf, exclude: bool, dry_run: bool) -> Result<()> {
+    let path = config::resolve_link(&path);
+    let mut config = Config::load()?;
+    let (target, relative) = containing_target(&config, &path)?;
+
+    let providers: Vec<PathBuf> = config.sources_of(&target).iter()
+        .filter(|source| std::fs::symlink_metadata(source.join(&relative)).is_ok())
+        .cloned()
+        .collect();
+    if providers.is_empty() {
+        return Err(DotlinkError::PathNotManaged(path));
+    }
+
+    let mut report = report::ChangeReport::new("remove", dry_run);
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    for source in &providers {
+        let links = links::links_into(&path, source);
+        let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(&target), "unlinked");
+        change.links = links.iter().map(|link| abbreviate_path(link)).collect();
+        if dry_run {
+            say!("{}Would unlink {} link(s) from {}", prefix, links.len(), abbreviate_path(source));
+            for link in &links {
+                say!("  {}", abbreviate_path(link));
+            }
+        } else {
+            let removed = links::remove_links(&links, &target);
+            say!("Unlinked {} link(s) from {}", removed, abbreviate_path(source));
+        }
+
+        if exclude {
+            let pattern = format!("/{}", relative.display());
+            if config.add_exclude(&target, source, pattern) {
+                say!("{}Excluded {} for {}", prefix, relative.display(), abbreviate_path(source));
+            }
+        }
+        report.changes.push(change);
+    }
+
+    if exclude {
+        if !dry_run {
+            config.save()?;
+        }
+    } else if !dry_run {
+        say!("Note: `amu update` will relink these files; pass --exclude to keep them unlinked");
+    }
+    report::emit_structured(&report)
+}
+
+#[derive(Default)]
+struct SetupOptions {
+    config_in: Option<PathBuf>,
+    default_target: Option<PathBuf>,
+    git: Option<PathBuf>,
+    discover: bool,
+    assume_yes: bool,
+}
+
+/// Before the first `amu add`, offer the setup wizard rather than creating an empty config.
+fn offer_setup() -> Result<()> {
+    use dialoguer::Confirm;
+    use std::io::IsTerminal;
+
+    if !setup::needed()? || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Ok(());
+    }
+    let accepted = Confirm::new()
+        .with_prompt("There is no amu config yet. Set one up first?")
+        .default(true)
+        .interact()
+        .map_err(|e| DotlinkError::IoError(std::io::Error::other(e)))?;
+    if accepted {
+        cmd_setup(SetupOptions::default())?;
+        say!();
+    }
+    Ok(())
+}
+
+/*
+ * First-run wizard: asks where the config lives, the default target, whether sources go in a
+ * git repository, and which existing setups to import. Options answer questions up front;
+ * with --assume-yes the rest take their defaults.
+ */
+fn cmd_setup(options: SetupOptions) -> Result<()> {
+    use dialoguer::{Confirm, Input, MultiSelect, Select};
+    use std::io::IsTerminal;
+
+    let SetupOptions { config_in, default_target, git, discover, assume_yes } = options;
+    if !assume_yes && !std::io::stdin().is_terminal() {
+        return Err(DotlinkError::NeedsTerminal("setup"));
+    }
+    let home = config::home_dir().ok_or(DotlinkError::HomeNotFound)?;
+    let prompt_error = |e: dialoguer::Error| DotlinkError::IoError(std::io::Error::other(e));
+    let ask_dir = |prompt: &str, default: &Path| -> Result<PathBuf> {
+        let answer: String = Input::new()
+            .with_prompt(prompt)
+            .default(abbreviate_path(default))
+            .interact_text()
+            .map_err(prompt_error)?;
+        Ok(config::absolute_path(Path::new(&answer)))
+    };
+    let ask = !assume_yes;
+    let config_path = Config::config_path()?;
+    let dotfiles = home.join("dotfiles");
+
+    let config_dir = match config_in {
+        Some(dir) => Some(config::absolute_path(&dir)),
+        None if ask && setup::needed()? => {
+            let choice = Select::new()
+                .with_prompt("Where should the config live?")
+                .items(&[
+                    format!("{} (default)", abbreviate_path(&config_path)),
+                    format!("In a dotfiles repository, as {}, linked from there", setup::REPO_CONFIG_FILE),
+                ])
+                .default(0)
+                .interact()
+                .map_err(prompt_error)?;
+            if choice == 1 { Some(ask_dir("Dotfiles repository", &dotfiles)?) } else { None }
+        }
+        None => None,
+    };
+
+    let default_target = match default_target {
+        Some(dir) => Some(config::absolute_path(&dir)),
+        None if ask => {
+            let choice = Select::new()
+                .with_prompt("Default target, used when a command is given none")
+                .items(&["The current directory (default)", "The home directory", "Another directory"])
+                .default(0)
+                .interact()
+                .map_err(prompt_error)?;
+            match choice {
+                1 => Some(home.clone()),
+                2 => Some(ask_dir("Default target", &home)?),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+
+    let git = match git {
+        Some(dir) => Some(config::absolute_path(&dir)),
+        None if ask => {
+            let wanted = Confirm::new()
+                .with_prompt("Keep sources in a git repository?")
+                .default(false)
+                .interact()
+                .map_err(prompt_error)?;
+            if wanted { Some(ask_dir("Repository", config_dir.as_deref().unwrap_or(&dotfiles))?) } else { None }
+        }
+        None => None,
+    };
+
+    let found = setup::discover(&home);
+    let imports: Vec<(migrate::Manager, PathBuf)> = if discover || found.is_empty() || !ask {
+        if discover { found } else { Vec::new() }
+    } else {
+        let items: Vec<String> = found.iter()
+            .map(|(manager, path)| format!("{}: {}", manager.name(), abbreviate_path(path)))
+            .collect();
+        let picked = MultiSelect::new()
+            .with_prompt("Import existing dotfiles? (space to select, enter to confirm)")
+            .items(&items)
+            .interact()
+            .map_err(prompt_error)?;
+        picked.into_iter().map(|i| found[i].clone()).collect()
+    };
+
+    let path = setup::apply(&setup::Setup { config_dir: config_dir.clone(), default_target: default_target.clone(), git: git.clone() })?;
+    match &config_dir {
+        Some(dir) => say!("Config: {} -> {}", abbreviate_path(&path), abbreviate_path(&dir.join(setup::REPO_CONFIG_FILE))),
+        None => say!("Config: {}", abbreviate_path(&path)),
+    }
+    if let Some(target) = &default_target {
+        say!("Default target: {}", abbreviate_path(target));
+    }
+    if let Some(dir) = &git {
+        say!("Git repository: {}", abbreviate_path(dir));
+    }
+    for (manager, setup_path) in imports {
+        say!();
+        let options = ImportOptions { from: Some(manager), target: None, into: None, on_conflict: None, dry_run: false };
+        cmd_import(Some(setup_path), options)?;
+    }
+    Ok(())
+}
+
+/*
+ * Scaffold a dotfiles directory
+ * Each package is a source directory mirroring the home directory and is registered
+ * with `~` as its target. Existing directories and entries are left as they are, so
+ * init can be re-run to add packages.
+ */
+fn cmd_init(dir: Option<PathBuf>, packages: Vec<String>, git: bool, dry_run: bool) -> Result<()> {
+    let home = config::home_dir().ok_or(DotlinkError::HomeNotFound)?;
+    let dir = match dir {
+        Some(dir) => config::absolute_path(&dir),
+        None => home.join("dotfiles"),
+    };
+    let packages = if packages.is_empty() { vec!["home".to_string()] } else { packages };
+    for package in &packages {
+        if package.is_empty() || package.contains(['/', '\\']) || package == "." || package == ".." {
+            return Err(DotlinkError::InvalidPackageName(package.clone()));
+        }
+    }
+
+    let mut config = Config::load()?;
+    let mut report = report::InitReport {
+        dir: abbreviate_path(&dir),
+        dry_run,
+        created: Vec::new(),
+        registered: Vec::new(),
+        already_registered: Vec::new(),
+        git: None,
+    };
+    let prefix = if dry_run { "[dry-run] Would create" } else { "Created" };
+    for source in packages.iter().map(|p| dir.join(p)) {
+        if !source.is_dir() {
+            if !dry_run {
+                std::fs::create_dir_all(&source)?;
+                debug!("created directory {}", source.display());
+            }
+            say!("{}: {}", prefix, abbreviate_path(&source));
+            report.created.push(abbreviate_path(&source));
+        }
+        if config.find_source(&home, &source).is_some() {
+            say!("Already registered: {} -> ~", abbreviate_path(&source));
+            report.already_registered.push(abbreviate_path(&source));
+        } else {
+            config.add_source(home.clone(), source.clone())?;
+            let verb = if dry_run { "[dry-run] Would register" } else { "Registered" };
+            say!("{}: {} -> ~", verb, abbreviate_path(&source));
+            report.registered.push(abbreviate_path(&source));
+        }
+    }
+
+    if git {
+        report.git = Some(if dry_run {
+            say!("[dry-run] Would run git init in {}", abbreviate_path(&dir));
+            "would_initialize"
+        } else if git::repo_root(&dir).is_some_and(|root| config::same_path(&root, &dir)) {
+            say!("Already a git repository: {}", abbreviate_path(&dir));
+            "already_a_repository"
+        } else {
+            git::init(&dir)?;
+            say!("Initialized git repository: {}", abbreviate_path(&dir));
+            "initialized"
+        });
+    }
+
+    if dry_run {
+        return report::emit_structured(&report);
+    }
+    config.save()?;
+    say!();
+    say!("Move dotfiles into a package (e.g. {}/.bashrc), then run `amu restore ~` to link them.",
+        abbreviate_path(&dir.join(&packages[0])));
+    report::emit_structured(&report)
+}
+
+fn cmd_undo(id: Option<u64>, list: bool, dry_run: bool) -> Result<()> {
+    if list {
+        let operations = history::list()?;
+        if !report::is_text() {
+            return report::emit(&operations);
+        }
+        if operations.is_empty() {
+            println!("No recorded operations.");
+            return Ok(());
+        }
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut table = Table::new();
+        for op in operations.iter().rev() {
+            let age = cache::describe_age(std::time::Duration::from_secs(now.saturating_sub(op.started_at)));
+            let undone = if op.undone { "(undone)" } else { "" };
+            table.row([op.id.to_string(), age, op.command.clone(), op.summary(), undone.to_string()]);
+        }
+        table.print();
+        return Ok(());
+    }
+
+    let operation = history::find(id)?;
+    let (op_id, command) = (operation.id, operation.command.clone());
+    let result = history::undo(operation, dry_run)?;
+    if !report::is_text() {
+        return report::emit(&serde_json::json!({
+            "id": op_id,
+            "command": command,
+            "dry_run": dry_run,
+            "reverted": result.reverted,
+            "skipped": result.skipped,
+        }));
+    }
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    println!("{}Undoing {}: amu {}", prefix, op_id, command);
+    for done in &result.reverted {
+        println!("  {} {}", style::ok(), done);
+    }
+    for skipped in &result.skipped {
+        println!("  - skipped {}", skipped);
+    }
+    Ok(())
+}
+
+/*
+ * Run a script of amu commands as one unit (`amu batch`)
+ * Every step is parsed before any runs. Config saves are kept in memory and written once at
+ * the end; if a step fails, the changes of the steps before it are reverted from the
+ * operation being recorded for undo, which then holds nothing. With --no-rollback, what the
+ * earlier steps did (config included) is kept instead.
+ */
+fn cmd_batch(file: Option<PathBuf>, dry_run: bool, structured: bool) -> Result<Exit> {
+    use std::io::Read;
+
+    let content = match file.filter(|f| f.as_os_str() != "-") {
+        Some(file) => std::fs::read_to_string(&file)?,
+        None => {
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            content
+        }
+    };
+
+    let mut steps = Vec::new();
+    for step in batch::parse(&content)? {
+        let (command, text) = parse_batch_step(&step, dry_run)?;
+        steps.push((step.line, command, text));
+    }
+
+    batch::set_running(true);
+    config::stage_saves();
+    let total = steps.len();
+    // The batch exits with the worst of its steps; one that fails ends it
+    let mut exit = Exit::Ok;
+    for (index, (line, command, text)) in steps.into_iter().enumerate() {
+        say!("[{}/{}] amu {}", index + 1, total, text);
+        let message = match dispatch(command, structured) {
+            Ok(step) if step < Exit::Errors => {
+                exit = exit.max(step);
+                continue;
+            }
+            Ok(step) => format!("exited with {}", step.code()),
+            Err(e) => e.to_string(),
+        };
+        roll_back_batch();
+        return Err(DotlinkError::BatchStep { line, message });
+    }
+    batch::set_running(false);
+    config::commit_staged()?;
+    say!("{}Ran {} step(s)", if dry_run { "[dry-run] " } else { "" }, total);
+    Ok(exit)
+}
+
+/// A batch step's command and how it is shown; with `dry_run`, the command's own dry run.
+fn parse_batch_step(step: &batch::Step, dry_run: bool) -> Result<(Commands, String)> {
+    let error = |message: String| DotlinkError::BatchStep { line: step.line, message };
+    let parse = |args: &[String]| {
+        let argv = std::iter::once("amu".to_string()).chain(args.iter().cloned());
+        Cli::command().try_get_matches_from(argv).map_err(|e| {
+            let rendered = e.to_string();
+            let first = rendered.lines().next().unwrap_or_default();
+            error(first.trim_start_matches("error: ").to_string())
+        })
+    };
+
+    let mut args = step.args.clone();
+    let mut matches = parse(&args)?;
+    let name = matches.subcommand_name().unwrap_or_default().to_string();
+    if matches!(name.as_str(), "batch" | "watch" | "daemon" | "serve" | "undo") {
+        return Err(error(format!("`amu {}` cannot run in a batch", name)));
+    }
+    // Global options set up the whole process before the first step, as does a command's --json
+    let mut innermost = &matches;
+    while let Some((_, sub)) = innermost.subcommand() {
+        innermost = sub;
+    }
+    let global = Cli::command().get_arguments()
+        .filter(|arg| arg.is_global_set())
+        .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .and_then(|arg| arg.get_long().map(String::from));
+    if let Some(flag) = global {
+        return Err(error(format!("--{} applies to the whole batch; give it to `amu batch` instead", flag)));
+    }
+    if innermost.try_get_one::<bool>("json").ok().flatten().copied().unwrap_or(false) {
+        return Err(error("--json applies to the whole batch; give `--format json` to `amu batch` instead".to_string()));
+    }
+
+    if dry_run {
+        // The innermost subcommand is the one taking --dry-run (`amu config add-entry`)
+        let flag = innermost.try_get_one::<bool>("dry_run").ok().flatten().copied();
+        let read_only = matches!(
+            name.as_str(),
+            "list" | "status" | "diff" | "prompt" | "verify-target" | "stats" | "audit" | "bench" | "diff-source" | "shellenv"
+                | "completions"
+        );
+        match flag {
+            Some(false) => {
+                args.push("--dry-run".to_string());
+                matches = parse(&args)?;
+            }
+            None if !read_only => return Err(error(format!("`amu {}` has no --dry-run", name))),
+            _ => {}
+        }
+    }
+
+    let cli = Cli::from_arg_matches(&matches).map_err(|e| error(e.to_string()))?;
+    Ok((cli.command, step.args.join(" ")))
+}
+
+/// Keep what the steps of a batch ending early did, config included.
+fn keep_batch() {
+    batch::set_running(false);
+    if let Err(e) = config::commit_staged() {
+        strict::warn(format!("could not save the config: {}", e));
+    }
+}
+
+/// Undo what the steps of a failed batch did, or keep it all with --no-rollback.
+fn roll_back_batch() {
+    if !transaction::rollback_enabled() {
+        keep_batch();
+        return;
+    }
+    batch::set_running(false);
+    config::discard_staged();
+    revert_recorded("by earlier steps");
+}
+
+/// Undo the changes this process has recorded so far, reporting what could not be undone.
+fn revert_recorded(made: &str) {
+    match history::revert_recorded() {
+        Ok(result) => {
+            if !result.reverted.is_empty() {
+                eprintln!("Rolled back {} change(s) made {}", result.reverted.len(), made);
+            }
+            for skipped in &result.skipped {
+                strict::warn(format!("not rolled back: {}", skipped));
+            }
+        }
+        Err(e) => strict::warn(format!("rollback failed: {}", e)),
+    }
+}
+
+fn cmd_export(
+    target: Option<PathBuf>,
+    all: bool,
+    output: Option<PathBuf>,
+    bundle: Option<PathBuf>,
+    with_sources: bool,
+) -> Result<()> {
+    let config = Config::load()?;
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, None)
+    } else {
+        vec![config.lookup_target(target)?]
+    };
+    let content = export::export(&config, &targets)?;
+    let exported = if all { "all targets".to_string() } else { abbreviate_path(&targets[0]) };
+    let mut report = report::ExportReport {
+        targets: targets.iter().map(|t| abbreviate_path(t)).collect(),
+        output: None,
+        bundle: None,
+        left_out: Vec::new(),
+        content: None,
+    };
+
+    match (output, bundle) {
+        (_, Some(path)) => {
+            let sources: Vec<PathBuf> = if with_sources {
+                let mut sources: Vec<PathBuf> = targets.iter().flat_map(|t| config.sources_of(t).iter().cloned()).collect();
+                sources.sort();
+                sources.dedup();
+                sources
+            } else {
+                Vec::new()
+            };
+            let left_out = export::write_bundle(&path, &content, &sources)?;
+            say!("Bundled {} into {}", exported, path.display());
+            for source in left_out {
+                say!("  Left out (not under the home directory): {}", abbreviate_path(&source));
+                report.left_out.push(abbreviate_path(&source));
+            }
+            report.bundle = Some(path.display().to_string());
+        }
+        (Some(path), None) => {
+            std::fs::write(&path, content)?;
+            debug!("wrote {}", path.display());
+            say!("Exported {} to {}", exported, path.display());
+            report.output = Some(path.display().to_string());
+        }
+        (None, None) if report::is_text() => print!("{}", content),
+        (None, None) => report.content = Some(content),
+    }
+    report::emit_structured(&report)
+}
+
+/// Flags of `amu import` beyond the file to read
+struct ImportOptions {
+    from: Option<migrate::Manager>,
+    target: Option<PathBuf>,
+    into: Option<PathBuf>,
+    on_conflict: Option<export::MergeStrategy>,
+    dry_run: bool,
+}
+
+/*
+ * Merge exported targets, or another manager's setup, into the config
+ * Targets already mapped to other sources are resolved by --on-conflict, or by asking
+ * when run interactively. Sources a bundle carries and chezmoi or yadm files converted
+ * into a new source are written before the config is saved. Links are left to
+ * `amu restore`.
+ */
+fn cmd_import(file: Option<PathBuf>, options: ImportOptions) -> Result<()> {
+    use dialoguer::Select;
+    use std::io::{IsTerminal, Read};
+
+    let ImportOptions { from, target, into, on_conflict, dry_run } = options;
+    let mut migration = None;
+    let mut bundle = None;
+    let imported = match from {
+        Some(manager) => {
+            let path = file.or_else(|| manager.default_path()).ok_or(DotlinkError::ImportPathRequired(manager.name()))?;
+            let path = config::resolve_path(&path);
+            let home = config::home_dir().ok_or(DotlinkError::HomeNotFound)?;
+            let home = config::resolve_path(&home);
+            let target = config::resolve_path(&target.or_else(|| manager.default_target(&path)).unwrap_or_else(|| home.clone()));
+            let into = config::resolve_path(&into.unwrap_or_else(|| home.join("dotfiles").join(manager.name())));
+            let read = migrate::read(manager, &path, &target, &into)?;
+            let imported = read.config.clone();
+            migration = Some(read);
+            imported
+        }
+        None => {
+            let file = file.unwrap_or_default();
+            let content = if file.as_os_str() == "-" {
+                let mut content = String::new();
+                std::io::stdin().read_to_string(&mut content)?;
+                content
+            } else if export::is_bundle(&file) {
+                let content = export::read_bundle(&file)?;
+                bundle = Some(file);
+                content
+            } else {
+                std::fs::read_to_string(&file)?
+            };
+            export::parse(&content)?
+        }
+    };
+    let mut config = Config::load()?;
+    let before = config.clone();
+
+    let changed = export::merge(&mut config, imported, |target| {
+        if let Some(strategy) = on_conflict {
+            return Ok(strategy);
+        }
+        if !std::io::stdin().is_terminal() {
+            return Err(DotlinkError::ImportConflict(target.to_path_buf()));
+        }
+        say!("{} is already registered:", abbreviate_path(target));
+        for source in before.get_sources(target).into_iter().flatten() {
+            say!("  - {}", abbreviate_path(source));
+        }
+        let choices = ["Keep existing sources", "Replace with imported sources", "Merge (append imported sources)"];
+        let selection = Select::new()
+            .with_prompt("How should it be imported?")
+            .items(&choices)
+            .default(0)
+            .interact()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok([export::MergeStrategy::Keep, export::MergeStrategy::Replace, export::MergeStrategy::Merge][selection])
+    })?;
+
+    let mut report = report::ImportReport { dry_run, ..Default::default() };
+    let prefix = if dry_run { "[dry-run] Would " } else { "" };
+    if let Some(migration) = &migration {
+        for (path, reason) in &migration.skipped {
+            say!("Skipped: {} ({})", abbreviate_path(path), reason);
+            report.skipped.push(report::SkippedFile { path: abbreviate_path(path), reason: reason.to_string() });
+        }
+        if let Some(into) = migration.into.as_ref().filter(|_| !changed.is_empty()) {
+            let files = if dry_run {
+                say!("{}convert {} file(s) into {}", prefix, migration.file_count(), abbreviate_path(into));
+                migration.file_count()
+            } else {
+                let written = migrate::apply(migration)?;
+                say!("Converted {} file(s) into {}", written, abbreviate_path(into));
+                written
+            };
+            report.converted = Some(report::ConvertedFiles { into: abbreviate_path(into), files });
+        }
+    }
+    // Sources the bundle carries for the targets taken, unless this machine has them
+    if let Some(bundle) = &bundle {
+        let sources: Vec<PathBuf> = changed.iter().flat_map(|t| config.sources_of(t).iter().cloned()).collect();
+        let missing = export::bundled_sources(bundle, &sources)?;
+        if !dry_run {
+            export::extract_sources(bundle, &missing)?;
+        }
+        for source in &missing {
+            say!("{}{}: {}", prefix, if dry_run { "extract" } else { "Extracted" }, abbreviate_path(source));
+            report.extracted.push(abbreviate_path(source));
+        }
+    }
+
+    if changed.is_empty() {
+        say!("Nothing to import; the config already has these targets.");
+        return report::emit_structured(&report);
+    }
+    report.imported = changed.iter().map(|t| abbreviate_path(t)).collect();
+
+    if dry_run {
+        say!("[dry-run] Would import:");
+        for target in &changed {
+            say!("  {}", abbreviate_path(target));
+        }
+        let config_diff = diff::unified(&before.to_yaml()?, &config.to_yaml()?, "config (current)", "config (after import)");
+        for line in config_diff.lines() {
+            say!("  {}", line);
+        }
+        report.config_diff = Some(config_diff);
+        return report::emit_structured(&report);
+    }
+
+    config.save()?;
+    for target in &changed {
+        say!("Imported: {}", abbreviate_path(target));
+    }
+    match migration.as_ref().and_then(|m| m.config.targets.keys().next().filter(|_| m.into.is_some())) {
+        // The manager's files are still in place; identical ones can become links
+        Some(target) => say!("Run `amu update {} --adopt-identical` to replace the copies there with links.", abbreviate_path(target)),
+        None => say!("Run `amu restore` to link the imported targets."),
+    }
+    report::emit_structured(&report)
+}
+
+/*
+ * Pin a path in a target to one source
+ * Other sources of the target stop linking the path, and any of their links under it are
+ * replaced by the pinned source's right away. Without --source, the source currently
+ * providing the path is pinned.
+ */
+fn cmd_bless(path: PathBuf, source: Option<PathBuf>) -> Result<()> {
+    let path = config::resolve_link(&path);
+    let mut config = Config::load()?;
+    let (target, relative) = containing_target(&config, &path)?;
+    let sources = config.sources_of(&target).to_vec();
+
+    let source = match source {
+        Some(given) => config.find_source(&target, &given).ok_or_else(|| DotlinkError::NotRegistered {
+            src: config::expand_path(&given),
+            dest: target.clone(),
+        })?,
+        None => links::link_destination(&path)
+            .and_then(|destination| sources.iter().find(|s| destination.starts_with(s)).cloned())
+            .ok_or_else(|| DotlinkError::PathNotManaged(path.clone()))?,
+    };
+    if std::fs::symlink_metadata(source.join(&relative)).is_err() {
+        return Err(DotlinkError::NotProvided { path: relative, src: source });
+    }
+
+    // The pin is saved once the path is linked from the source, not before
+    config.pin(&target, relative.clone(), source.clone());
+    for other in sources.iter().filter(|s| **s != source) {
+        let links = links::links_into(&path, other);
+        links::remove_links(&links, &target);
+    }
+    if source.exists() && target.exists() {
+        let ignore = ignore::patterns_for(&config, &target, &source);
+        restow_source(&config, &source, &target, &ignore)?;
+    }
+    config.save()?;
+
+    say!("Pinned: {} -> {}", relative.display(), abbreviate_path(&source));
+    report::emit_structured(&report::PinReport {
+        command: "bless",
+        path: relative.display().to_string(),
+        source: abbreviate_path(&source),
+    })
+}
+
+fn cmd_unbless(path: PathBuf) -> Result<()> {
+    let path = config::resolve_link(&path);
+    let mut config = Config::load()?;
+    let (target, relative) = containing_target(&config, &path)?;
+
+    let source = config.unpin(&target, &relative).ok_or_else(|| DotlinkError::NotPinned(path.clone()))?;
+    config.save()?;
+
+    say!("Unpinned: {} (was {})", relative.display(), abbreviate_path(&source));
+    report::emit_structured(&report::PinReport {
+        command: "unbless",
+        path: relative.display().to_string(),
+        source: abbreviate_path(&source),
+    })
+}
+
+fn cmd_priority(action: PriorityAction) -> Result<()> {
+    let mut config = Config::load()?;
+    match action {
+        PriorityAction::List { target } => {
+            let target = config.lookup_target(target)?;
+            let target = config.find_target(&target).ok_or(DotlinkError::TargetNotRegistered(target))?;
+            print_priority(&config, &target)
+        }
+        PriorityAction::Set { source, position, target } => {
+            let (source, target) = lookup_pair(&config, &source, target)?;
+            config.move_source(&target, &source, position as usize - 1)?;
+            config.save()?;
+
+            // Free the paths changing hands first, then link every source in the new order
+            if target.exists() {
+                precedence::release(&config, &target);
+                for source in config.sources_of(&target).iter().filter(|s| s.exists()) {
+                    let mut ignore = ignore::patterns_for(&config, &target, source);
+                    ignore.extend(precedence::skip_patterns(&precedence::contested_in(&config, &target, source)));
+                    stow::restow(&config, source, &target, &ignore)?;
+                }
+            }
+
+            let rank = config.sources_of(&target).iter().position(|s| *s == source).unwrap_or_default() + 1;
+            say!("Moved {} to position {}", abbreviate_path(&source), rank);
+            say!();
+            print_priority(&config, &target)
+        }
+    }
+}
+
+/*
+ * Backups made by `--on-conflict backup`
+ * Each backup directory's `backup.yaml` says which target the files came from, so they can
+ * be listed and put back.
+ */
+fn cmd_backup(action: BackupAction) -> Result<()> {
+    match action {
+        BackupAction::List => {
+            let backups = conflict::backups()?;
+            if !report::is_text() {
+                let entries: Vec<_> = backups.iter()
+                    .map(|backup| serde_json::json!({
+                        "name": backup.dir.file_name().map(|n| n.to_string_lossy()),
+                        "target": abbreviate_path(&backup.record.target),
+                        "files": backup.record.files,
+                    }))
+                    .collect();
+                return report::emit(&entries);
+            }
+            if backups.is_empty() {
+                println!("No backups.");
+                return Ok(());
+            }
+            for backup in &backups {
+                let name = backup.dir.file_name().unwrap_or_default().to_string_lossy();
+                println!("{} -> {}", name, abbreviate_path(&backup.record.target));
+                for file in &backup.record.files {
+                    println!("  {}", file.display());
+                }
+            }
+        }
+        BackupAction::Restore { name, dry_run } => {
+            let backup = conflict::find_backup(&name)?;
+            let prefix = if dry_run { "[dry-run] " } else { "" };
+            for restored in conflict::restore_backup(&backup, dry_run)? {
+                let path = abbreviate_path(&backup.record.target.join(&restored.path));
+                match restored.skipped {
+                    None => say!("{}Restored: {}", prefix, path),
+                    Some(reason) => strict::warn(format!("not restored: {} ({})", path, reason)),
+                }
+            }
+            if !dry_run && backup.dir.exists() {
+                say!("Kept {} for what was not restored", abbreviate_path(&backup.dir));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cmd_media(action: MediaAction) -> Result<()> {
+    let mut config = Config::load()?;
+    match action {
+        MediaAction::Mark { source, mount_point } => {
+            let source = media_path(&config, &source)?;
+            let mount_point = media_path(&config, &mount_point)?;
+            if !source.starts_with(&mount_point) {
+                return Err(DotlinkError::SourceOutsideMount { src: source, mount: mount_point });
+            }
+            config.mounts.insert(source.clone(), mount_point.clone());
+            config.save()?;
+            println!("Marked: {} (on {})", abbreviate_path(&source), abbreviate_path(&mount_point));
+        }
+        MediaAction::Unmark { source } => {
+            let source = media_path(&config, &source)?;
+            let mount_point = config.mounts.remove(&source).ok_or_else(|| DotlinkError::NotMarked(source.clone()))?;
+            config.save()?;
+            println!("Unmarked: {} (was on {})", abbreviate_path(&source), abbreviate_path(&mount_point));
+        }
+        MediaAction::List => {
+            if !report::is_text() {
+                let entries: Vec<_> = config.mounts.iter()
+                    .map(|(source, point)| serde_json::json!({
+                        "source": abbreviate_path(source),
+                        "mount_point": abbreviate_path(point),
+                        "mounted": mounts::is_mounted(point),
+                    }))
+                    .collect();
+                return report::emit(&entries);
+            }
+            if config.mounts.is_empty() {
+                println!("No sources marked.");
+                return Ok(());
+            }
+            let mut table = Table::new();
+            for (source, point) in &config.mounts {
+                let state = if mounts::is_mounted(point) { "mounted" } else { "not mounted" };
+                table.row([abbreviate_path(source), abbreviate_path(point), state.to_string()]);
+            }
+            table.print();
+        }
+    }
+    Ok(())
+}
+
+/*
+ * A source or mount point given to `amu media`, which may not exist while unmounted
+ * A marked or registered source matching any form of the path is used as stored; otherwise
+ * the path is made absolute, canonical if it exists.
+ */
+fn media_path(config: &Config, path: &Path) -> Result<PathBuf> {
+    let known = config.mounts.keys().chain(config.targets.values().flatten());
+    if let Some(found) = known.into_iter().find(|known| config::same_path(known, path)) {
+        return Ok(found.clone());
+    }
+    Ok(config::resolve_path(path))
+}
+
+/// A target's sources, highest priority first, with the paths each leaves to another.
+fn print_priority(config: &Config, target: &Path) -> Result<()> {
+    let mut report = report::TargetReport::new(abbreviate_path(target));
+    say!("{} (highest priority first):", abbreviate_path(target));
+    for (rank, source) in config.sources_of(target).iter().enumerate() {
+        let contested = precedence::contested_in(config, target, source);
+        say!("  {}. {}", rank + 1, abbreviate_path(source));
+        print_contested(&contested, "       ");
+        report.sources.push(report::PrioritySource { path: abbreviate_path(source), skipped: skipped_paths(&contested) });
+    }
+    report::emit_structured(&report)
+}
+
+fn cmd_update(
+    target: Option<PathBuf>,
+    all: bool,
+    under: Option<PathBuf>,
+    adopt_identical: bool,
+    on_conflict: OnConflict,
+    fold: Option<bool>,
+    dry_run: bool,
+) -> Result<Exit> {
+    let mut config = Config::load()?;
+    let adopt_identical = adopt_identical || config.adopts_identical();
+
+    // Determine targets
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, under.as_deref())
+    } else {
+        let t = config.lookup_target(target)?;
+        if config.get_sources(&t).is_none() {
+            if !report::is_text() {
+                return report::emit_error("Target not registered").map(|()| Exit::Ok);
+            }
+            println!("Target not registered: {}", abbreviate_path(&t));
+            return Ok(Exit::Ok);
+        }
+        vec![t]
+    };
+
+    let mut report = report::ChangeReport::new("update", dry_run);
+    if targets.is_empty() {
+        say!("{}", no_targets_message(&config, under.as_deref()));
+        return report::emit_structured(&report).map(|()| Exit::Ok);
+    }
+
+    // Fail fast before touching anything if a target cannot be written
+    if let Some(read_only) = targets.iter().find(|t| t.exists() && !links::is_writable(t)) {
+        return Err(DotlinkError::TargetReadOnly(read_only.clone()));
+    }
+    if let Some(fold) = fold {
+        set_folding(&mut config, &targets, fold);
+    }
+    if !dry_run {
+        strict_preflight(&config, &target_pairs(&config, &targets), on_conflict, false)?;
+        if fold.is_some() {
+            config.save()?;
+        }
+    }
+
+    let present = present_sources(&config);
+    let result = parallel::for_each_ordered(
+        &targets,
+        parallel::jobs(),
+        |target| update_target(&config, target, &present, adopt_identical, on_conflict, dry_run),
+        |changes| {
+            report.changes.extend(changes?);
+            Ok::<(), DotlinkError>(())
+        },
+    );
+    // Failures are kept for `amu retry` even when one ends the run
+    retry::save()?;
+    result?;
+
+    report::emit_structured(&report)?;
+    let count = |results: &[&str]| report.changes.iter().filter(|change| results.contains(&change.result)).count();
+    // Sources skipped for not being there are warnings, failed ones errors
+    Ok(Exit::from_counts(count(&["skipped", "unavailable"]), count(&["failed"])))
+}
+
+/// Restow every source of one target, as part of `amu update`.
+fn update_target(
+    config: &Config,
+    target: &Path,
+    present: &BTreeSet<PathBuf>,
+    adopt_identical: bool,
+    on_conflict: OnConflict,
+    dry_run: bool,
+) -> Result<Vec<report::Change>> {
+    let mut changes = Vec::new();
+    let Some(sources) = config.get_sources(target) else { return Ok(changes) };
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    say!("{}Updating {}:", prefix, abbreviate_path(target));
+    hooks::run(config, Hook::PreUpdate, target, sources, dry_run)?;
+    // Held from the first source to the last so a restow by `amu watch` cannot land between
+    // them; hooks run outside it, as they may run amu on the target themselves
+    let lock = if dry_run { None } else { Some(locks::acquire(target)?) };
+    // Links at paths a higher-priority source now wins make way for it
+    if !dry_run && target.exists() {
+        precedence::release(config, target);
+    }
+    for source in sources {
+        let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "updated");
+        let result = if source.exists() {
+            update_source(config, target, source, &mut change, adopt_identical, on_conflict, dry_run)
+        } else if let Some(point) = mounts::unmounted(config, source) {
+            say!("  Skipped (not mounted): {}", abbreviate_path(source));
+            change.result = "skipped";
+            change.message = Some(format!("not mounted: {}", abbreviate_path(point)));
+            Ok(())
+        } else if present.contains(source) {
+            Err(DotlinkError::SourceUnavailable(source.clone()))
+        } else {
+            say!("  Skipped (not found): {}", abbreviate_path(source));
+            change.result = "skipped";
+            change.message = Some("source not found".to_string());
+            Ok(())
+        };
+        match result {
+            Ok(()) if !dry_run && change.result == "updated" => retry::succeeded(target, source),
+            Ok(()) => {}
+            // One missing source must not end an --all run
+            Err(e) if matches!(e, DotlinkError::SourceUnavailable(_)) || !source.exists() => {
+                say!("  Skipped (unavailable): {}", abbreviate_path(source));
+                change.result = "unavailable";
+                change.message = Some("source is no longer available".to_string());
+                if !dry_run {
+                    retry::failed("update", target, source, "source is no longer available");
+                }
+            }
+            // A report names the failed source and goes on; text output stops at the error
+            Err(e) if !report::is_text() => {
+                change.result = "failed";
+                if !dry_run {
+                    retry::failed("update", target, source, &e);
+                }
+                change.message = Some(e.to_string());
+            }
+            Err(e) => {
+                if !dry_run {
+                    retry::failed("update", target, source, &e);
+                }
+                return Err(e);
+            }
+        }
+        changes.push(change);
+    }
+    drop(lock);
+    hooks::run(config, Hook::PostUpdate, target, sources, dry_run)?;
+    Ok(changes)
+}
+
+/// Adopt, resolve conflicts and restow one source of a target.
+fn update_source(
+    config: &Config,
+    target: &Path,
+    source: &Path,
+    change: &mut report::Change,
+    adopt_identical: bool,
+    on_conflict: OnConflict,
+    dry_run: bool,
+) -> Result<()> {
+    let mut ignore = ignore::patterns_for(config, target, source);
+    let contested = precedence::contested_in(config, target, source);
+    ignore.extend(precedence::skip_patterns(&contested));
+    change.skipped = skipped_paths(&contested);
+    let adopted = if adopt_identical {
+        adopt_identical_files(source, target, &ignore, dry_run)?
+    } else {
+        Vec::new()
+    };
+    let resolved = resolve_conflicts(config, on_conflict, source, target, &mut ignore, dry_run)?;
+    change.conflicts = conflict_reports(&resolved);
+    if dry_run {
+        for file in &adopted {
+            say!("    Would adopt: {} (identical to source)", file);
+        }
+        print_resolutions(&resolved, "    ", true);
+        print_contested(&contested, "    ");
+        change.plan = stow::dry_run_restow(config, source, target, &ignore)?;
+        if change.plan.is_empty() {
+            say!("  Would restow: {} (no changes)", abbreviate_path(source));
+        } else {
+            say!("  Would restow: {} ({} links)", abbreviate_path(source), change.plan.links());
+            print_plan_conflicts(&change.plan, "    ");
+        }
+        let stale = renames::find_stale_links(target, source);
+        for rename in renames::detect(source, &stale, &ignore) {
+            say!("    Would rename: {} -> {}", rename.from.display(), rename.to.display());
+        }
+        change.templates = render_templates(config, source, target, &ignore, "    ", true)?;
+    } else {
+        let before = (!report::is_text()).then(|| journal::linked_files(source, target, &[]));
+        let detected = restow_source(config, source, target, &ignore)?;
+        change.delta = before.map(|before| report::LinkDelta::between(&before, &journal::linked_files(source, target, &[])));
+        say!("  Restowed: {}", abbreviate_path(source));
+        for file in &adopted {
+            say!("    Adopted: {} (identical to source)", file);
+        }
+        print_resolutions(&resolved, "    ", false);
+        print_contested(&contested, "    ");
+        for rename in detected {
+            say!("    Renamed: {} -> {}", rename.from.display(), rename.to.display());
+        }
+        change.templates = render_templates(config, source, target, &ignore, "    ", false)?;
+    }
+    Ok(())
+}
+
+/// Sources present now; one missing later has become unavailable (an unmounted drive, say)
+/// rather than never having existed.
+fn present_sources(config: &Config) -> BTreeSet<PathBuf> {
+    config.targets.values().flatten().filter(|s| s.is_dir()).cloned().collect()
+}
+
+/// Every (target, source) pair of the given targets.
+fn target_pairs(config: &Config, targets: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    targets.iter()
+        .flat_map(|target| config.get_sources(target).into_iter().flatten().map(|source| (target.clone(), source.clone())))
+        .collect()
+}
+
+/*
+ * Check the pairs a command is about to link for what `--strict` treats as errors
+ * Sources that would be skipped (not found or not mounted), targets missing or read-only
+ * (unless the command creates them) and conflicts the strategy would leave in place or
+ * stop at. Every pair is checked before anything changes, so a strict run applies all of
+ * it or nothing. Does nothing outside strict mode.
+ */
+fn strict_preflight(config: &Config, pairs: &[(PathBuf, PathBuf)], on_conflict: OnConflict, creates_targets: bool) -> Result<()> {
+    if !strict::is_strict() {
+        return Ok(());
+    }
+    let mut warnings = Vec::new();
+    for (target, source) in pairs {
+        if !source.exists() {
+            warnings.push(match mounts::unmounted(config, source) {
+                Some(point) => format!("{}: not mounted at {}", abbreviate_path(source), abbreviate_path(point)),
+                None => format!("{}: source not found", abbreviate_path(source)),
+            });
+            continue;
+        }
+        if !target.exists() {
+            if !creates_targets {
+                warnings.push(format!("{}: target not found", abbreviate_path(target)));
+            }
+            continue;
+        }
+        if !links::is_writable(target) {
+            warnings.push(format!("{}: target is read-only", abbreviate_path(target)));
+            continue;
+        }
+        if matches!(on_conflict, OnConflict::Skip | OnConflict::Fail) {
+            let mut ignore = ignore::patterns_for(config, target, source);
+            ignore.extend(precedence::skip_patterns(&precedence::contested_in(config, target, source)));
+            let mut sources = config.sources_of(target).to_vec();
+            if config.find_source(target, source).is_none() {
+                sources.push(source.clone());
+            }
+            for path in conflict::find(source, target, &ignore, &sources) {
+                warnings.push(format!("{}: conflict with {}", abbreviate_path(source), abbreviate_path(&target.join(path))));
+            }
+        }
+    }
+    strict::check(&warnings)
+}
+
+/*
+ * Sync targets from a source, one file at a time
+ * After picking targets, every path the source would change is shown: missing links can
+ * be created or skipped, files in the way backed up, adopted, replaced or skipped.
+ * --assume-yes picks every target and the defaults (link, back up) without asking.
+ */
+fn cmd_sync(source: Option<PathBuf>, assume_yes: bool, dry_run: bool) -> Result<()> {
+    use std::io::IsTerminal;
+
+    let config = Config::load()?;
+
+    // Resolve source (defaults to current directory if omitted)
+    let source = match source {
+        Some(s) => normalize_path(&s)?,
+        None => normalize_path(&std::env::current_dir()?)?,
+    };
+
+    // Find targets that reference this source
+    let targets: Vec<PathBuf> = config.targets.keys()
+        .filter(|target| config.find_source(target, &source).is_some())
+        .map(|target| target.to_path_buf())
+        .collect();
+
+    let mut report = report::ChangeReport::new("sync", dry_run);
+    if targets.is_empty() {
+        say!("No targets found for source: {}", abbreviate_path(&source));
+        say!("(This directory is not registered as a source)");
+        return report::emit_structured(&report);
+    }
+
+    if !assume_yes && !std::io::stdin().is_terminal() {
+        return Err(DotlinkError::NeedsTerminal("sync"));
+    }
+
+    say!("Syncing from source: {}\n", abbreviate_path(&source));
+
+    // Interactive selection
+    let selected = if assume_yes { targets } else { select_targets_interactive(&targets)? };
+
+    // Update selected targets
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    for target in selected {
+        let mut change = report::Change::new(abbreviate_path(&source), abbreviate_path(&target), "updated");
+        if !target.exists() {
+            say!("- {} (target not found)", abbreviate_path(&target));
+            change.result = "skipped";
+            change.message = Some("target not found".to_string());
+            report.changes.push(change);
+            continue;
+        }
+        let mut ignore = ignore::patterns_for(&config, &target, &source);
+        let mut sources = config.sources_of(&target).to_vec();
+        if config.find_source(&target, &source).is_none() {
+            sources.push(source.clone());
+        }
+
+        let missing = conflict::missing(&source, &target, &ignore);
+        let conflicts = conflict::find(&source, &target, &ignore, &sources);
+        if !assume_yes && (!missing.is_empty() || !conflicts.is_empty()) {
+            say!("{}:", abbreviate_path(&target));
+        }
+
+        let mut skipped = Vec::new();
+        for path in missing {
+            if !assume_yes && !pick_missing(&path)? {
+                skipped.push(path);
+            }
+        }
+        let mut decided: Vec<(OnConflict, Vec<PathBuf>)> = Vec::new();
+        for path in conflicts {
+            let strategy = if assume_yes { OnConflict::Backup } else { pick_conflict(&source, &target, &path)? };
+            match decided.iter_mut().find(|(s, _)| *s == strategy) {
+                Some((_, paths)) => paths.push(path),
+                None => decided.push((strategy, vec![path])),
+            }
+        }
+
+        ignore.extend(skipped.iter().map(|path| format!("/{}", path.display())));
+        let mut resolved = Vec::new();
+        for (strategy, paths) in decided {
+            resolved.extend(conflict::apply(strategy, &source, &target, paths, &mut ignore, dry_run)?);
+        }
+
+        if dry_run {
+            let plan = stow::dry_run_restow(&config, &source, &target, &ignore)?;
+            say!("{}Would restow: {} ({} links)", prefix, abbreviate_path(&target), plan.links());
+            print_plan_conflicts(&plan, "    ");
+            change.plan = plan;
+        } else {
+            let detected = restow_source(&config, &source, &target, &ignore)?;
+            say!("{} {}", style::ok(), abbreviate_path(&target));
+            for rename in detected {
+                say!("    Renamed: {} -> {}", rename.from.display(), rename.to.display());
+            }
+        }
+        for path in &skipped {
+            say!("    - {} (not linked)", path.display());
+        }
+        print_resolutions(&resolved, "    ", dry_run);
+        change.conflicts = conflict_reports(&resolved);
+        if !skipped.is_empty() {
+            let paths: Vec<String> = skipped.iter().map(|path| path.display().to_string()).collect();
+            change.message = Some(format!("not linked: {}", paths.join(", ")));
+        }
+        report.changes.push(change);
+    }
+
+    report::emit_structured(&report)
+}
+
+/// Ask whether a path missing from the target should be linked.
+fn pick_missing(path: &Path) -> Result<bool> {
+    use dialoguer::Select;
+
+    let selection = Select::new()
+        .with_prompt(format!("  + {} (missing)", path.display()))
+        .items(&["Link", "Skip"])
+        .default(0)
+        .interact_opt()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(selection == Some(0))
+}
+
+/// Ask what to do with a file in the way of a link; Esc skips it.
+fn pick_conflict(source: &Path, target: &Path, path: &Path) -> Result<OnConflict> {
+    use dialoguer::Select;
+
+    let existing = target.join(path);
+    let provided = source.join(path);
+    let state = match (std::fs::read(&existing), std::fs::read(&provided)) {
+        (Ok(a), Ok(b)) if a == b => "identical to source",
+        (Ok(_), Ok(_)) => "differs from source",
+        _ if existing.is_symlink() => "foreign link",
+        _ => "in the way",
+    };
+    let can_diff = state == "differs from source";
+
+    let mut choices = vec!["Back up, then link", "Adopt into source, then link", "Link (delete the existing file)", "Skip"];
+    if can_diff {
+        choices.push("Show diff");
+    }
+    loop {
+        let selection = Select::new()
+            .with_prompt(format!("  {} {} ({})", style::warning(), path.display(), state))
+            .items(&choices)
+            .default(0)
+            .interact_opt()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        return Ok(match selection {
+            Some(0) => OnConflict::Backup,
+            Some(1) => OnConflict::Adopt,
+            Some(2) => OnConflict::Overwrite,
+            Some(4) => {
+                let old = std::fs::read_to_string(&provided).unwrap_or_default();
+                let new = std::fs::read_to_string(&existing).unwrap_or_default();
+                print!("{}", diff::unified(&old, &new, &abbreviate_path(&provided), &abbreviate_path(&existing)));
+                continue;
+            }
+            _ => OnConflict::Skip,
+        });
+    }
+}
+
+/*
+ * Fetch the repositories of registered sources and compare each with its upstream branch,
+ * without merging: "your dotfiles are 12 commits behind" on a machine rarely touched.
+ * Exits with failure when any repository is behind, ahead, or could not be fetched.
+ */
+fn cmd_verify_remote(target: Option<PathBuf>, json: bool) -> Result<Exit> {
+    let config = Config::load()?;
+    let repos = match target {
+        Some(target) => {
+            let target = config.lookup_target(Some(target))?;
+            git::repos(config.get_sources(&target).ok_or(DotlinkError::TargetNotRegistered(target.clone()))?)
+        }
+        None => git::repos(config.targets.values().flatten()),
+    };
+    if repos.is_empty() && !json {
+        say!("No registered source is in a git repository.");
+        return Ok(Exit::Ok);
+    }
+
+    let (repos, summary) = check_remotes(&repos)?;
+    let exit = Exit::from_counts(summary.warning, summary.error);
+    if json {
+        report::emit(&report::VerifyRemoteReport { repos, summary })?;
+    } else {
+        print_remotes(&repos);
+        println!("Summary: {} OK, {} warning, {} error", summary.ok, summary.warning, summary.error);
+    }
+    if exit != Exit::Ok {
+        return Ok(exit);
+    }
+    Ok(Exit::Ok)
+}
+
+/// Fetch each repository (in parallel, up to --jobs) and compare it with its upstream.
+fn check_remotes(repos: &[PathBuf]) -> Result<(Vec<report::RemoteReport>, report::StatusSummary)> {
+    let mut reports = Vec::new();
+    let mut summary = report::StatusSummary::default();
+    parallel::for_each_ordered(repos, parallel::jobs(), |repo| (repo.clone(), git::remote_state(repo)), |(repo, state)| {
+        match state.class() {
+            "ok" => summary.ok += 1,
+            "warning" => summary.warning += 1,
+            _ => summary.error += 1,
+        }
+        reports.push(report::RemoteReport { path: abbreviate_path(&repo), result: state.result(), status: state.class(), state });
+        Ok::<(), DotlinkError>(())
+    })?;
+    Ok((reports, summary))
+}
+
+/// The "Remotes:" section of `verify-remote` and `status --remote`.
+fn print_remotes(remotes: &[report::RemoteReport]) {
+    println!("Remotes:");
+    let mut table = Table::new();
+    for remote in remotes {
+        let mark = match remote.result {
+            "no_upstream" => style::paint("-", style::Tone::Dim),
+            _ => style::mark(remote.status),
+        };
+        table.row([format!("  {}", mark), style::path(&remote.path), format!("({})", remote.state.describe())]);
+    }
+    table.print();
+    println!();
+}
+
+/*
+ * Pull the repositories backing sources, then relink what their new commits changed
+ * Only targets with a source gaining or losing files (or whose templates changed) are
+ * updated; edits to linked files need no relinking. A dry run fetches but leaves work trees
+ * and links alone.
+ */
+fn cmd_pull(on_conflict: OnConflict, dry_run: bool) -> Result<Exit> {
+    let config = Config::load()?;
+    let repos = git::repos(config.targets.values().flatten());
+    let mut report = report::RepoSyncReport::new("pull", dry_run);
+    if repos.is_empty() {
+        say!("No registered source is in a git repository.");
+        return report::emit_structured(&report).map(|()| Exit::Ok);
+    }
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    say!("{}Pulling repositories:", prefix);
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut failed = false;
+    for repo in &repos {
+        let outcome = if !git::has_upstream(repo) {
+            Ok(("skipped", "no upstream branch".to_string()))
+        } else {
+            pull_repo(repo, dry_run).map(|files| {
+                if files.is_empty() {
+                    return ("unchanged", "already up to date".to_string());
+                }
+                let total = files.len();
+                let relinked: Vec<PathBuf> = files.into_iter()
+                    .filter(|(status, path)| *status != 'M' || path.to_string_lossy().ends_with(template::SUFFIX))
+                    .map(|(_, path)| repo.join(path))
+                    .collect();
+                let message = format!("{} file(s) changed, {} to relink", total, relinked.len());
+                changed.extend(relinked);
+                (if dry_run { "would_pull" } else { "pulled" }, message)
+            })
+        };
+        let (result, message) = outcome.unwrap_or_else(|e| {
+            failed = true;
+            ("failed", e.to_string())
+        });
+        let mark = match result {
+            "failed" => style::error(),
+            "skipped" | "unchanged" => style::paint("-", style::Tone::Dim),
+            _ => style::ok(),
+        };
+        say!("  {} {} ({})", mark, abbreviate_path(repo), message);
+        report.repos.push(report::RepoResult { path: abbreviate_path(repo), result, message });
+    }
+
+    let targets: Vec<PathBuf> = config.targets.iter()
+        .filter(|(_, sources)| sources.iter().any(|s| changed.iter().any(|path| path.starts_with(s))))
+        .map(|(target, _)| target.to_path_buf())
+        .collect();
+    say!();
+    if targets.is_empty() {
+        say!("No targets need updating.");
+    } else {
+        let present = present_sources(&config);
+        let adopt_identical = config.adopts_identical();
+        for target in &targets {
+            report.changes.extend(update_target(&config, target, &present, adopt_identical, on_conflict, dry_run)?);
+        }
+    }
+
+    report::emit_structured(&report)?;
+    if failed {
+        return Ok(Exit::Errors);
+    }
+    Ok(Exit::Ok)
+}
+
+/// Fast-forward a repository (in a dry run, only fetch) and return the files the new
+/// commits change.
+fn pull_repo(repo: &Path, dry_run: bool) -> Result<Vec<(char, PathBuf)>> {
+    let Some(before) = git::head(repo) else { return Ok(Vec::new()) };
+    if dry_run {
+        git::fetch(repo)?;
+        return git::changed_files(repo, &before, "@{u}");
+    }
+    git::pull(repo)?;
+    git::changed_files(repo, &before, "HEAD")
+}
+
+/*
+ * Commit everything in the repositories backing sources and push it
+ * A repository without an upstream branch is committed to but not pushed.
+ */
+fn cmd_push(message: Option<String>, dry_run: bool) -> Result<Exit> {
+    let config = Config::load()?;
+    let repos = git::repos(config.targets.values().flatten());
+    let mut report = report::RepoSyncReport::new("push", dry_run);
+    if repos.is_empty() {
+        say!("No registered source is in a git repository.");
+        return report::emit_structured(&report).map(|()| Exit::Ok);
+    }
+    let message = message.unwrap_or_else(|| {
+        format!("Update dotfiles from {}", config::hostname().unwrap_or_else(|| "this machine".to_string()))
+    });
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    say!("{}Pushing repositories:", prefix);
+    let mut failed = false;
+    for repo in &repos {
+        let state = git::state(repo).unwrap_or_default();
+        let upstream = git::has_upstream(repo);
+        let outcome = if dry_run {
+            let pending = state.uncommitted > 0 || state.ahead > 0;
+            Ok(if upstream && pending {
+                ("would_push", state.describe())
+            } else if state.uncommitted > 0 {
+                ("would_commit", format!("{}; no upstream branch", state.describe()))
+            } else if !upstream {
+                ("skipped", "no upstream branch".to_string())
+            } else {
+                ("unchanged", "nothing to push".to_string())
+            })
+        } else {
+            git::commit_all(repo, &message).and_then(|committed| {
+                let ahead = git::state(repo).unwrap_or_default().ahead;
+                if !upstream {
+                    let result = if committed { "committed" } else { "skipped" };
+                    Ok((result, "no upstream branch".to_string()))
+                } else if ahead == 0 {
+                    Ok(("unchanged", "nothing to push".to_string()))
+                } else {
+                    git::push(repo).map(|_| ("pushed", format!("{} commit(s)", ahead)))
+                }
+            })
+        };
+        let (result, detail) = outcome.unwrap_or_else(|e| {
+            failed = true;
+            ("failed", e.to_string())
+        });
+        let mark = match result {
+            "failed" => style::error(),
+            "unchanged" | "skipped" => style::paint("-", style::Tone::Dim),
+            _ => style::ok(),
+        };
+        say!("  {} {} ({})", mark, abbreviate_path(repo), detail);
+        report.repos.push(report::RepoResult { path: abbreviate_path(repo), result, message: detail });
+    }
+
+    report::emit_structured(&report)?;
+    if failed {
+        return Ok(Exit::Errors);
+    }
+    Ok(Exit::Ok)
+}
+
+/*
+ * Run git in the repository of registered sources
+ * Without --source, the repository containing the current directory is used if it holds a
+ * source, otherwise the only one there is. git's exit code becomes amu's.
+ */
+fn cmd_git(source: Option<PathBuf>, all: bool, args: Vec<String>) -> Result<()> {
+    let config = Config::load()?;
+    let repos = match source {
+        Some(source) => {
+            let source = normalize_path(&source)?;
+            vec![git::repo_root(&source).ok_or(DotlinkError::NotARepository(source))?]
+        }
+        None => {
+            let repos = git::repos(config.targets.values().flatten());
+            let current = std::env::current_dir().ok().and_then(|dir| git::repo_root(&dir));
+            match current.filter(|repo| repos.contains(repo)) {
+                Some(repo) if !all => vec![repo],
+                _ if repos.is_empty() => return Err(DotlinkError::NoRepository),
+                _ if all || repos.len() == 1 => repos,
+                _ => {
+                    let listed: Vec<String> = repos.iter().map(|r| abbreviate_path(r)).collect();
+                    return Err(DotlinkError::AmbiguousRepository(listed.join(", ")));
+                }
+            }
+        }
+    };
+
+    let mut failed = 0;
+    for repo in &repos {
+        if all {
+            println!("==> {}", abbreviate_path(repo));
+        }
+        let code = git::passthrough(repo, &args)?;
+        if code != 0 {
+            failed = code;
+        }
+        if all {
+            println!();
+        }
+    }
+    if failed != 0 {
+        // git's own code cannot be passed on from a batch step, which fails instead
+        if batch::is_running() {
+            return Err(DotlinkError::GitError(format!("exited with {}", failed)));
+        }
+        exit_with(failed, true);
+    }
+    Ok(())
+}
+
+/*
+ * Bring every target up to date in one go
+ * Pulls the git repositories backing sources, restows all targets, prunes entries whose
+ * source was deleted along with dangling links, then reports a status summary.
+ * Exits with status 1 if any step failed or a source is left in error.
+ */
+fn cmd_sync_all(dry_run: bool, json: bool) -> Result<Exit> {
+    use serde_json::{json, Value};
+
+    let mut config = Config::load()?;
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    let mut failed = false;
+
+    // Pull each repository once, however many sources live in it
+    let repos = git::repos(config.targets.values().flatten());
+
+    let mut pulled: Vec<Value> = Vec::new();
+    if !json && !repos.is_empty() {
+        println!("{}Pulling repositories:", prefix);
+    }
+    for repo in &repos {
+        let (result, message) = if !git::has_upstream(repo) {
+            ("skipped", Some("no upstream branch".to_string()))
+        } else if dry_run {
+            ("would_pull", None)
+        } else {
+            match git::pull(repo) {
+                Ok(message) => ("ok", Some(message)),
+                Err(e) => {
+                    failed = true;
+                    ("failed", Some(e.to_string()))
+                }
+            }
+        };
+        if !json {
+            let mark = match result {
+                "ok" | "would_pull" => style::ok(),
+                "failed" => style::error(),
+                _ => style::paint("-", style::Tone::Dim),
+            };
+            match &message {
+                Some(message) => println!("  {} {} ({})", mark, abbreviate_path(repo), message),
+                None => println!("  {} {}", mark, abbreviate_path(repo)),
+            }
+        }
+        pulled.push(json!({"repo": repo, "result": result, "message": message}));
+    }
+
+    // Restow every existing source; a failure is reported and the run continues
+    let mut updated: Vec<Value> = Vec::new();
+    if !json {
+        println!("{}Updating targets:", prefix);
+    }
+    for (target, sources) in &config.targets {
+        if !target.exists() {
+            continue;
+        }
+        for source in sources.iter().filter(|s| s.exists()) {
+            let ignore = ignore::patterns_for(&config, target, source);
+            let outcome = if dry_run {
+                stow::dry_run_restow(&config, source, target, &ignore).map(|plan| format!("{} links", plan.links()))
+            } else {
+                restow_source(&config, source, target, &ignore).map(|renamed| format!("{} renamed", renamed.len()))
+            };
+            let (result, message) = match outcome {
+                Ok(message) => (if dry_run { "would_restow" } else { "restowed" }, message),
+                Err(e) => {
+                    failed = true;
+                    ("failed", e.to_string())
+                }
+            };
+            if !json {
+                let mark = if result == "failed" { style::error() } else { style::ok() };
+                println!("  {} {} -> {} ({})", mark, abbreviate_path(source), abbreviate_path(target), message);
+            }
+            updated.push(json!({"target": target, "source": source, "result": result, "message": message}));
+        }
+    }
+
+    // Prune entries whose source was deleted; a missing parent suggests an unmounted
+    // volume rather than a deletion, so those entries are kept
+    let dead: Vec<(PathBuf, PathBuf)> = config.targets.iter()
+        .flat_map(|(target, sources)| sources.iter().map(move |source| (target.to_path_buf(), source.clone())))
+        .filter(|(_, source)| !source.exists() && source.parent().is_some_and(|parent| parent.is_dir()))
+        .collect();
+    let mut pruned: Vec<Value> = Vec::new();
+    let mut cleaned: Vec<Value> = Vec::new();
+    if !json {
+        println!("{}Pruning:", prefix);
+    }
+    for (target, source) in &dead {
+        if !dry_run {
+            config.remove_source(target, source)?;
+        }
+        if !json {
+            let verb = if dry_run { "Would prune" } else { "Pruned" };
+            println!("  {}: {} -> {} (source not found)", verb, abbreviate_path(source), abbreviate_path(target));
+        }
+        pruned.push(json!({"target": target, "source": source}));
+    }
+    if !dry_run && !dead.is_empty() {
+        config.save()?;
+    }
+    for target in config.targets.keys().filter(|t| t.exists()) {
+        let dangling = links::find_dangling_links(target);
+        if dangling.is_empty() {
+            continue;
+        }
+        if !dry_run {
+            links::cleanup_dangling_links(target, &dangling);
+        }
+        if !json {
+            let verb = if dry_run { "Would clean" } else { "Cleaned" };
+            println!("  {} {} dangling link(s) in {}", verb, dangling.len(), abbreviate_path(target));
+        }
+        cleaned.push(json!({"target": target, "links": dangling.len()}));
+    }
+    if !json && pruned.is_empty() && cleaned.is_empty() {
+        println!("  Nothing to prune.");
+    }
+
+    // Summarize the resulting state
+    let (mut ok, mut warning, mut error) = (0, 0, 0);
+    for (target, sources) in &config.targets {
+        for source in sources {
+            let ignore = ignore::patterns_for(&config, target, source);
+            match check_source_status(&config, source, target, &ignore).class() {
+                "ok" => ok += 1,
+                "warning" => warning += 1,
+                _ => error += 1,
+            }
+        }
+    }
+
+    if json {
+        let report = json!({
+            "dry_run": dry_run,
+            "pulled": pulled,
+            "updated": updated,
+            "pruned": pruned,
+            "cleaned": cleaned,
+            "summary": {"ok": ok, "warning": warning, "error": error},
+        });
+        println!("{report}");
+    } else {
+        println!("\nSummary: {} OK, {} warning, {} error", ok, warning, error);
+    }
+
+    if failed {
+        return Ok(Exit::Errors);
+    }
+    if error > 0 || warning > 0 {
+        return Ok(Exit::from_counts(warning, error));
+    }
+    Ok(Exit::Ok)
+}
+
+/*
+ * Interactively select targets
+ */
+fn select_targets_interactive(targets: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    use dialoguer::MultiSelect;
+
+    // "All" option + each target
+    let mut items: Vec<String> = vec!["All".to_string()];
+    items.extend(targets.iter().map(|t| abbreviate_path(t)));
+
+    // Default is all unselected (Esc to cancel)
+    let selections = MultiSelect::new()
+        .with_prompt("Select targets to update (Esc to cancel)")
+        .items(&items)
+        .interact_opt()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let selections = match selections {
+        None => {
+            say!("Cancelled.");
+            return Ok(vec![]);
+        }
+        Some(s) if s.is_empty() => {
+            say!("No targets selected.");
+            return Ok(vec![]);
+        }
+        Some(s) => s,
+    };
+
+    // If "All" is selected
+    if selections.contains(&0) {
+        Ok(targets.to_vec())
+    } else {
+        // Return selected targets (index 0 is "All", so subtract 1)
+        let result: Vec<PathBuf> = selections.iter()
+            .filter(|&&i| i > 0)
+            .map(|&i| targets[i - 1].clone())
+            .collect();
+        Ok(result)
+    }
+}
+
+fn cmd_list(target: Option<PathBuf>, all: bool, recursive: bool, verbose: bool, tree: bool) -> Result<()> {
+    let config = Config::load()?;
+    let json = !report::is_text();
+
+    // Determine targets
+    let target_list: Vec<PathBuf> = if all {
+        all_targets(&config, None)
+    } else {
+        let t = config.lookup_target(target)?;
+        if recursive {
+            // Recursive mode: collect the specified target and registered targets in its subdirectories
+            let mut targets: Vec<PathBuf> = all_targets(&config, Some(&t));
+            // Sort by path (parent directories come first)
+            targets.sort();
+            if targets.is_empty() {
+                if json {
+                    return report::emit_error("Target not registered");
+                }
+                println!("Target not registered: {}", abbreviate_path(&t));
+                return Ok(());
+            }
+            targets
+        } else if config.get_sources(&t).is_some() {
+            vec![t]
+        } else {
+            if json {
+                return report::emit_error("Target not registered");
+            }
+            println!("Target not registered: {}", abbreviate_path(&t));
+            return Ok(());
+        }
+    };
+
+    // Asking stow for its version is only worth it when the report is printed
+    let environment = if json { report::Environment::current() } else { report::Environment::default() };
+    let mut report = report::ListReport { targets: Vec::new(), environment };
+
+    if target_list.is_empty() {
+        if json {
+            return report::emit(&report);
+        }
+        println!("No targets registered.");
+        return Ok(());
+    }
+    if tree && !json {
+        print_list_tree(&config, &target_list, verbose);
+        return Ok(());
+    }
+
+    for target in &target_list {
+        say!("{}:", style::path(&abbreviate_path(target)));
+        let mut target_report = report::TargetReport::new(abbreviate_path(target));
+        // Sources and links are aligned separately, their columns differ
+        let mut table = Table::new();
+        let mut links_table = Table::new();
+        if let Some(sources) = config.get_sources(target) {
+            if verbose {
+                table.line("  sources:");
+                for source in sources {
+                    let mut ignore = ignore::patterns_for(&config, target, source);
+                    ignore.extend(precedence::skip_patterns(&precedence::contested_in(&config, target, source)));
+                    let (status, link_count) = source_health(&config, source, target, &ignore);
+                    let folded = match status {
+                        SourceStatus::Ok { folded, .. } => folded,
+                        _ => 0,
+                    };
+                    let profile = config.profile_of(target, source);
+                    table.row([
+                        &format!("    {}", health_mark(&status)),
+                        &style::path(&abbreviate_path(source)),
+                        &format!(
+                            "({}{})",
+                            status::describe_links(link_count, folded),
+                            profile.map(|profile| format!(", profile: {}", profile)).unwrap_or_default()
+                        ),
+                    ]);
+                    target_report.sources.push(report::ListSource {
+                        path: abbreviate_path(source),
+                        profile: profile.map(str::to_string),
+                        link_count: Some(link_count),
+                        folded_count: Some(folded).filter(|&n| n > 0),
+                        status: Some(status.class()),
+                    });
+                }
+                let links = collect_symlinks(target, sources);
+                if !links.is_empty() {
+                    links_table.line("  links:");
+                    for (link_path, link_target) in &links {
+                        links_table.row([format!("    {}", style::path(&abbreviate_path(link_path))), "->".to_string(), abbreviate_path(link_target)]);
+                    }
+                }
+                target_report.links = Some(links.iter()
+                    .map(|(link, destination)| report::LinkReport {
+                        link: abbreviate_path(link),
+                        destination: abbreviate_path(destination),
+                    })
+                    .collect());
+            } else {
+                for source in sources {
+                    let profile = config.profile_of(target, source);
+                    let note = profile.map(|profile| format!("(profile: {})", profile)).unwrap_or_default();
+                    table.row(["  -", &style::path(&abbreviate_path(source)), &note]);
+                    target_report.sources.push(report::ListSource {
+                        path: abbreviate_path(source),
+                        profile: profile.map(str::to_string),
+                        link_count: None,
+                        folded_count: None,
+                        status: None,
+                    });
+                }
+            }
+        }
+        report.targets.push(target_report);
+        if !json {
+            table.print();
+            links_table.print();
+            println!();
+        }
+    }
+
+    if json {
+        report::emit(&report)?;
+    }
+    Ok(())
+}
+
+/*
+ * `amu list --tree`
+ * Each target is drawn with its sources, followed by the listed targets inside it. With
+ * --verbose every source shows its health and the links into it by directory: ok, broken
+ * (dangling), or foreign (the source's path in the target is a link to somewhere else).
+ */
+fn print_list_tree(config: &Config, targets: &[PathBuf], verbose: bool) {
+    let roots: Vec<&PathBuf> = targets.iter()
+        .filter(|t| !targets.iter().any(|other| other != *t && t.starts_with(other)))
+        .collect();
+    for root in roots {
+        let label = format!("{}/", abbreviate_path(root).trim_end_matches('/'));
+        for line in target_node(config, root, label, targets, verbose).render() {
+            println!("{}", line);
+        }
+        println!();
+    }
+}
+
+fn target_node(config: &Config, target: &Path, label: String, targets: &[PathBuf], verbose: bool) -> tree::Node {
+    let mut node = tree::Node::new(label);
+    for source in config.get_sources(target).map(Vec::as_slice).unwrap_or_default() {
+        let profile = config.profile_of(target, source).map(|profile| format!("profile: {}", profile));
+        if !verbose {
+            let note = profile.map(|profile| style::paint(&format!(" ({})", profile), style::Tone::Dim)).unwrap_or_default();
+            node.push(tree::Node::new(format!("{}{}", abbreviate_path(source), note)));
+            continue;
+        }
+        let mut ignore = ignore::patterns_for(config, target, source);
+        ignore.extend(precedence::skip_patterns(&precedence::contested_in(config, target, source)));
+        let (status, link_count) = source_health(config, source, target, &ignore);
+        let folded = match status {
+            SourceStatus::Ok { folded, .. } => folded,
+            _ => 0,
+        };
+        let note = [Some(status::describe_links(link_count, folded)), profile].into_iter().flatten().collect::<Vec<_>>().join(", ");
+        let mut source_node = tree::Node::new(format!("{} {} {}", health_mark(&status), style::path(&abbreviate_path(source)), style::paint(&format!("({})", note), style::Tone::Dim)));
+        for (relative, label) in tree_links(target, source, &ignore) {
+            source_node.insert_path(&relative, |_| label.clone());
+        }
+        node.push(source_node);
+    }
+
+    // Listed targets directly inside this one
+    let inside = |t: &PathBuf, dir: &Path| t.as_path() != dir && t.starts_with(dir);
+    for child in targets.iter().filter(|t| inside(t, target)) {
+        if targets.iter().any(|other| inside(other, target) && inside(child, other)) {
+            continue;
+        }
+        let relative = child.strip_prefix(target).unwrap_or(child);
+        node.push(target_node(config, child, format!("{}/", relative.display()), targets, verbose));
+    }
+    node
+}
+
+/// Links for a source's part of the tree, by target-relative path, labelled with their state.
+fn tree_links(target: &Path, source: &Path, ignore: &[String]) -> BTreeMap<PathBuf, String> {
+    let name = |relative: &Path, suffix: &str| {
+        format!("{}{}", relative.file_name().unwrap_or_default().to_string_lossy(), suffix)
+    };
+    let mut links = BTreeMap::new();
+    for (link, _) in collect_symlinks(target, std::slice::from_ref(&source.to_path_buf())) {
+        let relative = link.strip_prefix(target).unwrap_or(&link).to_path_buf();
+        let label = if !link.exists() {
+            style::paint(&name(&relative, " (broken)"), style::Tone::Error)
+        } else if link.is_dir() {
+            style::paint(&name(&relative, "/"), style::Tone::Ok)
+        } else {
+            style::paint(&name(&relative, ""), style::Tone::Ok)
+        };
+        links.insert(relative, label);
+    }
+    for relative in list_source_files(source, ignore) {
+        let at = target.join(&relative);
+        if links.contains_key(&relative) || !at.is_symlink() {
+            continue;
+        }
+        let destination = std::fs::read_link(&at).unwrap_or_default();
+        let label = name(&relative, &format!(" (foreign -> {})", abbreviate_path(&destination)));
+        links.insert(relative, style::paint(&label, style::Tone::Warning));
+    }
+    links
+}
+
+/// Compact health indicator for a source status in the current theme (✓ ok, ! warning, ✗ error).
+fn health_mark(status: &SourceStatus) -> String {
+    style::mark(status.class())
+}
+
+fn collect_symlinks(target: &Path, sources: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    let mut links = Vec::new();
+    collect_symlinks_recursive(sources, target, &mut links);
+    links.sort();
+    links
+}
+
+fn collect_symlinks_recursive(sources: &[PathBuf], current: &Path, links: &mut Vec<(PathBuf, PathBuf)>) {
+    if let Some(entries) = unreadable::read_dir(current) {
+        for entry in entries {
+            let path = entry.path();
+            if path.is_symlink() {
+                // Check if this symlink points to one of our sources
+                if let Some(abs_target) = links::link_destination(&path) {
+                    for source in sources {
+                        if abs_target.starts_with(source) {
+                            links.push((path.clone(), abs_target));
+                            break;
+                        }
+                    }
+                }
+            } else if path.is_dir() {
+                collect_symlinks_recursive(sources, &path, links);
+            }
+        }
+    }
+}
+
+/*
+ * Show what `amu update` would change, file by file
+ * Each source is compared as update would link it (filters, priorities and templates
+ * included) without touching anything. --name-only prints just the target paths.
+ */
+fn cmd_diff(target: Option<PathBuf>, all: bool, under: Option<PathBuf>, name_only: bool, json: bool) -> Result<()> {
+    let config = Config::load()?;
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, under.as_deref())
+    } else {
+        let t = config.lookup_target(target)?;
+        let t = config.find_target(&t).ok_or(DotlinkError::TargetNotRegistered(t))?;
+        vec![t]
+    };
+
+    let mut report = report::DiffReport { targets: Vec::new() };
+    let mut names = BTreeSet::new();
+    let mut table = Table::new();
+    for target in &targets {
+        let mut target_report = report::TargetReport::new(abbreviate_path(target));
+        let symlinks = links::find_all_symlinks(target);
+        table.line(format!("{}:", abbreviate_path(target)));
+        for source in config.get_sources(target).into_iter().flatten() {
+            if !source.exists() || !target.exists() {
+                table.row(["  -", &abbreviate_path(source), "(skipped: source or target not found)"]);
+                continue;
+            }
+            let pending = diff::pending(&config, target, source, &symlinks)?;
+            let files = pending.added.iter().chain(&pending.removed).chain(pending.changed.iter().map(|(path, _)| path));
+            names.extend(files.map(|path| target.join(path)));
+            if pending.is_empty() {
+                table.row([format!("  {}", style::ok()).as_str(), &abbreviate_path(source), "(no changes)"]);
+            } else {
+                table.line(format!("  {}:", abbreviate_path(source)));
+            }
+            for path in &pending.added {
+                table.line(format!("    + {}", path.display()));
+            }
+            for path in &pending.removed {
+                table.line(format!("    - {}", path.display()));
+            }
+            for (path, content) in &pending.changed {
+                table.line(format!("    ~ {}", path.display()));
+                match content {
+                    diff::ContentDiff::Text(text) => {
+                        for line in text.lines() {
+                            table.line(format!("        {}", line));
+                        }
+                    }
+                    other => table.line(format!("        ({})", other.summary())),
+                }
+            }
+            for conflict in &pending.conflicts {
+                table.line(format!("    {} {}", style::warning(), conflict));
+            }
+
+            let display = |paths: &[PathBuf]| paths.iter().map(|p| p.display().to_string()).collect();
+            target_report.sources.push(report::PendingSource {
+                path: abbreviate_path(source),
+                added: display(&pending.added),
+                removed: display(&pending.removed),
+                changed: pending.changed.into_iter()
+                    .map(|(path, content)| {
+                        let result = content.summary();
+                        let diff = match content {
+                            diff::ContentDiff::Text(text) => Some(text),
+                            _ => None,
+                        };
+                        report::FileDiff { file: path.display().to_string(), result, diff }
+                    })
+                    .collect(),
+                conflicts: pending.conflicts,
+            });
+        }
+        report.targets.push(target_report);
+    }
+
+    if json {
+        return report::emit(&report);
+    }
+    if name_only {
+        for name in &names {
+            println!("{}", abbreviate_path(name));
+        }
+    } else {
+        table.print();
+    }
+    Ok(())
+}
+
+/*
+ * Report (and with --adopt, take in) what managed directories hold that no source provides
+ * Adopted orphans are moved into the source managing their directory, which is then
+ * restowed so they are linked back in place.
+ */
+fn cmd_orphans(
+    target: Option<PathBuf>,
+    all: bool,
+    under: Option<PathBuf>,
+    ignore: Vec<String>,
+    adopt: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let config = Config::load()?;
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, under.as_deref())
+    } else {
+        let t = config.lookup_target(target)?;
+        let t = config.find_target(&t).ok_or(DotlinkError::TargetNotRegistered(t))?;
+        vec![t]
+    };
+    let skip = [config.orphan_ignore.clone(), ignore].concat();
+
+    let mut report = report::OrphansReport { targets: Vec::new() };
+    let mut found = 0;
+    for target in &targets {
+        let orphans = orphans::find(&config, target, &skip);
+        if orphans.is_empty() {
+            continue;
+        }
+        found += orphans.len();
+        say!("{}:", abbreviate_path(target));
+        let mut entries = Vec::new();
+        for orphan in &orphans {
+            let slash = if orphan.is_dir { "/" } else { "" };
+            say!("  ? {}{} (in {})", orphan.path.display(), slash, abbreviate_path(&orphan.source));
+            entries.push(report::OrphanReport {
+                path: orphan.path.display().to_string(),
+                kind: if orphan.is_dir { "directory" } else { "file" },
+                source: abbreviate_path(&orphan.source),
+                adopted: false,
+            });
+        }
+
+        if adopt {
+            if !links::is_writable(target) {
+                return Err(DotlinkError::TargetReadOnly(target.clone()));
+            }
+            let mut by_source: BTreeMap<&Path, Vec<PathBuf>> = BTreeMap::new();
+            for orphan in &orphans {
+                by_source.entry(&orphan.source).or_default().extend(adopt::real_files(target, &orphan.path));
+            }
+            for (source, files) in by_source {
+                for file in &files {
+                    let verb = if dry_run { "Would adopt" } else { "Adopted" };
+                    say!("    {}: {} -> {}", verb, file.display(), abbreviate_path(source));
+                }
+                if !dry_run && !files.is_empty() {
+                    adopt::adopt(source, target, &files)?;
+                    stow::restow(&config, source, target, &ignore::patterns_for(&config, target, source))?;
+                }
+                for entry in entries.iter_mut() {
+                    let adopted = files.iter().any(|f| f.starts_with(&entry.path));
+                    entry.adopted |= adopted && !dry_run;
+                }
+            }
+        }
+        say!();
+        report.targets.push(report::TargetOrphans { path: abbreviate_path(target), orphans: entries });
+    }
+
+    if found == 0 {
+        say!("No orphans found.");
+    } else if !adopt {
+        say!("{} orphan(s); take them into their source with --adopt, or hide them with --ignore", found);
+    }
+    report::emit_structured(&report)
+}
+
+/// Print the diff between a real file in the target and its source counterpart, indented under the file entry
+fn add_content_diff(table: &mut Table, target_file: &Path, source_file: &Path) {
+    match diff::compare_files(target_file, source_file) {
+        diff::ContentDiff::Text(text) => {
+            for line in text.lines() {
+                table.line(format!("        {}", line));
+            }
+        }
+        other => table.line(format!("        ({})", other.summary())),
+    }
+}
+
+struct StatusOptions {
+    recursive: bool,
+    diff_content: bool,
+    since: Option<std::time::Duration>,
+    json: bool,
+    notify: bool,
+    no_cache: bool,
+    remote: bool,
+    fix: Option<Fix>,
+}
+
+/// `status --fix`: what to do with real files in the way of links, and whether it is confirmed
+#[derive(Clone, Copy)]
+struct Fix {
+    real_files: Option<OnConflict>,
+    yes: bool,
+}
+
+fn cmd_status(target: Option<PathBuf>, all: bool, under: Option<PathBuf>, options: StatusOptions) -> Result<Exit> {
+    let StatusOptions { recursive, diff_content, since, json, notify, no_cache, remote, fix } = options;
+    let config = Config::load()?;
+    if notify && config.notify.is_empty() {
+        strict::warn("--notify has no effect; set notify.webhook or notify.command in the config");
+    }
+
     // Determine targets
     let target_list: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
+        all_targets(&config, under.as_deref())
     } else {
-        let t = resolve_target(target)?;
+        let t = config.lookup_target(target)?;
         if recursive {
             // Recursive mode: collect the specified target and registered targets in its subdirectories
-            let mut targets: Vec<PathBuf> = config.targets.keys()
-                .filter(|registered| registered.starts_with(&t))
-                .cloned()
-                .collect();
+            let mut targets: Vec<PathBuf> = all_targets(&config, Some(&t));
             // Sort by path (parent directories come first)
             targets.sort();
             if targets.is_empty() {
                 if json {
-                    println!("{{\"error\": \"Target not registered\"}}");
+                    report::emit_error("Target not registered")?;
                 } else {
                     println!("Target not registered: {}", abbreviate_path(&t));
                 }
-                return Ok(());
+                return Ok(Exit::Ok);
+            }
+            targets
+        } else if config.get_sources(&t).is_some() {
+            vec![t]
+        } else {
+            if json {
+                report::emit_error("Target not registered")?;
+            } else {
+                println!("Target not registered: {}", abbreviate_path(&t));
+            }
+            return Ok(Exit::Ok);
+        }
+    };
+
+    let mut report = report::StatusReport {
+        targets: Vec::new(),
+        summary: report::StatusSummary::default(),
+        cached_at: None,
+        repairs: None,
+        unreadable: Vec::new(),
+        remotes: None,
+        environment: if json || notify { report::Environment::current() } else { report::Environment::default() },
+    };
+
+    if target_list.is_empty() {
+        if json {
+            report::emit(&report)?;
+        } else {
+            println!("{}", no_targets_message(&config, under.as_deref()));
+        }
+        return Ok(Exit::Ok);
+    }
+
+    if let Some(fix) = fix {
+        report.repairs = Some(fix_targets(&config, &target_list, fix));
+    }
+    // Fetched first, so each source's `git:` line counts against the fetched upstream
+    let remotes = if remote {
+        let sources = target_list.iter().filter_map(|target| config.get_sources(target)).flatten();
+        Some(check_remotes(&git::repos(sources))?)
+    } else {
+        None
+    };
+
+    // A window reaching back before 1970 takes in everything
+    let cutoff = since.map(|window| std::time::SystemTime::now().checked_sub(window).unwrap_or(std::time::UNIX_EPOCH));
+    // The daemon's statuses predate the repairs
+    let cache = if no_cache || fix.is_some() { None } else { cache::StatusCache::load_fresh() };
+    let cached = |target: &Path| cache.as_ref().and_then(|c| c.sources(&config, target));
+    if target_list.iter().any(|t| cached(t).is_some()) {
+        report.cached_at = cache.as_ref().map(|c| c.updated_at);
+    }
+
+    parallel::for_each_ordered(
+        &target_list,
+        parallel::jobs(),
+        |target| status_target(&config, target, cached(target), cutoff, diff_content),
+        |outcome| {
+            if let Some((target_report, summary)) = outcome {
+                report.targets.push(target_report);
+                report.summary.ok += summary.ok;
+                report.summary.warning += summary.warning;
+                report.summary.error += summary.error;
+            }
+            Ok::<(), DotlinkError>(())
+        },
+    )?;
+
+    if let Some((remotes, summary)) = remotes {
+        if !json {
+            print_remotes(&remotes);
+        }
+        report.summary.ok += summary.ok;
+        report.summary.warning += summary.warning;
+        report.summary.error += summary.error;
+        report.remotes = Some(remotes);
+    }
+    let report::StatusSummary { ok, warning, error } = report.summary;
+    report.unreadable = unreadable::skipped();
+    if json {
+        report::emit(&report)?;
+    } else {
+        println!("Summary: {} OK, {} warning, {} error", ok, warning, error);
+        if let (Some(_), Some(cache)) = (report.cached_at, &cache) {
+            println!("(cached by amu daemon {}; --no-cache to check now)", cache::describe_age(cache.age()));
+        }
+    }
+    if error > 0 || warning > 0 {
+        if notify {
+            if let Err(e) = notify::send(&config.notify, &report::to_json(&report)?) {
+                strict::warn(e);
+            }
+        }
+        return Ok(Exit::from_counts(warning, error));
+    }
+
+    Ok(Exit::Ok)
+}
+
+/// Repair every target for `status --fix`, printing what was done, and total the repairs.
+fn fix_targets(config: &Config, targets: &[PathBuf], fix: Fix) -> report::StatusRepairs {
+    let mut total = report::StatusRepairs::default();
+    for target in targets {
+        let repairs = fix_target(config, target, fix);
+        total.broken_removed += repairs.broken_removed;
+        total.links_created += repairs.links_created;
+        total.files_resolved += repairs.files_resolved;
+        total.files_left += repairs.files_left;
+        total.failed += repairs.failed;
+    }
+
+    let report::StatusRepairs { broken_removed, links_created, files_resolved, files_left, failed } = total;
+    if broken_removed + links_created + files_resolved + files_left + failed == 0 {
+        say!("Nothing to repair.\n");
+        return total;
+    }
+    say!(
+        "Repaired: {} broken link(s) removed, {} link(s) created, {} real file(s) moved",
+        broken_removed, links_created, files_resolved
+    );
+    if files_left > 0 {
+        match fix.real_files {
+            Some(_) => say!("{} real file(s) left in place; rerun with --yes to move them", files_left),
+            None => say!("{} real file(s) left in place; use --real-files backup|adopt with --yes", files_left),
+        }
+    }
+    if failed > 0 {
+        say!("{} source(s) could not be relinked", failed);
+    }
+    say!();
+    total
+}
+
+/*
+ * Repair one target for `status --fix`
+ * For each source, broken links at paths it provides are removed, real files in the way are
+ * backed up or adopted when --real-files is given and --yes confirms it (left in place
+ * otherwise), and the source is linked again so missing links are created. Sources whose
+ * status is an error, on unmounted media or in a read-only target are left alone.
+ */
+fn fix_target(config: &Config, target: &Path, fix: Fix) -> report::StatusRepairs {
+    let mut repairs = report::StatusRepairs::default();
+    let Some(sources) = config.get_sources(target) else { return repairs };
+    let mut lines = Vec::new();
+    let mut claims = precedence::Claims::default();
+    for source in sources {
+        let mut ignore = ignore::patterns_for(config, target, source);
+        let files = list_source_files(source, &ignore);
+        let contested = claims.contested(source, &files);
+        claims.claim(source, &files);
+        match status::source_status(config, target, source) {
+            SourceStatus::SourceNotFound
+            | SourceStatus::TargetNotFound
+            | SourceStatus::PermissionDenied(_)
+            | SourceStatus::ReadOnlyTarget
+            | SourceStatus::NotMounted(_) => continue,
+            _ => {}
+        }
+        ignore.extend(precedence::skip_patterns(&contested));
+
+        let broken: Vec<PathBuf> = scan_pair(source, target, &ignore).broken.iter().map(|b| target.join(b)).collect();
+        for link in &broken {
+            lines.push(format!("  Removed broken link: {}", link.strip_prefix(target).unwrap_or(link).display()));
+        }
+        repairs.broken_removed += links::remove_links(&broken, target);
+
+        let strategy = fix.real_files.unwrap_or(OnConflict::Skip);
+        let confirmed = fix.yes && strategy != OnConflict::Skip;
+        let result = resolve_conflicts(config, strategy, source, target, &mut ignore, !confirmed)
+            .and_then(|resolved| {
+                let planned = stow::dry_run(config, source, target, &ignore)?.created().count();
+                if planned > 0 {
+                    stow::stow(config, source, target, &ignore)?;
+                }
+                Ok((resolved, planned))
+            });
+        match result {
+            Ok((resolved, planned)) => {
+                for resolution in &resolved {
+                    let note = if confirmed { resolution.action.to_string() } else { "left in place".to_string() };
+                    lines.push(format!("  {} {} ({})", style::warning(), resolution.path.display(), note));
+                }
+                if confirmed {
+                    repairs.files_resolved += resolved.len();
+                } else {
+                    repairs.files_left += resolved.len();
+                }
+                if planned > 0 {
+                    lines.push(format!("  {} {} ({} link(s) created)", style::ok(), abbreviate_path(source), planned));
+                    repairs.links_created += planned;
+                }
+            }
+            Err(e) => {
+                lines.push(format!("  {} {} ({})", style::error(), abbreviate_path(source), e));
+                repairs.failed += 1;
+            }
+        }
+    }
+
+    if !lines.is_empty() {
+        say!("Fixing {}:", abbreviate_path(target));
+        for line in lines {
+            say!("{}", line);
+        }
+        say!();
+    }
+    repairs
+}
+
+/// Status of every source of one target, taken from `cached` when the daemon has it;
+/// `None` when --since leaves nothing to report.
+fn status_target(
+    config: &Config,
+    target: &Path,
+    cached: Option<&[(PathBuf, SourceStatus)]>,
+    cutoff: Option<std::time::SystemTime>,
+    diff_content: bool,
+) -> Option<(report::TargetReport<report::SourceStatusReport>, report::StatusSummary)> {
+    let json = !report::is_text();
+    let sources = config.get_sources(target)?;
+    let mut summary = report::StatusSummary::default();
+
+    // With --since, only sources with recent changes are reported
+    let selected: Vec<(&PathBuf, Option<Vec<String>>)> = sources.iter()
+        .map(|source| {
+            let changed = cutoff.map(|cutoff| {
+                changed_files(source, &ignore::patterns_for(config, target, source), cutoff)
+            });
+            (source, changed)
+        })
+        .filter(|(_, changed)| changed.as_ref().is_none_or(|c| !c.is_empty()))
+        .collect();
+    if selected.is_empty() {
+        return None;
+    }
+
+    if !json {
+        match config::symlink_destination(target) {
+            Some(resolved) => say!("{} -> {}:", style::path(&abbreviate_path(target)), style::path(&abbreviate_path(&resolved))),
+            None => say!("{}:", style::path(&abbreviate_path(target))),
+        }
+    }
+    let mut table = Table::new();
+    let mut target_report = report::TargetReport::new(abbreviate_path(target));
+
+    let [ok, warning, error] = [style::ok(), style::warning(), style::error()].map(|symbol| format!("  {}", symbol));
+    for (source, changed) in selected {
+        let status = cached
+            .and_then(|statuses| statuses.iter().find(|(s, _)| s == source))
+            .map(|(_, status)| status.clone())
+            .unwrap_or_else(|| status::source_status(config, target, source));
+        let mut entry = report::SourceStatusReport::from_status(abbreviate_path(source), &status);
+        match &status {
+            SourceStatus::Ok { link_count, folded } => {
+                if !json {
+                    let note = format!("({})", status::describe_links(*link_count, *folded));
+                    table.row([ok.as_str(), &style::path(&abbreviate_path(source)), &note]);
+                }
+            }
+            SourceStatus::Copied { file_count } => {
+                if !json {
+                    table.row([ok.as_str(), &style::path(&abbreviate_path(source)), &format!("({} copied files)", file_count)]);
+                }
+            }
+            SourceStatus::HardLinked { file_count } => {
+                if !json {
+                    table.row([ok.as_str(), &style::path(&abbreviate_path(source)), &format!("({} hard-linked files)", file_count)]);
+                }
+            }
+            SourceStatus::BrokenHardLinks(paths) => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(hard links broken)"]);
+                    for path in paths {
+                        table.line(format!("    - {}", path));
+                    }
+                }
+            }
+            SourceStatus::Drift(paths) => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(copies out of date)"]);
+                    for path in paths {
+                        table.line(format!("    - {}", path));
+                    }
+                }
+            }
+            SourceStatus::SourceNotFound => {
+                if !json {
+                    table.row([error.as_str(), &style::path(&abbreviate_path(source)), "(source not found)"]);
+                }
+            }
+            SourceStatus::TargetNotFound => {
+                if !json {
+                    table.row([error.as_str(), &style::path(&abbreviate_path(source)), "(target not found)"]);
+                }
+            }
+            SourceStatus::BrokenLinks(links) => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(broken links)"]);
+                    for link in links {
+                        table.line(format!("    - {}", link));
+                    }
+                }
+            }
+            SourceStatus::PinMismatch(paths) => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(pinned paths not linked)"]);
+                    for path in paths {
+                        table.line(format!("    - {}", path));
+                    }
+                }
+            }
+            SourceStatus::Conflicts(msg) => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(conflicts detected)"]);
+                    for line in msg.lines().take(5) {
+                        if !line.trim().is_empty() {
+                            table.line(format!("    {}", line.trim()));
+                        }
+                    }
+                }
+            }
+            SourceStatus::RealFiles(files) => {
+                if diff_content {
+                    entry.diffs = Some(files.iter().map(|f| {
+                        let result = diff::compare_files(&target.join(f), &source.join(f));
+                        let summary = result.summary();
+                        let diff = match result {
+                            diff::ContentDiff::Text(text) => Some(text),
+                            _ => None,
+                        };
+                        report::FileDiff { file: f.clone(), result: summary, diff }
+                    }).collect());
+                }
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(real files found)"]);
+                    for file in files {
+                        table.line(format!("    - {} (expected symlink)", file));
+                        if diff_content {
+                            add_content_diff(&mut table, &target.join(file), &source.join(file));
+                        }
+                    }
+                }
+            }
+            SourceStatus::ReadOnlyTarget => {
+                if !json {
+                    table.row([warning.as_str(), &style::path(&abbreviate_path(source)), "(target is read-only)"]);
+                }
+            }
+            SourceStatus::PermissionDenied(msg) => {
+                if !json {
+                    table.row([error.as_str(), &style::path(&abbreviate_path(source)), &format!("(permission denied: {})", msg)]);
+                }
+            }
+            SourceStatus::Sparse { link_count, excluded, stale } => {
+                if !json {
+                    let note = format!("({} links, {} file(s) outside sparse checkout)", link_count, excluded);
+                    table.row([ok.as_str(), &style::path(&abbreviate_path(source)), &note]);
+                    for path in stale {
+                        table.line(format!("    - {} (not checked out; `amu update` removes its link)", path));
+                    }
+                }
+            }
+            SourceStatus::NotMounted(point) => {
+                if !json {
+                    table.row(["  -", &style::path(&abbreviate_path(source)), &format!("(not mounted: {})", abbreviate_path(point))]);
+                }
+            }
+        }
+        match status.class() {
+            "ok" => summary.ok += 1,
+            "warning" => summary.warning += 1,
+            _ => summary.error += 1,
+        }
+
+        // Repository state is shown alongside but does not change the source's status; a
+        // status served by the daemon skips it, as running git would undo the cache's speedup
+        let repo_state = if cached.is_none() { git::state(source) } else { None };
+        if let Some(state) = repo_state {
+            if !json && !state.is_clean() {
+                table.line(format!("    git: {}", state.describe()));
+            }
+            entry.git = Some(state);
+        }
+
+        if let Some(changed) = changed {
+            if !json {
+                add_changed_files(&mut table, &changed);
+            }
+            entry.changed = Some(changed);
+        }
+        target_report.sources.push(entry);
+    }
+
+    if !json {
+        table.print();
+        say!();
+    }
+    Some((target_report, summary))
+}
+
+/*
+ * One-line summary for shell prompts
+ * Nothing is printed when every source is fine, and `?` when no daemon is keeping the
+ * cache warm, so a prompt never waits on a filesystem scan.
+ */
+fn cmd_prompt() -> Result<()> {
+    let Some(cache) = cache::StatusCache::load_fresh() else {
+        println!("?");
+        return Ok(());
+    };
+    let statuses = cache.targets.values().flatten().map(|(_, status)| status.class());
+    let (warning, error) = statuses.fold((0, 0), |(w, e), class| match class {
+        "warning" => (w + 1, e),
+        "error" => (w, e + 1),
+        _ => (w, e),
+    });
+    let mut parts = Vec::new();
+    if warning > 0 {
+        parts.push(format!("{}{}", theme::warning(), warning));
+    }
+    if error > 0 {
+        parts.push(format!("{}{}", theme::error(), error));
+    }
+    println!("{}", parts.join(" "));
+    Ok(())
+}
+
+/// Changed files shown per source before the rest is summarized
+const CHANGED_FILES_SHOWN: usize = 5;
+
+fn add_changed_files(table: &mut Table, changed: &[String]) {
+    for file in changed.iter().take(CHANGED_FILES_SHOWN) {
+        table.line(format!("    changed: {}", file));
+    }
+    if changed.len() > CHANGED_FILES_SHOWN {
+        table.line(format!("    ... and {} more", changed.len() - CHANGED_FILES_SHOWN));
+    }
+}
+
+/*
+ * Source entries modified at or after the cutoff
+ * Directories are included (with a trailing `/`) because adding or deleting a file
+ * only shows up in its parent directory's mtime.
+ */
+fn changed_files(source: &Path, ignore: &[String], cutoff: std::time::SystemTime) -> Vec<String> {
+    fn collect(base: &Path, current: &Path, ignore: &[String], cutoff: std::time::SystemTime, changed: &mut Vec<String>) {
+        let is_recent = |path: &Path| {
+            std::fs::symlink_metadata(path)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified >= cutoff)
+        };
+
+        if is_recent(current) {
+            let relative = current.strip_prefix(base).unwrap_or(current);
+            let shown = if relative.as_os_str().is_empty() { Path::new(".") } else { relative };
+            changed.push(format!("{}/", shown.display()));
+        }
+
+        let Some(entries) = unreadable::read_dir(current) else { return };
+        let mut paths: Vec<PathBuf> = entries.map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            let relative = path.strip_prefix(base).unwrap_or(&path);
+            if ignore::is_ignored(relative, ignore) {
+                continue;
+            }
+            if path.is_dir() && !path.is_symlink() {
+                collect(base, &path, ignore, cutoff, changed);
+            } else if is_recent(&path) {
+                changed.push(relative.display().to_string());
+            }
+        }
+    }
+
+    let mut changed = Vec::new();
+    collect(source, source, ignore, cutoff, &mut changed);
+    changed
+}
+
+fn cmd_clear(target: Option<PathBuf>, all: bool, under: Option<PathBuf>, recorded_only: bool, dry_run: bool) -> Result<()> {
+    let mut config = Config::load()?;
+    let mut report = report::ChangeReport::new("clear", dry_run);
+
+    if config.targets.is_empty() {
+        say!("No targets registered.");
+        return report::emit_structured(&report);
+    }
+
+    // Determine which targets to clear
+    let targets_to_clear: Vec<PathBuf> = if all {
+        all_targets(&config, under.as_deref())
+    } else {
+        let t = config.lookup_target(target)?;
+        if config.get_sources(&t).is_none() {
+            if !report::is_text() {
+                return report::emit_error("Target not registered");
+            }
+            println!("Target not registered: {}", abbreviate_path(&t));
+            return Ok(());
+        }
+        vec![t]
+    };
+    if targets_to_clear.is_empty() {
+        say!("{}", no_targets_message(&config, under.as_deref()));
+        return report::emit_structured(&report);
+    }
+
+    // dry-run mode: preview only
+    if dry_run {
+        say!("[dry-run] Would clear:");
+        for target in &targets_to_clear {
+            say!("  {}", abbreviate_path(target));
+            if let Some(sources) = config.get_sources(target) {
+                for source in sources {
+                    let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "cleared");
+                    if recorded_only {
+                        let recorded = recorded_links(source, target)?;
+                        change.plan.actions = recorded.iter().map(|link| PlanAction::LinkRemove { path: link.clone() }).collect();
+                        say!("    {} ({} recorded links)", abbreviate_path(source), recorded.len());
+                    } else if source.exists() && target.exists() {
+                        let ignore = ignore::patterns_for(&config, target, source);
+                        change.plan = stow::dry_run_unstow(&config, source, target, &ignore)?;
+                        say!("    {} ({} links)", abbreviate_path(source), change.plan.links());
+                        print_plan_conflicts(&change.plan, "      ");
+                    }
+                    report.changes.push(change);
+                }
+            }
+        }
+
+        let mut cleared = config.clone();
+        for target in &targets_to_clear {
+            cleared.targets.remove(&config::PathKey::new(target));
+        }
+        say!();
+        say!("[dry-run] Config entries that would be deleted:");
+        for target in &targets_to_clear {
+            for source in config.sources_of(target) {
+                say!("  {} -> {}", abbreviate_path(source), abbreviate_path(target));
+            }
+        }
+        let config_diff = diff::unified(&config.to_yaml()?, &cleared.to_yaml()?, "config (current)", "config (after clear)");
+        say!();
+        say!("[dry-run] Config diff:");
+        for line in config_diff.lines() {
+            say!("  {}", line);
+        }
+        report.config_diff = Some(config_diff);
+        return report::emit_structured(&report);
+    }
+
+    for target in &targets_to_clear {
+        if let Some(sources) = config.get_sources(target) {
+            for source in sources {
+                let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "cleared");
+                if recorded_only {
+                    if journal::recorded(source, target)?.is_none() {
+                        strict::warn(format_args!("No links recorded for {} -> {}; leaving its links in place", source.display(), target.display()));
+                    }
+                    let recorded: Vec<PathBuf> = recorded_links(source, target)?.iter().map(|r| target.join(r)).collect();
+                    links::remove_links(&recorded, target);
+                    change.links = recorded.iter().map(|link| abbreviate_path(link)).collect();
+                } else if source.exists() && target.exists() {
+                    let ignore = ignore::patterns_for(&config, target, source);
+                    if let Err(e) = stow::unstow(&config, source, target, &ignore) {
+                        strict::warn(format_args!("Failed to unstow {} -> {}: {}", source.display(), target.display(), e));
+                        change.result = "failed";
+                        change.message = Some(e.to_string());
+                    }
+                }
+                if let Err(e) = journal::forget(source, target) {
+                    strict::warn(format_args!("recorded links not forgotten: {}", e));
+                }
+                template::remove(source, target, false)?;
+                report.changes.push(change);
+            }
+        }
+        config.targets.remove(&config::PathKey::new(target));
+    }
+
+    config.save()?;
+
+    if let Some(dir) = under.filter(|_| all) {
+        say!("Cleared {} target(s) under {}.", targets_to_clear.len(), dir.display());
+    } else if all {
+        say!("Cleared all registered sources.");
+    } else {
+        say!("Cleared: {}", abbreviate_path(&targets_to_clear[0]));
+    }
+    report::emit_structured(&report)
+}
+
+/// What to print when --all selects nothing: no targets at all, or none at or below --under.
+fn no_targets_message(config: &Config, under: Option<&Path>) -> String {
+    match under {
+        Some(dir) if !config.targets.is_empty() => format!("No targets registered under {}.", abbreviate_path(dir)),
+        _ => "No targets registered.".to_string(),
+    }
+}
+
+/// Targets selected by --all: every registered target, or only those at or below --under.
+fn all_targets(config: &Config, under: Option<&Path>) -> Vec<PathBuf> {
+    let targets = match under {
+        Some(dir) => config.targets_under(dir),
+        None => config.targets.keys().map(|t| t.to_path_buf()).collect(),
+    };
+    // With `symlinked_targets: refuse`, symlinked targets are skipped rather than failing the run
+    targets.into_iter()
+        .filter(|target| match config.check_symlinked(target) {
+            Ok(()) => true,
+            Err(e) => {
+                strict::warn(e.to_string().lines().next().unwrap_or_default());
+                false
+            }
+        })
+        .collect()
+}
+
+/// Recorded links of a pair that still point into the source, target-relative.
+fn recorded_links(source: &Path, target: &Path) -> Result<Vec<PathBuf>> {
+    Ok(journal::recorded(source, target)?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|relative| {
+            let link = target.join(relative);
+            link.is_symlink() && links::link_destination(&link).is_some_and(|d| d.starts_with(source))
+        })
+        .collect())
+}
+
+fn cmd_clean(target: Option<PathBuf>, all: bool, recursive: bool, dry_run: bool) -> Result<()> {
+    let config = Config::load()?;
+
+    // Determine targets
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, None)
+    } else {
+        let t = config.lookup_target(target)?;
+        if recursive {
+            let mut targets: Vec<PathBuf> = all_targets(&config, Some(&t));
+            targets.sort();
+            if targets.is_empty() {
+                say!("Target not registered: {}", abbreviate_path(&t));
+                return report::emit_error("Target not registered");
+            }
+            targets
+        } else if config.get_sources(&t).is_some() {
+            vec![t]
+        } else {
+            say!("Target not registered: {}", abbreviate_path(&t));
+            return report::emit_error("Target not registered");
+        }
+    };
+
+    let mut report = report::CleanReport { dry_run, targets: Vec::new() };
+    if targets.is_empty() {
+        say!("No targets registered.");
+        return report::emit_structured(&report);
+    }
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    let mut total_removed = 0;
+
+    for target in &targets {
+        let dangling = links::find_dangling_links(target);
+
+        if dangling.is_empty() {
+            continue;
+        }
+
+        if dry_run {
+            for rel in &dangling {
+                say!("{}Would remove: {}", prefix, target.join(rel).display());
             }
-            targets
-        } else if config.targets.contains_key(&t) {
-            vec![t]
         } else {
-            if json {
-                println!("{{\"error\": \"Target not registered\"}}");
-            } else {
-                println!("Target not registered: {}", abbreviate_path(&t));
-            }
-            return Ok(());
+            links::cleanup_dangling_links(target, &dangling);
         }
-    };
 
-    if target_list.is_empty() {
+        let count = dangling.len();
+        say!(
+            "{}Cleaned {} dangling link(s) in {}",
+            prefix,
+            count,
+            abbreviate_path(target)
+        );
+        report.targets.push(report::CleanedTarget {
+            path: abbreviate_path(target),
+            removed: dangling.iter().map(|rel| rel.display().to_string()).collect(),
+        });
+
+        total_removed += count;
+    }
+
+    if total_removed == 0 {
+        say!("No dangling links found.");
+    }
+
+    report::emit_structured(&report)
+}
+
+fn cmd_verify_target(dir: PathBuf, json: bool) -> Result<()> {
+    let config = Config::load()?;
+    let dir = config.lookup_target(Some(dir))?;
+
+    if !dir.is_dir() {
+        return Err(DotlinkError::TargetNotFound(dir));
+    }
+
+    let registered = config.get_sources(&dir).is_some();
+    let sources: Vec<&PathBuf> = config.targets.values().flatten().collect();
+
+    let mut managed_count = 0;
+    let mut foreign_count = 0;
+    let mut broken_count = 0;
+    let mut json_links: Vec<String> = Vec::new();
+
+    if !json {
+        let note = if registered { "registered" } else { "not registered" };
+        println!("{} ({}):", abbreviate_path(&dir), note);
+    }
+
+    let symlinks = links::find_all_symlinks(&dir);
+    for entry in &symlinks {
+        // Which registered source (if any) this link points into
+        let source = sources.iter().find(|s| entry.destination.starts_with(s));
+        let status = if entry.dangling {
+            broken_count += 1;
+            "broken"
+        } else if source.is_some() {
+            managed_count += 1;
+            "managed"
+        } else {
+            foreign_count += 1;
+            "foreign"
+        };
+
+        if json {
+            let source_json = match source {
+                Some(s) => format!(", \"source\": \"{}\"", abbreviate_path(s)),
+                None => String::new(),
+            };
+            json_links.push(format!(
+                "{{\"path\": \"{}\", \"destination\": \"{}\", \"status\": \"{}\"{}}}",
+                entry.relative.display(), abbreviate_path(&entry.destination), status, source_json
+            ));
+        } else {
+            let marker = match status {
+                "managed" => style::ok(),
+                "broken" => style::error(),
+                _ => style::paint("?", style::Tone::Warning),
+            };
+            let suffix = if status == "managed" { String::new() } else { format!(" ({})", status) };
+            println!(
+                "  {} {} -> {}{}",
+                marker, entry.relative.display(), abbreviate_path(&entry.destination), suffix
+            );
+        }
+    }
+
+    if json {
+        println!(
+            "{{\"path\": \"{}\", \"registered\": {}, \"links\": [{}], \"summary\": {{\"managed\": {}, \"foreign\": {}, \"broken\": {}}}}}",
+            abbreviate_path(&dir), registered, json_links.join(", "), managed_count, foreign_count, broken_count
+        );
+    } else {
+        if symlinks.is_empty() {
+            println!("  No symlinks found.");
+        }
+        println!();
+        println!(
+            "Summary: {} managed, {} foreign, {} broken",
+            managed_count, foreign_count, broken_count
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_stats(sort: StatsSort, limit: Option<usize>, json: bool) -> Result<()> {
+    let config = Config::load()?;
+
+    struct SourceStats {
+        source: PathBuf,
+        target: PathBuf,
+        files: usize,
+        links: usize,
+        duration: std::time::Duration,
+    }
+
+    let mut rows: Vec<SourceStats> = Vec::new();
+    for (target, sources) in &config.targets {
+        for source in sources {
+            let ignore = ignore::patterns_for(&config, target, source);
+            let started = std::time::Instant::now();
+            let files = count_source_files(source, &ignore);
+            let links = if target.exists() { scan_pair(source, target, &ignore).links } else { 0 };
+            rows.push(SourceStats {
+                source: source.clone(),
+                target: target.to_path_buf(),
+                files,
+                links,
+                duration: started.elapsed(),
+            });
+        }
+    }
+
+    if rows.is_empty() {
         if json {
-            println!("{{\"targets\": [], \"summary\": {{\"ok\": 0, \"warning\": 0, \"error\": 0}}}}");
+            println!("{{\"sources\": [], \"summary\": {{\"sources\": 0, \"files\": 0, \"links\": 0}}}}");
         } else {
             println!("No targets registered.");
         }
         return Ok(());
     }
 
-    let mut ok_count = 0;
-    let mut warning_count = 0;
-    let mut error_count = 0;
-    let mut json_targets: Vec<String> = Vec::new();
+    // Largest first; ties keep config order
+    match sort {
+        StatsSort::Files => rows.sort_by_key(|r| std::cmp::Reverse(r.files)),
+        StatsSort::Links => rows.sort_by_key(|r| std::cmp::Reverse(r.links)),
+        StatsSort::Duration => rows.sort_by_key(|r| std::cmp::Reverse(r.duration)),
+    }
 
-    for target in &target_list {
-        if let Some(sources) = config.get_sources(target) {
-            if !json {
-                println!("{}:", abbreviate_path(target));
+    let total_sources = rows.len();
+    let total_files: usize = rows.iter().map(|r| r.files).sum();
+    let total_links: usize = rows.iter().map(|r| r.links).sum();
+
+    if let Some(limit) = limit {
+        rows.truncate(limit);
+    }
+
+    if json {
+        let sources_json: Vec<String> = rows.iter().map(|r| format!(
+            "{{\"path\": \"{}\", \"target\": \"{}\", \"files\": {}, \"links\": {}, \"scan_ms\": {:.3}}}",
+            json_escape(&abbreviate_path(&r.source)),
+            json_escape(&abbreviate_path(&r.target)),
+            r.files,
+            r.links,
+            r.duration.as_secs_f64() * 1000.0
+        )).collect();
+        println!(
+            "{{\"sources\": [{}], \"summary\": {{\"sources\": {}, \"files\": {}, \"links\": {}}}}}",
+            sources_json.join(", "), total_sources, total_files, total_links
+        );
+        return Ok(());
+    }
+
+    println!("{:>7} {:>7} {:>10}  SOURCE -> TARGET", "FILES", "LINKS", "SCAN");
+    for r in &rows {
+        println!(
+            "{:>7} {:>7} {:>8.1}ms  {} -> {}",
+            r.files,
+            r.links,
+            r.duration.as_secs_f64() * 1000.0,
+            abbreviate_path(&r.source),
+            abbreviate_path(&r.target)
+        );
+    }
+    println!();
+    println!("Total: {} sources, {} files, {} links", total_sources, total_files, total_links);
+
+    Ok(())
+}
+
+fn cmd_bench(runs: usize) -> Result<()> {
+    let config = Config::load()?;
+    say!("Running each phase {} time(s)...", runs);
+    let result = bench::run(&config, runs)?;
+    if !report::is_text() {
+        return report::emit(&result);
+    }
+
+    let ms = |time: f64| format!("{:.1}ms", time);
+    say!();
+    say!("Config load: {}", ms(result.config_load_ms));
+    if !result.targets.is_empty() {
+        let mut table = Table::new();
+        table.row(["TARGET", "SOURCES", "STATUS", "PLAN", "LINKS"]);
+        for target in &result.targets {
+            table.row([
+                target.path.clone(),
+                target.sources.to_string(),
+                ms(target.status_ms),
+                target.plan_ms.map_or("-".to_string(), ms),
+                target.links_planned.to_string(),
+            ]);
+        }
+        say!();
+        table.print();
+    }
+    say!();
+    let backend = format!("{:?}", result.backend).to_lowercase();
+    say!("Total: {} (median of {} run(s), {} backend)", ms(result.total_ms), result.runs, backend);
+    Ok(())
+}
+
+fn cmd_audit(fail_on: audit::Severity, json: bool) -> Result<Exit> {
+    let config = Config::load()?;
+    let findings = audit::run(&config);
+
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let (high, medium, low) = (
+        count(audit::Severity::High),
+        count(audit::Severity::Medium),
+        count(audit::Severity::Low),
+    );
+
+    if json {
+        let findings_json: Vec<String> = findings.iter().map(|f| format!(
+            "{{\"severity\": \"{}\", \"check\": \"{}\", \"path\": \"{}\", \"message\": \"{}\"}}",
+            f.severity.as_str(), f.check, json_escape(&abbreviate_path(&f.path)), json_escape(&f.message)
+        )).collect();
+        println!(
+            "{{\"findings\": [{}], \"summary\": {{\"high\": {}, \"medium\": {}, \"low\": {}}}}}",
+            findings_json.join(", "), high, medium, low
+        );
+    } else {
+        if findings.is_empty() {
+            println!("No issues found.");
+        }
+        for f in &findings {
+            println!("{:<7} {:<22} {}", f.severity.as_str().to_uppercase(), f.check, abbreviate_path(&f.path));
+            println!("        {}", f.message);
+        }
+        println!();
+        println!("Summary: {} high, {} medium, {} low", high, medium, low);
+    }
+
+    let failing = findings.iter().filter(|f| f.severity >= fail_on).count();
+    if failing > 0 {
+        return Ok(Exit::from_counts(failing, 0));
+    }
+
+    Ok(Exit::Ok)
+}
+
+fn cmd_dedupe(target: Option<PathBuf>, all: bool, yes: bool, dry_run: bool) -> Result<()> {
+    let mut config = Config::load()?;
+
+    let targets: Vec<PathBuf> = if all {
+        all_targets(&config, None)
+    } else {
+        let t = config.lookup_target(target)?;
+        if config.get_sources(&t).is_none() {
+            say!("Target not registered: {}", abbreviate_path(&t));
+            return report::emit_error("Target not registered");
+        }
+        vec![t]
+    };
+
+    let mut report = report::DedupeReport { dry_run, targets: Vec::new(), merged: 0 };
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    let mut merged = 0;
+
+    for target in &targets {
+        let Some(sources) = config.get_sources(target).cloned() else { continue };
+        let groups = find_duplicate_sources(&config, &sources);
+        if groups.is_empty() {
+            continue;
+        }
+
+        say!("{}{}:", prefix, abbreviate_path(target));
+        let mut deduped = report::DedupeTarget { path: abbreviate_path(target), groups: Vec::new() };
+        for (group, reason) in groups {
+            say!("  Duplicate sources ({}):", reason);
+            for source in &group {
+                say!("    - {}", abbreviate_path(source));
             }
-            let mut json_sources: Vec<String> = Vec::new();
 
-            for source in sources {
-                let status = check_source_status(source, target);
-                match &status {
-                    SourceStatus::Ok { link_count } => {
-                        if json {
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"ok\", \"link_count\": {}}}",
-                                abbreviate_path(source), link_count
-                            ));
-                        } else {
-                            println!("  \u{2713} {} ({} links)", abbreviate_path(source), link_count);
-                        }
-                        ok_count += 1;
-                    }
-                    SourceStatus::SourceNotFound => {
-                        if json {
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"error\", \"message\": \"source not found\"}}",
-                                abbreviate_path(source)
-                            ));
-                        } else {
-                            println!("  \u{2717} {} (source not found)", abbreviate_path(source));
-                        }
-                        error_count += 1;
-                    }
-                    SourceStatus::TargetNotFound => {
-                        if json {
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"error\", \"message\": \"target not found\"}}",
-                                abbreviate_path(source)
-                            ));
-                        } else {
-                            println!("  \u{2717} {} (target not found)", abbreviate_path(source));
-                        }
-                        error_count += 1;
-                    }
-                    SourceStatus::BrokenLinks(links) => {
-                        if json {
-                            let links_json: Vec<String> = links.iter().map(|l| format!("\"{}\"", l)).collect();
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"warning\", \"message\": \"broken links\", \"details\": [{}]}}",
-                                abbreviate_path(source), links_json.join(", ")
-                            ));
-                        } else {
-                            println!("  ! {} (broken links)", abbreviate_path(source));
-                            for link in links {
-                                println!("    - {}", link);
-                            }
-                        }
-                        warning_count += 1;
-                    }
-                    SourceStatus::Conflicts(msg) => {
-                        if json {
-                            let escaped_msg = msg.replace('\"', "\\\"").replace('\n', "\\n");
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"warning\", \"message\": \"conflicts\", \"details\": \"{}\"}}",
-                                abbreviate_path(source), escaped_msg
-                            ));
-                        } else {
-                            println!("  ! {} (conflicts detected)", abbreviate_path(source));
-                            for line in msg.lines().take(5) {
-                                if !line.trim().is_empty() {
-                                    println!("    {}", line.trim());
-                                }
-                            }
-                        }
-                        warning_count += 1;
-                    }
-                    SourceStatus::RealFiles(files) => {
-                        if json {
-                            let files_json: Vec<String> = files.iter().map(|f| format!("\"{}\"", f)).collect();
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"warning\", \"message\": \"real files (expected symlinks)\", \"details\": [{}]}}",
-                                abbreviate_path(source), files_json.join(", ")
-                            ));
-                        } else {
-                            println!("  ! {} (real files found)", abbreviate_path(source));
-                            for file in files {
-                                println!("    - {} (expected symlink)", file);
-                            }
-                        }
-                        warning_count += 1;
-                    }
-                    SourceStatus::PermissionDenied(msg) => {
-                        if json {
-                            json_sources.push(format!(
-                                "{{\"path\": \"{}\", \"status\": \"error\", \"message\": \"permission denied: {}\"}}",
-                                abbreviate_path(source), msg
-                            ));
-                        } else {
-                            println!("  \u{2717} {} (permission denied: {})", abbreviate_path(source), msg);
-                        }
-                        error_count += 1;
-                    }
+            let keep = if yes || dry_run {
+                Some(0)
+            } else {
+                select_source_to_keep(&group)?
+            };
+            deduped.groups.push(report::DuplicateGroup {
+                reason: reason.to_string(),
+                sources: group.iter().map(|s| abbreviate_path(s)).collect(),
+                kept: keep.map(|keep| abbreviate_path(&group[keep])),
+            });
+            let Some(keep) = keep else {
+                say!("  Skipped.");
+                continue;
+            };
+            let kept = &group[keep];
+
+            if dry_run {
+                say!("  Would keep {}", abbreviate_path(kept));
+                merged += group.len() - 1;
+                continue;
+            }
+
+            for (i, source) in group.iter().enumerate() {
+                if i == keep {
+                    continue;
+                }
+                if source.exists() && target.exists() {
+                    let ignore = ignore::patterns_for(&config, target, source);
+                    stow::unstow(&config, source, target, &ignore)?;
                 }
+                config.remove_source(target, source)?;
+                merged += 1;
+            }
+
+            if kept.exists() && target.exists() {
+                let ignore = ignore::patterns_for(&config, target, kept);
+                stow::restow(&config, kept, target, &ignore)?;
+            }
+            say!("  {} Kept {}", style::ok(), abbreviate_path(kept));
+        }
+        say!();
+        report.targets.push(deduped);
+    }
+
+    if merged == 0 {
+        say!("No duplicate sources found.");
+        return report::emit_structured(&report);
+    }
+
+    if !dry_run {
+        config.save()?;
+    }
+    say!("{}Merged {} duplicate entr{}", prefix, merged, if merged == 1 { "y" } else { "ies" });
+    report.merged = merged;
+    report::emit_structured(&report)
+}
+
+/*
+ * Group a target's sources that are the same directory or hold identical files
+ * Returns each group (in config order) with the reason it was grouped
+ */
+fn find_duplicate_sources(config: &Config, sources: &[PathBuf]) -> Vec<(Vec<PathBuf>, &'static str)> {
+    let mut groups: Vec<(Vec<PathBuf>, &'static str)> = Vec::new();
+    let mut grouped: Vec<&PathBuf> = Vec::new();
+
+    // Same directory reached through different paths (e.g. via a symlink)
+    for (i, a) in sources.iter().enumerate() {
+        if grouped.contains(&a) {
+            continue;
+        }
+        let key = config::PathKey::new(a);
+        let group: Vec<PathBuf> = sources[i..].iter()
+            .filter(|b| !grouped.contains(b) && config::PathKey::new(b) == key)
+            .cloned()
+            .collect();
+        if group.len() > 1 {
+            grouped.extend(sources.iter().filter(|s| group.contains(s)));
+            groups.push((group, "same canonical path"));
+        }
+    }
+
+    // Distinct directories with identical contents
+    let fingerprints: Vec<(PathBuf, Option<u64>)> = sources.iter()
+        .filter(|s| !grouped.contains(s))
+        .map(|s| (s.clone(), source_fingerprint(s, &ignore::source_patterns(config, s))))
+        .collect();
+    let mut seen: Vec<u64> = Vec::new();
+    for (_, fingerprint) in &fingerprints {
+        let Some(fingerprint) = fingerprint else { continue };
+        if seen.contains(fingerprint) {
+            continue;
+        }
+        seen.push(*fingerprint);
+        let group: Vec<PathBuf> = fingerprints.iter()
+            .filter(|(_, f)| f.as_ref() == Some(fingerprint))
+            .map(|(s, _)| s.clone())
+            .collect();
+        // Equal fingerprints only make it likely; a source joins the group once its files
+        // compare equal byte for byte
+        let group: Vec<PathBuf> = group.iter()
+            .filter(|source| *source == &group[0] || same_contents(config, &group[0], source))
+            .cloned()
+            .collect();
+        if group.len() > 1 {
+            groups.push((group, "identical contents"));
+        }
+    }
+
+    groups
+}
+
+/// Whether two sources hold the same files with the same bytes.
+fn same_contents(config: &Config, a: &Path, b: &Path) -> bool {
+    let files = list_source_files(a, &ignore::source_patterns(config, a));
+    files == list_source_files(b, &ignore::source_patterns(config, b))
+        && files.iter().all(|file| {
+            matches!((std::fs::read(a.join(file)), std::fs::read(b.join(file))), (Ok(x), Ok(y)) if x == y)
+        })
+}
+
+/*
+ * Hash of a source's relative file names and contents
+ * Empty or missing sources have no fingerprint, so they never match each other.
+ */
+fn source_fingerprint(source: &Path, ignore: &[String]) -> Option<u64> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let files = list_source_files(source, ignore);
+    if files.is_empty() {
+        return None;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for file in &files {
+        file.hash(&mut hasher);
+        file_hash(&source.join(file))?.hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+/// Hash of a file's contents, or None if it cannot be read.
+fn file_hash(path: &Path) -> Option<u64> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    std::fs::read(path).ok()?.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/*
+ * Compare the file trees of two sources
+ * Files are matched by source-relative path and compared by content hash, to show how
+ * two dotfiles repositories overlap before merging them.
+ */
+fn cmd_diff_source(a: PathBuf, b: PathBuf, json: bool) -> Result<()> {
+    use std::collections::BTreeSet;
+
+    let config = Config::load()?;
+    let a = normalize_path(&a)?;
+    let b = normalize_path(&b)?;
+
+    let files_a: BTreeSet<PathBuf> = list_source_files(&a, &ignore::source_patterns(&config, &a)).into_iter().collect();
+    let files_b: BTreeSet<PathBuf> = list_source_files(&b, &ignore::source_patterns(&config, &b)).into_iter().collect();
+
+    let only_a: Vec<&PathBuf> = files_a.difference(&files_b).collect();
+    let only_b: Vec<&PathBuf> = files_b.difference(&files_a).collect();
+    let (identical, different): (Vec<&PathBuf>, Vec<&PathBuf>) = files_a.intersection(&files_b)
+        .partition(|file| {
+            let hash = file_hash(&a.join(file));
+            hash.is_some() && hash == file_hash(&b.join(file))
+        });
+
+    if json {
+        let report = serde_json::json!({
+            "a": a,
+            "b": b,
+            "only_a": only_a,
+            "only_b": only_b,
+            "identical": identical,
+            "different": different,
+        });
+        println!("{report}");
+        return Ok(());
+    }
+
+    println!("Comparing {} (a) with {} (b):", abbreviate_path(&a), abbreviate_path(&b));
+    let sections = [
+        ("Only in a", "-", &only_a),
+        ("Only in b", "+", &only_b),
+        ("Different", "~", &different),
+    ];
+    for (title, mark, files) in sections {
+        if files.is_empty() {
+            continue;
+        }
+        println!("\n{} ({}):", title, files.len());
+        for file in files.iter() {
+            println!("  {} {}", mark, file.display());
+        }
+    }
+    println!(
+        "\nSummary: {} identical, {} different, {} only in a, {} only in b",
+        identical.len(), different.len(), only_a.len(), only_b.len()
+    );
+    Ok(())
+}
+
+fn select_source_to_keep(group: &[PathBuf]) -> Result<Option<usize>> {
+    use dialoguer::Select;
+
+    let items: Vec<String> = group.iter().map(|s| abbreviate_path(s)).collect();
+    let selection = Select::new()
+        .with_prompt("Which entry should be kept? (Esc to skip)")
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(selection)
+}
+
+/*
+ * Add or remove target/source entries in the config without linking
+ * With --json, entries are read from stdin and applied all or nothing: the config is
+ * only saved if every entry could be processed. Entries already in the requested state
+ * are reported as unchanged rather than failing, so callers can re-apply the same input.
+ */
+fn cmd_config(action: ConfigAction) -> Result<()> {
+    let (adding, source, target, json) = match action {
+        ConfigAction::AddEntry { source, target, json } => (true, source, target, json),
+        ConfigAction::RemoveEntry { source, target, json } => (false, source, target, json),
+        ConfigAction::Migrate { dry_run } => return cmd_config_migrate(dry_run),
+        ConfigAction::Get { .. } => unreachable!("`config get` is dispatched on its own"),
+        ConfigAction::Set { key, value, add, remove } => {
+            let change = if add { settings::Change::Add } else if remove { settings::Change::Remove } else { settings::Change::Set };
+            return cmd_config_set(&key, Some(&value), change);
+        }
+        ConfigAction::Unset { key } => return cmd_config_set(&key, None, settings::Change::Set),
+        ConfigAction::Edit => return cmd_config_edit(),
+        ConfigAction::Dump { flat, json } => return cmd_config_dump(flat, json),
+        ConfigAction::Path => {
+            println!("{}", Config::config_path()?.display());
+            return Ok(());
+        }
+    };
+
+    let mut config = Config::load()?;
+    let requested = if json {
+        let mut input = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+        entries::parse(&input)?
+    } else {
+        // As for every other command: `default_target:`, then the current directory
+        let target = match target.or_else(|| config.default_target.clone()) {
+            Some(t) => t,
+            None => std::env::current_dir()?,
+        };
+        vec![entries::Entry { target, source: source.expect("clap requires a source without --json") }]
+    };
+
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    for entry in requested {
+        let result = if adding {
+            let entry = entries::Entry { target: entries::resolve(&entry.target), source: entries::resolve(&entry.source) };
+            config.add_source(entry.target.clone(), entry.source.clone()).map(|_| entry)
+        } else {
+            let target = config.find_target(&entry.target).unwrap_or_else(|| entries::resolve(&entry.target));
+            let source = config.find_source(&target, &entry.source).unwrap_or_else(|| entries::resolve(&entry.source));
+            config.remove_source(&target, &source).map(|_| entries::Entry { target, source })
+        };
+        match result {
+            Ok(entry) => changed.push(entry),
+            Err(DotlinkError::AlreadyRegistered { src, dest } | DotlinkError::NotRegistered { src, dest }) => {
+                unchanged.push(entries::Entry { target: dest, source: src });
             }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if !changed.is_empty() {
+        config.save()?;
+    }
+
+    let verb = if adding { "added" } else { "removed" };
+    if json {
+        println!("{}", serde_json::json!({ verb: changed, "unchanged": unchanged }));
+    } else {
+        for entry in &changed {
+            let label = if adding { "Added entry" } else { "Removed entry" };
+            println!("{}: {} -> {}", label, abbreviate_path(&entry.source), abbreviate_path(&entry.target));
+        }
+        for entry in &unchanged {
+            let label = if adding { "Already registered" } else { "Not registered" };
+            println!("{}: {} -> {}", label, abbreviate_path(&entry.source), abbreviate_path(&entry.target));
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Rewrite the config file in the current schema version
+ * Older files are read as they are and converted whenever amu saves the config; this
+ * converts one without waiting for a change. `amu undo` puts the old file back.
+ */
+fn cmd_config_migrate(dry_run: bool) -> Result<()> {
+    let path = Config::config_path()?;
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        say!("No config file at {}", abbreviate_path(&path));
+        return Ok(());
+    };
+    let document: serde_yaml::Value = serde_yaml::from_str(&content).unwrap_or_default();
+    let version = schema::version(&document);
+    if version >= schema::VERSION {
+        say!("{} is already at version {}", abbreviate_path(&path), version);
+        return Ok(());
+    }
+
+    let config = Config::load()?;
+    if dry_run {
+        let migrated = config.to_yaml()?;
+        for line in diff::unified(&content, &migrated, "config (current)", "config (migrated)").lines() {
+            say!("  {}", line);
+        }
+        say!();
+        say!("[dry-run] {} would be migrated from version {} to {}", abbreviate_path(&path), version, schema::VERSION);
+        return Ok(());
+    }
+    config.save()?;
+    say!("Migrated {} from version {} to {}", abbreviate_path(&path), version, schema::VERSION);
+    Ok(())
+}
 
-            if json {
-                json_targets.push(format!(
-                    "{{\"path\": \"{}\", \"sources\": [{}]}}",
-                    abbreviate_path(target), json_sources.join(", ")
-                ));
-            } else {
-                println!();
-            }
+fn cmd_config_get(key: &str) -> Result<Exit> {
+    let key = settings::parse_key(key)?;
+    // As loaded, so settings from conf.d/ fragments are seen too
+    let document = schema::to_document(&Config::load()?).map_err(DotlinkError::ConfigParseError)?;
+    match settings::get(&document, &key) {
+        Some(value) => {
+            println!("{}", settings::display(value));
+            Ok(Exit::Ok)
         }
+        // Not set: nothing to print and exit 1, as `git config` does; not a warning
+        None => Ok(Exit::Warnings),
     }
+}
 
-    if json {
-        println!(
-            "{{\"targets\": [{}], \"summary\": {{\"ok\": {}, \"warning\": {}, \"error\": {}}}}}",
-            json_targets.join(", "), ok_count, warning_count, error_count
-        );
+/// Print the config as loaded: sources resolved, the active profile's entries merged in.
+fn cmd_config_dump(flat: bool, json: bool) -> Result<()> {
+    let mut effective = Config::load()?;
+    effective.profiles.clear();
+    let document = schema::to_document(&effective).map_err(DotlinkError::ConfigParseError)?;
+    if flat {
+        for line in settings::flatten(&document) {
+            println!("{}", line);
+        }
+    } else if json {
+        println!("{}", report::to_json(&document)?);
+    } else if report::is_text() {
+        print!("{}", serde_yaml::to_string(&document).map_err(|e| DotlinkError::ConfigParseError(e.to_string()))?);
     } else {
-        println!("Summary: {} OK, {} warning, {} error", ok_count, warning_count, error_count);
+        report::emit(&document)?;
     }
+    Ok(())
+}
 
-    if error_count > 0 || warning_count > 0 {
-        std::process::exit(1);
+/// `amu config set/unset`: `value` is None to unset.
+fn cmd_config_set(key: &str, value: Option<&str>, change: settings::Change) -> Result<()> {
+    let config = Config::load()?;
+    let Some(content) = settings::apply(&config, key, value, change)? else {
+        say!("{} unchanged", key);
+        return Ok(());
+    };
+    Config::save_content(content)?;
+    match (value, change) {
+        (None, _) => say!("Unset {}", key),
+        (Some(value), settings::Change::Add) => say!("Added {} to {}", value, key),
+        (Some(value), settings::Change::Remove) => say!("Removed {} from {}", value, key),
+        (Some(value), settings::Change::Set) => say!("Set {} = {}", key, value),
     }
-
     Ok(())
 }
 
-fn cmd_clear(target: Option<PathBuf>, all: bool, dry_run: bool) -> Result<()> {
-    let mut config = Config::load()?;
+/*
+ * Edit the config file in $VISUAL or $EDITOR (vi if neither is set)
+ * The editor works on a copy; the file is replaced only if the copy loads, so a typo
+ * leaves the config as it was. The edited text is kept as written, comments included.
+ */
+fn cmd_config_edit() -> Result<()> {
+    let path = Config::config_path()?;
+    let original = std::fs::read_to_string(&path).unwrap_or_default();
+    // Created anew (never an existing file or link) and readable by the user only
+    let copy = tempfile::Builder::new().prefix("amu-config-").suffix(".yaml").tempfile()?;
+    std::fs::write(copy.path(), &original)?;
 
-    if config.targets.is_empty() {
-        println!("No targets registered.");
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(copy.path())
+        .status();
+    let edited = std::fs::read_to_string(copy.path());
+    drop(copy);
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => return Err(DotlinkError::ConfigSaveError(format!("{editor} exited with an error; config left unchanged"))),
+        Err(e) => return Err(DotlinkError::ConfigSaveError(format!("could not run {editor}: {e}"))),
+    }
+    let edited = edited?;
+    if edited == original {
+        say!("No changes to {}", abbreviate_path(&path));
         return Ok(());
     }
+    if let Err(e) = Config::validate(&edited) {
+        eprintln!("{} was left unchanged: the edited config does not load", abbreviate_path(&path));
+        return Err(e);
+    }
+    Config::save_content(edited)?;
+    say!("Saved {}", abbreviate_path(&path));
+    Ok(())
+}
 
-    // Determine which targets to clear
-    let targets_to_clear: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
-    } else {
-        let t = resolve_target(target)?;
-        if !config.targets.contains_key(&t) {
-            println!("Target not registered: {}", abbreviate_path(&t));
-            return Ok(());
+fn cmd_template(action: TemplateAction) -> Result<()> {
+    match action {
+        TemplateAction::Init { file, source, target, vars, dry_run } => {
+            cmd_template_init(file, source, target, vars, dry_run)
         }
-        vec![t]
+    }
+}
+
+/*
+ * Convert a real file in a target into `<source>/<path>.tmpl`
+ * Values of saved variables (and of new ones given with --var, which are saved) are
+ * replaced by placeholders. The template must render back to the original content, and
+ * the source is registered for the target. The original file is left in place.
+ */
+fn cmd_template_init(
+    file: PathBuf,
+    source: PathBuf,
+    target: Option<PathBuf>,
+    assignments: Vec<String>,
+    dry_run: bool,
+) -> Result<()> {
+    let file = config::resolve_link(&file);
+    let mut config = Config::load()?;
+    let (target, relative) = match target {
+        Some(target) => {
+            let target = resolve_target(Some(target))?;
+            let relative = file.strip_prefix(&target)
+                .map_err(|_| DotlinkError::NotInTarget { path: file.clone(), target: target.clone() })?
+                .to_path_buf();
+            (target, relative)
+        }
+        None => containing_target(&config, &file)?,
     };
+    if !std::fs::symlink_metadata(&file).is_ok_and(|m| m.is_file()) {
+        return Err(DotlinkError::NotARegularFile(file));
+    }
+    let source = normalize_path(&source)?;
+    if !source.is_dir() {
+        return Err(DotlinkError::SourceNotFound(source));
+    }
+
+    let mut vars = vars::load()?;
+    for assignment in &assignments {
+        let (name, value) = assignment.split_once('=')
+            .ok_or_else(|| DotlinkError::InvalidAssignment(assignment.clone()))?;
+        vars::validate_name(name)?;
+        vars.insert(name.to_string(), value.to_string());
+    }
+
+    let content = std::fs::read_to_string(&file)
+        .map_err(|e| DotlinkError::FileNotText { path: file.clone(), reason: e.to_string() })?;
+    let (template, used) = template::extract(&content, &vars);
+    if template::render(&template, &vars).ok().as_deref() != Some(content.as_str()) {
+        return Err(DotlinkError::TemplateMismatch(file));
+    }
+
+    let mut destination = source.join(&relative).into_os_string();
+    destination.push(template::SUFFIX);
+    let destination = PathBuf::from(destination);
+    if destination.exists() {
+        return Err(DotlinkError::TemplateExists(destination));
+    }
 
-    // dry-run mode: preview only
     if dry_run {
-        println!("[dry-run] Would clear:");
-        for target in &targets_to_clear {
-            println!("  {}", abbreviate_path(target));
-            if let Some(sources) = config.targets.get(target) {
-                for source in sources {
-                    if source.exists() && target.exists() {
-                        let output = stow::dry_run_unstow(source, target)?;
-                        let links = stow::parse_dry_run_output(&output);
-                        println!("    {} ({} links)", abbreviate_path(source), links.len());
-                    }
-                }
-            }
-        }
+        println!("[dry-run] Would create {}:", abbreviate_path(&destination));
+        print!("{}", template);
         return Ok(());
     }
 
-    for target in &targets_to_clear {
-        if let Some(sources) = config.targets.get(target) {
-            for source in sources {
-                if source.exists() && target.exists() {
-                    if let Err(e) = stow::unstow(source, target) {
-                        eprintln!("Warning: Failed to unstow {} -> {}: {}", source.display(), target.display(), e);
-                    }
-                }
-            }
-        }
-        config.targets.remove(target);
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&destination, &template)?;
+    debug!("wrote {}", destination.display());
+    vars::save(&vars)?;
+    if config.find_source(&target, &source).is_none() {
+        config.add_source(target.clone(), source.clone())?;
+        config.save()?;
+        println!("Registered: {} -> {}", abbreviate_path(&source), abbreviate_path(&target));
     }
 
-    config.save()?;
-
-    if all {
-        println!("Cleared all registered sources.");
+    if used.is_empty() {
+        println!("Created template: {} (no variables found)", abbreviate_path(&destination));
     } else {
-        println!("Cleared: {}", abbreviate_path(&targets_to_clear[0]));
+        println!("Created template: {} (variables: {})", abbreviate_path(&destination), used.join(", "));
     }
     Ok(())
 }
 
-fn cmd_clean(target: Option<PathBuf>, all: bool, recursive: bool, dry_run: bool) -> Result<()> {
-    let config = Config::load()?;
+fn cmd_vars(action: VarsAction) -> Result<()> {
+    let mut vars = vars::load()?;
 
-    // Determine targets
-    let targets: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
-    } else {
-        let t = resolve_target(target)?;
-        if recursive {
-            let mut targets: Vec<PathBuf> = config.targets.keys()
-                .filter(|registered| registered.starts_with(&t))
-                .cloned()
-                .collect();
-            targets.sort();
-            if targets.is_empty() {
-                println!("Target not registered: {}", abbreviate_path(&t));
-                return Ok(());
+    match action {
+        VarsAction::List { json } => {
+            if json || !report::is_text() {
+                report::emit(&vars)?;
+            } else if vars.is_empty() {
+                println!("No variables set.");
+            } else {
+                for (name, value) in &vars {
+                    println!("{} = {}", name, value);
+                }
             }
-            targets
-        } else if config.targets.contains_key(&t) {
-            vec![t]
-        } else {
-            println!("Target not registered: {}", abbreviate_path(&t));
-            return Ok(());
         }
-    };
-
-    if targets.is_empty() {
-        println!("No targets registered.");
-        return Ok(());
-    }
-
-    let prefix = if dry_run { "[dry-run] " } else { "" };
-    let mut total_removed = 0;
-
-    for target in &targets {
-        let dangling = links::find_dangling_links(target);
-
-        if dangling.is_empty() {
-            continue;
+        VarsAction::Set { name, value } => {
+            vars::validate_name(&name)?;
+            println!("Set: {} = {}", name, value);
+            vars.insert(name, value);
+            vars::save(&vars)?;
         }
-
-        if dry_run {
-            for rel in &dangling {
-                println!("{}Would remove: {}", prefix, target.join(rel).display());
+        VarsAction::Unset { name } => {
+            if vars.remove(&name).is_none() {
+                return Err(DotlinkError::VariableNotSet(name));
             }
-        } else {
-            links::cleanup_dangling_links(target, &dangling);
+            vars::save(&vars)?;
+            println!("Unset: {}", name);
         }
-
-        let count = dangling.len();
-        println!(
-            "{}Cleaned {} dangling link(s) in {}",
-            prefix,
-            count,
-            abbreviate_path(target)
-        );
-
-        total_removed += count;
-    }
-
-    if total_removed == 0 {
-        println!("No dangling links found.");
     }
 
     Ok(())
 }
 
-fn cmd_restore(target: Option<PathBuf>, all: bool, dry_run: bool) -> Result<()> {
-    let config = Config::load()?;
+/// Flags of `amu restore` beyond target selection
+struct RestoreOptions {
+    dry_run: bool,
+    why_failed: bool,
+    json: bool,
+    chunk: Option<usize>,
+    restart: bool,
+    stop_on_conflict: bool,
+    on_conflict: OnConflict,
+    mount_wait: Option<std::time::Duration>,
+    dirs: mkdir::DirOptions,
+    /// `--fold` (true) or `--no-fold` (false), kept for every pair restored
+    fold: Option<bool>,
+}
+
+fn cmd_restore(target: Option<PathBuf>, all: bool, under: Option<PathBuf>, options: RestoreOptions) -> Result<Exit> {
+    let RestoreOptions { dry_run, why_failed, json, chunk, restart, stop_on_conflict, on_conflict, mount_wait, dirs, fold } = options;
+    let mut config = Config::load()?;
 
     // Determine targets
     let target_list: Vec<PathBuf> = if all {
-        config.targets.keys().cloned().collect()
+        all_targets(&config, under.as_deref())
     } else {
-        let t = resolve_target(target)?;
-        if config.targets.contains_key(&t) {
+        let t = config.lookup_target(target)?;
+        if config.get_sources(&t).is_some() {
             vec![t]
+        } else if json {
+            return report::emit_error("Target not registered").map(|()| Exit::Ok);
         } else {
             println!("Target not registered: {}", abbreviate_path(&t));
-            return Ok(());
+            return Ok(Exit::Ok);
         }
     };
 
     if target_list.is_empty() {
-        println!("No targets registered.");
-        return Ok(());
+        say!("{}", no_targets_message(&config, under.as_deref()));
+        return Ok(Exit::Ok);
+    }
+
+    if let Some(fold) = fold {
+        set_folding(&mut config, &target_list, fold);
+    }
+
+    // Give automounters a chance before sources on unmounted media are given up on
+    if let Some(timeout) = mount_wait {
+        let points: BTreeSet<PathBuf> = target_list.iter()
+            .flat_map(|target| config.get_sources(target).into_iter().flatten())
+            .filter_map(|source| mounts::unmounted(&config, source))
+            .map(Path::to_path_buf)
+            .collect();
+        if !points.is_empty() {
+            say!("Waiting up to {}s for {} mount point(s)...", timeout.as_secs(), points.len());
+            for point in mounts::wait(&points.into_iter().collect::<Vec<_>>(), timeout) {
+                say!("  Not mounted: {}", abbreviate_path(&point));
+            }
+            say!();
+        }
     }
 
     // dry-run mode: preview only
     if dry_run {
-        println!("[dry-run] Would restore:");
+        let mut report = report::ChangeReport::new("restore", true);
+        say!("[dry-run] Would restore:");
         for target in &target_list {
-            println!("  {}:", abbreviate_path(target));
+            say!("  {}:", abbreviate_path(target));
             if let Some(sources) = config.get_sources(target) {
+                hooks::run(&config, Hook::PreRestore, target, sources, true)?;
+                hooks::run(&config, Hook::PostRestore, target, sources, true)?;
+                let mut claims = precedence::Claims::default();
                 for source in sources {
                     if source.exists() {
+                        let mut ignore = ignore::patterns_for(&config, target, source);
+                        let files = list_source_files(source, &ignore);
+                        let contested = claims.contested(source, &files);
+                        claims.claim(source, &files);
+                        ignore.extend(precedence::skip_patterns(&contested));
+
+                        let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "restored");
                         // Also show if target doesn't exist
                         if target.exists() {
-                            let output = stow::dry_run(source, target)?;
-                            let links = stow::parse_dry_run_output(&output);
-                            println!("    {} ({} links)", abbreviate_path(source), links.len());
+                            let resolved = resolve_conflicts(&config, on_conflict, source, target, &mut ignore, true)?;
+                            change.plan = stow::dry_run(&config, source, target, &ignore)?;
+                            change.conflicts = conflict_reports(&resolved);
+                            say!("    {} ({} links)", abbreviate_path(source), change.plan.links());
+                            print_plan_conflicts(&change.plan, "      ");
+                            print_resolutions(&resolved, "      ", true);
                         } else {
-                            println!("    {} (target would be created)", abbreviate_path(source));
+                            let message = match mkdir::plan(target, dirs.parents) {
+                                Ok(_) => "target would be created".to_string(),
+                                Err(e) => format!("target would not be created: {}", e),
+                            };
+                            say!("    {} ({})", abbreviate_path(source), message);
+                            change.message = Some(message);
                         }
+                        print_contested(&contested, "      ");
+                        report.changes.push(change);
                     } else {
-                        println!("    {} (source not found)", abbreviate_path(source));
+                        let reason = match mounts::unmounted(&config, source) {
+                            Some(point) => format!("not mounted: {}", abbreviate_path(point)),
+                            None => "source not found".to_string(),
+                        };
+                        say!("    {} ({})", abbreviate_path(source), reason);
+                        let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "skipped");
+                        change.message = Some(reason);
+                        report.changes.push(change);
                     }
                 }
             }
         }
-        return Ok(());
+        if json {
+            report::emit(&report)?;
+        }
+        return Ok(Exit::Ok);
     }
 
-    let mut success = 0;
-    let mut failed = 0;
+    strict_preflight(&config, &target_pairs(&config, &target_list), on_conflict, true)?;
+    if fold.is_some() {
+        config.save()?;
+    }
 
-    for target in &target_list {
-        if let Some(sources) = config.get_sources(target) {
-            println!("{}:", abbreviate_path(target));
+    if restart {
+        Checkpoint::clear()?;
+    }
+    let checkpoint = Checkpoint::load()?;
+    if !checkpoint.is_empty() && !json {
+        println!("Resuming previous restore ({} source(s) already linked)\n", checkpoint.len());
+    }
 
-            // Create target directory if it doesn't exist
-            if !target.exists() {
-                if let Err(e) = std::fs::create_dir_all(target) {
-                    eprintln!("  Failed to create target directory: {}", e);
-                    failed += sources.len();
-                    continue;
-                }
-            }
+    let run = RestoreRun {
+        present: present_sources(&config),
+        checkpoint: Mutex::new(checkpoint),
+        linked: AtomicUsize::new(0),
+        halted: AtomicBool::new(false),
+        chunk,
+        stop_on_conflict,
+        on_conflict,
+        dirs,
+    };
+    // Chunked and stop-at-first-conflict restores depend on the order sources are linked in
+    let jobs = if chunk.is_some() || stop_on_conflict { 1 } else { parallel::jobs() };
 
-            for source in sources {
-                if source.exists() {
-                    match stow::stow(source, target) {
-                        Ok(()) => {
-                            println!("  \u{2713} {}", abbreviate_path(source));
-                            success += 1;
-                        }
-                        Err(e) => {
-                            println!("  \u{2717} {} ({})", abbreviate_path(source), e);
-                            failed += 1;
-                        }
-                    }
-                } else {
-                    println!("  \u{2717} {} (source not found)", abbreviate_path(source));
-                    failed += 1;
-                }
+    let mut success = 0;
+    let mut paused = false;
+    let mut stopped = false;
+    let mut failures: Vec<RestoreFailure> = Vec::new();
+    let mut target_reports: Vec<report::TargetReport<report::RestoreSource>> = Vec::new();
+
+    let result = parallel::for_each_ordered(
+        &target_list,
+        jobs,
+        |target| restore_target(&config, target, &run),
+        |outcome| {
+            let Some(outcome) = outcome else { return Ok(()) };
+            success += outcome.success;
+            paused |= outcome.paused;
+            stopped |= outcome.stopped;
+            failures.extend(outcome.failures);
+            if json {
+                target_reports.push(outcome.report);
             }
+            outcome.error.map_or(Ok(()), Err)
+        },
+    );
+
+    // Failures are kept for `amu retry` even when one ends the run
+    for failure in &failures {
+        retry::failed("restore", &failure.target, &failure.source, &failure.message);
+    }
+    retry::save()?;
+    result?;
+    let linked_this_run = run.linked.into_inner();
+    let failed = failures.len();
+    if !paused && failed == 0 {
+        Checkpoint::clear()?;
+    }
+
+    if json {
+        report::emit(&report::RestoreReport {
+            targets: target_reports,
+            summary: report::RestoreSummary { succeeded: success, failed },
+            paused,
+            stopped,
+            failures: failures.iter().map(RestoreFailure::to_report).collect(),
+        })?;
+    } else {
+        if paused {
+            println!("Paused after {} source(s); run restore again to continue.", linked_this_run);
+        }
+        if stopped {
+            println!("Stopped at the first conflict; remaining sources were not restored.");
+        }
+        println!("Done: {} succeeded, {} failed", success, failed);
+
+        if why_failed && !failures.is_empty() {
             println!();
+            println!("Failures:");
+            for failure in &failures {
+                println!("  {} <- {}", abbreviate_path(&failure.target), abbreviate_path(&failure.source));
+                println!("    class:   {}", failure.class);
+                println!("    error:   {}", failure.message);
+                println!("    suggest: {}", failure.suggestion);
+            }
         }
     }
 
-    println!("Done: {} succeeded, {} failed", success, failed);
-
     if failed > 0 {
-        std::process::exit(1);
+        return Ok(Exit::Errors);
     }
 
-    Ok(())
+    Ok(Exit::Ok)
 }
 
 /*
- * Enum representing the status of a source
+ * Re-attempt the pairs recorded as failed by restore, update or an earlier retry
+ * Each pair is restowed on its own, with the precedence, filters and templates `update`
+ * applies, so targets whose other sources are fine are not touched. Pairs no longer in
+ * the config are dropped from the record.
  */
-enum SourceStatus {
-    Ok { link_count: usize },
-    SourceNotFound,
-    TargetNotFound,
-    BrokenLinks(Vec<String>),
-    Conflicts(String),
-    RealFiles(Vec<String>),
-    PermissionDenied(String),
-}
-
-fn check_source_status(source: &Path, target: &Path) -> SourceStatus {
-    // Permission check
-    if let Err(e) = std::fs::read_dir(source) {
-        if e.kind() == std::io::ErrorKind::PermissionDenied {
-            return SourceStatus::PermissionDenied(format!("source: {}", source.display()));
+fn cmd_retry(list: bool, clear: bool, on_conflict: OnConflict, dry_run: bool) -> Result<Exit> {
+    let failed = retry::load()?;
+    let mut report = report::ChangeReport::new("retry", dry_run);
+    if failed.is_empty() {
+        say!("No failed entries to retry.");
+        return report::emit_structured(&report).map(|()| Exit::Ok);
+    }
+    if list || clear {
+        for entry in &failed {
+            say!("{} -> {} ({}): {}", abbreviate_path(&entry.source), abbreviate_path(&entry.target), entry.command, entry.error);
+            let mut change = report::Change::new(abbreviate_path(&entry.source), abbreviate_path(&entry.target), "failed");
+            change.message = Some(entry.error.clone());
+            report.changes.push(change);
+            if clear {
+                retry::forget(&entry.target, &entry.source);
+            }
         }
-        return SourceStatus::SourceNotFound;
+        if clear {
+            retry::save()?;
+            say!("Forgot {} failed entr{}", failed.len(), if failed.len() == 1 { "y" } else { "ies" });
+        }
+        return report::emit_structured(&report).map(|()| Exit::Ok);
     }
-    if !source.exists() {
-        return SourceStatus::SourceNotFound;
+
+    let config = Config::load()?;
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    say!("{}Retrying {} failed entr{}:", prefix, failed.len(), if failed.len() == 1 { "y" } else { "ies" });
+    let mut succeeded = 0;
+    for entry in &failed {
+        let (target, source) = (&entry.target, &entry.source);
+        let mut change = report::Change::new(abbreviate_path(source), abbreviate_path(target), "updated");
+        if config.find_source(target, source).is_none() {
+            say!("  Dropped (no longer registered): {} -> {}", abbreviate_path(source), abbreviate_path(target));
+            change.result = "skipped";
+            change.message = Some("no longer registered".to_string());
+            if !dry_run {
+                retry::forget(target, source);
+            }
+            report.changes.push(change);
+            continue;
+        }
+
+        say!("{}:", abbreviate_path(target));
+        let result = if !source.exists() {
+            Err(DotlinkError::SourceNotFound(source.clone()))
+        } else if !target.exists() && dry_run {
+            Ok(())
+        } else {
+            let created = if target.exists() { Ok(Vec::new()) } else { mkdir::create(target, &mkdir::DirOptions::default()) };
+            created.and_then(|_| update_source(&config, target, source, &mut change, false, on_conflict, dry_run))
+        };
+        match result {
+            Ok(()) => {
+                succeeded += 1;
+                if !dry_run {
+                    retry::succeeded(target, source);
+                }
+            }
+            Err(e) => {
+                say!("  {} {} ({})", style::error(), abbreviate_path(source), e);
+                change.result = "failed";
+                change.message = Some(e.to_string());
+                if !dry_run {
+                    retry::failed("retry", target, source, &e);
+                }
+            }
+        }
+        report.changes.push(change);
     }
-    if !target.exists() {
-        return SourceStatus::TargetNotFound;
+    retry::save()?;
+
+    let failures = report.changes.iter().filter(|c| c.result == "failed").count();
+    say!();
+    say!("Done: {} succeeded, {} failed", succeeded, failures);
+    report::emit_structured(&report)?;
+    if failures > 0 {
+        return Ok(Exit::Errors);
     }
+    Ok(Exit::Ok)
+}
+
+/// Shared state of a restore across targets
+struct RestoreRun {
+    /// Sources that existed when the run started
+    present: BTreeSet<PathBuf>,
+    checkpoint: Mutex<Checkpoint>,
+    /// Sources linked by this run, for --chunk
+    linked: AtomicUsize,
+    /// Set once --chunk or --stop-on-conflict ends the run; later targets are not started
+    halted: AtomicBool,
+    chunk: Option<usize>,
+    stop_on_conflict: bool,
+    on_conflict: OnConflict,
+    /// How a missing target is created
+    dirs: mkdir::DirOptions,
+}
 
-    // Check for broken links
-    let broken_links = find_broken_links(source, target);
-    if !broken_links.is_empty() {
-        return SourceStatus::BrokenLinks(broken_links);
+/// What restoring one target did
+struct TargetRestore {
+    report: report::TargetReport<report::RestoreSource>,
+    failures: Vec<RestoreFailure>,
+    success: usize,
+    paused: bool,
+    stopped: bool,
+    /// The checkpoint could not be saved; the restore stops
+    error: Option<DotlinkError>,
+}
+
+/// Link every source of one target, as part of `amu restore`; `None` if the target has no
+/// sources or the run was already halted.
+fn restore_target(config: &Config, target: &Path, run: &RestoreRun) -> Option<TargetRestore> {
+    if run.halted.load(Ordering::SeqCst) {
+        return None;
     }
+    let sources = config.get_sources(target)?;
+    say!("{}:", style::path(&abbreviate_path(target)));
+    let mut outcome = TargetRestore {
+        report: report::TargetReport::new(abbreviate_path(target)),
+        failures: Vec::new(),
+        success: 0,
+        paused: false,
+        stopped: false,
+        error: None,
+    };
 
-    // Check for real files (files exist where symlinks should be)
-    let real_files = find_real_files(source, target);
-    if !real_files.is_empty() {
-        return SourceStatus::RealFiles(real_files);
+    // Create target directory if it doesn't exist
+    if !target.exists() {
+        if let Err(e) = mkdir::create(target, &run.dirs) {
+            if report::is_text() {
+                eprintln!("  Failed to create target directory: {}", e);
+            }
+            for source in sources {
+                let failure = RestoreFailure::target_create_failed(source, target, &e);
+                outcome.report.sources.push(failure.to_report());
+                outcome.failures.push(failure);
+            }
+            return Some(outcome);
+        }
     }
 
-    // Check for conflicts
-    if let Ok(output) = stow::dry_run(source, target) {
-        if output.contains("CONFLICT") || output.contains("existing target") {
-            return SourceStatus::Conflicts(output);
+    // Report a read-only target once per source instead of a cascade of stow errors
+    if !links::is_writable(target) {
+        say!("  {} target is read-only", style::error());
+        for source in sources {
+            let failure = RestoreFailure::read_only(source, target);
+            outcome.report.sources.push(failure.to_report());
+            outcome.failures.push(failure);
         }
+        say!();
+        return Some(outcome);
     }
 
-    // Count links
-    let link_count = count_links(source, target);
-    SourceStatus::Ok { link_count }
-}
+    if let Err(e) = hooks::run(config, Hook::PreRestore, target, sources, false) {
+        outcome.error = Some(e);
+        return Some(outcome);
+    }
+    // Held from the first source to the last so a restow by `amu watch` cannot land between
+    // them; hooks run outside it, as they may run amu on the target themselves
+    let lock = match locks::acquire(target) {
+        Ok(lock) => lock,
+        Err(e) => {
+            outcome.error = Some(e);
+            return Some(outcome);
+        }
+    };
 
-/*
- * Detect broken symbolic links
- */
-fn find_broken_links(source: &Path, target: &Path) -> Vec<String> {
-    let mut broken = Vec::new();
-    find_broken_links_recursive(source, target, source, &mut broken);
-    broken
-}
+    // Sources are applied in configured order; earlier ones win contested paths
+    let mut claims = precedence::Claims::default();
+    let transaction = transaction::Transaction::begin();
+    let mut failed_link = None;
+    for source in sources {
+        let mut ignore = ignore::patterns_for(config, target, source);
+        let files = list_source_files(source, &ignore);
+        let contested = claims.contested(source, &files);
+
+        if run.checkpoint.lock().unwrap().contains(target, source) {
+            retry::succeeded(target, source);
+            claims.claim(source, &files);
+            say!("  = {} (already restored)", abbreviate_path(source));
+            outcome.report.sources.push(report::RestoreSource::new(abbreviate_path(source), "already_restored"));
+            outcome.success += 1;
+            continue;
+        }
+        if run.chunk.is_some_and(|n| run.linked.load(Ordering::SeqCst) >= n) {
+            outcome.paused = true;
+            run.halted.store(true, Ordering::SeqCst);
+            break;
+        }
 
-fn find_broken_links_recursive(source_base: &Path, target: &Path, current_source: &Path, broken: &mut Vec<String>) {
-    if let Ok(entries) = std::fs::read_dir(current_source) {
-        for entry in entries.flatten() {
-            let source_path = entry.path();
-            let relative = source_path.strip_prefix(source_base).unwrap_or(&source_path);
-            let target_path = target.join(relative);
+        let attempted = source.exists() && (!run.stop_on_conflict || contested.is_empty());
+        let before = (attempted && !report::is_text()).then(|| journal::linked_files(source, target, &[]));
+        let result = if let Some(point) = mounts::unmounted(config, source) {
+            Err(RestoreFailure::not_mounted(source, target, point))
+        } else if !source.exists() && run.present.contains(source) {
+            Err(RestoreFailure::unavailable(source, target))
+        } else if !source.exists() {
+            Err(RestoreFailure::source_not_found(source, target))
+        } else if !attempted {
+            Err(RestoreFailure::contested(source, target, &contested))
+        } else {
+            ignore.extend(precedence::skip_patterns(&contested));
+            resolve_conflicts(config, run.on_conflict, source, target, &mut ignore, false)
+                .and_then(|resolved| stow::stow(config, source, target, &ignore).map(|_| resolved))
+                .and_then(|resolved| {
+                    template::apply(config, source, target, &ignore, false).map(|rendered| (resolved, rendered))
+                })
+                .map_err(|e| RestoreFailure::from_error(source, target, &e))
+        };
+        let delta = before.map(|before| report::LinkDelta::between(&before, &journal::linked_files(source, target, &[])));
 
-            if source_path.is_dir() && !source_path.is_symlink() {
-                find_broken_links_recursive(source_base, target, &source_path, broken);
-            } else if target_path.is_symlink() {
-                // Check if link is broken
-                if !target_path.exists() {
-                    broken.push(relative.display().to_string());
+        match result {
+            Ok((resolved, rendered)) => {
+                if let Err(e) = run.checkpoint.lock().unwrap().mark(target, source) {
+                    outcome.error = Some(e);
+                    return Some(outcome);
                 }
+                claims.claim(source, &files);
+                run.linked.fetch_add(1, Ordering::SeqCst);
+                retry::succeeded(target, source);
+                say!("  {} {}", style::ok(), style::path(&abbreviate_path(source)));
+                print_contested(&contested, "    ");
+                print_resolutions(&resolved, "    ", false);
+                let templates = print_renderings(rendered, "    ", false);
+                let mut entry = report::RestoreSource::new(abbreviate_path(source), "ok");
+                entry.skipped = Some(skipped_paths(&contested));
+                entry.conflicts = Some(conflict_reports(&resolved));
+                entry.templates = Some(templates).filter(|t| !t.is_empty());
+                entry.delta = delta;
+                outcome.report.sources.push(entry);
+                outcome.success += 1;
+            }
+            Err(failure) => {
+                say!("  {} {} ({})", style::error(), style::path(&abbreviate_path(source)), failure.message);
+                // A vanished source links nothing to roll back for
+                if attempted && failure.class != "unavailable" && failed_link.is_none() {
+                    failed_link = Some(source.clone());
+                }
+                let conflict = matches!(failure.class, "conflict" | "contested");
+                outcome.report.sources.push(failure.to_report());
+                outcome.failures.push(failure);
+                if run.stop_on_conflict && conflict {
+                    outcome.stopped = true;
+                    run.halted.store(true, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+    }
+
+    // A source that failed to link takes the target's other new links with it
+    if let Some(failed) = failed_link.filter(|_| transaction::rollback_enabled()) {
+        for (changes, removed) in transaction.rollback() {
+            let path = abbreviate_path(&changes.source);
+            let Some(entry) = outcome.report.sources.iter_mut().find(|e| e.path == path && e.status == "ok") else {
+                continue;
+            };
+            say!("  {} {} (rolled back {} link(s))", style::rolled_back(), style::path(&path), removed);
+            let failure = RestoreFailure::rolled_back(&changes.source, target, &failed);
+            *entry = failure.to_report();
+            outcome.failures.push(failure);
+            outcome.success -= 1;
+            if let Err(e) = run.checkpoint.lock().unwrap().unmark(target, &changes.source) {
+                outcome.error = Some(e);
             }
         }
     }
+
+    drop(lock);
+    if outcome.success > 0 {
+        let _ = hooks::run(config, Hook::PostRestore, target, sources, false);
+    }
+    say!();
+    Some(outcome)
 }
 
 /*
- * Detect real files where symlinks should exist
+ * A single failed (source, target) pair from restore, classified for automation
  */
-fn find_real_files(source: &Path, target: &Path) -> Vec<String> {
-    let mut real_files = Vec::new();
-    find_real_files_recursive(source, target, source, &mut real_files);
-    real_files
+struct RestoreFailure {
+    source: PathBuf,
+    target: PathBuf,
+    class: &'static str,
+    message: String,
+    suggestion: String,
 }
 
-fn find_real_files_recursive(source_base: &Path, target: &Path, current_source: &Path, real_files: &mut Vec<String>) {
-    if let Ok(entries) = std::fs::read_dir(current_source) {
-        for entry in entries.flatten() {
-            let source_path = entry.path();
-            let relative = source_path.strip_prefix(source_base).unwrap_or(&source_path);
-            let target_path = target.join(relative);
+impl RestoreFailure {
+    fn source_not_found(source: &Path, target: &Path) -> Self {
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "source_not_found",
+            message: "source not found".to_string(),
+            suggestion: format!("amu remove {} {}", abbreviate_path(source), abbreviate_path(target)),
+        }
+    }
 
-            if source_path.is_dir() && !source_path.is_symlink() {
-                find_real_files_recursive(source_base, target, &source_path, real_files);
-            } else if source_path.is_file() {
-                // If a file with the same name exists in target and is not a symlink
-                if target_path.exists() && !target_path.is_symlink() {
-                    real_files.push(relative.display().to_string());
-                }
-            }
+    fn not_mounted(source: &Path, target: &Path, point: &Path) -> Self {
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "not_mounted",
+            message: format!("not mounted: {}", abbreviate_path(point)),
+            suggestion: format!("amu restore {} --mount-wait 30s (after mounting {})", abbreviate_path(target), abbreviate_path(point)),
         }
     }
-}
 
-/*
- * Count the number of links from source to target
- */
-fn count_links(source: &Path, target: &Path) -> usize {
-    let mut count = 0;
-    count_links_recursive(source, target, source, &mut count);
-    count
-}
+    fn unavailable(source: &Path, target: &Path) -> Self {
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "unavailable",
+            message: "source is no longer available".to_string(),
+            suggestion: format!("amu restore {} (once {} is back)", abbreviate_path(target), abbreviate_path(source)),
+        }
+    }
+
+    fn target_create_failed(source: &Path, target: &Path, error: &DotlinkError) -> Self {
+        let class = match error {
+            DotlinkError::ParentsModeRefused { .. } => "target_missing",
+            DotlinkError::IoError(e) if e.kind() == std::io::ErrorKind::PermissionDenied => "permission_denied",
+            _ => "target_create_failed",
+        };
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class,
+            message: format!("failed to create target directory: {}", error),
+            suggestion: format!("mkdir -p {}", abbreviate_path(target)),
+        }
+    }
 
-fn count_links_recursive(source_base: &Path, target: &Path, current_source: &Path, count: &mut usize) {
-    if let Ok(entries) = std::fs::read_dir(current_source) {
-        for entry in entries.flatten() {
-            let source_path = entry.path();
-            let relative = source_path.strip_prefix(source_base).unwrap_or(&source_path);
-            let target_path = target.join(relative);
+    fn read_only(source: &Path, target: &Path) -> Self {
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "read_only",
+            message: "target is read-only".to_string(),
+            suggestion: format!("ls -ld {}", abbreviate_path(target)),
+        }
+    }
 
-            if source_path.is_dir() && !source_path.is_symlink() {
-                count_links_recursive(source_base, target, &source_path, count);
-            } else if target_path.is_symlink() {
-                *count += 1;
-            }
+    fn contested(source: &Path, target: &Path, contested: &[precedence::Contested]) -> Self {
+        let paths: Vec<String> = contested.iter()
+            .map(|c| format!("{} by {}", c.path.display(), abbreviate_path(&c.winner)))
+            .collect();
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "contested",
+            message: format!("{} path(s) already provided: {}", contested.len(), paths.join(", ")),
+            suggestion: format!("amu restore {} --continue", abbreviate_path(target)),
+        }
+    }
+
+    fn rolled_back(source: &Path, target: &Path, failed: &Path) -> Self {
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class: "rolled_back",
+            message: format!("rolled back after {} failed", abbreviate_path(failed)),
+            suggestion: format!("amu restore {} (after fixing {})", abbreviate_path(target), abbreviate_path(failed)),
+        }
+    }
+
+    fn from_error(source: &Path, target: &Path, error: &DotlinkError) -> Self {
+        if matches!(error, DotlinkError::SourceUnavailable(_)) || !source.exists() {
+            return RestoreFailure::unavailable(source, target);
+        }
+        let message = error.to_string();
+        let lower = message.to_lowercase();
+
+        let (class, suggestion) = if stow::is_conflict(&message) {
+            ("conflict", format!("amu status {}", abbreviate_path(target)))
+        } else if lower.contains("permission denied") {
+            ("permission_denied", format!("ls -la {}", abbreviate_path(target)))
+        } else {
+            ("stow_error", format!("amu restore {} --dry-run", abbreviate_path(target)))
+        };
+
+        RestoreFailure {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            class,
+            message: message.trim().lines().collect::<Vec<_>>().join(" "),
+            suggestion,
         }
     }
+
+    fn to_report(&self) -> report::RestoreSource {
+        let mut entry = report::RestoreSource::new(abbreviate_path(&self.source), "failed");
+        entry.target = Some(abbreviate_path(&self.target));
+        entry.class = Some(self.class);
+        entry.message = Some(self.message.clone());
+        entry.suggestion = Some(self.suggestion.clone());
+        entry
+    }
 }
 
-fn abbreviate_path(path: &Path) -> String {
-    if let Some(home) = dirs::home_dir() {
-        if let Ok(stripped) = path.strip_prefix(&home) {
-            return format!("~/{}", stripped.display());
+fn cmd_watch(once: bool, debounce_ms: u64, interval_ms: u64, log: Option<PathBuf>) -> Result<()> {
+    use std::time::Duration;
+
+    let log = watch::WatchLog::open(log)?;
+    let debounce = Duration::from_millis(debounce_ms);
+
+    if once {
+        if watch::run_once(debounce, &log)? == 0 {
+            println!("No pending changes.");
         }
+        return Ok(());
     }
-    path.display().to_string()
+
+    println!("Watching registered sources (Ctrl-C to stop)...");
+    watch::run(Duration::from_millis(interval_ms), debounce, &log)
+}
+
+fn json_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
 }
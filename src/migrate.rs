@@ -0,0 +1,374 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::config::{self, Config, PathKey};
+use crate::error::{DotlinkError, Result};
+use crate::git;
+use crate::linker::platform;
+
+/// Dotfile managers `amu import --from` reads an existing setup from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Manager {
+    /// A stow directory; each package becomes a source of the stow target
+    Stow,
+    /// A chezmoi source directory, converted into a plain source
+    Chezmoi,
+    /// A yadm repository, whose tracked files are copied into a plain source
+    Yadm,
+}
+
+impl Manager {
+    pub fn name(self) -> &'static str {
+        match self {
+            Manager::Stow => "stow",
+            Manager::Chezmoi => "chezmoi",
+            Manager::Yadm => "yadm",
+        }
+    }
+
+    /// Where the manager keeps its setup when no path is given.
+    pub fn default_path(self) -> Option<PathBuf> {
+        self.default_path_in(&config::home_dir()?)
+    }
+
+    /// Where the manager keeps its setup in the home directory `home`.
+    pub fn default_path_in(self, home: &Path) -> Option<PathBuf> {
+        match self {
+            Manager::Stow => None,
+            Manager::Chezmoi => Some(home.join(".local/share/chezmoi")),
+            Manager::Yadm => Some(home.join(".local/share/yadm/repo.git")),
+        }
+    }
+
+    /// The target linked into: the stow target (from `.stowrc`, else the stow directory's
+    /// parent), or the home directory for the others.
+    pub fn default_target(self, path: &Path) -> Option<PathBuf> {
+        match self {
+            Manager::Stow => stowrc_target(path).or_else(|| path.parent().map(Path::to_path_buf)),
+            Manager::Chezmoi | Manager::Yadm => config::home_dir(),
+        }
+    }
+}
+
+/// What one path of a converted source is made from
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    Dir { mode: Option<u32> },
+    /// A copy of `from`, with its permissions set to `mode` if the manager recorded them
+    File { from: PathBuf, mode: Option<u32> },
+    Symlink { to: PathBuf },
+}
+
+/*
+ * A setup read from another manager
+ * A stow directory is registered as it is. chezmoi and yadm keep files in a form amu cannot
+ * link (encoded names, a bare repository), so they are converted into a new source
+ * directory, `into`, which is written by `apply`.
+ */
+pub struct Migration {
+    pub config: Config,
+    pub into: Option<PathBuf>,
+    /// Paths relative to `into`, parents before their contents
+    pub entries: Vec<(PathBuf, Entry)>,
+    /// Paths of the setup left out, with the reason
+    pub skipped: Vec<(PathBuf, &'static str)>,
+}
+
+impl Migration {
+    fn new(target: &Path, sources: Vec<PathBuf>, into: Option<PathBuf>) -> Self {
+        let mut config = Config::default();
+        if !sources.is_empty() {
+            config.targets.insert(PathKey::new(target), sources);
+        }
+        Migration { config, into, entries: Vec::new(), skipped: Vec::new() }
+    }
+
+    /// Files and links `apply` writes.
+    pub fn file_count(&self) -> usize {
+        self.entries.iter().filter(|(_, entry)| !matches!(entry, Entry::Dir { .. })).count()
+    }
+}
+
+/// Read the setup at `path`, to be linked into `target`; `into` receives converted files.
+pub fn read(manager: Manager, path: &Path, target: &Path, into: &Path) -> Result<Migration> {
+    if manager != Manager::Stow && fs::read_dir(into).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(DotlinkError::ImportDestinationNotEmpty(into.to_path_buf()));
+    }
+    match manager {
+        Manager::Stow => read_stow(path, target),
+        Manager::Chezmoi => read_chezmoi(path, target, into),
+        Manager::Yadm => read_yadm(path, target, into),
+    }
+}
+
+/// Write the converted source; returns the number of files and links written.
+pub fn apply(migration: &Migration) -> Result<usize> {
+    let Some(into) = &migration.into else { return Ok(0) };
+    fs::create_dir_all(into)?;
+    for (relative, entry) in &migration.entries {
+        let path = into.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match entry {
+            Entry::Dir { mode } => {
+                fs::create_dir_all(&path)?;
+                set_mode(&path, *mode)?;
+            }
+            Entry::File { from, mode } => {
+                fs::copy(from, &path)?;
+                set_mode(&path, *mode)?;
+            }
+            Entry::Symlink { to } => platform::symlink(to, &path)?,
+        }
+    }
+    Ok(migration.file_count())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+/// Entries of a directory, sorted by name so conversions are deterministic.
+fn sorted_entries(dir: &Path) -> Result<Vec<fs::DirEntry>> {
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+fn read_stow(dir: &Path, target: &Path) -> Result<Migration> {
+    let packages = sorted_entries(dir)?
+        .into_iter()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')))
+        .collect();
+    Ok(Migration::new(target, packages, None))
+}
+
+/// `--target` (or `-t`) from the stow directory's `.stowrc`.
+fn stowrc_target(dir: &Path) -> Option<PathBuf> {
+    let content = fs::read_to_string(dir.join(".stowrc")).ok()?;
+    let mut words = content.split_whitespace();
+    let mut target = None;
+    while let Some(word) = words.next() {
+        if let Some(value) = word.strip_prefix("--target=") {
+            target = Some(value.to_string());
+        } else if word == "--target" || word == "-t" {
+            target = words.next().map(str::to_string);
+        }
+    }
+    Some(config::resolve_path_in(dir, Path::new(&target?.replace("$HOME", "~"))))
+}
+
+/*
+ * chezmoi source state
+ * Names carry attributes as prefixes (`private_`, `executable_`, `dot_`, ...) and are
+ * decoded into plain names. Templates, scripts, encrypted files and chezmoi's own files
+ * cannot be carried over and are reported as skipped. A `.chezmoiroot` file moves the
+ * source state into a subdirectory.
+ */
+fn read_chezmoi(dir: &Path, target: &Path, into: &Path) -> Result<Migration> {
+    let root = match fs::read_to_string(dir.join(".chezmoiroot")) {
+        Ok(relative) => dir.join(relative.trim()),
+        Err(_) => dir.to_path_buf(),
+    };
+    let mut migration = Migration::new(target, vec![into.to_path_buf()], Some(into.to_path_buf()));
+    walk_chezmoi(&root, Path::new(""), &mut migration)?;
+    Ok(migration)
+}
+
+fn walk_chezmoi(dir: &Path, decoded: &Path, migration: &mut Migration) -> Result<()> {
+    for entry in sorted_entries(dir)? {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_dir = path.is_dir() && !path.is_symlink();
+        // chezmoi ignores other dot files in its source directory (.git, .gitignore, ...)
+        if name.starts_with('.') {
+            if name.starts_with(".chezmoi") {
+                migration.skipped.push((path, "chezmoi configuration"));
+            }
+            continue;
+        }
+        let attributes = match decode_chezmoi(&name, is_dir) {
+            Ok(attributes) => attributes,
+            Err(reason) => {
+                migration.skipped.push((path, reason));
+                continue;
+            }
+        };
+        let relative = decoded.join(&attributes.name);
+        if is_dir {
+            migration.entries.push((relative.clone(), Entry::Dir { mode: attributes.mode }));
+            walk_chezmoi(&path, &relative, migration)?;
+        } else if attributes.symlink {
+            let to = fs::read_to_string(&path)?.trim().to_string();
+            migration.entries.push((relative, Entry::Symlink { to: PathBuf::from(to) }));
+        } else {
+            migration.entries.push((relative, Entry::File { from: path, mode: attributes.mode }));
+        }
+    }
+    Ok(())
+}
+
+/// A chezmoi source name decoded into the target name and its attributes
+#[derive(Debug, PartialEq)]
+struct Attributes {
+    name: String,
+    mode: Option<u32>,
+    symlink: bool,
+}
+
+fn decode_chezmoi(name: &str, is_dir: bool) -> std::result::Result<Attributes, &'static str> {
+    if !is_dir && name.ends_with(".tmpl") {
+        return Err("chezmoi template; port it to an amu template by hand");
+    }
+    let (mut private, mut readonly, mut executable, mut symlink) = (false, false, false, false);
+    let mut rest = name;
+    while let Some((prefix, remainder)) = rest.split_once('_') {
+        match prefix {
+            "run" | "modify" => return Err("chezmoi script"),
+            "remove" => return Err("chezmoi removal entry"),
+            "encrypted" => return Err("encrypted file"),
+            "private" => private = true,
+            "readonly" => readonly = true,
+            "executable" => executable = true,
+            "symlink" => symlink = true,
+            "create" | "empty" | "exact" | "external" => {}
+            "literal" => {
+                rest = remainder;
+                break;
+            }
+            "dot" => {
+                return Ok(Attributes { name: format!(".{}", remainder), mode: mode(is_dir, private, readonly, executable), symlink });
+            }
+            _ => break,
+        }
+        rest = remainder;
+    }
+    Ok(Attributes { name: rest.to_string(), mode: mode(is_dir, private, readonly, executable), symlink })
+}
+
+/// Permissions for the attributes; None when chezmoi would leave the defaults.
+fn mode(is_dir: bool, private: bool, readonly: bool, executable: bool) -> Option<u32> {
+    if !(private || readonly || executable) {
+        return None;
+    }
+    if is_dir {
+        // A read-only directory could not be filled; only privacy is kept
+        return private.then_some(0o700);
+    }
+    let mut mode = if executable { 0o755 } else { 0o644 };
+    if private {
+        mode &= 0o700;
+    }
+    if readonly {
+        mode &= !0o222;
+    }
+    Some(mode)
+}
+
+/*
+ * yadm repository
+ * yadm tracks files in place, in a bare repository whose work tree is the home directory.
+ * The tracked files are copied into the new source. Alternates (`file##class`) are picked
+ * per machine by yadm and left for the user to copy.
+ */
+fn read_yadm(repo: &Path, home: &Path, into: &Path) -> Result<Migration> {
+    let mut migration = Migration::new(home, vec![into.to_path_buf()], Some(into.to_path_buf()));
+    let mut dirs = std::collections::BTreeSet::new();
+    for relative in git::tracked_files(repo, home)? {
+        let from = home.join(&relative);
+        if relative.to_string_lossy().contains("##") {
+            migration.skipped.push((from, "yadm alternate; copy the variant for this machine by hand"));
+            continue;
+        }
+        let Ok(metadata) = fs::symlink_metadata(&from) else {
+            migration.skipped.push((from, "missing from the home directory"));
+            continue;
+        };
+        for parent in relative.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty()) {
+            if dirs.insert(parent.to_path_buf()) {
+                migration.entries.push((parent.to_path_buf(), Entry::Dir { mode: None }));
+            }
+        }
+        let entry = if metadata.file_type().is_symlink() {
+            Entry::Symlink { to: fs::read_link(&from)? }
+        } else {
+            Entry::File { from, mode: None }
+        };
+        migration.entries.push((relative, entry));
+    }
+    // Parents before their contents
+    migration.entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(migration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_decode_chezmoi() {
+        let decoded = decode_chezmoi("private_executable_dot_local", false).unwrap();
+        assert_eq!(decoded, Attributes { name: ".local".to_string(), mode: Some(0o700), symlink: false });
+        assert_eq!(decode_chezmoi("dot_zshrc", false).unwrap().name, ".zshrc");
+        assert_eq!(decode_chezmoi("dot_my_file", false).unwrap().name, ".my_file");
+        assert_eq!(decode_chezmoi("private_dot_ssh", true).unwrap().mode, Some(0o700));
+        assert!(decode_chezmoi("symlink_dot_vimrc", false).unwrap().symlink);
+        assert_eq!(decode_chezmoi("literal_dot_keep", false).unwrap().name, "dot_keep");
+        assert_eq!(decode_chezmoi("plain_name", false).unwrap().name, "plain_name");
+        assert!(decode_chezmoi("dot_gitconfig.tmpl", false).is_err());
+        assert!(decode_chezmoi("run_once_install.sh", false).is_err());
+    }
+
+    #[test]
+    fn test_read_chezmoi() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().join("chezmoi");
+        fs::create_dir_all(dir.join("dot_config/nvim")).unwrap();
+        fs::write(dir.join("dot_config/nvim/init.lua"), "").unwrap();
+        fs::write(dir.join("executable_dot_hook"), "").unwrap();
+        fs::write(dir.join("dot_gitconfig.tmpl"), "").unwrap();
+        fs::write(dir.join(".chezmoiignore"), "").unwrap();
+        let into = temp.path().join("into");
+
+        let migration = read(Manager::Chezmoi, &dir, Path::new("/home"), &into).unwrap();
+        let paths: Vec<&Path> = migration.entries.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(paths, [Path::new(".config"), Path::new(".config/nvim"), Path::new(".config/nvim/init.lua"), Path::new(".hook")]);
+        assert_eq!(migration.skipped.len(), 2);
+        assert_eq!(migration.config.sources_of(Path::new("/home")), vec![into.clone()]);
+
+        assert_eq!(apply(&migration).unwrap(), 2);
+        assert!(into.join(".config/nvim/init.lua").is_file());
+        assert!(matches!(read(Manager::Chezmoi, &dir, Path::new("/home"), &into), Err(DotlinkError::ImportDestinationNotEmpty(_))));
+    }
+
+    #[test]
+    fn test_read_stow() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().join("dotfiles");
+        fs::create_dir_all(dir.join("zsh")).unwrap();
+        fs::create_dir_all(dir.join("git")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("README.md"), "").unwrap();
+        assert_eq!(Manager::Stow.default_target(&dir), Some(temp.path().to_path_buf()));
+
+        let migration = read(Manager::Stow, &dir, temp.path(), Path::new("/unused")).unwrap();
+        assert_eq!(migration.config.sources_of(temp.path()), vec![dir.join("git"), dir.join("zsh")]);
+        assert!(migration.entries.is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::error::{DotlinkError, Result};
+use crate::ownership::{self, Owner};
+
+/*
+ * Creating missing target directories (`restore --parents-mode`, `--dir-mode`, `--dir-owner`)
+ * A missing `~/.config/app` is fine to create with its parents and the user's defaults; a
+ * missing `/etc/app` usually means a package is not installed, and creating `/etc` parts
+ * on the way would be wrong. Directories are created one level at a time so each new
+ * one gets the requested mode and owner, and nothing that already existed is touched.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ParentsMode {
+    /// Create the target and any missing parent directories
+    #[default]
+    All,
+    /// Create the target only if its parent directory exists
+    Target,
+    /// Never create a missing target
+    None,
+}
+
+impl ParentsMode {
+    /// "all", "target" or "none", as given to --parents-mode
+    pub fn label(self) -> &'static str {
+        match self {
+            ParentsMode::All => "all",
+            ParentsMode::Target => "target",
+            ParentsMode::None => "none",
+        }
+    }
+}
+
+/// How missing targets are created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirOptions {
+    pub parents: ParentsMode,
+    /// Permission bits for created directories (default: from the umask)
+    pub mode: Option<u32>,
+    /// Owner for created directories (default: the home owner when running as root)
+    pub owner: Option<Owner>,
+}
+
+/// The directories creating `target` takes, outermost first, or why it may not be created.
+pub fn plan(target: &Path, parents: ParentsMode) -> Result<Vec<PathBuf>> {
+    let mut missing: Vec<PathBuf> = target.ancestors()
+        .take_while(|dir| fs::symlink_metadata(dir).is_err())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    let refused = match parents {
+        ParentsMode::None => !missing.is_empty(),
+        ParentsMode::Target => missing.len() > 1,
+        ParentsMode::All => false,
+    };
+    if refused {
+        return Err(DotlinkError::ParentsModeRefused {
+            target: target.to_path_buf(),
+            missing: missing[0].clone(),
+            mode: parents.label(),
+        });
+    }
+    Ok(missing)
+}
+
+/*
+ * Create a missing target as `options` allow; returns the directories created
+ * Targets sharing parents may be restored in parallel (--jobs), so a directory another
+ * job created in the meantime is taken as it is, and left out of the result.
+ */
+pub fn create(target: &Path, options: &DirOptions) -> Result<Vec<PathBuf>> {
+    make(plan(target, options.parents)?, options)
+}
+
+fn make(planned: Vec<PathBuf>, options: &DirOptions) -> Result<Vec<PathBuf>> {
+    let mut created = Vec::new();
+    for dir in planned {
+        match fs::create_dir(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => continue,
+            Err(e) => return Err(e.into()),
+        }
+        set_mode(&dir, options.mode)?;
+        match options.owner {
+            Some(owner) => ownership::chown(&dir, owner)?,
+            None => ownership::fix_written(&dir),
+        }
+        crate::debug!("created directory {}", dir.display());
+        created.push(dir);
+    }
+    Ok(created)
+}
+
+#[cfg(unix)]
+fn set_mode(dir: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match mode {
+        // Set after creating, so the umask does not clear bits that were asked for
+        Some(mode) => fs::set_permissions(dir, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_dir: &Path, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Parse an octal mode such as `755` or `0700`.
+pub fn parse_mode(value: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("invalid mode: {value} (use octal, e.g. 755)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_plan_and_create() {
+        let temp = TempDir::new().unwrap();
+        let target = temp.path().join("a/b/c");
+
+        assert!(plan(&target, ParentsMode::None).is_err());
+        assert!(matches!(
+            plan(&target, ParentsMode::Target),
+            Err(DotlinkError::ParentsModeRefused { missing, .. }) if missing == temp.path().join("a")
+        ));
+        assert_eq!(plan(&temp.path().join("a"), ParentsMode::Target).unwrap(), vec![temp.path().join("a")]);
+        assert!(plan(temp.path(), ParentsMode::None).unwrap().is_empty());
+
+        let options = DirOptions { mode: Some(0o700), ..DirOptions::default() };
+        let created = create(&target, &options).unwrap();
+        assert_eq!(created, vec![temp.path().join("a"), temp.path().join("a/b"), target.clone()]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o7777, 0o700);
+        }
+
+        // Directories made by someone else since the plan are taken as they are
+        let sibling = temp.path().join("a/b/d");
+        let planned = plan(&sibling, ParentsMode::All).unwrap();
+        fs::create_dir(&sibling).unwrap();
+        assert!(make(planned, &options).unwrap().is_empty());
+        assert_eq!(parse_mode("0755"), Ok(0o755));
+        assert!(parse_mode("789").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// How often `wait` looks at the mount points again
+const POLL: Duration = Duration::from_millis(200);
+
+/*
+ * Sources on removable or network media (`amu media mark`)
+ * Such a source is expected to be missing while its media is not mounted; commands report
+ * it as not mounted rather than not found. Looking at a mount point is also what triggers an
+ * automounter, so waiting for one is just looking again until it appears.
+ */
+
+/// Whether something is mounted at `point`: it is the root, or lives on another device than
+/// its parent directory.
+#[cfg(unix)]
+pub fn is_mounted(point: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = fs::metadata(point) else { return false };
+    match point.parent() {
+        Some(parent) => fs::metadata(parent).is_ok_and(|p| p.dev() != metadata.dev()),
+        None => true,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_mounted(point: &Path) -> bool {
+    point.is_dir()
+}
+
+/// The mount point of a marked source whose media is not mounted.
+pub fn unmounted<'a>(config: &'a Config, source: &Path) -> Option<&'a Path> {
+    config.mounts.get(source).map(PathBuf::as_path).filter(|point| !source.exists() && !is_mounted(point))
+}
+
+/// Wait up to `timeout` for the given mount points; returns those still not mounted.
+pub fn wait(points: &[PathBuf], timeout: Duration) -> Vec<PathBuf> {
+    // A timeout past what the clock can hold waits until the points are mounted
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        let missing: Vec<PathBuf> = points.iter().filter(|p| !is_mounted(p)).cloned().collect();
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if missing.is_empty() || left == Some(Duration::ZERO) {
+            return missing;
+        }
+        thread::sleep(left.map_or(POLL, |left| POLL.min(left)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_mounted() {
+        let temp = TempDir::new().unwrap();
+        assert!(is_mounted(Path::new("/")));
+        assert!(!is_mounted(&temp.path().join("drive")));
+
+        let dir = temp.path().join("plain");
+        fs::create_dir(&dir).unwrap();
+        assert!(!is_mounted(&dir));
+        assert_eq!(wait(std::slice::from_ref(&dir), Duration::ZERO), vec![dir]);
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::Notify;
+use crate::error::{DotlinkError, Result};
+
+/*
+ * Drift notifications for unattended runs (cron, launchd, systemd timers)
+ * The status JSON is POSTed to the webhook with curl and piped to the command through
+ * `sh -c`, so no HTTP client is linked into amu. Both are tried before reporting a failure.
+ */
+pub fn send(notify: &Notify, report: &str) -> Result<()> {
+    let mut errors = Vec::new();
+
+    if let Some(url) = &notify.webhook {
+        let mut curl = Command::new("curl");
+        curl.args(["-fsS", "-X", "POST", "-H", "Content-Type: application/json", "--data-binary", "@-", "--", url]);
+        if let Err(e) = pipe(curl, report) {
+            errors.push(format!("webhook: {e}"));
+        }
+    }
+    if let Some(command) = &notify.command {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        if let Err(e) = pipe(shell, report) {
+            errors.push(format!("command: {e}"));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(DotlinkError::NotifyError(errors.join("; ")))
+    }
+}
+
+/// Run a command with `input` on stdin, failing on a non-zero exit status.
+fn pipe(mut command: Command, input: &str) -> std::result::Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!("{} ({})", output.status, stderr.trim()))
+    }
+}
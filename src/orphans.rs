@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::ignore;
+use crate::links;
+use crate::unreadable;
+
+/*
+ * Unmanaged files inside managed directories (`amu orphans`)
+ * A directory is managed when a source provides files directly in it, and so is every
+ * directory below it; a source directory holding only subdirectories (like `.config`) is
+ * shared with other programs and left out, as is a target that is the home directory.
+ * Whatever a managed directory holds that no source of the target provides is an orphan:
+ * caches, state or settings written by the program. An orphan directory is reported as a
+ * whole rather than file by file.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Orphan {
+    /// Target-relative path
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// The highest-priority source managing the directory the orphan is in
+    pub source: PathBuf,
+}
+
+/// Orphans in a target, sorted by path; paths matching `skip` are not reported.
+pub fn find(config: &Config, target: &Path, skip: &[String]) -> Vec<Orphan> {
+    let Some(sources) = config.get_sources(target) else { return Vec::new() };
+    let mut managed: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for source in sources.iter().filter(|s| s.is_dir()) {
+        let ignore = ignore::patterns_for(config, target, source);
+        collect_managed(source, Path::new(""), &ignore, false, &mut managed);
+    }
+    if config::home_dir().is_some_and(|home| config::same_path(&home, target)) {
+        managed.remove(Path::new(""));
+    }
+
+    let provided = |relative: &Path| sources.iter().any(|s| fs::symlink_metadata(s.join(relative)).is_ok());
+    let mut orphans = Vec::new();
+    for (dir, source) in &managed {
+        let at = target.join(dir);
+        if at.is_symlink() {
+            continue;
+        }
+        let Some(entries) = unreadable::read_dir(&at) else { continue };
+        for entry in entries {
+            let relative = dir.join(entry.file_name());
+            let path = entry.path();
+            // Links into a source are the sources' business, broken or not
+            let into_source = path.is_symlink()
+                && links::link_destination(&path).is_some_and(|d| sources.iter().any(|s| d.starts_with(s)));
+            if provided(&relative) || into_source || ignore::is_ignored(&relative, skip) {
+                continue;
+            }
+            let is_dir = path.is_dir() && !path.is_symlink();
+            orphans.push(Orphan { path: relative, is_dir, source: source.clone() });
+        }
+    }
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    orphans
+}
+
+/// Record the source's managed directories, keeping the first source found for each.
+fn collect_managed(source: &Path, relative: &Path, ignore: &[String], inherited: bool, managed: &mut BTreeMap<PathBuf, PathBuf>) {
+    let Some(entries) = unreadable::read_dir(&source.join(relative)) else { return };
+    let mut dirs = Vec::new();
+    let mut has_files = false;
+    for entry in entries {
+        let child = relative.join(entry.file_name());
+        if ignore::is_ignored(&child, ignore) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() && !path.is_symlink() {
+            dirs.push(child);
+        } else {
+            has_files = true;
+        }
+    }
+    let is_managed = inherited || has_files;
+    if is_managed {
+        managed.entry(relative.to_path_buf()).or_insert_with(|| source.to_path_buf());
+    }
+    for dir in dirs {
+        collect_managed(source, &dir, ignore, is_managed, managed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathKey;
+    use crate::linker::platform;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_orphans() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("dotfiles");
+        let target = root.join("target");
+        fs::create_dir_all(source.join(".config/app/themes")).unwrap();
+        fs::write(source.join(".config/app/settings"), "").unwrap();
+        fs::create_dir_all(target.join(".config/app/themes")).unwrap();
+        fs::create_dir_all(target.join(".config/app/cache/blobs")).unwrap();
+        fs::create_dir_all(target.join(".config/other")).unwrap();
+        platform::symlink(&source.join(".config/app/settings"), &target.join(".config/app/settings")).unwrap();
+        fs::write(target.join(".config/app/state.json"), "").unwrap();
+        fs::write(target.join(".config/app/themes/custom"), "").unwrap();
+        fs::write(target.join(".config/app/history.log"), "").unwrap();
+
+        let mut config = Config::default();
+        config.targets.insert(PathKey::new(&target), vec![source.clone()]);
+        let orphans = find(&config, &target, &["*.log".to_string()]);
+        let paths: Vec<(&Path, bool)> = orphans.iter().map(|o| (o.path.as_path(), o.is_dir)).collect();
+        // `.config` only holds directories in the source, so `.config/other` is not an orphan
+        assert_eq!(
+            paths,
+            [
+                (Path::new(".config/app/cache"), true),
+                (Path::new(".config/app/state.json"), false),
+                (Path::new(".config/app/themes/custom"), false),
+            ]
+        );
+        assert!(orphans.iter().all(|o| o.source == source));
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+/*
+ * Ownership fix-ups for running as root against another user's home
+ * When amu runs as root (e.g. via sudo) and the home directory belongs to a regular
+ * user, everything amu creates is handed over to that user instead of staying root-owned.
+ */
+
+/// Home directory of the user who invoked sudo, if running under sudo as root.
+#[cfg(unix)]
+pub fn sudo_user_home() -> Option<PathBuf> {
+    use std::ffi::{CStr, CString};
+
+    if unsafe { libc::geteuid() } != 0 {
+        return None;
+    }
+    let user = std::env::var("SUDO_USER").ok().filter(|u| !u.is_empty() && u != "root")?;
+    let c_user = CString::new(user).ok()?;
+
+    let passwd = unsafe { libc::getpwnam(c_user.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+    Some(PathBuf::from(dir.to_string_lossy().into_owned()))
+}
+
+#[cfg(not(unix))]
+pub fn sudo_user_home() -> Option<PathBuf> {
+    None
+}
+
+/// The (uid, gid) created files should belong to, or None when no change is needed.
+#[cfg(unix)]
+fn owner() -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::OnceLock;
+
+    static OWNER: OnceLock<Option<(u32, u32)>> = OnceLock::new();
+    *OWNER.get_or_init(|| {
+        if unsafe { libc::geteuid() } != 0 {
+            return None;
+        }
+        let metadata = std::fs::metadata(config::home_dir()?).ok()?;
+        if metadata.uid() == 0 {
+            return None;
+        }
+        Some((metadata.uid(), metadata.gid()))
+    })
+}
+
+/// Hand a path amu just wrote inside the home to the home owner, along with any
+/// root-owned parent directories (e.g. a freshly created `~/.config/amu`).
+#[cfg(unix)]
+pub fn fix_written(path: &Path) {
+    use std::os::unix::fs::MetadataExt;
+
+    let Some((uid, gid)) = owner() else { return };
+    let Some(home) = config::home_dir() else { return };
+    if !path.starts_with(&home) {
+        return;
+    }
+    lchown(path, uid, gid);
+
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == home || !dir.starts_with(&home) {
+            break;
+        }
+        match std::fs::symlink_metadata(dir) {
+            Ok(m) if m.uid() == 0 => lchown(dir, uid, gid),
+            _ => break,
+        }
+        current = dir.parent();
+    }
+}
+
+#[cfg(not(unix))]
+pub fn fix_written(_path: &Path) {}
+
+/// Hand links pointing into `source` (and directories created for them) to the home owner.
+#[cfg(unix)]
+pub fn fix_links(source: &Path, target: &Path) {
+    use std::os::unix::fs::MetadataExt;
+
+    let Some((uid, gid)) = owner() else { return };
+    for entry in crate::links::find_all_symlinks(target) {
+        if !entry.destination.starts_with(source) {
+            continue;
+        }
+        let link = target.join(&entry.relative);
+        lchown(&link, uid, gid);
+
+        let mut current = link.parent();
+        while let Some(dir) = current {
+            if dir == target {
+                break;
+            }
+            match std::fs::symlink_metadata(dir) {
+                Ok(m) if m.uid() == 0 => lchown(dir, uid, gid),
+                _ => break,
+            }
+            current = dir.parent();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn fix_links(_source: &Path, _target: &Path) {}
+
+#[cfg(unix)]
+fn lchown(path: &Path, uid: u32, gid: u32) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) {
+        unsafe {
+            libc::lchown(c_path.as_ptr(), uid, gid);
+        }
+    }
+}
+
+/// An explicit owner for created files (`--dir-owner USER[:GROUP]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    /// None keeps the group the directory was created with
+    pub gid: Option<u32>,
+}
+
+/// Parse `USER[:GROUP]`; names are looked up, numeric ids taken as they are.
+#[cfg(unix)]
+pub fn parse_owner(spec: &str) -> Result<Owner, String> {
+    use std::ffi::CString;
+
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let uid = match user.parse() {
+        Ok(uid) => uid,
+        Err(_) => {
+            let c_user = CString::new(user).map_err(|_| format!("invalid user: {}", user))?;
+            let passwd = unsafe { libc::getpwnam(c_user.as_ptr()) };
+            if passwd.is_null() {
+                return Err(format!("unknown user: {}", user));
+            }
+            unsafe { (*passwd).pw_uid }
+        }
+    };
+    let gid = match group {
+        None => None,
+        Some(group) => Some(match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => {
+                let c_group = CString::new(group).map_err(|_| format!("invalid group: {}", group))?;
+                let entry = unsafe { libc::getgrnam(c_group.as_ptr()) };
+                if entry.is_null() {
+                    return Err(format!("unknown group: {}", group));
+                }
+                unsafe { (*entry).gr_gid }
+            }
+        }),
+    };
+    Ok(Owner { uid, gid })
+}
+
+#[cfg(not(unix))]
+pub fn parse_owner(_spec: &str) -> Result<Owner, String> {
+    Err("setting an owner is only supported on Unix".to_string())
+}
+
+/// Give a path to `owner`, without following a symlink.
+#[cfg(unix)]
+pub fn chown(path: &Path, owner: Owner) -> std::io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(owner.uid), owner.gid)
+}
+
+#[cfg(not(unix))]
+pub fn chown(_path: &Path, _owner: Owner) -> std::io::Result<()> {
+    Ok(())
+}
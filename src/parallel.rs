@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+
+use crate::report;
+
+/*
+ * Per-target work on a small thread pool (`--jobs`)
+ * Workers run with their human output captured; results, and the captured output with
+ * them, are handed back in input order as soon as every earlier item has finished, so
+ * output reads the same as a serial run.
+ */
+static JOBS: OnceLock<usize> = OnceLock::new();
+
+/// Set the number of worker threads for this process (`--jobs`); 0 means one per CPU.
+pub fn set_jobs(jobs: usize) {
+    let _ = JOBS.set(jobs);
+}
+
+/// Worker threads to use: `--jobs`, or the number of CPUs.
+pub fn jobs() -> usize {
+    match JOBS.get().copied().unwrap_or(0) {
+        0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    }
+}
+
+/*
+ * Run `work` on every item with up to `jobs` threads and pass each result to `consume`,
+ * in item order
+ * `consume` runs on the calling thread after the item's captured output is printed.
+ * If it returns an error, no further items are started and the error is returned once
+ * the workers have stopped.
+ */
+pub fn for_each_ordered<T, R, E>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut consume: impl FnMut(R) -> Result<(), E>,
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+{
+    let jobs = jobs.min(items.len());
+    if jobs <= 1 {
+        for item in items {
+            consume(work(item))?;
+        }
+        return Ok(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else { break };
+                    let (result, output) = report::capture(|| work(item));
+                    if sender.send((index, result, output)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut ready = BTreeMap::new();
+        let mut expected = 0;
+        for (index, result, output) in receiver {
+            ready.insert(index, (result, output));
+            while let Some((result, output)) = ready.remove(&expected) {
+                expected += 1;
+                print!("{}", output);
+                if let Err(e) = consume(result) {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_ordered_keeps_order() {
+        let items: Vec<u64> = (0..20).collect();
+        let mut seen = Vec::new();
+        for_each_ordered(&items, 4, |n| {
+            thread::sleep(std::time::Duration::from_millis(20 - n));
+            n * 2
+        }, |r| {
+            seen.push(r);
+            Ok::<(), ()>(())
+        }).unwrap();
+        assert_eq!(seen, (0..20).map(|n| n * 2).collect::<Vec<_>>());
+
+        let result = for_each_ordered(&items, 4, |n| *n, |n| if n == 3 { Err(n) } else { Ok(()) });
+        assert_eq!(result, Err(3));
+    }
+}
@@ -0,0 +1,263 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::home_dir;
+
+/*
+ * Path expansion and comparison
+ * Paths reach amu as typed (`~/x`, `../x`, through symlinks such as /tmp -> /private/tmp),
+ * as stored in the config (canonical at add time, or written by hand), and as found on
+ * disk, where they may no longer exist. Everything that expands, resolves or compares
+ * paths goes through here so every command treats them alike; the config module
+ * re-exports these helpers.
+ */
+
+/// Expand a leading `~` to the home directory.
+pub fn expand_path(path: &Path) -> PathBuf {
+    let path_str = path.to_string_lossy();
+    let expanded = shellexpand::tilde_with_context(&path_str, || {
+        home_dir().map(|h| h.to_string_lossy().into_owned())
+    });
+    PathBuf::from(expanded.as_ref())
+}
+
+/// Resolve `.` and `..` components without touching the filesystem,
+/// for paths that may not exist and cannot be canonicalized.
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other.as_os_str()),
+        }
+    }
+    result
+}
+
+/// Tilde-expanded, absolute (against the current directory) and lexically normalized.
+pub fn absolute_path(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(cwd) => absolute_path_in(&cwd, path),
+        Err(_) => normalize_lexically(&expand_path(path)),
+    }
+}
+
+/// `absolute_path` for a path relative to `base` rather than the current directory.
+pub fn absolute_path_in(base: &Path, path: &Path) -> PathBuf {
+    normalize_lexically(&base.join(expand_path(path)))
+}
+
+/// Canonicalize the deepest existing ancestor of an absolute path and append the rest.
+pub fn canonicalize_existing(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find_map(|ancestor| {
+            let rest = path.strip_prefix(ancestor).ok()?;
+            // Joining an empty rest would add a trailing separator
+            ancestor.canonicalize().ok().map(|c| if rest.as_os_str().is_empty() { c } else { c.join(rest) })
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// The form a path is stored in: absolute and canonical as far as it exists.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    canonicalize_existing(&absolute_path(path))
+}
+
+/// `resolve_path` for a path relative to `base` rather than the current directory.
+pub fn resolve_path_in(base: &Path, path: &Path) -> PathBuf {
+    canonicalize_existing(&absolute_path_in(base, path))
+}
+
+/// The stored form of a path that may itself be a link: its parent resolved, its name kept.
+pub fn resolve_link(path: &Path) -> PathBuf {
+    let path = absolute_path(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => canonicalize_existing(parent).join(name),
+        _ => path,
+    }
+}
+
+/// The stored form of a path that must exist; the error if it cannot be resolved.
+pub fn resolve_existing(path: &Path) -> io::Result<PathBuf> {
+    absolute_path(path).canonicalize()
+}
+
+/*
+ * A path in the form used to compare it with other paths
+ * Two keys are equal when they refer to the same location, whichever form each was
+ * written in; a key keeps the path as given, which is what it shows and is saved as. A
+ * path that no longer exists is resolved through its deepest existing ancestor, so an
+ * entry stored canonically still matches after its directory is deleted. The config's
+ * targets are keyed by it.
+ */
+#[derive(Debug, Clone)]
+pub struct PathKey {
+    path: PathBuf,
+    resolved: PathBuf,
+}
+
+impl PathKey {
+    pub fn new(path: &Path) -> Self {
+        PathKey { path: path.to_path_buf(), resolved: resolve_path(path) }
+    }
+
+    /// The path as given.
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The form keys are compared in.
+    pub fn resolved(&self) -> &Path {
+        &self.resolved
+    }
+
+    /// Whether this path is `other` or inside it.
+    pub fn starts_with(&self, other: &PathKey) -> bool {
+        self.resolved.starts_with(&other.resolved)
+    }
+}
+
+impl PartialEq for PathKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.resolved == other.resolved
+    }
+}
+
+impl Eq for PathKey {}
+
+impl PartialOrd for PathKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PathKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.resolved.cmp(&other.resolved)
+    }
+}
+
+impl std::hash::Hash for PathKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.resolved.hash(state);
+    }
+}
+
+impl std::ops::Deref for PathKey {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for PathKey {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl From<&Path> for PathKey {
+    fn from(path: &Path) -> Self {
+        PathKey::new(path)
+    }
+}
+
+impl From<PathBuf> for PathKey {
+    fn from(path: PathBuf) -> Self {
+        let resolved = resolve_path(&path);
+        PathKey { path, resolved }
+    }
+}
+
+impl Serialize for PathKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.path.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        PathBuf::deserialize(deserializer).map(PathKey::from)
+    }
+}
+
+/// Where a path that is itself a symlink resolves to; `None` for anything else.
+pub fn symlink_destination(path: &Path) -> Option<PathBuf> {
+    path.is_symlink().then(|| canonicalize_existing(path))
+}
+
+/// Whether two paths refer to the same location in any of their forms.
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    a == b || PathKey::new(a) == PathKey::new(b)
+}
+
+/// Display form of a path, with the home directory shortened to `~`.
+pub fn abbreviate_path(path: &Path) -> String {
+    if let Some(home) = home_dir() {
+        if let Ok(stripped) = path.strip_prefix(&home) {
+            return format!("~/{}", stripped.display());
+        }
+    }
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_expand_path_with_tilde() {
+        let home = dirs::home_dir().unwrap();
+        let path = Path::new("~/.config");
+        let expanded = expand_path(path);
+        assert_eq!(expanded, home.join(".config"));
+    }
+
+    #[test]
+    fn test_expand_path_without_tilde() {
+        let path = Path::new("/usr/local/bin");
+        let expanded = expand_path(path);
+        assert_eq!(expanded, PathBuf::from("/usr/local/bin"));
+    }
+
+    #[test]
+    fn test_normalize_lexically() {
+        assert_eq!(
+            normalize_lexically(Path::new("/a/b/../c/./d")),
+            PathBuf::from("/a/c/d")
+        );
+    }
+
+    #[test]
+    fn test_path_key() {
+        let temp = TempDir::new().unwrap();
+        let real = temp.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        crate::linker::platform::symlink(&real, &temp.path().join("link")).unwrap();
+
+        let canonical = PathKey::new(&real.join("gone"));
+        assert_eq!(PathKey::new(&temp.path().join("link/./x/../gone")), canonical);
+        assert!(canonical.starts_with(&PathKey::new(&temp.path().join("link"))));
+        assert_ne!(PathKey::new(&temp.path().join("other")), canonical);
+        assert!(same_path(&temp.path().join("link"), &real));
+        assert_eq!(resolve_path(&temp.path().join("link/")), real.canonicalize().unwrap());
+        assert_eq!(resolve_path_in(&temp.path().join("link"), Path::new("a/../b")), real.canonicalize().unwrap().join("b"));
+        assert_eq!(resolve_existing(&temp.path().join("link")).unwrap(), real.canonicalize().unwrap());
+        assert!(resolve_existing(&temp.path().join("other")).is_err());
+
+        // A key keeps the form it was written in; a map finds it through any other
+        let written = temp.path().join("link/gone");
+        let targets = std::collections::BTreeMap::from([(PathKey::new(&written), 1)]);
+        let (stored, _) = targets.get_key_value(&canonical).unwrap();
+        assert_eq!(stored.as_path(), written);
+        assert_eq!(serde_yaml::to_string(stored).unwrap().trim(), written.display().to_string());
+        assert_eq!(resolve_link(&temp.path().join("link/./x")), real.canonicalize().unwrap().join("x"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::say;
+
+/*
+ * What linking or unlinking a source would do (`--dry-run`)
+ * The built-in linker describes its own operations; stow's are read from `stow -n -v`, so
+ * the rest of amu never looks at stow's output. Paths are relative to the target.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Plan {
+    pub actions: Vec<PlanAction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlanAction {
+    /// A symlink at `path` pointing to `destination` (relative to the link's directory)
+    LinkCreate { path: PathBuf, destination: PathBuf },
+    LinkRemove { path: PathBuf },
+    /// A copy of the source's file written at `path` (`mode: copy`)
+    Copy { path: PathBuf },
+    /// A hard link to the source's file made at `path` (`mode: hardlink`)
+    HardLink { path: PathBuf },
+    /// A copied or hard-linked file deleted from the target
+    Delete { path: PathBuf },
+    /// Something in the target stands in the way; nothing is changed while there is one
+    Conflict {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+        message: String,
+    },
+}
+
+impl fmt::Display for PlanAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanAction::LinkCreate { path, destination } => write!(f, "LINK: {} => {}", path.display(), destination.display()),
+            PlanAction::LinkRemove { path } => write!(f, "UNLINK: {}", path.display()),
+            PlanAction::Copy { path } => write!(f, "COPY: {}", path.display()),
+            PlanAction::HardLink { path } => write!(f, "HARDLINK: {}", path.display()),
+            PlanAction::Delete { path } => write!(f, "DELETE: {}", path.display()),
+            PlanAction::Conflict { message, .. } => write!(f, "CONFLICT: {}", message),
+        }
+    }
+}
+
+impl Plan {
+    /*
+     * Read the plan from `stow -n -v` output
+     * Lines look like "LINK: .zshrc => ../dotfiles/zsh/.zshrc" (stow 2.4 may append
+     * "(reverts previous action)"), "UNLINK: .zshrc" and, under a conflict warning,
+     * "  * existing target is neither a link nor a directory: .zshrc". Anything else
+     * (MKDIR:, notes) is left out.
+     */
+    pub fn from_stow(output: &str) -> Plan {
+        let mut plan = Plan::default();
+        for line in output.lines().map(str::trim) {
+            let line = line.strip_suffix("(reverts previous action)").map(str::trim_end).unwrap_or(line);
+            if let Some(link) = line.strip_prefix("LINK: ") {
+                let (path, destination) = link.split_once(" => ").unwrap_or((link, ""));
+                plan.actions.push(PlanAction::LinkCreate { path: path.into(), destination: destination.into() });
+            } else if let Some(path) = line.strip_prefix("UNLINK: ") {
+                plan.actions.push(PlanAction::LinkRemove { path: path.into() });
+            } else if let Some(message) = line.strip_prefix("* ") {
+                plan.conflict(message.to_string());
+            }
+        }
+        plan
+    }
+
+    /// Add a conflict; its path is taken from the end of the message (`...: <path>`).
+    pub fn conflict(&mut self, message: String) {
+        let path = message.rsplit_once(": ").map(|(_, path)| PathBuf::from(path));
+        self.actions.push(PlanAction::Conflict { path, message });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Links (or copies) created or removed
+    pub fn links(&self) -> usize {
+        self.actions.iter().filter(|a| !matches!(a, PlanAction::Conflict { .. })).count()
+    }
+
+    /// Paths that would be linked or copied
+    pub fn created(&self) -> impl Iterator<Item = &PathBuf> {
+        self.actions.iter().filter_map(|a| match a {
+            PlanAction::LinkCreate { path, .. } | PlanAction::Copy { path } | PlanAction::HardLink { path } => Some(path),
+            _ => None,
+        })
+    }
+
+    /// Conflict messages
+    pub fn conflicts(&self) -> impl Iterator<Item = &str> {
+        self.actions.iter().filter_map(|a| match a {
+            PlanAction::Conflict { message, .. } => Some(message.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.conflicts().next().is_some()
+    }
+
+    /// Print one line per action, the same in every dry run.
+    pub fn print(&self, indent: &str) {
+        if self.is_empty() {
+            say!("{}No changes would be made.", indent);
+        }
+        for action in &self.actions {
+            say!("{}{}", indent, action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_stow() {
+        let output = "LINK: .config/nvim/init.lua => ../../../dotfiles/nvim/.config/nvim/init.lua\n\
+                      MKDIR: .config\n\
+                      UNLINK: .zshrc\n\
+                      LINK: .zshrc => ../dotfiles/zsh/.zshrc (reverts previous action)\n";
+        let plan = Plan::from_stow(output);
+        assert_eq!(plan.links(), 3);
+        assert_eq!(plan.actions[2], PlanAction::LinkCreate { path: ".zshrc".into(), destination: "../dotfiles/zsh/.zshrc".into() });
+        assert_eq!(plan.actions[1].to_string(), "UNLINK: .zshrc");
+
+        let output = "WARNING! stowing zsh would cause conflicts:\n  \
+                      * existing target is neither a link nor a directory: .zshrc\nAll operations aborted.\n";
+        let plan = Plan::from_stow(output);
+        assert_eq!(plan.links(), 0);
+        assert!(plan.has_conflicts());
+        assert_eq!(
+            serde_json::to_value(&plan).unwrap(),
+            serde_json::json!([{
+                "action": "conflict",
+                "path": ".zshrc",
+                "message": "existing target is neither a link nor a directory: .zshrc",
+            }])
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::ignore;
+use crate::link::list_source_files;
+use crate::links;
+
+/*
+ * Which source provides each path of a target
+ * A target's sources are listed in priority order (`amu priority` reorders them) and the
+ * first one to provide a path wins it. A later source providing the same path is contested
+ * there: the path is skipped for it so the rest of its files can still be linked.
+ */
+#[derive(Default)]
+pub struct Claims {
+    owners: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// A path a source provides that an earlier source already won
+pub struct Contested {
+    pub path: PathBuf,
+    pub winner: PathBuf,
+}
+
+impl Claims {
+    /// Paths in `files` (relative to the source) already won by another source.
+    pub fn contested(&self, source: &Path, files: &[PathBuf]) -> Vec<Contested> {
+        files.iter()
+            .filter_map(|file| {
+                let winner = self.owners.get(file)?;
+                (winner != source).then(|| Contested { path: file.clone(), winner: winner.clone() })
+            })
+            .collect()
+    }
+
+    /// Record `source` as the provider of every file not won yet.
+    pub fn claim(&mut self, source: &Path, files: &[PathBuf]) {
+        for file in files {
+            self.owners.entry(file.clone()).or_insert_with(|| source.to_path_buf());
+        }
+    }
+}
+
+/// Paths `source` provides in `target` that a source listed before it has won. A source not
+/// registered yet ranks after all registered ones.
+pub fn contested_in(config: &Config, target: &Path, source: &Path) -> Vec<Contested> {
+    let mut claims = Claims::default();
+    for earlier in config.get_sources(target).into_iter().flatten().take_while(|s| *s != source) {
+        let ignore = ignore::patterns_for(config, target, earlier);
+        claims.claim(earlier, &list_source_files(earlier, &ignore));
+    }
+    let ignore = ignore::patterns_for(config, target, source);
+    claims.contested(source, &list_source_files(source, &ignore))
+}
+
+/*
+ * Remove links a source still has at paths a higher-priority source now wins
+ * Needed after the order changes, so the winner can take the path on its next link.
+ * Returns the links removed.
+ */
+pub fn release(config: &Config, target: &Path) -> Vec<PathBuf> {
+    let mut released = Vec::new();
+    for source in config.get_sources(target).into_iter().flatten() {
+        for contested in contested_in(config, target, source) {
+            let link = target.join(&contested.path);
+            if link.is_symlink() && links::link_destination(&link).is_some_and(|d| d.starts_with(source)) {
+                released.push(link);
+            }
+        }
+    }
+    links::remove_links(&released, target);
+    released
+}
+
+/// Ignore patterns that keep contested paths from being linked.
+pub fn skip_patterns(contested: &[Contested]) -> Vec<String> {
+    contested.iter().map(|c| format!("/{}", c.path.display())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_source_wins() {
+        let mut claims = Claims::default();
+        let first = Path::new("/dotfiles/base");
+        let second = Path::new("/dotfiles/work");
+
+        claims.claim(first, &[PathBuf::from("bin/tool"), PathBuf::from(".zshrc")]);
+        let contested = claims.contested(second, &[PathBuf::from("bin/tool"), PathBuf::from(".gitconfig")]);
+
+        assert_eq!(contested.len(), 1);
+        assert_eq!(contested[0].path, PathBuf::from("bin/tool"));
+        assert_eq!(contested[0].winner, first);
+        assert_eq!(skip_patterns(&contested), vec!["/bin/tool".to_string()]);
+
+        // Claiming again never changes the winner
+        claims.claim(second, &[PathBuf::from("bin/tool")]);
+        assert!(claims.contested(first, &[PathBuf::from("bin/tool")]).is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use serde::Serialize;
+
+use crate::error::{DotlinkError, Result};
+use crate::state::Action;
+
+/*
+ * Machine-readable progress stream (`--progress-json`)
+ * One JSON object per line, written to stderr or a caller-supplied file descriptor,
+ * so frontends can show live progress without parsing human output.
+ */
+
+static SINK: OnceLock<Mutex<File>> = OnceLock::new();
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    Started { command: &'a str },
+    Linked { action: Action, source: &'a Path, target: &'a Path },
+    Unlinked { source: &'a Path, target: &'a Path },
+    Conflicted { action: Action, source: &'a Path, target: &'a Path, message: String },
+    Failed { action: Action, source: &'a Path, target: &'a Path, message: String },
+    Finished { success: bool },
+}
+
+/// Start writing events to file descriptor `fd` (2 for stderr).
+#[cfg(unix)]
+pub fn enable(fd: i32) -> Result<()> {
+    use std::os::unix::io::FromRawFd;
+
+    // Duplicate so the caller's descriptor stays open independently of ours
+    let dup = unsafe { libc::dup(fd) };
+    if dup < 0 {
+        return Err(DotlinkError::IoError(std::io::Error::other(format!(
+            "invalid progress file descriptor: {fd}"
+        ))));
+    }
+    let file = unsafe { File::from_raw_fd(dup) };
+    let _ = SINK.set(Mutex::new(file));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn enable(_fd: i32) -> Result<()> {
+    Err(DotlinkError::IoError(std::io::Error::other(
+        "--progress-json is only supported on unix",
+    )))
+}
+
+pub fn emit(event: Event) {
+    let Some(sink) = SINK.get() else { return };
+    let Ok(line) = serde_json::to_string(&event) else { return };
+    if let Ok(mut file) = sink.lock() {
+        let _ = writeln!(file, "{line}");
+        let _ = file.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_shape() {
+        let event = Event::Linked {
+            action: Action::Stow,
+            source: Path::new("/src"),
+            target: Path::new("/dst"),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"linked","action":"stow","source":"/src","target":"/dst"}"#
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::links::{self, SymlinkEntry};
+use crate::ownership;
+use crate::state;
+use crate::unreadable;
+
+const INDEX_DIR: &str = "index";
+
+/// Source-relative path -> inode, recorded after each successful link
+type InodeIndex = BTreeMap<PathBuf, u64>;
+
+/// A file that was renamed inside a source since it was last linked
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+fn index_path(source: &Path) -> Result<PathBuf> {
+    let name = source.to_string_lossy().replace(std::path::MAIN_SEPARATOR, "%");
+    Ok(state::state_dir()?.join(INDEX_DIR).join(format!("{name}.yaml")))
+}
+
+/// Snapshot the inodes of every linkable file in a source.
+pub fn record(source: &Path, ignore: &[String]) -> Result<()> {
+    let index = scan_inodes(source, ignore);
+    let path = index_path(source)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content = serde_yaml::to_string(&index)
+        .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    let _lock = state::lock();
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+fn load(source: &Path) -> InodeIndex {
+    index_path(source)
+        .ok()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|c| serde_yaml::from_str(&c).ok())
+        .unwrap_or_default()
+}
+
+/// Dangling links in the target that point into the source.
+pub fn find_stale_links(target: &Path, source: &Path) -> Vec<SymlinkEntry> {
+    links::find_all_symlinks(target)
+        .into_iter()
+        .filter(|entry| entry.dangling && entry.destination.starts_with(source))
+        .collect()
+}
+
+/*
+ * Match stale links against the last inode snapshot of the source
+ * A stale path whose inode now lives under a new name was renamed, not deleted.
+ */
+pub fn detect(source: &Path, stale: &[SymlinkEntry], ignore: &[String]) -> Vec<Rename> {
+    let previous = load(source);
+    if previous.is_empty() {
+        return Vec::new();
+    }
+
+    let current: BTreeMap<u64, PathBuf> = scan_inodes(source, ignore)
+        .into_iter()
+        .filter(|(path, _)| !previous.contains_key(path))
+        .map(|(path, inode)| (inode, path))
+        .collect();
+
+    let mut renames = Vec::new();
+    for link in stale {
+        let Ok(old) = link.destination.strip_prefix(source) else { continue };
+
+        if let Some(new) = previous.get(old).and_then(|inode| current.get(inode)) {
+            renames.push(Rename { from: old.to_path_buf(), to: new.clone() });
+        }
+    }
+    renames
+}
+
+/// Files added to and removed from a source since its last snapshot.
+/// Returns None if the source has never been snapshotted.
+pub fn changes_since_snapshot(source: &Path, ignore: &[String]) -> Option<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let exists = index_path(source).is_ok_and(|p| p.exists());
+    if !exists {
+        return None;
+    }
+
+    let previous = load(source);
+    let current = scan_inodes(source, ignore);
+    let added = current.keys().filter(|p| !previous.contains_key(*p)).cloned().collect();
+    let removed = previous.keys().filter(|p| !current.contains_key(*p)).cloned().collect();
+    Some((added, removed))
+}
+
+fn scan_inodes(source: &Path, ignore: &[String]) -> InodeIndex {
+    let mut index = InodeIndex::new();
+    scan_inodes_recursive(source, source, ignore, &mut index);
+    index
+}
+
+fn scan_inodes_recursive(base: &Path, current: &Path, ignore: &[String], index: &mut InodeIndex) {
+    let Some(entries) = unreadable::read_dir(current) else { return };
+    for entry in entries {
+        let path = entry.path();
+        let Ok(relative) = path.strip_prefix(base) else { continue };
+        if ignore::is_ignored(relative, ignore) {
+            continue;
+        }
+        if path.is_dir() && !path.is_symlink() {
+            scan_inodes_recursive(base, &path, ignore, index);
+        } else if let Some(inode) = inode_of(&path) {
+            index.insert(relative.to_path_buf(), inode);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode_of(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).ok().map(|m| m.ino())
+}
+
+#[cfg(not(unix))]
+fn inode_of(_path: &Path) -> Option<u64> {
+    None
+}
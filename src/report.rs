@@ -0,0 +1,748 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::config::{self, Config};
+use crate::error::{DotlinkError, Result};
+use crate::git::{RemoteState, RepoState};
+use crate::plan::Plan;
+use crate::status::SourceStatus;
+use crate::stow;
+
+/*
+ * Structured command output (`--format json|yaml`)
+ * Commands build the report structs below alongside their human output; in a structured
+ * format the human output is silenced and the report is printed once at the end.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+    Yaml,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// Select the output format for this process (`--format`).
+pub fn set_format(format: Format) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn format() -> Format {
+    FORMAT.get().copied().unwrap_or(Format::Text)
+}
+
+/// Whether human-readable output is wanted; a command's own `--json` flag also turns it off.
+pub fn is_text() -> bool {
+    format() == Format::Text
+}
+
+/// `println!` that is silenced when a structured format is selected
+#[macro_export]
+macro_rules! say {
+    () => {
+        $crate::say!("")
+    };
+    ($($arg:tt)*) => {
+        if $crate::report::is_text() {
+            $crate::report::write_line(format_args!($($arg)*));
+        }
+    };
+}
+
+thread_local! {
+    /// Human output of the current thread, while `capture` runs
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Print a line of human output, or add it to the output being captured on this thread.
+pub fn write_line(args: fmt::Arguments) {
+    write_str(&format!("{}\n", args));
+}
+
+/// Print human output, or add it to the output being captured on this thread.
+pub fn write_str(s: &str) {
+    let captured = CAPTURED.with(|captured| {
+        captured.borrow_mut().as_mut().map(|buffer| buffer.push_str(s)).is_some()
+    });
+    if !captured {
+        print!("{}", s);
+    }
+}
+
+/// Run `f` with this thread's human output collected instead of printed.
+pub fn capture<R>(f: impl FnOnce() -> R) -> (R, String) {
+    let previous = CAPTURED.with(|captured| captured.borrow_mut().replace(String::new()));
+    let result = f();
+    let output = CAPTURED.with(|captured| std::mem::replace(&mut *captured.borrow_mut(), previous));
+    (result, output.unwrap_or_default())
+}
+
+/// Print a report in the selected format, as JSON if only a command's `--json` asked for it.
+pub fn emit<T: Serialize>(report: &T) -> Result<()> {
+    match format() {
+        Format::Yaml => {
+            let yaml = serde_yaml::to_string(report).map_err(|e| DotlinkError::IoError(io::Error::other(e)))?;
+            print!("{}", yaml);
+        }
+        Format::Text | Format::Json => println!("{}", to_json(report)?),
+    }
+    Ok(())
+}
+
+/// Print a report if a structured format is selected; text output was already printed as the command ran.
+pub fn emit_structured<T: Serialize>(report: &T) -> Result<()> {
+    if is_text() { Ok(()) } else { emit(report) }
+}
+
+/// Print `{"error": message}` in the selected format.
+pub fn emit_error(message: &str) -> Result<()> {
+    emit(&serde_json::json!({ "error": message }))
+}
+
+/*
+ * Single-line JSON with a space after `:` and `,`
+ * This is the layout amu has always printed, so scripts matching on it keep working.
+ */
+pub fn to_json<T: Serialize>(report: &T) -> Result<String> {
+    let mut out = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, Spaced);
+    report.serialize(&mut serializer).map_err(|e| DotlinkError::IoError(io::Error::other(e)))?;
+    Ok(String::from_utf8(out).expect("serde_json writes UTF-8"))
+}
+
+struct Spaced;
+
+impl serde_json::ser::Formatter for Spaced {
+    fn begin_array_value<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if first { Ok(()) } else { writer.write_all(b", ") }
+    }
+
+    fn begin_object_key<W: ?Sized + io::Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if first { Ok(()) } else { writer.write_all(b", ") }
+    }
+
+    fn begin_object_value<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b": ")
+    }
+}
+
+/// A target with per-source entries, the shape shared by list, status and restore
+#[derive(Debug, Serialize)]
+pub struct TargetReport<S> {
+    pub path: String,
+    pub sources: Vec<S>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<LinkReport>>,
+}
+
+impl<S> TargetReport<S> {
+    pub fn new(path: String) -> Self {
+        TargetReport { path, sources: Vec::new(), links: None }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkReport {
+    pub link: String,
+    pub destination: String,
+}
+
+/// `amu list`
+#[derive(Debug, Serialize)]
+pub struct ListReport {
+    pub targets: Vec<TargetReport<ListSource>>,
+    pub environment: Environment,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListSource {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_count: Option<usize>,
+    /// Links counted through directory links into the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folded_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+}
+
+/// `amu status`
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub targets: Vec<TargetReport<SourceStatusReport>>,
+    pub summary: StatusSummary,
+    /// When the daemon computed the statuses (milliseconds since the Unix epoch), if they came from its cache
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_at: Option<u64>,
+    /// What `--fix` repaired before the statuses were taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repairs: Option<StatusRepairs>,
+    /// Directories skipped because they could not be read
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unreadable: Vec<PathBuf>,
+    /// The sources' repositories against their upstream branches (`--remote`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remotes: Option<Vec<RemoteReport>>,
+    pub environment: Environment,
+}
+
+/// `amu verify-remote`: each repository holding a source against its upstream branch
+#[derive(Debug, Serialize)]
+pub struct VerifyRemoteReport {
+    pub repos: Vec<RemoteReport>,
+    pub summary: StatusSummary,
+}
+
+/// A repository after fetching: `result` is "up_to_date", "behind", "ahead", "diverged",
+/// "no_upstream" or "fetch_failed"
+#[derive(Debug, Serialize)]
+pub struct RemoteReport {
+    pub path: String,
+    pub result: &'static str,
+    pub status: &'static str,
+    #[serde(flatten)]
+    pub state: RemoteState,
+}
+
+/// The machine a list or status report was made on, so reports collected in one place
+/// can be told apart
+#[derive(Debug, Default, Serialize)]
+pub struct Environment {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// "linux-x86_64", "macos-aarch64", ...
+    pub platform: String,
+    pub amu_version: &'static str,
+    /// Version of the installed stow, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stow_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_path: Option<String>,
+}
+
+impl Environment {
+    pub fn current() -> Self {
+        Environment {
+            hostname: config::hostname(),
+            user: config::user_name(),
+            platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+            amu_version: env!("CARGO_PKG_VERSION"),
+            stow_version: stow::version(),
+            config_path: Config::config_path().ok().map(|path| config::abbreviate_path(&path)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatusRepairs {
+    /// Broken links removed from paths a source provides
+    pub broken_removed: usize,
+    pub links_created: usize,
+    /// Real files backed up or adopted with --real-files
+    pub files_resolved: usize,
+    /// Real files left in place: no --real-files, or no --yes
+    pub files_left: usize,
+    /// Sources that could not be relinked
+    pub failed: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatusSummary {
+    pub ok: usize,
+    pub warning: usize,
+    pub error: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceStatusReport {
+    pub path: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_count: Option<usize>,
+    /// Links counted through directory links into the source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folded_count: Option<usize>,
+    /// Files copied into the target (`mode: copy`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copied_count: Option<usize>,
+    /// Files hard-linked into the target (`mode: hardlink`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardlinked_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Details>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diffs: Option<Vec<FileDiff>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed: Option<Vec<String>>,
+    /// State of the git repository containing the source, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<RepoState>,
+}
+
+impl SourceStatusReport {
+    pub fn new(path: String, status: &'static str) -> Self {
+        SourceStatusReport {
+            path,
+            status,
+            link_count: None,
+            folded_count: None,
+            copied_count: None,
+            hardlinked_count: None,
+            message: None,
+            details: None,
+            diffs: None,
+            changed: None,
+            git: None,
+        }
+    }
+
+    /// The entry for a source's status: its counts, and what is wrong with the paths involved.
+    pub fn from_status(path: String, status: &SourceStatus) -> Self {
+        let mut entry = SourceStatusReport::new(path, status.class());
+        let (message, details) = match status {
+            SourceStatus::Ok { link_count, folded } => {
+                entry.link_count = Some(*link_count);
+                entry.folded_count = Some(*folded).filter(|&n| n > 0);
+                (None, None)
+            }
+            SourceStatus::Copied { file_count } => {
+                entry.copied_count = Some(*file_count);
+                (None, None)
+            }
+            SourceStatus::HardLinked { file_count } => {
+                entry.hardlinked_count = Some(*file_count);
+                (None, None)
+            }
+            SourceStatus::BrokenHardLinks(paths) => (Some("hard links broken".to_string()), Some(Details::Paths(paths.clone()))),
+            SourceStatus::Drift(paths) => (Some("copies out of date".to_string()), Some(Details::Paths(paths.clone()))),
+            SourceStatus::SourceNotFound => (Some("source not found".to_string()), None),
+            SourceStatus::TargetNotFound => (Some("target not found".to_string()), None),
+            SourceStatus::BrokenLinks(links) => (Some("broken links".to_string()), Some(Details::Paths(links.clone()))),
+            SourceStatus::PinMismatch(paths) => (Some("pinned paths not linked".to_string()), Some(Details::Paths(paths.clone()))),
+            SourceStatus::Conflicts(text) => (Some("conflicts".to_string()), Some(Details::Text(text.clone()))),
+            SourceStatus::RealFiles(files) => {
+                (Some("real files (expected symlinks)".to_string()), Some(Details::Paths(files.clone())))
+            }
+            SourceStatus::ReadOnlyTarget => (Some("target is read-only".to_string()), None),
+            SourceStatus::PermissionDenied(text) => (Some(format!("permission denied: {}", text)), None),
+            SourceStatus::Sparse { link_count, excluded, stale } => {
+                entry.link_count = Some(*link_count);
+                let details = (!stale.is_empty()).then(|| Details::Paths(stale.clone()));
+                (Some(format!("{} file(s) outside sparse checkout", excluded)), details)
+            }
+            SourceStatus::NotMounted(point) => (Some(format!("not mounted: {}", point.display())), None),
+        };
+        entry.message = message;
+        entry.details = details;
+        entry
+    }
+}
+
+/// Paths involved in a warning, or the raw output explaining it
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Details {
+    Paths(Vec<String>),
+    Text(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiff {
+    pub file: String,
+    pub result: &'static str,
+    pub diff: Option<String>,
+}
+
+/// `amu diff`
+#[derive(Debug, Serialize)]
+pub struct DiffReport {
+    pub targets: Vec<TargetReport<PendingSource>>,
+}
+
+/// What `amu update` would change for one source; paths are target-relative
+#[derive(Debug, Serialize)]
+pub struct PendingSource {
+    pub path: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<FileDiff>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+}
+
+/// `amu orphans`
+#[derive(Debug, Serialize)]
+pub struct OrphansReport {
+    pub targets: Vec<TargetOrphans>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetOrphans {
+    pub path: String,
+    pub orphans: Vec<OrphanReport>,
+}
+
+/// A path no source provides, target-relative
+#[derive(Debug, Serialize)]
+pub struct OrphanReport {
+    pub path: String,
+    /// "file" or "directory"
+    pub kind: &'static str,
+    /// The source managing the directory it is in
+    pub source: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub adopted: bool,
+}
+
+/// `amu restore`
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    pub targets: Vec<TargetReport<RestoreSource>>,
+    pub summary: RestoreSummary,
+    pub paused: bool,
+    pub stopped: bool,
+    pub failures: Vec<RestoreSource>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreSource {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<Vec<SkippedPath>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicts: Option<Vec<ConflictReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates: Option<Vec<TemplateReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<LinkDelta>,
+}
+
+impl RestoreSource {
+    pub fn new(path: String, status: &'static str) -> Self {
+        RestoreSource {
+            path,
+            target: None,
+            status,
+            class: None,
+            message: None,
+            suggestion: None,
+            skipped: None,
+            conflicts: None,
+            templates: None,
+            delta: None,
+        }
+    }
+}
+
+/// Links a run created and removed for one source, target-relative
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LinkDelta {
+    pub created: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl LinkDelta {
+    /// The source's links before a run against those after it (`journal::linked_files`).
+    pub fn between(before: &BTreeSet<PathBuf>, after: &BTreeSet<PathBuf>) -> Self {
+        LinkDelta {
+            created: after.difference(before).map(|p| p.display().to_string()).collect(),
+            removed: before.difference(after).map(|p| p.display().to_string()).collect(),
+        }
+    }
+}
+
+/// `amu priority list`: one entry per source, highest priority first
+#[derive(Debug, Serialize)]
+pub struct PrioritySource {
+    pub path: String,
+    pub skipped: Vec<SkippedPath>,
+}
+
+/// A path left to an earlier source that also provides it
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedPath {
+    pub path: String,
+    pub provided_by: String,
+}
+
+/// A file rendered from a template: "rendered", "unchanged", "modified" (edited since, left
+/// alone), "occupied" (another file in the way) or "failed"
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateReport {
+    pub path: String,
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// A file that was in the way of a link and how `--on-conflict` dealt with it
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictReport {
+    pub path: String,
+    pub action: String,
+}
+
+/// `amu pull` and `amu push`: what happened in each repository, then the pairs updated
+#[derive(Debug, Serialize)]
+pub struct RepoSyncReport {
+    pub command: &'static str,
+    pub dry_run: bool,
+    pub repos: Vec<RepoResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+}
+
+impl RepoSyncReport {
+    pub fn new(command: &'static str, dry_run: bool) -> Self {
+        RepoSyncReport { command, dry_run, repos: Vec::new(), changes: Vec::new() }
+    }
+}
+
+/// A repository's result: "pulled", "pushed", "committed" (nothing to push to), "unchanged",
+/// "skipped" or "failed"; "would_pull", "would_push" or "would_commit" in a dry run
+#[derive(Debug, Serialize)]
+pub struct RepoResult {
+    pub path: String,
+    pub result: &'static str,
+    pub message: String,
+}
+
+/// `amu add`, `remove`, `update` and `clear`: one entry per (source, target) pair touched
+#[derive(Debug, Serialize)]
+pub struct ChangeReport {
+    pub command: &'static str,
+    pub dry_run: bool,
+    pub changes: Vec<Change>,
+    /// Unified diff of the config file, for dry runs that would rewrite it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_diff: Option<String>,
+}
+
+impl ChangeReport {
+    pub fn new(command: &'static str, dry_run: bool) -> Self {
+        ChangeReport { command, dry_run, changes: Vec::new(), config_diff: None }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub source: String,
+    pub target: String,
+    /// What happened (or would happen) to the pair: "added", "removed", "unlinked" (part of
+    /// the source), "updated", "restored", "cleared", "skipped", "unavailable" (the source
+    /// vanished while running) or "failed"
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    /// What linking or unlinking would do (dry runs)
+    #[serde(skip_serializing_if = "Plan::is_empty")]
+    pub plan: Plan,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<ConflictReport>,
+    /// Paths left to a higher-priority source
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedPath>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TemplateReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Links created and removed (`update`, when not a dry run)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<LinkDelta>,
+}
+
+/// `amu adopt`: the real files moved into the source, then linked back
+#[derive(Debug, Serialize)]
+pub struct AdoptReport {
+    pub source: String,
+    pub target: String,
+    pub dry_run: bool,
+    pub files: Vec<AdoptedFile>,
+    /// Where the source files the adopted ones replaced were backed up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdoptedFile {
+    pub path: String,
+    /// The source already had a file there
+    pub replaces_source: bool,
+}
+
+/// `amu bless` and `amu unbless`
+#[derive(Debug, Serialize)]
+pub struct PinReport {
+    pub command: &'static str,
+    pub path: String,
+    pub source: String,
+}
+
+/// `amu init`: the package directories made and the entries registered for them
+#[derive(Debug, Serialize)]
+pub struct InitReport {
+    pub dir: String,
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub registered: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub already_registered: Vec<String>,
+    /// "initialized", "already_a_repository" or "would_initialize", with `--git`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<&'static str>,
+}
+
+/// `amu clean`: the dangling links removed (or that would be) per target
+#[derive(Debug, Serialize)]
+pub struct CleanReport {
+    pub dry_run: bool,
+    pub targets: Vec<CleanedTarget>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CleanedTarget {
+    pub path: String,
+    pub removed: Vec<String>,
+}
+
+/// `amu dedupe`: each target's groups of duplicate sources and the one kept of each
+#[derive(Debug, Serialize)]
+pub struct DedupeReport {
+    pub dry_run: bool,
+    pub targets: Vec<DedupeTarget>,
+    pub merged: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DedupeTarget {
+    pub path: String,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub reason: String,
+    pub sources: Vec<String>,
+    /// None when the group was skipped
+    pub kept: Option<String>,
+}
+
+/// `amu export`: where the config went; `content` when it is printed rather than written
+#[derive(Debug, Serialize)]
+pub struct ExportReport {
+    pub targets: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+    /// Sources a bundle could not hold, not being under the home directory
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub left_out: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// `amu import`: the targets taken into the config and the files written for them
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: Vec<String>,
+    /// Files of another manager that could not be converted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedFile>,
+    /// Files converted into a new source, and where
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub converted: Option<ConvertedFiles>,
+    /// Sources extracted from a bundle
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extracted: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_diff: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConvertedFiles {
+    pub into: String,
+    pub files: usize,
+}
+
+impl Change {
+    pub fn new(source: String, target: String, result: &'static str) -> Self {
+        Change {
+            source,
+            target,
+            result,
+            links: Vec::new(),
+            plan: Plan::default(),
+            conflicts: Vec::new(),
+            skipped: Vec::new(),
+            templates: Vec::new(),
+            message: None,
+            delta: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json_layout() {
+        let environment = Environment { platform: "linux-x86_64".to_string(), amu_version: "0.1.9", ..Environment::default() };
+        let report = StatusReport {
+            targets: Vec::new(),
+            summary: StatusSummary::default(),
+            cached_at: None,
+            repairs: None,
+            unreadable: Vec::new(),
+            remotes: None,
+            environment,
+        };
+        assert_eq!(
+            to_json(&report).unwrap(),
+            r#"{"targets": [], "summary": {"ok": 0, "warning": 0, "error": 0}, "environment": {"platform": "linux-x86_64", "amu_version": "0.1.9"}}"#
+        );
+
+        let mut source = SourceStatusReport::new("~/dotfiles".to_string(), "warning");
+        source.details = Some(Details::Text("a \"b\"\nc".to_string()));
+        assert_eq!(
+            to_json(&source).unwrap(),
+            r#"{"path": "~/dotfiles", "status": "warning", "details": "a \"b\"\nc"}"#
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DotlinkError, Result};
+use crate::ownership;
+use crate::state;
+
+const FAILED_FILE: &str = "failed.yaml";
+
+/*
+ * Entries that failed in the last restore or update (`amu retry`)
+ * Outcomes are noted per (target, source) pair while a command runs and written once at
+ * the end: a failure is added (or its error replaced), a success removes the pair. Pairs
+ * a run did not attempt keep whatever was recorded for them.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failed {
+    /// The command that failed: "restore", "update" or "retry"
+    pub command: String,
+    pub target: PathBuf,
+    pub source: PathBuf,
+    pub error: String,
+}
+
+enum Outcome {
+    Succeeded { target: PathBuf, source: PathBuf },
+    Failed(Failed),
+}
+
+static OUTCOMES: Mutex<Vec<Outcome>> = Mutex::new(Vec::new());
+
+fn outcomes() -> MutexGuard<'static, Vec<Outcome>> {
+    OUTCOMES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn failed_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(FAILED_FILE))
+}
+
+/// Note that a pair was linked.
+pub fn succeeded(target: &Path, source: &Path) {
+    outcomes().push(Outcome::Succeeded { target: target.to_path_buf(), source: source.to_path_buf() });
+}
+
+/// Note that a pair failed to link.
+pub fn failed(command: &str, target: &Path, source: &Path, error: impl ToString) {
+    outcomes().push(Outcome::Failed(Failed {
+        command: command.to_string(),
+        target: target.to_path_buf(),
+        source: source.to_path_buf(),
+        error: error.to_string(),
+    }));
+}
+
+/// The recorded failures, oldest first.
+pub fn load() -> Result<Vec<Failed>> {
+    let path = failed_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    serde_yaml::from_str(&content).map_err(|e| DotlinkError::StateError(e.to_string()))
+}
+
+/// Apply the outcomes noted so far to the recorded failures.
+pub fn save() -> Result<()> {
+    let noted = std::mem::take(&mut *outcomes());
+    if noted.is_empty() {
+        return Ok(());
+    }
+    let mut entries = load()?;
+    for outcome in noted {
+        match outcome {
+            Outcome::Succeeded { target, source } => {
+                entries.retain(|e| e.target != target || e.source != source);
+            }
+            Outcome::Failed(failed) => match entries.iter_mut().find(|e| e.target == failed.target && e.source == failed.source) {
+                Some(entry) => *entry = failed,
+                None => entries.push(failed),
+            },
+        }
+    }
+    write(&entries)
+}
+
+/// Forget a recorded failure without retrying it.
+pub fn forget(target: &Path, source: &Path) {
+    succeeded(target, source);
+}
+
+fn write(entries: &[Failed]) -> Result<()> {
+    let path = failed_path()?;
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml::to_string(entries).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
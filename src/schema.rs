@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::config::{Config, PathKey};
+use crate::linker::{Backend, Mode};
+
+/*
+ * Config file schema versions
+ * Version 1 (no `version:` key) maps each target to a bare list of sources and keeps fold
+ * modes, filters and profile entries in tables of their own. Version 2 lists each target's
+ * sources as entries that carry their metadata:
+ *
+ *   version: 2
+ *   targets:
+ *     /home/me:
+ *       - source: /home/me/dotfiles/home
+ *         priority: 1
+ *         added: 2026-10-17
+ *
+ * In memory the config keeps the version 1 layout plus `Config::metadata`; files are
+ * converted when read and written, so a version 1 file loads as is and is saved back as
+ * version 2.
+ */
+
+/// Schema version written by this build
+pub const VERSION: u64 = 2;
+
+/// Fields of an entry that version 1 has no place for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMeta {
+    /// Date the entry was registered (`2026-10-17`)
+    pub added: Option<String>,
+    /// Backend linking this entry, overriding `backend:`
+    pub backend: Option<Backend>,
+    /// `copy` or `hardlink` to copy or hard-link the source's files rather than symlink them
+    pub mode: Option<Mode>,
+    /// UTC time after which `amu gc` removes the entry (`2026-10-17T18:00:00Z`)
+    pub expires: Option<String>,
+    /// Boot the entry was added in; `amu gc` removes it once the machine has restarted
+    pub session: Option<String>,
+    /// Priority the entry is stored with; kept only while config files are merged and saved
+    pub priority: Option<usize>,
+}
+
+impl EntryMeta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_none() && self.backend.is_none() && self.mode.is_none() && self.expires.is_none() && self.session.is_none()
+            && self.priority.is_none()
+    }
+}
+
+/// One source of a target, as stored in a version 2 file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub source: PathBuf,
+
+    /// Position among the target's sources (1 = highest); entries without one keep their place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<usize>,
+
+    /// Profile the entry belongs to; none for entries shared by every machine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fold: bool,
+
+    /// Source-relative paths left unlinked (`excludes:` in version 1)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+
+    /// Source-relative paths that alone are linked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only: Vec<String>,
+}
+
+/// An entry as written by hand: a bare source path is accepted too. Parsed directly
+/// rather than as an untagged enum, so a mistyped field is named in the error.
+fn stored_entry(value: Value) -> Result<Entry, String> {
+    match value {
+        Value::String(source) => Ok(Entry { source: PathBuf::from(source), ..Entry::default() }),
+        Value::Mapping(_) => serde_yaml::from_value(value).map_err(|e| e.to_string()),
+        _ => Err("expected a source path or a mapping with `source:`".to_string()),
+    }
+}
+
+/// Top-level keys of version 1 whose contents move into the entries
+pub const ENTRY_TABLES: [&str; 5] = ["targets", "profiles", "fold", "excludes", "only"];
+
+/// Schema version of a parsed config document.
+pub fn version(document: &Value) -> u64 {
+    document.get("version").and_then(Value::as_u64).unwrap_or(1)
+}
+
+/// Parse a config document of any supported version.
+pub fn parse(document: Value) -> Result<Config, String> {
+    let version = version(&document);
+    if version > VERSION {
+        return Err(format!("config version {version} is newer than this amu supports ({VERSION}); upgrade amu"));
+    }
+    let mut document = document;
+    let entries = match document.as_mapping_mut() {
+        Some(root) => {
+            root.remove("version");
+            if version >= 2 { root.remove("targets") } else { None }
+        }
+        None => None,
+    };
+
+    let mut config: Config = serde_yaml::from_value(document).map_err(|e| e.to_string())?;
+    if let Some(entries) = entries {
+        let entries: BTreeMap<PathBuf, Vec<Value>> = serde_yaml::from_value(entries)
+            .map_err(|e| format!("targets: {e}"))?;
+        for (target, entries) in entries {
+            let mut entries: Vec<Entry> = entries.into_iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    stored_entry(entry).map_err(|e| format!("targets: {}: entry {}: {e}", target.display(), index + 1))
+                })
+                .collect::<Result<_, _>>()?;
+            let position: BTreeMap<PathBuf, usize> = entries.iter().enumerate()
+                .map(|(index, entry)| (entry.source.clone(), index + 1))
+                .collect();
+            entries.sort_by_key(|entry| entry.priority.unwrap_or(position[&entry.source]));
+            for entry in entries {
+                insert(&mut config, &target, entry);
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Add a stored entry to the in-memory tables.
+fn insert(config: &mut Config, target: &Path, entry: Entry) {
+    let targets = match &entry.profile {
+        Some(profile) => &mut config.profiles.entry(profile.clone()).or_default().targets,
+        None => &mut config.targets,
+    };
+    let sources = targets.entry(PathKey::new(target)).or_default();
+    if !sources.contains(&entry.source) {
+        sources.push(entry.source.clone());
+    }
+
+    let source = entry.source;
+    if entry.fold && !config.folds(target, &source) {
+        config.set_fold(target, &source, true);
+    }
+    for pattern in entry.ignore {
+        config.add_exclude(target, &source, pattern);
+    }
+    for pattern in entry.only {
+        config.add_only(target, &source, pattern);
+    }
+    let meta = EntryMeta {
+        added: entry.added,
+        backend: entry.backend,
+        mode: entry.mode,
+        expires: entry.expires,
+        session: entry.session,
+        priority: entry.priority,
+    };
+    if !meta.is_empty() {
+        config.metadata.entry(target.to_path_buf()).or_default().insert(source, meta);
+    }
+}
+
+/*
+ * The config as a version 2 document
+ * `config` is the stored form (profile entries not merged in). Shared entries come before
+ * the profiles' ones, as they are merged; priorities number them in that order, unless an
+ * entry's metadata gives its own (see `rank`).
+ */
+pub fn to_document(config: &Config) -> Result<Value, String> {
+    let mut entries: BTreeMap<PathBuf, Vec<Entry>> = BTreeMap::new();
+    let profiles = config.profiles.iter().map(|(name, profile)| (Some(name), &profile.targets));
+    for (profile, targets) in std::iter::once((None, &config.targets)).chain(profiles) {
+        for (target, sources) in targets {
+            let listed = entries.entry(target.to_path_buf()).or_default();
+            for source in sources {
+                let meta = config.metadata.get(target.as_path()).and_then(|m| m.get(source)).cloned().unwrap_or_default();
+                listed.push(Entry {
+                    source: source.clone(),
+                    priority: Some(meta.priority.unwrap_or(listed.len() + 1)),
+                    profile: profile.cloned(),
+                    added: meta.added,
+                    backend: meta.backend,
+                    mode: meta.mode,
+                    expires: meta.expires,
+                    session: meta.session,
+                    fold: config.folds(target, source),
+                    ignore: config.excludes_for(target, source).to_vec(),
+                    only: config.only_for(target, source).to_vec(),
+                });
+            }
+        }
+    }
+
+    let rest = serde_yaml::to_value(config).map_err(|e| e.to_string())?;
+    let mut document = Mapping::new();
+    document.insert("version".into(), VERSION.into());
+    document.insert("targets".into(), serde_yaml::to_value(entries).map_err(|e| e.to_string())?);
+    if let Value::Mapping(rest) = rest {
+        for (key, value) in rest {
+            if !key.as_str().is_some_and(|key| ENTRY_TABLES.contains(&key)) {
+                document.insert(key, value);
+            }
+        }
+    }
+    Ok(Value::Mapping(document))
+}
+
+/// Give every entry of `config` (the stored form) the priority `to_document` numbers it with.
+pub fn rank(config: &mut Config) {
+    let mut ranks: Vec<(PathBuf, PathBuf, usize)> = Vec::new();
+    let mut counts: BTreeMap<&PathKey, usize> = BTreeMap::new();
+    let profiles = config.profiles.values().map(|profile| &profile.targets);
+    for targets in std::iter::once(&config.targets).chain(profiles) {
+        for (target, sources) in targets {
+            let count = counts.entry(target).or_default();
+            for source in sources {
+                *count += 1;
+                ranks.push((target.to_path_buf(), source.clone(), *count));
+            }
+        }
+    }
+    for (target, source, priority) in ranks {
+        config.metadata.entry(target).or_default().entry(source).or_default().priority = Some(priority);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_1_round_trip() {
+        let v1 = "targets:\n  /home:\n    - /dotfiles/zsh\n    - /dotfiles/vim\nfold:\n  /home:\n    - /dotfiles/vim\n\
+                  excludes:\n  /home:\n    /dotfiles/zsh:\n      - /zshenv\nprofiles:\n  work:\n    targets:\n      /home:\n        - /dotfiles/work\n";
+        let config = parse(serde_yaml::from_str(v1).unwrap()).unwrap();
+        assert_eq!(config.sources_of(Path::new("/home")), vec![PathBuf::from("/dotfiles/zsh"), PathBuf::from("/dotfiles/vim")]);
+
+        let document = to_document(&config).unwrap();
+        assert_eq!(version(&document), 2);
+        let entries = document["targets"]["/home"].as_sequence().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["ignore"][0].as_str(), Some("/zshenv"));
+        assert_eq!(entries[1]["fold"].as_bool(), Some(true));
+        assert_eq!(entries[2]["profile"].as_str(), Some("work"));
+        assert!(document.get("fold").is_none() && document.get("profiles").is_none());
+
+        let reloaded = parse(document).unwrap();
+        assert_eq!(reloaded.targets, config.targets);
+        assert_eq!(reloaded.fold, config.fold);
+        assert_eq!(reloaded.excludes, config.excludes);
+        assert_eq!(reloaded.profiles["work"].targets, config.profiles["work"].targets);
+    }
+
+    #[test]
+    fn test_version_2_entries() {
+        let v2 = "version: 2\ntargets:\n  /home:\n    - source: /dotfiles/b\n      priority: 3\n      backend: native\n    \
+                  - /dotfiles/a\n";
+        let config = parse(serde_yaml::from_str(v2).unwrap()).unwrap();
+        // The bare entry keeps its place (2), ahead of the one given priority 3
+        assert_eq!(config.sources_of(Path::new("/home")), vec![PathBuf::from("/dotfiles/a"), PathBuf::from("/dotfiles/b")]);
+        assert_eq!(config.metadata[Path::new("/home")][Path::new("/dotfiles/b")].backend, Some(Backend::Native));
+
+        assert!(parse(serde_yaml::from_str("version: 3\n").unwrap()).unwrap_err().contains("newer"));
+
+        let typo = "version: 2\ntargets:\n  /home:\n    - /dotfiles/a\n    - sorce: /dotfiles/b\n";
+        let error = parse(serde_yaml::from_str(typo).unwrap()).unwrap_err();
+        assert!(error.starts_with("targets: /home: entry 2: unknown field `sorce`"), "{error}");
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{normalize_path, resolve_target, Config};
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::link::{add_to_target, lookup_pair, remove_from_target, restow_source};
+use crate::linker::Mode;
+use crate::precedence;
+use crate::status::{source_status, SourceStatus};
+
+/*
+ * JSON-RPC 2.0 over stdio (`amu serve --stdio`)
+ * One request per line on stdin, one response per line on stdout. The config is kept
+ * in memory between calls and only re-read when the file changes on disk.
+ */
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Operation failed inside amu (the message carries the amu error)
+const AMU_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    /// Absent for notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<DotlinkError> for RpcError {
+    fn from(e: DotlinkError) -> Self {
+        RpcError { code: AMU_ERROR, message: e.to_string() }
+    }
+}
+
+#[derive(Deserialize)]
+struct TargetParams {
+    target: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct PairParams {
+    source: PathBuf,
+    target: PathBuf,
+}
+
+/// Config cached across requests, reloaded when its modification time changes
+struct Session {
+    config: Config,
+    modified: Option<SystemTime>,
+}
+
+impl Session {
+    fn new() -> Result<Self> {
+        Ok(Session { config: Config::load()?, modified: config_modified() })
+    }
+
+    fn config(&mut self) -> Result<&mut Config> {
+        let modified = config_modified();
+        if modified != self.modified {
+            self.config = Config::load()?;
+            self.modified = modified;
+        }
+        Ok(&mut self.config)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.config.save()?;
+        self.modified = config_modified();
+        Ok(())
+    }
+}
+
+fn config_modified() -> Option<SystemTime> {
+    let path = Config::config_path().ok()?;
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub fn run_stdio() -> Result<()> {
+    let mut session = Session::new()?;
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let code = if serde_json::from_str::<Value>(&line).is_ok() { INVALID_REQUEST } else { PARSE_ERROR };
+                write_response(&mut stdout, error_response(Value::Null, RpcError { code, message: e.to_string() }))?;
+                continue;
+            }
+        };
+        if request.jsonrpc.as_deref() != Some("2.0") {
+            let error = RpcError { code: INVALID_REQUEST, message: "jsonrpc must be \"2.0\"".to_string() };
+            write_response(&mut stdout, error_response(request.id.unwrap_or(Value::Null), error))?;
+            continue;
+        }
+
+        let shutdown = request.method == "shutdown";
+        let result = handle(&mut session, &request.method, request.params);
+        if let Some(id) = request.id {
+            let response = match result {
+                Ok(value) => json!({"jsonrpc": "2.0", "id": id, "result": value}),
+                Err(error) => error_response(id, error),
+            };
+            write_response(&mut stdout, response)?;
+        }
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn write_response(out: &mut impl Write, response: Value) -> Result<()> {
+    writeln!(out, "{response}")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": error.code, "message": error.message}})
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> std::result::Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError { code: INVALID_PARAMS, message: e.to_string() })
+}
+
+fn handle(session: &mut Session, method: &str, raw: Value) -> std::result::Result<Value, RpcError> {
+    match method {
+        "list" => list(session, params(raw)?),
+        "status" => status(session, params(raw)?),
+        "add" => add(session, params(raw)?),
+        "remove" => remove(session, params(raw)?),
+        "update" => update(session, params(raw)?),
+        "shutdown" => Ok(Value::Null),
+        _ => Err(RpcError { code: METHOD_NOT_FOUND, message: format!("unknown method: {method}") }),
+    }
+}
+
+/// Targets selected by an optional `target` param (all targets when omitted)
+fn selected_targets(config: &Config, target: Option<PathBuf>) -> Result<Vec<PathBuf>> {
+    match target {
+        Some(t) => {
+            let t = resolve_target(Some(t))?;
+            match config.find_target(&t) {
+                Some(stored) => Ok(vec![stored]),
+                None => Err(DotlinkError::NotRegistered { src: PathBuf::new(), dest: t }),
+            }
+        }
+        None => Ok(config.targets.keys().map(|t| t.to_path_buf()).collect()),
+    }
+}
+
+fn list(session: &mut Session, p: TargetParams) -> std::result::Result<Value, RpcError> {
+    let config = session.config()?;
+    let targets: Vec<Value> = selected_targets(config, p.target)?
+        .iter()
+        .map(|t| json!({"path": t, "sources": config.get_sources(t)}))
+        .collect();
+    Ok(json!({"targets": targets}))
+}
+
+fn status(session: &mut Session, p: TargetParams) -> std::result::Result<Value, RpcError> {
+    let config = session.config()?;
+    let mut targets = Vec::new();
+    for target in selected_targets(config, p.target)? {
+        let sources: Vec<Value> = config.get_sources(&target)
+            .into_iter()
+            .flatten()
+            .map(|source| {
+                let mut entry = status_json(source_status(config, &target, source));
+                entry["path"] = json!(source);
+                entry
+            })
+            .collect();
+        targets.push(json!({"path": target, "sources": sources}));
+    }
+    Ok(json!({"targets": targets}))
+}
+
+fn status_json(status: SourceStatus) -> Value {
+    match status {
+        SourceStatus::Ok { link_count, folded } => json!({"status": "ok", "links": link_count, "folded": folded}),
+        SourceStatus::SourceNotFound => json!({"status": "error", "message": "source not found"}),
+        SourceStatus::TargetNotFound => json!({"status": "error", "message": "target not found"}),
+        SourceStatus::PermissionDenied(details) => {
+            json!({"status": "error", "message": "permission denied", "details": details})
+        }
+        SourceStatus::BrokenLinks(files) => {
+            json!({"status": "warning", "message": "broken links", "details": files})
+        }
+        SourceStatus::Conflicts(details) => json!({"status": "warning", "message": "conflicts", "details": details}),
+        SourceStatus::RealFiles(files) => {
+            json!({"status": "warning", "message": "real files (expected symlinks)", "details": files})
+        }
+        SourceStatus::ReadOnlyTarget => json!({"status": "warning", "message": "target is read-only"}),
+        SourceStatus::PinMismatch(paths) => {
+            json!({"status": "warning", "message": "pinned paths not linked", "details": paths})
+        }
+        SourceStatus::NotMounted(point) => json!({"status": "warning", "message": "not mounted", "details": point}),
+        SourceStatus::Copied { file_count } => json!({"status": "ok", "copied": file_count}),
+        SourceStatus::Drift(paths) => json!({"status": "warning", "message": "copies out of date", "details": paths}),
+        SourceStatus::HardLinked { file_count } => json!({"status": "ok", "hardlinked": file_count}),
+        SourceStatus::BrokenHardLinks(paths) => json!({"status": "warning", "message": "hard links broken", "details": paths}),
+        SourceStatus::Sparse { link_count, excluded, stale } => json!({
+            "status": "ok",
+            "links": link_count,
+            "message": format!("{} file(s) outside sparse checkout", excluded),
+            "details": stale,
+        }),
+    }
+}
+
+fn add(session: &mut Session, p: PairParams) -> std::result::Result<Value, RpcError> {
+    let source = normalize_path(&p.source)?;
+    let target = resolve_target(Some(p.target))?;
+    if !source.is_dir() {
+        return Err(DotlinkError::SourceNotFound(source).into());
+    }
+
+    let config = session.config()?;
+    let ignore = ignore::link_patterns(config, &source);
+    add_to_target(config, &source, &target, Mode::Link, false, &ignore)?;
+    session.save()?;
+    Ok(json!({"source": source, "target": target}))
+}
+
+fn remove(session: &mut Session, p: PairParams) -> std::result::Result<Value, RpcError> {
+    let config = session.config()?;
+    let (source, target) = lookup_pair(config, &p.source, Some(p.target))?;
+    remove_from_target(config, &source, &target)?;
+    session.save()?;
+    Ok(json!({"source": source, "target": target}))
+}
+
+fn update(session: &mut Session, p: TargetParams) -> std::result::Result<Value, RpcError> {
+    let config = session.config()?;
+    let mut results = Vec::new();
+    for target in selected_targets(config, p.target)? {
+        // As `amu update`: links at paths a higher-priority source now wins make way for it
+        if target.exists() {
+            precedence::release(config, &target);
+        }
+        for source in config.get_sources(&target).into_iter().flatten() {
+            if !source.exists() {
+                results.push(json!({"target": target, "source": source, "status": "skipped"}));
+                continue;
+            }
+            let mut ignore = ignore::patterns_for(config, &target, source);
+            ignore.extend(precedence::skip_patterns(&precedence::contested_in(config, &target, source)));
+            let renamed: Vec<Value> = restow_source(config, source, &target, &ignore)?
+                .into_iter()
+                .map(|r| json!({"from": r.from, "to": r.to}))
+                .collect();
+            results.push(json!({"target": target, "source": source, "status": "restowed", "renamed": renamed}));
+        }
+    }
+    Ok(json!({"results": results}))
+}
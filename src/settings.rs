@@ -0,0 +1,270 @@
+use serde_yaml::{Mapping, Value};
+
+use crate::config::Config;
+use crate::error::{DotlinkError, Result};
+
+/*
+ * Settings by dotted key (`amu config get/set/unset`)
+ * A key names a top-level setting or a field below one: `backend`, `ignore`,
+ * `hooks.post-restore`, `notify.webhook`, `vars.EMAIL`. Changes are made to the file as
+ * amu would save it and checked by loading the result, so a value of the wrong type, or
+ * a key the config does not have, is refused rather than written and later ignored.
+ */
+
+/// Top-level keys that hold the target/source mapping rather than settings
+const MAPPING_KEYS: [&str; 3] = ["targets", "version", "profiles"];
+
+/// How `set` changes the value at a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Replace it
+    Set,
+    /// Append to a list, unless already there
+    Add,
+    /// Remove from a list
+    Remove,
+}
+
+/// Split a dotted key, refusing the mapping itself.
+pub fn parse_key(key: &str) -> Result<Vec<String>> {
+    let parts: Vec<String> = key.split('.').map(String::from).collect();
+    if parts.iter().any(String::is_empty) {
+        return Err(DotlinkError::UnknownSetting(key.to_string()));
+    }
+    if MAPPING_KEYS.contains(&parts[0].as_str()) {
+        return Err(DotlinkError::MappingKey(key.to_string()));
+    }
+    Ok(parts)
+}
+
+/// A value as typed on the command line: YAML (`true`, `[a, b]`), else a plain string.
+pub fn parse_value(text: &str) -> Value {
+    match serde_yaml::from_str::<Value>(text) {
+        Ok(Value::Null) | Err(_) => Value::String(text.to_string()),
+        Ok(value) => value,
+    }
+}
+
+/// A value for printing: scalars as they are, lists and mappings as YAML.
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Null => String::new(),
+        other => serde_yaml::to_string(other).unwrap_or_default().trim_end().to_string(),
+    }
+}
+
+/*
+ * The document as `key=value` lines (`amu config dump --flat`), for grep and diff
+ * Keys are dotted as `amu config get` takes them; list items are indexed (`ignore[0]`) and
+ * keys that are not plain words are quoted (`targets["/home/me"]`). Values are printed as
+ * `get` prints scalars, JSON-quoted if they span lines; empty lists and mappings as `[]`, `{}`.
+ */
+pub fn flatten(document: &Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    flatten_into(String::new(), document, &mut lines);
+    lines
+}
+
+fn flatten_into(key: String, value: &Value, lines: &mut Vec<String>) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (name, value) in mapping {
+                flatten_into(join_key(&key, &display(name)), value, lines);
+            }
+        }
+        Value::Sequence(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                flatten_into(format!("{key}[{index}]"), value, lines);
+            }
+        }
+        Value::Mapping(_) => lines.push(format!("{key}={{}}")),
+        Value::Sequence(_) => lines.push(format!("{key}=[]")),
+        Value::Tagged(tagged) => flatten_into(key, &tagged.value, lines),
+        scalar => {
+            let shown = display(scalar);
+            let shown = if shown.contains('\n') { serde_json::Value::String(shown).to_string() } else { shown };
+            lines.push(format!("{key}={shown}"));
+        }
+    }
+}
+
+fn join_key(parent: &str, name: &str) -> String {
+    let plain = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match (plain, parent.is_empty()) {
+        (true, true) => name.to_string(),
+        (true, false) => format!("{parent}.{name}"),
+        (false, _) => format!("{parent}[{}]", serde_json::Value::String(name.to_string())),
+    }
+}
+
+pub fn get<'a>(document: &'a Value, key: &[String]) -> Option<&'a Value> {
+    key.iter().try_fold(document, |value, part| value.as_mapping()?.get(part.as_str()))
+}
+
+/// Change the value at `key`, creating mappings on the way; false if nothing changed.
+pub fn set(document: &mut Value, key: &[String], value: Value, change: Change) -> bool {
+    let Some((last, parents)) = key.split_last() else { return false };
+    let mut current = &mut *document;
+    for part in parents {
+        if !current.is_mapping() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        let mapping = current.as_mapping_mut().expect("just made a mapping");
+        current = mapping.entry(part.as_str().into()).or_insert_with(|| Value::Mapping(Mapping::new()));
+    }
+    if !current.is_mapping() {
+        *current = Value::Mapping(Mapping::new());
+    }
+    let mapping = current.as_mapping_mut().expect("just made a mapping");
+    let slot = mapping.entry(last.as_str().into()).or_insert(Value::Null);
+
+    match change {
+        Change::Set => {
+            if *slot == value {
+                return false;
+            }
+            *slot = value;
+        }
+        Change::Add => {
+            if !slot.is_sequence() {
+                *slot = Value::Sequence(Vec::new());
+            }
+            let list = slot.as_sequence_mut().expect("just made a list");
+            if list.contains(&value) {
+                return false;
+            }
+            list.push(value);
+        }
+        Change::Remove => {
+            let Some(list) = slot.as_sequence_mut() else { return false };
+            let before = list.len();
+            list.retain(|item| *item != value);
+            if list.len() == before {
+                return false;
+            }
+            if list.is_empty() {
+                unset(document, key);
+            }
+        }
+    }
+    true
+}
+
+/// Remove the value at `key`, and mappings left empty above it; false if it was not set.
+pub fn unset(document: &mut Value, key: &[String]) -> bool {
+    let Some((last, parents)) = key.split_last() else { return false };
+    if parents.is_empty() {
+        return document.as_mapping_mut().and_then(|m| m.remove(last.as_str())).is_some();
+    }
+    let Some(parent) = document.as_mapping_mut().and_then(|m| m.get_mut(parents[0].as_str())) else { return false };
+    let removed = unset(parent, &key[1..]);
+    if removed && parent.as_mapping().is_some_and(Mapping::is_empty) {
+        document.as_mapping_mut().expect("has the parent").remove(parents[0].as_str());
+    }
+    removed
+}
+
+/*
+ * The config file after changing one setting, checked by loading it
+ * A value that fails to load as typed (`755` for a string, say) is tried again as a
+ * string. A key the config drops on loading is unknown.
+ */
+pub fn apply(config: &Config, key: &str, value: Option<&str>, change: Change) -> Result<Option<String>> {
+    let parts = parse_key(key)?;
+    let document: Value = serde_yaml::from_str(&config.to_yaml()?).map_err(|e| DotlinkError::ConfigSaveError(e.to_string()))?;
+
+    let candidates = match value {
+        None => vec![None],
+        Some(text) => {
+            let parsed = parse_value(text);
+            let plain = Value::String(text.to_string());
+            if parsed == plain { vec![Some(parsed)] } else { vec![Some(parsed), Some(plain)] }
+        }
+    };
+    let mut error = None;
+    for candidate in candidates {
+        let mut changed = document.clone();
+        let modified = match candidate.clone() {
+            None => unset(&mut changed, &parts),
+            Some(value) => set(&mut changed, &parts, value, change),
+        };
+        if !modified {
+            return Ok(None);
+        }
+        let content = serde_yaml::to_string(&changed).map_err(|e| DotlinkError::ConfigSaveError(e.to_string()))?;
+        match Config::validate(&content) {
+            Ok(loaded) => {
+                let saved: Value = serde_yaml::from_str(&loaded.to_yaml()?).unwrap_or_default();
+                if candidate.is_some() && change != Change::Remove && get(&saved, &parts).is_none() {
+                    return Err(DotlinkError::UnknownSetting(key.to_string()));
+                }
+                return serde_yaml::to_string(&changed)
+                    .map(Some)
+                    .map_err(|e| DotlinkError::ConfigSaveError(e.to_string()));
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    let reason = match error {
+        Some(DotlinkError::ConfigParseError(reason)) => reason,
+        Some(e) => e.to_string(),
+        None => String::new(),
+    };
+    Err(DotlinkError::InvalidSetting { key: key.to_string(), reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Vec<String> {
+        parse_key(key).unwrap()
+    }
+
+    #[test]
+    fn test_set_get_unset() {
+        let mut document: Value = serde_yaml::from_str("version: 2\ntargets: {}\n").unwrap();
+        assert!(set(&mut document, &key("hooks.post-restore"), parse_value("fc-cache -f"), Change::Set));
+        assert_eq!(get(&document, &key("hooks.post-restore")).map(display), Some("fc-cache -f".to_string()));
+        assert!(!set(&mut document, &key("hooks.post-restore"), parse_value("fc-cache -f"), Change::Set));
+
+        assert!(set(&mut document, &key("ignore"), parse_value("*.bak"), Change::Add));
+        assert!(set(&mut document, &key("ignore"), parse_value("*.tmp"), Change::Add));
+        assert!(!set(&mut document, &key("ignore"), parse_value("*.bak"), Change::Add));
+        assert_eq!(display(get(&document, &key("ignore")).unwrap()), "- '*.bak'\n- '*.tmp'");
+        assert!(set(&mut document, &key("ignore"), parse_value("*.bak"), Change::Remove));
+        assert!(set(&mut document, &key("ignore"), parse_value("*.tmp"), Change::Remove));
+        assert!(get(&document, &key("ignore")).is_none());
+
+        assert_eq!(parse_value("true"), Value::Bool(true));
+        assert_eq!(parse_value("~"), Value::String("~".to_string()));
+        assert!(unset(&mut document, &key("hooks.post-restore")));
+        assert!(get(&document, &key("hooks")).is_none());
+        assert!(!unset(&mut document, &key("hooks.post-restore")));
+
+        assert!(matches!(parse_key("targets"), Err(DotlinkError::MappingKey(_))));
+        assert!(matches!(parse_key("hooks."), Err(DotlinkError::UnknownSetting(_))));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let document: Value = serde_yaml::from_str(
+            "version: 2\ntargets:\n  /home/me:\n  - source: /dotfiles/zsh\n    ignore: []\n\
+             hooks:\n  post-restore: \"a\\nb\"\nvars:\n  EMAIL: me@example.com\n",
+        )
+        .unwrap();
+        assert_eq!(
+            flatten(&document),
+            [
+                "version=2",
+                "targets[\"/home/me\"][0].source=/dotfiles/zsh",
+                "targets[\"/home/me\"][0].ignore=[]",
+                "hooks.post-restore=\"a\\nb\"",
+                "vars.EMAIL=me@example.com",
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::error::Result;
+use crate::git;
+use crate::linker::platform;
+use crate::migrate::Manager;
+
+/// Name of the config file kept in a dotfiles repository
+pub const REPO_CONFIG_FILE: &str = "amu.yaml";
+
+/// Directories in the home directory that may be stow directories
+const STOW_DIRS: &[&str] = &["dotfiles", ".dotfiles"];
+
+/*
+ * First-run setup (`amu setup`)
+ * Instead of an empty config appearing at the default location on the first save, the user
+ * is asked where the config should live, which target commands use by default and whether
+ * sources go in a git repository; setups of other managers found in the home directory can
+ * then be imported.
+ */
+#[derive(Debug, Default)]
+pub struct Setup {
+    /// Directory to keep the config in (as `amu.yaml`), linked from the default location
+    pub config_dir: Option<PathBuf>,
+    pub default_target: Option<PathBuf>,
+    /// Directory for sources, made a git repository unless it is one
+    pub git: Option<PathBuf>,
+}
+
+/// Whether there is no config yet, nor a link where it would be.
+pub fn needed() -> Result<bool> {
+    Ok(fs::symlink_metadata(Config::config_path()?).is_err())
+}
+
+/// Setups of other managers in the home directory that `amu import --from` can read.
+pub fn discover(home: &Path) -> Vec<(Manager, PathBuf)> {
+    let mut found: Vec<(Manager, PathBuf)> = STOW_DIRS.iter()
+        .map(|name| home.join(name))
+        .filter(|dir| has_packages(dir))
+        .map(|dir| (Manager::Stow, dir))
+        .collect();
+    for manager in [Manager::Chezmoi, Manager::Yadm] {
+        if let Some(path) = manager.default_path_in(home).filter(|path| path.is_dir()) {
+            found.push((manager, path));
+        }
+    }
+    found
+}
+
+/// Whether `dir` holds a directory that is not hidden, as a stow package would be.
+fn has_packages(dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else { return false };
+    entries.flatten().any(|entry| {
+        !entry.file_name().to_string_lossy().starts_with('.') && entry.path().is_dir()
+    })
+}
+
+/// Create the git repository and the config as set up; returns the config file's path.
+pub fn apply(setup: &Setup) -> Result<PathBuf> {
+    if let Some(dir) = &setup.git {
+        fs::create_dir_all(dir)?;
+        if !git::repo_root(dir).is_some_and(|root| config::same_path(&root, dir)) {
+            git::init(dir)?;
+        }
+    }
+
+    let path = Config::config_path()?;
+    if let Some(dir) = &setup.config_dir {
+        let file = dir.join(REPO_CONFIG_FILE);
+        fs::create_dir_all(dir)?;
+        if !file.exists() {
+            fs::write(&file, "")?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&path).is_err() {
+            platform::symlink(&file, &path)?;
+        }
+    }
+
+    let mut config = Config::load()?;
+    if setup.default_target.is_some() {
+        config.default_target = setup.default_target.clone();
+    }
+    config.save()?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_discover() {
+        let temp = TempDir::new().unwrap();
+        let home = temp.path();
+        fs::create_dir_all(home.join("dotfiles/zsh")).unwrap();
+        fs::create_dir_all(home.join(".dotfiles/.git")).unwrap();
+        fs::create_dir_all(home.join(".local/share/chezmoi")).unwrap();
+
+        let found = discover(home);
+        assert_eq!(found, vec![
+            (Manager::Stow, home.join("dotfiles")),
+            (Manager::Chezmoi, home.join(".local/share/chezmoi")),
+        ]);
+    }
+}
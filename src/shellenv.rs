@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::config::Config;
+
+/*
+ * Sources used in place, without links (`amu shellenv`)
+ * A source's `bin/` directory (or the source itself, when it is named `bin`) is put on
+ * PATH and its `share/` directory on XDG_DATA_DIRS. Sources earlier in the config come
+ * first, and directories already on the variable are not added again, so evaluating the
+ * output twice is harmless.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EnvShell {
+    /// POSIX sh, bash and zsh
+    Sh,
+    Fish,
+}
+
+impl EnvShell {
+    /// The shell named by `$SHELL`, falling back to sh.
+    pub fn detect() -> Self {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        match Path::new(&shell).file_name().and_then(|n| n.to_str()) {
+            Some("fish") => EnvShell::Fish,
+            _ => EnvShell::Sh,
+        }
+    }
+}
+
+/// Directories to expose, in precedence order
+#[derive(Debug, Default, PartialEq)]
+pub struct Exposed {
+    pub path: Vec<PathBuf>,
+    pub data_dirs: Vec<PathBuf>,
+}
+
+pub fn exposed(config: &Config) -> Exposed {
+    let mut exposed = Exposed::default();
+    for source in config.targets.values().flatten() {
+        let bin = if source.file_name().is_some_and(|n| n == "bin") { source.clone() } else { source.join("bin") };
+        if bin.is_dir() && !exposed.path.contains(&bin) {
+            exposed.path.push(bin);
+        }
+        let share = source.join("share");
+        if share.is_dir() && !exposed.data_dirs.contains(&share) {
+            exposed.data_dirs.push(share);
+        }
+    }
+    exposed
+}
+
+/// Shell code prepending the exposed directories to PATH and XDG_DATA_DIRS.
+pub fn render(shell: EnvShell, exposed: &Exposed) -> String {
+    let mut out = String::new();
+    // Prepending in reverse leaves the first source first
+    for dir in exposed.path.iter().rev() {
+        out.push_str(&prepend(shell, "PATH", dir, None));
+    }
+    for dir in exposed.data_dirs.iter().rev() {
+        out.push_str(&prepend(shell, "XDG_DATA_DIRS", dir, Some("/usr/local/share:/usr/share")));
+    }
+    out
+}
+
+fn prepend(shell: EnvShell, var: &str, dir: &Path, default: Option<&str>) -> String {
+    let dir = dir.to_string_lossy();
+    match shell {
+        EnvShell::Sh => {
+            let current = match default {
+                Some(default) => format!("${{{}:-{}}}", var, default),
+                None => format!("${}", var),
+            };
+            format!(
+                "case \":${}:\" in *:{}:*) ;; *) export {}={}\":{}\" ;; esac\n",
+                var, sh_quote(&dir), var, sh_quote(&dir), current
+            )
+        }
+        // fish keeps PATH as a list; other variables stay colon-separated strings
+        EnvShell::Fish if var == "PATH" => {
+            format!("contains -- {dir} $PATH; or set -gx PATH {dir} $PATH\n", dir = fish_quote(&dir))
+        }
+        EnvShell::Fish => {
+            let mut out = String::new();
+            if let Some(default) = default {
+                out.push_str(&format!("set -q {var}; or set -gx {var} {default}\n"));
+            }
+            out.push_str(&format!(
+                "contains -- {dir} (string split : -- ${var}); or set -gx {var} {dir}:${var}\n",
+                dir = fish_quote(&dir)
+            ));
+            out
+        }
+    }
+}
+
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let exposed = Exposed {
+            path: vec![PathBuf::from("/a/bin"), PathBuf::from("/b's/bin")],
+            data_dirs: vec![PathBuf::from("/a/share")],
+        };
+        assert_eq!(
+            render(EnvShell::Sh, &exposed),
+            "case \":$PATH:\" in *:'/b'\\''s/bin':*) ;; *) export PATH='/b'\\''s/bin'\":$PATH\" ;; esac\n\
+             case \":$PATH:\" in *:'/a/bin':*) ;; *) export PATH='/a/bin'\":$PATH\" ;; esac\n\
+             case \":$XDG_DATA_DIRS:\" in *:'/a/share':*) ;; *) export XDG_DATA_DIRS='/a/share'\":${XDG_DATA_DIRS:-/usr/local/share:/usr/share}\" ;; esac\n"
+        );
+        assert!(render(EnvShell::Fish, &exposed).starts_with("contains -- '/b\\'s/bin' $PATH; or set -gx PATH '/b\\'s/bin' $PATH\n"));
+    }
+}
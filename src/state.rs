@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{DotlinkError, Result};
+use crate::ownership;
+
+const STATE_DIR: &str = "state";
+const STATE_DIR_ENV: &str = "AMU_STATE_DIR";
+const INTENTS_DIR: &str = "intents";
+
+/// A mutating operation on a (source, target) pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Stow,
+    Unstow,
+    Restow,
+}
+
+impl Action {
+    /// The action that returns the target to its state before this one.
+    /// A restow starts and ends fully linked, so it is its own inverse.
+    pub fn inverse(self) -> Action {
+        match self {
+            Action::Stow => Action::Unstow,
+            Action::Unstow => Action::Stow,
+            Action::Restow => Action::Restow,
+        }
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Action::Stow => "stow",
+            Action::Unstow => "unstow",
+            Action::Restow => "restow",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Write-ahead record of an operation that is about to touch the filesystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Intent {
+    pub action: Action,
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Directory holding amu's runtime state: `AMU_STATE_DIR` if set, otherwise next to the config file.
+pub fn state_dir() -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var(STATE_DIR_ENV) {
+        if !dir.is_empty() {
+            return Ok(PathBuf::from(dir));
+        }
+    }
+    let config_path = Config::config_path()?;
+    let parent = config_path.parent().unwrap_or(Path::new("."));
+    Ok(parent.join(STATE_DIR))
+}
+
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Held while a state file is read, changed and written back, so `--jobs` workers
+/// updating the same file do not lose each other's changes.
+pub fn lock() -> MutexGuard<'static, ()> {
+    STATE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn intents_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join(INTENTS_DIR))
+}
+
+/*
+ * Run an operation guarded by an intent record
+ * The record is written before `op` runs and removed once it returns (whether it
+ * succeeded or reported an error), so only a crash or power loss leaves it behind.
+ */
+pub fn with_intent<T>(intent: Intent, op: impl FnOnce() -> Result<T>) -> Result<T> {
+    let record = record_intent(&intent)?;
+    let result = op();
+    let _ = fs::remove_file(&record);
+    result
+}
+
+fn record_intent(intent: &Intent) -> Result<PathBuf> {
+    let dir = intents_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    // Workers of one process can record intents in the same nanosecond
+    static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("{}-{}-{}.yaml", nanos, std::process::id(), sequence));
+
+    let content = serde_yaml::to_string(intent)
+        .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(path)
+}
+
+/// Intent records left behind by an interrupted run, oldest first.
+/// Unreadable records are skipped.
+pub fn pending_intents() -> Result<Vec<(PathBuf, Intent)>> {
+    let dir = intents_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(e) => e,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "yaml"))
+        .collect();
+    paths.sort();
+
+    let mut intents = Vec::new();
+    for path in paths {
+        let Ok(content) = fs::read_to_string(&path) else { continue };
+        if let Ok(intent) = serde_yaml::from_str::<Intent>(&content) {
+            intents.push((path, intent));
+        }
+    }
+    Ok(intents)
+}
+
+/// Remove an intent record once it has been resolved.
+pub fn discard_intent(record: &Path) -> Result<()> {
+    fs::remove_file(record)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_inverse() {
+        assert_eq!(Action::Stow.inverse(), Action::Unstow);
+        assert_eq!(Action::Restow.inverse(), Action::Restow);
+        assert_eq!(Action::Unstow.inverse(), Action::Stow);
+    }
+
+    #[test]
+    fn test_intent_roundtrip() {
+        let intent = Intent {
+            action: Action::Restow,
+            source: PathBuf::from("/home/user/dotfiles/nvim"),
+            target: PathBuf::from("/home/user/.config/nvim"),
+        };
+        let yaml = serde_yaml::to_string(&intent).unwrap();
+        assert!(yaml.contains("action: restow"));
+
+        let parsed: Intent = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.action, Action::Restow);
+        assert_eq!(parsed.source, intent.source);
+    }
+}
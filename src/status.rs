@@ -0,0 +1,288 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Config};
+use crate::git;
+use crate::ignore;
+use crate::linker::{self, Mode};
+use crate::links;
+use crate::mounts;
+use crate::precedence;
+use crate::stow;
+use crate::unreadable;
+
+/*
+ * Enum representing the status of a source
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SourceStatus {
+    /// `folded` of the links are files reached through a directory link into the source
+    Ok {
+        link_count: usize,
+        #[serde(default)]
+        folded: usize,
+    },
+    SourceNotFound,
+    TargetNotFound,
+    BrokenLinks(Vec<String>),
+    Conflicts(String),
+    RealFiles(Vec<String>),
+    PermissionDenied(String),
+    ReadOnlyTarget,
+    /// Paths pinned to the source but linked from elsewhere or not at all
+    PinMismatch(Vec<String>),
+    /// The source lives on media not mounted at this mount point
+    NotMounted(PathBuf),
+    /// Every file copied into the target and unchanged (`mode: copy`)
+    Copied { file_count: usize },
+    /// Copies that differ from the source, each with what is wrong
+    Drift(Vec<String>),
+    /// Every file hard-linked into the target is still the source's (`mode: hardlink`)
+    HardLinked { file_count: usize },
+    /// Files no longer hard-linked to the source's (replaced on one side) or missing
+    BrokenHardLinks(Vec<String>),
+    /// Fine, in a sparse checkout leaving `excluded` tracked files out of the working tree;
+    /// `stale` lists those still linked in the target (dangling until `update` drops them)
+    Sparse { link_count: usize, excluded: usize, stale: Vec<String> },
+}
+
+impl SourceStatus {
+    /// Severity reported in summaries: "ok", "warning" or "error"
+    pub fn class(&self) -> &'static str {
+        match self {
+            SourceStatus::Ok { .. } | SourceStatus::Copied { .. } | SourceStatus::HardLinked { .. } | SourceStatus::Sparse { .. } => "ok",
+            SourceStatus::SourceNotFound | SourceStatus::TargetNotFound | SourceStatus::PermissionDenied(_) => "error",
+            SourceStatus::BrokenLinks(_)
+            | SourceStatus::Conflicts(_)
+            | SourceStatus::RealFiles(_)
+            | SourceStatus::ReadOnlyTarget
+            | SourceStatus::PinMismatch(_)
+            | SourceStatus::Drift(_)
+            | SourceStatus::BrokenHardLinks(_)
+            | SourceStatus::NotMounted(_) => "warning",
+        }
+    }
+}
+
+pub fn check_source_status(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> SourceStatus {
+    source_health(config, source, target, ignore).0
+}
+
+/// A source's status in a target together with the number of links it has there.
+pub fn source_health(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> (SourceStatus, usize) {
+    // Permission check
+    if let Err(e) = std::fs::read_dir(source) {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            return (SourceStatus::PermissionDenied(format!("source: {}", source.display())), 0);
+        }
+        return (SourceStatus::SourceNotFound, 0);
+    }
+    if !source.exists() {
+        return (SourceStatus::SourceNotFound, 0);
+    }
+    if !target.exists() {
+        return (SourceStatus::TargetNotFound, 0);
+    }
+
+    let mode = linker::mode_for(config, source, target);
+    if mode != Mode::Link {
+        let drift = linker::copy::drift(mode, source, target, ignore);
+        let status = match (mode, drift.paths.is_empty()) {
+            _ if !links::is_writable(target) => SourceStatus::ReadOnlyTarget,
+            (Mode::Hardlink, true) => SourceStatus::HardLinked { file_count: drift.copied },
+            (Mode::Hardlink, false) => SourceStatus::BrokenHardLinks(drift.paths),
+            (_, true) => SourceStatus::Copied { file_count: drift.copied },
+            (_, false) => SourceStatus::Drift(drift.paths),
+        };
+        return (status, 0);
+    }
+
+    let scan = scan_pair(source, target, ignore);
+    let status = if !links::is_writable(target) {
+        SourceStatus::ReadOnlyTarget
+    } else if !scan.broken.is_empty() {
+        SourceStatus::BrokenLinks(scan.broken)
+    } else if !scan.real_files.is_empty() {
+        // Real files exist where symlinks should be
+        SourceStatus::RealFiles(scan.real_files)
+    } else {
+        match stow::dry_run(config, source, target, ignore) {
+            Ok(plan) if plan.has_conflicts() => SourceStatus::Conflicts(plan.conflicts().collect::<Vec<_>>().join("\n")),
+            _ => SourceStatus::Ok { link_count: scan.links, folded: scan.folded },
+        }
+    };
+    (status, scan.links)
+}
+
+/// What a single walk of a source finds at the matching paths of its target
+#[derive(Default)]
+pub struct PairScan {
+    /// Symlinks into the source at paths it provides; a folded directory counts for each file in it
+    pub links: usize,
+    /// Files counted in `links` because a directory link above them points into the source
+    pub folded: usize,
+    /// Those symlinks whose destination is missing
+    pub broken: Vec<String>,
+    /// Real files where the source provides a file
+    pub real_files: Vec<String>,
+}
+
+/*
+ * Walk a source once, inspecting the target path of every file it provides
+ * status, list --verbose and stats share this walk rather than scanning once per check.
+ * amu links file by file, but a directory linked whole (stow's folding, or by hand) stands
+ * for every file below it: those are counted as links and as folded, not as real files.
+ * Live links elsewhere are another source's and are not counted; dangling ones still are.
+ */
+pub fn scan_pair(source: &Path, target: &Path, ignore: &[String]) -> PairScan {
+    let mut scan = PairScan::default();
+    let resolved = config::resolve_path(source);
+    scan_pair_recursive(source, &resolved, target, source, ignore, false, &mut scan);
+    scan.broken.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+    scan.real_files.sort_by(|a, b| Path::new(a).cmp(Path::new(b)));
+    scan
+}
+
+fn scan_pair_recursive(
+    source_base: &Path,
+    resolved_base: &Path,
+    target: &Path,
+    current_source: &Path,
+    ignore: &[String],
+    folded: bool,
+    scan: &mut PairScan,
+) {
+    if let Some(entries) = unreadable::read_dir(current_source) {
+        for entry in entries {
+            let source_path = entry.path();
+            let relative = source_path.strip_prefix(source_base).unwrap_or(&source_path);
+            if ignore::is_ignored(relative, ignore) {
+                continue;
+            }
+            let target_path = target.join(relative);
+
+            if source_path.is_dir() && !source_path.is_symlink() {
+                let folded = folded || is_folded(&target_path, &source_path);
+                scan_pair_recursive(source_base, resolved_base, target, &source_path, ignore, folded, scan);
+            } else if folded {
+                scan.links += 1;
+                scan.folded += 1;
+            } else if target_path.is_symlink() {
+                // A live link elsewhere belongs to another source; a dangling one is still broken
+                let into_source = links::link_destination(&target_path)
+                    .is_some_and(|d| d.starts_with(resolved_base) || d.starts_with(source_base));
+                if !into_source && target_path.exists() {
+                    continue;
+                }
+                scan.links += 1;
+                if !target_path.exists() {
+                    scan.broken.push(relative.display().to_string());
+                }
+            } else if source_path.is_file() && target_path.exists() {
+                scan.real_files.push(relative.display().to_string());
+            }
+        }
+    }
+}
+
+/// Whether `link` is a directory link to the source directory `dir`.
+fn is_folded(link: &Path, dir: &Path) -> bool {
+    link.is_symlink() && links::link_destination(link).is_some_and(|d| config::same_path(&d, dir))
+}
+
+/// "12 links", or "12 links, 5 via folded directories"
+pub fn describe_links(link_count: usize, folded: usize) -> String {
+    if folded == 0 {
+        format!("{} links", link_count)
+    } else {
+        format!("{} links, {} via folded directories", link_count, folded)
+    }
+}
+
+/*
+ * Paths pinned to `source` that are not linked from it
+ * Each comes with a description of the path and what provides it instead.
+ */
+pub fn unmet_pins(config: &Config, target: &Path, source: &Path) -> Vec<(PathBuf, String)> {
+    let Some(pins) = config.pins.get(target) else {
+        return Vec::new();
+    };
+    let others: Vec<&PathBuf> = config.get_sources(target)
+        .map(|sources| sources.iter().filter(|s| s.as_path() != source).collect())
+        .unwrap_or_default();
+
+    pins.iter()
+        .filter(|(_, pinned)| pinned.as_path() == source)
+        .filter_map(|(relative, _)| {
+            let path = target.join(relative);
+            let described = if let Some(other) = others.iter().find(|other| !links::links_into(&path, other).is_empty()) {
+                format!("{} (provided by {})", relative.display(), config::abbreviate_path(other))
+            } else if links::links_into(&path, source).is_empty() {
+                format!("{} (not linked)", relative.display())
+            } else {
+                return None;
+            };
+            Some((relative.clone(), described))
+        })
+        .collect()
+}
+
+/*
+ * Status of one registered source in a target, as `amu status` reports it
+ * A pinned path taken by another source also shows up as a conflict; the pin is reported
+ * instead when it is the only conflict, and a conflict elsewhere in the source as usual.
+ */
+pub fn source_status(config: &Config, target: &Path, source: &Path) -> SourceStatus {
+    if let Some(point) = mounts::unmounted(config, source) {
+        return SourceStatus::NotMounted(point.to_path_buf());
+    }
+    // Paths a higher-priority source wins are its to report, not conflicts of this one
+    let mut ignore = ignore::patterns_for(config, target, source);
+    ignore.extend(precedence::skip_patterns(&precedence::contested_in(config, target, source)));
+    let unmet = unmet_pins(config, target, source);
+    let only_pins_conflict = || {
+        let mut without_pins = ignore.clone();
+        without_pins.extend(unmet.iter().map(|(relative, _)| format!("/{}", relative.display())));
+        !matches!(check_source_status(config, source, target, &without_pins), SourceStatus::Conflicts(_))
+    };
+    let mismatch = || SourceStatus::PinMismatch(unmet.iter().map(|(_, described)| described.clone()).collect());
+    match check_source_status(config, source, target, &ignore) {
+        SourceStatus::Ok { .. } if !unmet.is_empty() => mismatch(),
+        SourceStatus::Conflicts(_) if !unmet.is_empty() && only_pins_conflict() => mismatch(),
+        SourceStatus::Ok { link_count, folded } => sparse_status(source, target, &ignore, link_count, folded),
+        status => status,
+    }
+}
+
+/*
+ * Ok, or Sparse when the source is in a sparse checkout
+ * Files left out of the working tree are not missing: their links, if any remain from before
+ * the checkout was narrowed, are reported as stale rather than broken.
+ */
+fn sparse_status(source: &Path, target: &Path, ignore: &[String], link_count: usize, folded: usize) -> SourceStatus {
+    let excluded: Vec<PathBuf> = git::sparse_excluded(source)
+        .into_iter()
+        .filter(|relative| !ignore::is_ignored(relative, ignore))
+        .collect();
+    if excluded.is_empty() {
+        return SourceStatus::Ok { link_count, folded };
+    }
+    let stale = excluded.iter()
+        .filter(|relative| {
+            let link = target.join(relative);
+            link.is_symlink() && !link.exists() && links::link_destination(&link).is_some_and(|d| d.starts_with(source))
+        })
+        .map(|relative| relative.display().to_string())
+        .collect();
+    SourceStatus::Sparse { link_count, excluded: excluded.len(), stale }
+}
+
+/// Status of every source registered for a target, in priority order.
+pub fn check_target(config: &Config, target: &Path) -> Vec<(PathBuf, SourceStatus)> {
+    config.get_sources(target)
+        .map(|sources| {
+            sources.iter().map(|source| (source.clone(), source_status(config, target, source))).collect()
+        })
+        .unwrap_or_default()
+}
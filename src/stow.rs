@@ -1,97 +1,304 @@
-use std::path::Path;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
+use crate::config::Config;
 use crate::error::{DotlinkError, Result};
+use crate::history::{self, Change};
+use crate::ignore;
+use crate::journal;
+use crate::linker::{self, Backend, Mode};
+use crate::locks;
+use crate::log;
+use crate::ownership;
+use crate::plan::Plan;
+use crate::progress::{self, Event};
+use crate::renames;
+use crate::state::{self, Action, Intent};
+use crate::strict;
+use crate::transaction;
+
+static STOW_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
 
 pub fn check_installed() -> Result<()> {
-    let output = Command::new("which")
-        .arg("stow")
-        .output()
-        .map_err(|_| DotlinkError::StowNotFound)?;
+    stow_path().map(|_| ()).ok_or(DotlinkError::StowNotFound)
+}
 
-    if output.status.success() {
-        Ok(())
+/// The installed stow's version ("2.3.1"), if stow is installed.
+pub fn version() -> Option<String> {
+    let output = stow_command().arg("--version").output().ok()?;
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The version from `stow --version` output: "stow (GNU Stow) version 2.3.1"
+fn parse_version(output: &str) -> Option<String> {
+    output.lines().next()?.rsplit(' ').next().filter(|v| v.starts_with(|c: char| c.is_ascii_digit())).map(String::from)
+}
+
+/// Location of the stow executable, looked up on PATH once per process.
+fn stow_path() -> Option<&'static PathBuf> {
+    STOW_PATH
+        .get_or_init(|| find_executable("stow", &std::env::var_os("PATH").unwrap_or_default()))
+        .as_ref()
+}
+
+/// Program to spawn for stow: the resolved path, or the bare name so spawn errors stay meaningful.
+fn stow_command() -> Command {
+    match stow_path() {
+        Some(path) => Command::new(path),
+        None => Command::new("stow"),
+    }
+}
+
+/*
+ * Search a PATH-style list of directories for an executable
+ * On Windows, names are also tried with each extension in PATHEXT (stow is usually
+ * installed as `stow.bat` or `stow.exe` there).
+ */
+fn find_executable(name: &str, path_var: &OsStr) -> Option<PathBuf> {
+    let candidates = executable_names(name);
+    std::env::split_paths(path_var)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .flat_map(|dir| candidates.iter().map(move |candidate| dir.join(candidate)))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn executable_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+#[cfg(not(unix))]
+fn executable_names(name: &str) -> Vec<String> {
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    std::iter::once(name.to_string())
+        .chain(extensions.split(';').filter(|e| !e.is_empty()).map(|e| format!("{}{}", name, e.to_lowercase())))
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+pub fn stow(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    guarded(config, Action::Stow, source, target, ignore, || run(config, Action::Stow, source, target, ignore))
+}
+
+pub fn unstow(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    guarded(config, Action::Unstow, source, target, ignore, || run(config, Action::Unstow, source, target, ignore))
+}
+
+pub fn restow(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    guarded(config, Action::Restow, source, target, ignore, || run(config, Action::Restow, source, target, ignore))
+}
+
+/// Link or unlink with the pair's backend, or copy (or hard-link) for pairs in those modes.
+fn run(config: &Config, action: Action, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    let mode = linker::mode_for(config, source, target);
+    if mode != Mode::Link {
+        return linker::copy::apply(mode, action, source, target, ignore);
+    }
+    match linker::backend_for(config, source, target) {
+        Backend::Native => linker::native::apply(action, source, target, ignore, linker::folds(config, source, target)),
+        _ => run_stow(config, stow_flags(action), source, target, ignore),
+    }
+}
+
+fn stow_flags(action: Action) -> &'static [&'static str] {
+    match action {
+        Action::Stow => &[],
+        Action::Unstow => &["-D"],
+        Action::Restow => &["-R"],
+    }
+}
+
+/// Run a mutating stow call with a write-ahead intent record.
+fn guarded(
+    config: &Config,
+    action: Action,
+    source: &Path,
+    target: &Path,
+    ignore: &[String],
+    op: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let intent = Intent {
+        action,
+        source: source.to_path_buf(),
+        target: target.to_path_buf(),
+    };
+    // A source can vanish after the config was read, e.g. an unmounted external drive
+    if action != Action::Unstow && !source.is_dir() {
+        return Err(DotlinkError::SourceUnavailable(source.to_path_buf()));
+    }
+    // Another amu process (the watcher, or a command run by hand) may be changing the target
+    let _lock = locks::acquire(target)?;
+    crate::info!("{} {} -> {}", action, source.display(), target.display());
+    let before = journal::linked_files(source, target, ignore);
+    // Unlinking creates nothing, so there is nothing to roll back
+    let rollback = action != Action::Unstow && transaction::rollback_enabled();
+    // Links the built-in linker makes are recorded as it goes; stow's, and copies, are inferred
+    let external = (linker::mode_for(config, source, target) != Mode::Link
+        || linker::backend_for(config, source, target) == Backend::Stow)
+        && history::is_recording();
+    let snapshot = (rollback || external).then(|| transaction::snapshot(source, target, ignore));
+    let result = state::with_intent(intent, op);
+    if let Some(snapshot) = snapshot {
+        let changes = transaction::changes(&snapshot, source, target, ignore);
+        if external {
+            for dir in changes.created_dirs() {
+                history::record(Change::DirCreated { path: dir.clone() });
+            }
+            for (link, destination) in changes.removed_links() {
+                history::record(Change::LinkRemoved { link: link.clone(), destination: destination.clone() });
+            }
+            for link in changes.created_links() {
+                history::record(Change::LinkCreated { link: link.clone() });
+            }
+            for path in changes.created_files() {
+                if let Some(fingerprint) = linker::copy::file_fingerprint(path) {
+                    history::record(Change::Written { path: path.clone(), fingerprint });
+                }
+            }
+        }
+        if rollback {
+            transaction::record(changes, result.is_err());
+        }
+    }
+    if let Err(e) = result {
+        let e = if source.is_dir() { e } else { DotlinkError::SourceUnavailable(source.to_path_buf()) };
+        let message = e.to_string();
+        if is_conflict(&message) {
+            progress::emit(Event::Conflicted { action, source, target, message });
+        } else {
+            progress::emit(Event::Failed { action, source, target, message });
+        }
+        return Err(e);
+    }
+
+    // The links are in place either way; only the record of them is missing
+    if let Err(e) = journal::update(source, target, &before, ignore) {
+        strict::warn(format_args!("links not recorded: {}", e));
+    }
+    if action == Action::Unstow {
+        progress::emit(Event::Unlinked { source, target });
     } else {
-        Err(DotlinkError::StowNotFound)
+        ownership::fix_links(source, target);
+        // Keep the inode snapshot current so later updates can detect renames
+        renames::record(source, ignore)?;
+        progress::emit(Event::Linked { action, source, target });
     }
+    Ok(())
 }
 
-pub fn stow(source: &Path, target: &Path) -> Result<()> {
-    run_stow(&[], source, target)
+/// Whether a stow error message reports files that would be overwritten.
+pub fn is_conflict(message: &str) -> bool {
+    let lower = message.to_lowercase();
+    lower.contains("conflict") || lower.contains("existing target")
 }
 
-pub fn unstow(source: &Path, target: &Path) -> Result<()> {
-    run_stow(&["-D"], source, target)
+/// Perform an action recorded in an intent.
+pub fn apply(config: &Config, action: Action, source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
+    match action {
+        Action::Stow => stow(config, source, target, ignore),
+        Action::Unstow => unstow(config, source, target, ignore),
+        Action::Restow => restow(config, source, target, ignore),
+    }
 }
 
-pub fn restow(source: &Path, target: &Path) -> Result<()> {
-    run_stow(&["-R"], source, target)
+pub fn dry_run(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
+    dry_run_as(config, Action::Stow, source, target, ignore)
 }
 
-pub fn dry_run(source: &Path, target: &Path) -> Result<String> {
-    dry_run_with_args(&[], source, target)
+pub fn dry_run_unstow(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
+    dry_run_as(config, Action::Unstow, source, target, ignore)
 }
 
-pub fn dry_run_unstow(source: &Path, target: &Path) -> Result<String> {
-    dry_run_with_args(&["-D"], source, target)
+pub fn dry_run_restow(config: &Config, source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
+    dry_run_as(config, Action::Restow, source, target, ignore)
 }
 
-pub fn dry_run_restow(source: &Path, target: &Path) -> Result<String> {
-    dry_run_with_args(&["-R"], source, target)
+/// What an action would do, from whichever backend links (or copies) the pair.
+fn dry_run_as(config: &Config, action: Action, source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
+    let mode = linker::mode_for(config, source, target);
+    if mode != Mode::Link {
+        return Ok(linker::copy::dry_run(mode, action, source, target, ignore));
+    }
+    match linker::backend_for(config, source, target) {
+        Backend::Native => Ok(linker::native::dry_run(action, source, target, ignore, linker::folds(config, source, target))),
+        _ => dry_run_with_args(config, stow_flags(action), source, target, ignore),
+    }
 }
 
-fn dry_run_with_args(extra_args: &[&str], source: &Path, target: &Path) -> Result<String> {
+fn dry_run_with_args(config: &Config, extra_args: &[&str], source: &Path, target: &Path, ignore: &[String]) -> Result<Plan> {
     let (parent, dirname) = split_source_path(source)?;
 
-    let mut cmd = Command::new("stow");
-    cmd.arg("-n").arg("-v").arg("--no-folding");
+    let mut cmd = stow_command();
+    cmd.arg("-n").arg("-v");
+    if !linker::folds(config, source, target) {
+        cmd.arg("--no-folding");
+    }
     for arg in extra_args {
         cmd.arg(arg);
     }
+    for pattern in ignore {
+        cmd.arg(format!("--ignore={}", ignore::to_stow_regex(pattern)));
+    }
     cmd.arg("-t").arg(target);
     cmd.arg("-d").arg(&parent);
     cmd.arg(&dirname);
 
-    let output = cmd.output().map_err(|e| DotlinkError::StowError(e.to_string()))?;
-
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-    Ok(stderr)
-}
+    crate::debug!("run: {}", log::command_line(&cmd));
+    let output = cmd.output().map_err(spawn_error)?;
 
-/*
- * Extract link operations from stow's dry-run output
- * Example output: "LINK: .config/nvim/init.lua => ../../../dotfiles/nvim/init.lua"
- */
-pub fn parse_dry_run_output(output: &str) -> Vec<String> {
-    output
-        .lines()
-        .filter(|line| line.contains("LINK:") || line.contains("UNLINK:"))
-        .map(|line| line.trim().to_string())
-        .collect()
+    Ok(Plan::from_stow(&String::from_utf8_lossy(&output.stderr)))
 }
 
-fn run_stow(extra_args: &[&str], source: &Path, target: &Path) -> Result<()> {
+fn run_stow(config: &Config, extra_args: &[&str], source: &Path, target: &Path, ignore: &[String]) -> Result<()> {
     let (parent, dirname) = split_source_path(source)?;
 
-    let mut cmd = Command::new("stow");
-    cmd.arg("--no-folding");
+    let mut cmd = stow_command();
+    if !linker::folds(config, source, target) {
+        cmd.arg("--no-folding");
+    }
     for arg in extra_args {
         cmd.arg(arg);
     }
+    for pattern in ignore {
+        cmd.arg(format!("--ignore={}", ignore::to_stow_regex(pattern)));
+    }
     cmd.arg("-t").arg(target);
     cmd.arg("-d").arg(&parent);
     cmd.arg(&dirname);
 
-    let output = cmd.output().map_err(|e| DotlinkError::StowError(e.to_string()))?;
+    crate::debug!("run: {}", log::command_line(&cmd));
+    let output = cmd.output().map_err(spawn_error)?;
 
     if output.status.success() {
         Ok(())
     } else {
         let stderr = String::from_utf8_lossy(&output.stderr);
+        crate::debug!("stow exited with {}: {}", output.status, stderr.trim());
         Err(DotlinkError::StowError(stderr.to_string()))
     }
 }
 
+/// Error for a stow process that could not be started.
+fn spawn_error(e: std::io::Error) -> DotlinkError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        DotlinkError::StowNotFound
+    } else {
+        DotlinkError::StowError(e.to_string())
+    }
+}
+
 fn split_source_path(source: &Path) -> Result<(String, String)> {
     let parent = source
         .parent()
@@ -113,6 +320,12 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("stow (GNU Stow) version 2.3.1\n"), Some("2.3.1".to_string()));
+        assert_eq!(parse_version("usage: stow"), None);
+    }
+
     #[test]
     fn test_split_source_path() {
         let source = PathBuf::from("/home/user/dotfiles/nvim");
@@ -121,6 +334,27 @@ mod tests {
         assert_eq!(dirname, "nvim");
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_find_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let empty = temp.path().join("empty");
+        let bin = temp.path().join("bin");
+        std::fs::create_dir(&empty).unwrap();
+        std::fs::create_dir(&bin).unwrap();
+        std::fs::write(bin.join("stow"), "#!/bin/sh\n").unwrap();
+        std::fs::write(bin.join("notes"), "").unwrap();
+        std::fs::set_permissions(bin.join("stow"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path_var = std::env::join_paths([&empty, &bin]).unwrap();
+        assert_eq!(find_executable("stow", &path_var), Some(bin.join("stow")));
+        // Not executable, or not on PATH at all
+        assert_eq!(find_executable("notes", &path_var), None);
+        assert_eq!(find_executable("stow", &std::env::join_paths([&empty]).unwrap()), None);
+    }
+
     #[test]
     fn test_split_source_path_nested() {
         let source = PathBuf::from("/home/user/work/dotfiles/.config");
@@ -0,0 +1,47 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+use crate::error::{DotlinkError, Result};
+
+/*
+ * Strict mode (`--strict`, or `strict: true` in the config)
+ * Commands that link check every pair for warning-level conditions before changing anything
+ * and refuse to run if there are any, so a provisioning run applies everything or nothing.
+ * A warning printed while any command runs makes it exit with an error once it is done.
+ */
+static REQUESTED: OnceLock<bool> = OnceLock::new();
+static RESOLVED: OnceLock<bool> = OnceLock::new();
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Turn strict mode on for this process (`--strict`), whatever the config says.
+pub fn set_strict(enabled: bool) {
+    let _ = REQUESTED.set(enabled);
+}
+
+/// Whether strict mode is on: `--strict`, then `strict:` in the config.
+pub fn is_strict() -> bool {
+    *RESOLVED.get_or_init(|| {
+        REQUESTED.get().copied().unwrap_or(false) || Config::load().is_ok_and(|config| config.strict.unwrap_or(false))
+    })
+}
+
+/// Print a warning to stderr and count it against strict mode.
+pub fn warn(message: impl Display) {
+    crate::warn!("{}", message);
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Warnings printed so far.
+pub fn warnings() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Refuse to go on in strict mode when a pre-flight check found warning-level conditions.
+pub fn check(found: &[String]) -> Result<()> {
+    if found.is_empty() || !is_strict() {
+        return Ok(());
+    }
+    Err(DotlinkError::StrictWarnings { count: found.len(), details: found.join("\n  ") })
+}
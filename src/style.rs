@@ -0,0 +1,147 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::theme;
+
+/*
+ * Colors in text output (`--color`, or `color:` in the config)
+ * Every command marks results and paths through these helpers, so a new command is
+ * colored like the others without choosing codes itself. `auto` colors only a terminal
+ * and honors `NO_COLOR` and `TERM=dumb`; `always` and `never` decide regardless.
+ * Table widths skip the escape codes (see `table::width`).
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How a piece of text output is colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    Ok,
+    Warning,
+    Error,
+    /// Files and directories
+    Path,
+    /// Secondary details
+    Dim,
+}
+
+static REQUESTED: OnceLock<ColorChoice> = OnceLock::new();
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Use this choice for the process (`--color`), whatever the config says.
+pub fn set_color(choice: ColorChoice) {
+    let _ = REQUESTED.set(choice);
+}
+
+/// `--color`, then `color:` in the config, then `auto`.
+pub fn enabled() -> bool {
+    *ENABLED.get_or_init(|| {
+        let choice = REQUESTED.get().copied().or_else(|| Config::load().ok().and_then(|config| config.color)).unwrap_or_default();
+        match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                use std::io::IsTerminal;
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+        }
+    })
+}
+
+fn code(tone: Tone) -> &'static str {
+    match tone {
+        Tone::Ok => "32",
+        Tone::Warning => "33",
+        Tone::Error => "31",
+        Tone::Path => "36",
+        Tone::Dim => "2",
+    }
+}
+
+/// `text` in the tone's color, or as is when colors are off.
+pub fn paint(text: &str, tone: Tone) -> String {
+    if enabled() { format!("\x1b[{}m{}\x1b[0m", code(tone), text) } else { text.to_string() }
+}
+
+/// Tone of a status class ("ok", "warning" or "error").
+pub fn tone_of(class: &str) -> Tone {
+    match class {
+        "ok" => Tone::Ok,
+        "warning" => Tone::Warning,
+        _ => Tone::Error,
+    }
+}
+
+/// The theme's symbol for a status class, colored.
+pub fn mark(class: &str) -> String {
+    let symbol = match class {
+        "ok" => theme::ok(),
+        "warning" => theme::warning(),
+        _ => theme::error(),
+    };
+    paint(symbol, tone_of(class))
+}
+
+pub fn ok() -> String {
+    mark("ok")
+}
+
+pub fn warning() -> String {
+    mark("warning")
+}
+
+pub fn error() -> String {
+    mark("error")
+}
+
+pub fn rolled_back() -> String {
+    paint(theme::rolled_back(), Tone::Warning)
+}
+
+pub fn path(text: &str) -> String {
+    paint(text, Tone::Path)
+}
+
+/// `text` without the escape codes `paint` adds.
+pub fn strip(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI: ESC [ parameters final-byte
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(strip("\x1b[32m✓\x1b[0m ~/dotfiles/vim"), "✓ ~/dotfiles/vim");
+        assert_eq!(strip("plain"), "plain");
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::OnceLock;
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::style;
+
+/*
+ * Column-aligned human output
+ * Widths are measured in terminal cells, so CJK file names and emoji (two cells wide)
+ * line up with ASCII ones. With `--no-align` cells are joined by a single space instead.
+ */
+static ALIGN: OnceLock<bool> = OnceLock::new();
+
+/// Turn column alignment off for this process (`--no-align`).
+pub fn set_align(align: bool) {
+    let _ = ALIGN.set(align);
+}
+
+pub fn aligned() -> bool {
+    ALIGN.get().copied().unwrap_or(true)
+}
+
+/// Display width of a string in terminal cells; color codes take none.
+pub fn width(s: &str) -> usize {
+    if s.contains('\x1b') { UnicodeWidthStr::width(style::strip(s).as_str()) } else { UnicodeWidthStr::width(s) }
+}
+
+/// Rows of cells, plus free-form lines (such as details under a row) that keep their place
+/// between rows without affecting column widths
+#[derive(Debug, Default)]
+pub struct Table {
+    rows: Vec<Row>,
+}
+
+#[derive(Debug)]
+enum Row {
+    Cells(Vec<String>),
+    Line(String),
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table::default()
+    }
+
+    pub fn row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rows.push(Row::Cells(cells.into_iter().map(Into::into).collect()));
+    }
+
+    pub fn line(&mut self, line: impl Into<String>) {
+        self.rows.push(Row::Line(line.into()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Render with columns padded to their widest cell; each line ends with a newline.
+    pub fn render(&self) -> String {
+        self.render_with(aligned())
+    }
+
+    fn render_with(&self, align: bool) -> String {
+        let mut widths: Vec<usize> = Vec::new();
+        for row in &self.rows {
+            if let Row::Cells(cells) = row {
+                for (i, cell) in cells.iter().enumerate() {
+                    if i == widths.len() {
+                        widths.push(0);
+                    }
+                    widths[i] = widths[i].max(width(cell));
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for row in &self.rows {
+            match row {
+                Row::Line(line) => out.push_str(line),
+                Row::Cells(cells) => {
+                    let last = cells.iter().rposition(|c| !c.is_empty()).map_or(0, |i| i + 1);
+                    for (i, cell) in cells[..last].iter().enumerate() {
+                        if i > 0 {
+                            out.push(' ');
+                        }
+                        out.push_str(cell);
+                        if align && i + 1 < last {
+                            out.extend(std::iter::repeat_n(' ', widths[i] - width(cell)));
+                        }
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Print the rendered table to stdout.
+    pub fn print(&self) {
+        crate::report::write_str(&self.render());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_aligns_by_display_width() {
+        let mut table = Table::new();
+        table.row(["  ✓", "~/dotfiles/設定", "(3 links)"]);
+        table.line("    - detail");
+        table.row(["  !", "~/dotfiles/ab", "(1 links)"]);
+        table.row(["  ✗", "~/x", ""]);
+
+        assert_eq!(
+            table.render_with(true),
+            "  ✓ ~/dotfiles/設定 (3 links)\n    - detail\n  ! ~/dotfiles/ab   (1 links)\n  ✗ ~/x\n"
+        );
+        assert_eq!(
+            table.render_with(false),
+            "  ✓ ~/dotfiles/設定 (3 links)\n    - detail\n  ! ~/dotfiles/ab (1 links)\n  ✗ ~/x\n"
+        );
+    }
+}
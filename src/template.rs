@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Config};
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::link::list_source_files;
+use crate::ownership;
+use crate::state;
+use crate::vars::{self, Vars};
+use crate::yaml;
+
+/// File name suffix marking a source file as a template
+pub const SUFFIX: &str = ".tmpl";
+
+/// Start of a placeholder, and how a literal one is written
+const OPEN: &str = "{{";
+const ESCAPED_OPEN: &str = "\\{{";
+
+/// Record of the files rendered into targets, in the state directory
+const RECORD_FILE: &str = "rendered.yaml";
+
+/*
+ * Replace variable values in a file's content with `{{ name }}` placeholders
+ * At each position the longest matching value wins, so overlapping values (a user name
+ * inside a home path, say) extract the more specific variable. A value is only replaced where
+ * it stands as a whole word: `arm` is left alone inside `charm`. A literal `{{` is escaped,
+ * so the template renders back to the content. Returns the template and the names of the
+ * variables it uses.
+ */
+pub fn extract(content: &str, vars: &Vars) -> (String, Vec<String>) {
+    let mut candidates: Vec<(&String, &String)> = vars.iter().filter(|(_, value)| !value.is_empty()).collect();
+    candidates.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+
+    let mut template = String::with_capacity(content.len());
+    let mut used: Vec<String> = Vec::new();
+    let mut rest = content;
+    let mut previous: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        let whole_word = |value: &str| {
+            let next = rest[value.len()..].chars().next();
+            !(joins(previous, value.chars().next()) || joins(value.chars().last(), next))
+        };
+        match candidates.iter().find(|(_, value)| rest.starts_with(value.as_str()) && whole_word(value)) {
+            Some((name, value)) => {
+                template.push_str(&format!("{{{{ {} }}}}", name));
+                if !used.contains(name) {
+                    used.push(name.to_string());
+                }
+                previous = value.chars().last();
+                rest = &rest[value.len()..];
+            }
+            None if rest.starts_with(OPEN) => {
+                template.push_str(ESCAPED_OPEN);
+                previous = Some('{');
+                rest = &rest[OPEN.len()..];
+            }
+            None => {
+                template.push(c);
+                previous = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    used.sort();
+    (template, used)
+}
+
+/// Whether two adjacent characters belong to the same word
+fn joins(before: Option<char>, after: Option<char>) -> bool {
+    let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    word(before) && word(after)
+}
+
+/// Substitute `{{ name }}` placeholders with variable values; `\{{` is a literal `{{`.
+pub fn render(template: &str, vars: &Vars) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        if rest[..start].ends_with('\\') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str(OPEN);
+            rest = &rest[start + OPEN.len()..];
+            continue;
+        }
+        let Some(end) = rest[start..].find("}}") else { break };
+        let name = rest[start + 2..start + end].trim();
+        let value = vars.get(name).ok_or_else(|| DotlinkError::VariableNotSet(name.to_string()))?;
+        output.push_str(&rest[..start]);
+        output.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/*
+ * Rendering templates into targets
+ * Each `<path>.tmpl` in a source is rendered to `<path>` in the targets the source is linked
+ * into, as a real file. A fingerprint of what was written is kept in the state directory, so
+ * a later update rewrites the file when the template or a variable has changed but leaves
+ * it alone once it has been edited by hand.
+ */
+
+/// Variables templates see: the built-ins, then the config's `vars:`, then this machine's.
+pub fn variables(config: &Config) -> Result<Vars> {
+    let mut all = builtin_vars();
+    all.extend(config.vars.clone());
+    all.extend(vars::load()?);
+    Ok(all)
+}
+
+/// `os`, `arch`, `hostname`, `user` and `home`
+fn builtin_vars() -> Vars {
+    let mut builtins = Vars::new();
+    builtins.insert("os".to_string(), std::env::consts::OS.to_string());
+    builtins.insert("arch".to_string(), std::env::consts::ARCH.to_string());
+    if let Some(hostname) = config::hostname() {
+        builtins.insert("hostname".to_string(), hostname);
+    }
+    if let Some(user) = config::user_name() {
+        builtins.insert("user".to_string(), user);
+    }
+    if let Some(home) = config::home_dir() {
+        builtins.insert("home".to_string(), home.to_string_lossy().into_owned());
+    }
+    builtins
+}
+
+/// What rendering a template did to its file in the target
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Written, or would be in a dry run
+    Rendered,
+    /// Already up to date
+    Unchanged,
+    /// Edited since it was rendered; left alone
+    Modified,
+    /// Something amu did not render is in the way
+    Occupied,
+    /// The template could not be rendered
+    Failed(String),
+}
+
+impl Outcome {
+    /// "rendered", "unchanged", "modified", "occupied" or "failed"
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Rendered => "rendered",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Modified => "modified",
+            Outcome::Occupied => "occupied",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+}
+
+/// One template of a source and what happened to it, by target-relative path
+pub struct Rendering {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    /// The rendered content, unless rendering failed
+    pub content: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Record {
+    #[serde(default)]
+    files: BTreeMap<PathBuf, RenderedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RenderedFile {
+    template: PathBuf,
+    fingerprint: String,
+}
+
+fn record_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(RECORD_FILE))
+}
+
+fn load_record() -> Result<Record> {
+    match fs::read_to_string(record_path()?) {
+        Ok(content) => yaml::from_str(&content).map_err(DotlinkError::StateError),
+        Err(_) => Ok(Record::default()),
+    }
+}
+
+fn save_record(record: &Record) -> Result<()> {
+    let path = record_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = serde_yaml::to_string(record).map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+/// Stable across builds, unlike `DefaultHasher` (FNV-1a)
+pub fn fingerprint(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Templates of a source, relative to it: `*.tmpl` files not ignored otherwise.
+pub fn templates(source: &Path, ignore: &[String]) -> Vec<PathBuf> {
+    let ignore: Vec<String> = ignore.iter().filter(|p| *p != ignore::TEMPLATE_PATTERN).cloned().collect();
+    list_source_files(source, &ignore)
+        .into_iter()
+        .filter(|file| file.to_string_lossy().ends_with(SUFFIX))
+        .collect()
+}
+
+/// What to do with a rendered file given what is at its path and what was last written there.
+fn decide(rendered: &str, existing: Option<&[u8]>, recorded: Option<&str>) -> Outcome {
+    match existing {
+        None => Outcome::Rendered,
+        Some(current) if current == rendered.as_bytes() => Outcome::Unchanged,
+        Some(current) => match recorded {
+            Some(written) if written == fingerprint(current) => Outcome::Rendered,
+            Some(_) => Outcome::Modified,
+            None => Outcome::Occupied,
+        },
+    }
+}
+
+/// Render a source's templates into a target.
+pub fn apply(config: &Config, source: &Path, target: &Path, ignore: &[String], dry_run: bool) -> Result<Vec<Rendering>> {
+    let templates = templates(source, ignore);
+    if templates.is_empty() {
+        return Ok(Vec::new());
+    }
+    let vars = variables(config)?;
+    let _lock = state::lock();
+    let mut record = load_record()?;
+    let mut renderings = Vec::new();
+
+    for template in templates {
+        let template_path = source.join(&template);
+        let relative = PathBuf::from(template.to_string_lossy().trim_end_matches(SUFFIX));
+        let path = target.join(&relative);
+        let rendered = fs::read_to_string(&template_path)
+            .map_err(DotlinkError::from)
+            .and_then(|content| render(&content, &vars));
+        let outcome = match &rendered {
+            Err(e) => Outcome::Failed(e.to_string()),
+            Ok(content) => {
+                let recorded = record.files.get(&path)
+                    .filter(|r| r.template == template_path)
+                    .map(|r| r.fingerprint.as_str());
+                // Only regular files are ever overwritten
+                let outcome = match fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => decide(content, Some(&fs::read(&path)?), recorded),
+                    Ok(_) => Outcome::Occupied,
+                    Err(_) => decide(content, None, recorded),
+                };
+                if !dry_run && matches!(outcome, Outcome::Rendered | Outcome::Unchanged) {
+                    if outcome == Outcome::Rendered {
+                        if let Some(parent) = path.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        fs::write(&path, content)?;
+                        ownership::fix_written(&path);
+                        crate::debug!("rendered {}", path.display());
+                    }
+                    record.files.insert(path.clone(), RenderedFile {
+                        template: template_path.clone(),
+                        fingerprint: fingerprint(content.as_bytes()),
+                    });
+                }
+                outcome
+            }
+        };
+        renderings.push(Rendering { path: relative, outcome, content: rendered.ok() });
+    }
+
+    if !dry_run {
+        save_record(&record)?;
+    }
+    Ok(renderings)
+}
+
+/*
+ * Delete the files a source's templates rendered into a target, when it is removed
+ * Files edited since they were rendered are kept. Returns the target paths deleted.
+ */
+pub fn remove(source: &Path, target: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+    let _lock = state::lock();
+    let mut record = load_record()?;
+    let ours: Vec<PathBuf> = record.files.iter()
+        .filter(|(path, file)| path.starts_with(target) && file.template.starts_with(source))
+        .map(|(path, _)| path.clone())
+        .collect();
+    let mut removed = Vec::new();
+    for path in ours {
+        let unchanged = fs::read(&path).is_ok_and(|content| fingerprint(&content) == record.files[&path].fingerprint);
+        if unchanged {
+            if !dry_run {
+                fs::remove_file(&path)?;
+                crate::debug!("deleted rendered file {}", path.display());
+            }
+            removed.push(path.clone());
+        }
+        if !dry_run {
+            record.files.remove(&path);
+        }
+    }
+    if !dry_run {
+        save_record(&record)?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vars {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_extract_and_render() {
+        let vars = vars(&[("user", "alice"), ("home", "/home/alice"), ("empty", "")]);
+        let content = "[user]\n  name = alice\n[core]\n  excludesfile = /home/alice/.gitignore\n";
+
+        let (template, used) = extract(content, &vars);
+        assert_eq!(template, "[user]\n  name = {{ user }}\n[core]\n  excludesfile = {{ home }}/.gitignore\n");
+        assert_eq!(used, vec!["home", "user"]);
+        assert_eq!(render(&template, &vars).unwrap(), content);
+    }
+
+    #[test]
+    fn test_extract_whole_words() {
+        let vars = vars(&[("arch", "arm"), ("user", "al")]);
+        let content = "charm arm al_x al/bin (al) arm64";
+        let (template, used) = extract(content, &vars);
+        assert_eq!(template, "charm {{ arch }} al_x {{ user }}/bin ({{ user }}) arm64");
+        assert_eq!(used, vec!["arch", "user"]);
+        assert_eq!(render(&template, &vars).unwrap(), content);
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        let vars = vars(&[("user", "alice")]);
+        assert_eq!(render("\\{{ user }} is {{ user }}", &vars).unwrap(), "{{ user }} is alice");
+
+        let content = "alice writes {{ braces }}";
+        let (template, used) = extract(content, &vars);
+        assert_eq!(template, "{{ user }} writes \\{{ braces }}");
+        assert_eq!(used, vec!["user"]);
+        assert_eq!(render(&template, &vars).unwrap(), content);
+    }
+
+    #[test]
+    fn test_decide() {
+        let written = fingerprint(b"old render");
+        assert_eq!(decide("new", None, None), Outcome::Rendered);
+        assert_eq!(decide("same", Some(b"same"), None), Outcome::Unchanged);
+        assert_eq!(decide("new", Some(b"old render"), Some(&written)), Outcome::Rendered);
+        assert_eq!(decide("new", Some(b"edited by hand"), Some(&written)), Outcome::Modified);
+        assert_eq!(decide("new", Some(b"someone else's"), None), Outcome::Occupied);
+        assert_eq!(fingerprint(b""), "cbf29ce484222325");
+    }
+
+    #[test]
+    fn test_render_missing_variable() {
+        assert!(matches!(render("{{ email }}", &Vars::new()), Err(DotlinkError::VariableNotSet(_))));
+        assert_eq!(render("no placeholders {{", &Vars::new()).unwrap(), "no placeholders {{");
+    }
+}
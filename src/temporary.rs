@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::{abbreviate_path, Config};
+use crate::error::{DotlinkError, Result};
+use crate::hooks::{self, Hook};
+use crate::link;
+use crate::log::{civil_from_days, days_from_civil};
+use crate::schema::EntryMeta;
+use crate::strict;
+
+/*
+ * Temporary entries (`amu tmp-add`)
+ * An entry can carry `expires:` (a UTC time) or `session:` (the boot it was added in). Once
+ * the time has passed or the machine has restarted, `amu gc` (and `amu daemon`, as it runs)
+ * unlinks the source and drops the entry, so trying out a colleague's config or a short
+ * experiment leaves nothing behind in the permanent mapping.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifetime {
+    /// Until this long from now
+    For(Duration),
+    /// Until the machine restarts
+    Session,
+}
+
+/// Latest expiry `format_time` writes with a four-digit year (9999-12-31T23:59:59Z)
+const LATEST: u64 = 253_402_300_799;
+
+/// `expires:` and `session:` for an entry added now with the given lifetime.
+pub fn stamp(lifetime: Lifetime) -> Result<(Option<String>, Option<String>)> {
+    match lifetime {
+        Lifetime::For(ttl) => {
+            let expires = now().checked_add(ttl.as_secs()).filter(|&expires| expires <= LATEST);
+            let expires = expires.ok_or(DotlinkError::LifetimeOutOfRange(ttl.as_secs()))?;
+            Ok((Some(format_time(expires)), None))
+        }
+        Lifetime::Session => Ok((None, Some(boot_id().ok_or(DotlinkError::SessionUnknown)?))),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Identifies the current boot: changes whenever the machine restarts.
+#[cfg(target_os = "linux")]
+pub fn boot_id() -> Option<String> {
+    let id = fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(id.trim().to_string()).filter(|id| !id.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+pub fn boot_id() -> Option<String> {
+    // "{ sec = 1760000000, usec = 123 } Fri Oct 17 ..."
+    let output = std::process::Command::new("sysctl").args(["-n", "kern.boottime"]).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let sec = text.split("sec = ").nth(1)?.split(',').next()?.trim();
+    Some(sec.to_string()).filter(|sec| !sec.is_empty())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn boot_id() -> Option<String> {
+    None
+}
+
+/// `2026-10-17T18:00:00Z`
+fn format_time(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Seconds since the epoch of a time written by `format_time`.
+fn parse_time(text: &str) -> Option<u64> {
+    let (date, time) = text.trim().strip_suffix('Z')?.split_once('T')?;
+    let date: Vec<i64> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let time: Vec<u64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice()) else { return None };
+    let days = days_from_civil(year, u32::try_from(month).ok()?, u32::try_from(day).ok()?);
+    let days = u64::try_from(days).ok()?;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Whether an entry's time is up at `now`, or its boot `session` is over.
+fn is_expired(meta: &EntryMeta, now: u64, boot: Option<&str>) -> bool {
+    let timed_out = meta.expires.as_deref().and_then(parse_time).is_some_and(|expires| expires <= now);
+    let restarted = meta.session.as_deref().is_some_and(|session| boot.is_some_and(|boot| boot != session));
+    timed_out || restarted
+}
+
+/// Registered (target, source) pairs that are temporary and due for removal.
+pub fn expired(config: &Config) -> Vec<(PathBuf, PathBuf)> {
+    let (now, boot) = (now(), boot_id());
+    config.metadata.iter()
+        .flat_map(|(target, sources)| sources.iter().map(move |(source, meta)| (target, source, meta)))
+        .filter(|(target, source, _)| config.find_source(target, source).is_some())
+        .filter(|(_, _, meta)| is_expired(meta, now, boot.as_deref()))
+        .map(|(target, source, _)| (target.clone(), source.clone()))
+        .collect()
+}
+
+/*
+ * Unlink and unregister the expired pairs; with `dry_run`, only list them
+ * Returns the pairs removed. A pair that fails is warned about and stays registered, to
+ * be tried again by the next `gc`; the others are still removed, and the config lists
+ * exactly the pairs that were not.
+ */
+pub fn collect(dry_run: bool) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut config = Config::load()?;
+    let expired = expired(&config);
+    if dry_run || expired.is_empty() {
+        return Ok(expired);
+    }
+    let mut removed = Vec::new();
+    for (target, source) in expired {
+        match remove_pair(&mut config, &target, &source) {
+            Ok(()) => removed.push((target, source)),
+            Err(e) => strict::warn(format!(
+                "could not remove expired {} -> {}: {}", abbreviate_path(&source), abbreviate_path(&target), e
+            )),
+        }
+    }
+    if !removed.is_empty() {
+        config.save()?;
+    }
+    Ok(removed)
+}
+
+fn remove_pair(config: &mut Config, target: &Path, source: &Path) -> Result<()> {
+    hooks::run(config, Hook::PreRemove, target, &[source.to_path_buf()], false)?;
+    link::remove_from_target(config, source, target)?;
+    crate::info!("expired {} -> {}", source.display(), target.display());
+    hooks::run(config, Hook::PostRemove, target, &[source.to_path_buf()], false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_round_trip() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(parse_time("2026-10-17T18:30:05Z"), Some(1_792_261_805));
+        for secs in [0, 951_782_400, 1_792_261_805, 4_107_542_399] {
+            assert_eq!(parse_time(&format_time(secs)), Some(secs));
+        }
+        assert_eq!(parse_time("2026-10-17"), None);
+        assert_eq!(parse_time(&format_time(LATEST)), Some(LATEST));
+    }
+
+    #[test]
+    fn test_stamp_out_of_range() {
+        assert!(stamp(Lifetime::For(Duration::from_secs(u64::MAX - 10))).is_err());
+        assert!(stamp(Lifetime::For(Duration::from_secs(3600))).is_ok());
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let timed = EntryMeta { expires: Some(format_time(1000)), ..EntryMeta::default() };
+        assert!(!is_expired(&timed, 999, None));
+        assert!(is_expired(&timed, 1000, None));
+
+        let session = EntryMeta { session: Some("boot-a".to_string()), ..EntryMeta::default() };
+        assert!(!is_expired(&session, 0, Some("boot-a")));
+        assert!(is_expired(&session, 0, Some("boot-b")));
+        // Where the boot cannot be told, the entry stays
+        assert!(!is_expired(&session, 0, None));
+        assert!(!is_expired(&EntryMeta::default(), u64::MAX, Some("boot-b")));
+    }
+}
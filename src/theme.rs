@@ -0,0 +1,132 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/*
+ * Symbols marking results in text output (`--theme`, or `theme:` in the config)
+ * The default glyphs render poorly on some terminals and trip up log collectors, so they
+ * can be swapped for ASCII, Nerd Font icons or plain words; `symbols:` in the config
+ * overrides single ones. Structured output is not affected.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// ✓ ! ✗ ↺
+    #[default]
+    Unicode,
+    /// + ! x <
+    Ascii,
+    /// Nerd Font icons
+    NerdFont,
+    /// OK WARN FAIL UNDONE
+    Words,
+}
+
+/// Per-symbol overrides from the config
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Symbols {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ok: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolled_back: Option<String>,
+}
+
+impl Symbols {
+    pub fn is_empty(&self) -> bool {
+        self.ok.is_none() && self.warning.is_none() && self.error.is_none() && self.rolled_back.is_none()
+    }
+}
+
+/// The symbols in use, with overrides applied
+#[derive(Debug, Clone, PartialEq)]
+struct Resolved {
+    ok: String,
+    warning: String,
+    error: String,
+    rolled_back: String,
+}
+
+impl Theme {
+    fn symbols(self) -> [&'static str; 4] {
+        match self {
+            Theme::Unicode => ["\u{2713}", "!", "\u{2717}", "\u{21ba}"],
+            Theme::Ascii => ["+", "!", "x", "<"],
+            Theme::NerdFont => ["\u{f00c}", "\u{f071}", "\u{f00d}", "\u{f0e2}"],
+            Theme::Words => ["OK", "WARN", "FAIL", "UNDONE"],
+        }
+    }
+
+    fn resolve(self, overrides: &Symbols) -> Resolved {
+        let [ok, warning, error, rolled_back] = self.symbols();
+        let pick = |over: &Option<String>, default: &str| over.clone().unwrap_or_else(|| default.to_string());
+        Resolved {
+            ok: pick(&overrides.ok, ok),
+            warning: pick(&overrides.warning, warning),
+            error: pick(&overrides.error, error),
+            rolled_back: pick(&overrides.rolled_back, rolled_back),
+        }
+    }
+}
+
+static REQUESTED: OnceLock<Theme> = OnceLock::new();
+static RESOLVED: OnceLock<Resolved> = OnceLock::new();
+static ASCII: OnceLock<bool> = OnceLock::new();
+
+/// Use this theme for the process (`--theme`), whatever the config says.
+pub fn set_theme(theme: Theme) {
+    let _ = REQUESTED.set(theme);
+}
+
+/// `--theme`, then `theme:` in the config; `symbols:` overrides apply to either.
+fn resolved() -> &'static Resolved {
+    RESOLVED.get_or_init(|| {
+        let config = Config::load().unwrap_or_default();
+        let theme = REQUESTED.get().copied().or(config.theme).unwrap_or_default();
+        theme.resolve(&config.symbols)
+    })
+}
+
+pub fn ok() -> &'static str {
+    &resolved().ok
+}
+
+pub fn warning() -> &'static str {
+    &resolved().warning
+}
+
+pub fn error() -> &'static str {
+    &resolved().error
+}
+
+pub fn rolled_back() -> &'static str {
+    &resolved().rolled_back
+}
+
+/// Whether the theme keeps to ASCII (`ascii` and `words`), for glyphs beyond the symbols.
+pub fn is_ascii() -> bool {
+    *ASCII.get_or_init(|| {
+        let theme = REQUESTED.get().copied().or_else(|| Config::load().ok().and_then(|config| config.theme)).unwrap_or_default();
+        matches!(theme, Theme::Ascii | Theme::Words)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_overrides() {
+        let overrides = Symbols { error: Some("ERR".to_string()), ..Symbols::default() };
+        let resolved = Theme::Ascii.resolve(&overrides);
+        assert_eq!(resolved.ok, "+");
+        assert_eq!(resolved.error, "ERR");
+        assert_eq!(Theme::Words.resolve(&Symbols::default()).warning, "WARN");
+    }
+}
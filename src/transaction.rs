@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::ignore;
+use crate::journal;
+use crate::links;
+use crate::linker::{copy, platform};
+
+/*
+ * Undoing partially applied link operations
+ * Before a stow, restow or unstow, the links into the source and the directories mirroring
+ * it in the target are noted; afterwards the difference is what the operation created,
+ * whichever backend made it. Files at the source's paths are noted too, as copy and
+ * hard-link modes create those in place of links. A failed operation has its creations removed again. Commands
+ * applying several sources to a target open a `Transaction` so that a failure also undoes
+ * the sources linked before it. Links removed by an operation are not recreated.
+ */
+static ROLLBACK: OnceLock<bool> = OnceLock::new();
+
+/// Turn rollback off for this process (`--no-rollback`).
+pub fn set_rollback(enabled: bool) {
+    let _ = ROLLBACK.set(enabled);
+}
+
+pub fn rollback_enabled() -> bool {
+    ROLLBACK.get().copied().unwrap_or(true)
+}
+
+/// Links (with what they point to), files and directories under a target that belong to a source
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    links: BTreeMap<PathBuf, PathBuf>,
+    files: BTreeSet<PathBuf>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+/// What one link operation created (and which links it removed) in a target
+#[derive(Debug)]
+pub struct Changes {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    ignore: Vec<String>,
+    links: Vec<PathBuf>,
+    /// Files copied or hard-linked from the source
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+    /// Links that disappeared, with what they pointed to
+    removed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Changes {
+    pub fn created_links(&self) -> &[PathBuf] {
+        &self.links
+    }
+
+    pub fn removed_links(&self) -> &[(PathBuf, PathBuf)] {
+        &self.removed
+    }
+
+    pub fn created_files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Directories created to hold the links and files, outermost first
+    pub fn created_dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.files.is_empty() && self.dirs.is_empty()
+    }
+
+    /// Remove what the operation created; returns the number of links removed.
+    pub fn undo(&self) -> usize {
+        let mut removed = 0;
+        for link in self.links.iter().rev() {
+            let ours = link.is_symlink() && links::link_destination(link).is_some_and(|d| d.starts_with(&self.source));
+            if ours && platform::remove_link(link).is_ok() {
+                removed += 1;
+            }
+        }
+        // A copy edited since is kept
+        for file in self.files.iter().rev() {
+            let Ok(relative) = file.strip_prefix(&self.target) else { continue };
+            let wanted = copy::file_fingerprint(&self.source.join(relative));
+            if wanted.is_some() && copy::file_fingerprint(file) == wanted && fs::remove_file(file).is_ok() {
+                let _ = copy::forget(file);
+                removed += 1;
+            }
+        }
+        // Deepest first; a directory that has gained other entries stays
+        let mut dirs = self.dirs.clone();
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in dirs {
+            let _ = fs::remove_dir(dir);
+        }
+        // The journal drops the links that no longer exist
+        let linked = journal::linked_files(&self.source, &self.target, &self.ignore);
+        let _ = journal::update(&self.source, &self.target, &linked, &self.ignore);
+        removed
+    }
+}
+
+/// Note the source's links and mirrored directories in the target.
+pub fn snapshot(source: &Path, target: &Path, ignore: &[String]) -> Snapshot {
+    fn collect(base: &Path, current: &Path, target: &Path, ignore: &[String], snapshot: &mut Snapshot) {
+        let Ok(entries) = fs::read_dir(current) else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(base) else { continue };
+            if ignore::is_ignored(relative, ignore) {
+                continue;
+            }
+            let at = target.join(relative);
+            if at.is_symlink() {
+                if links::link_destination(&at).is_some_and(|d| d.starts_with(base)) {
+                    let raw = fs::read_link(&at).unwrap_or_default();
+                    snapshot.links.insert(at, raw);
+                }
+            } else if at.is_file() && path.is_file() {
+                snapshot.files.insert(at);
+            } else if at.is_dir() && path.is_dir() && !path.is_symlink() {
+                snapshot.dirs.insert(at);
+                collect(base, &path, target, ignore, snapshot);
+            }
+        }
+    }
+
+    let mut snapshot = Snapshot::default();
+    collect(source, source, target, ignore, &mut snapshot);
+    snapshot
+}
+
+/// What appeared in the target since `before` was taken.
+pub fn changes(before: &Snapshot, source: &Path, target: &Path, ignore: &[String]) -> Changes {
+    let after = snapshot(source, target, ignore);
+    Changes {
+        source: source.to_path_buf(),
+        target: target.to_path_buf(),
+        ignore: ignore.to_vec(),
+        links: after.links.keys().filter(|l| !before.links.contains_key(*l)).cloned().collect(),
+        files: after.files.difference(&before.files).cloned().collect(),
+        dirs: after.dirs.difference(&before.dirs).cloned().collect(),
+        removed: before.links.iter()
+            .filter(|(l, _)| !after.links.contains_key(*l))
+            .map(|(l, d)| (l.clone(), d.clone()))
+            .collect(),
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Vec<Changes>>> = const { RefCell::new(None) };
+}
+
+/*
+ * Link operations on the current thread, recorded until the transaction is dropped
+ * (keeping them) or rolled back. Transactions do not nest.
+ */
+pub struct Transaction {
+    _private: (),
+}
+
+impl Transaction {
+    pub fn begin() -> Self {
+        ACTIVE.with(|active| *active.borrow_mut() = Some(Vec::new()));
+        Transaction { _private: () }
+    }
+
+    /// Undo every recorded operation, newest first. Returns the operations that had
+    /// created something, with the number of links each lost.
+    pub fn rollback(self) -> Vec<(Changes, usize)> {
+        let recorded = ACTIVE.with(|active| active.borrow_mut().take()).unwrap_or_default();
+        recorded.into_iter()
+            .rev()
+            .filter(|changes| !changes.is_empty())
+            .map(|changes| {
+                let removed = changes.undo();
+                (changes, removed)
+            })
+            .collect()
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.borrow_mut().take());
+    }
+}
+
+/*
+ * Hand the changes of a finished operation to the open transaction
+ * Without one, the changes of a failed operation are undone right away; they are
+ * returned if nothing took them.
+ */
+pub fn record(changes: Changes, failed: bool) {
+    let pending = ACTIVE.with(|active| match active.borrow_mut().as_mut() {
+        Some(recorded) => {
+            recorded.push(changes);
+            None
+        }
+        None => Some(changes),
+    });
+    if let Some(changes) = pending.filter(|_| failed) {
+        changes.undo();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_changes_undo() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("source");
+        let target = root.join("target");
+        fs::create_dir_all(source.join("a/b")).unwrap();
+        fs::create_dir_all(target.join("a")).unwrap();
+        fs::write(source.join("a/b/file"), "x").unwrap();
+        fs::write(source.join("top"), "x").unwrap();
+        fs::write(source.join("copied"), "x").unwrap();
+        fs::write(source.join("edited"), "x").unwrap();
+        platform::symlink(&source.join("top"), &target.join("top")).unwrap();
+
+        let before = snapshot(&source, &target, &[]);
+        fs::create_dir(target.join("a/b")).unwrap();
+        platform::symlink(&source.join("a/b/file"), &target.join("a/b/file")).unwrap();
+        fs::write(target.join("copied"), "x").unwrap();
+        fs::write(target.join("edited"), "y").unwrap();
+
+        let changes = changes(&before, &source, &target, &[]);
+        assert_eq!(changes.links, vec![target.join("a/b/file")]);
+        assert_eq!(changes.files, vec![target.join("copied"), target.join("edited")]);
+        assert_eq!(changes.dirs, vec![target.join("a/b")]);
+
+        // The copy goes; the file no longer as the source has it stays
+        assert_eq!(changes.undo(), 2);
+        assert!(!target.join("copied").exists());
+        assert!(target.join("edited").exists());
+        assert!(!target.join("a/b").exists());
+        assert!(target.join("a").is_dir());
+        assert!(target.join("top").is_symlink());
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let source = root.join("source");
+        let target = root.join("target");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(source.join("file"), "x").unwrap();
+
+        let transaction = Transaction::begin();
+        let before = snapshot(&source, &target, &[]);
+        platform::symlink(&source.join("file"), &target.join("file")).unwrap();
+        record(changes(&before, &source, &target, &[]), false);
+        assert!(target.join("file").is_symlink());
+
+        let undone = transaction.rollback();
+        assert_eq!(undone.len(), 1);
+        assert_eq!(undone[0].1, 1);
+        assert!(!target.join("file").exists());
+    }
+}
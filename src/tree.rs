@@ -0,0 +1,94 @@
+use std::path::{Component, Path};
+
+use crate::theme;
+
+/*
+ * Tree-style human output (`amu list --tree`)
+ * Nodes are labels with children, drawn with box-drawing branches (ASCII ones under the
+ * `ascii` and `words` themes). `insert_path` nests a target-relative path under one node per
+ * directory, so linked files appear under the directories that hold them.
+ */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Node {
+    pub label: String,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(label: impl Into<String>) -> Self {
+        Node { label: label.into(), children: Vec::new() }
+    }
+
+    pub fn push(&mut self, child: Node) {
+        self.children.push(child);
+    }
+
+    /// Add `path` below this node: a `dir/` node for each directory (shared between paths),
+    /// then a node labelled `leaf(file name)`.
+    pub fn insert_path(&mut self, path: &Path, leaf: impl Fn(&str) -> String) {
+        let parts: Vec<String> = path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let Some((file, dirs)) = parts.split_last() else { return };
+        let mut node = self;
+        for dir in dirs {
+            let label = format!("{}/", dir);
+            let index = match node.children.iter().position(|child| child.label == label) {
+                Some(index) => index,
+                None => {
+                    node.children.push(Node::new(label));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+        }
+        node.children.push(Node::new(leaf(file)));
+    }
+
+    /// The node and its descendants as lines, the node's own label first.
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = vec![self.label.clone()];
+        render_children(&self.children, "", &mut lines);
+        lines
+    }
+}
+
+fn render_children(children: &[Node], prefix: &str, lines: &mut Vec<String>) {
+    let (branch, last, pipe) = if theme::is_ascii() { ("|-- ", "`-- ", "|   ") } else { ("├── ", "└── ", "│   ") };
+    for (i, child) in children.iter().enumerate() {
+        let is_last = i + 1 == children.len();
+        lines.push(format!("{}{}{}", prefix, if is_last { last } else { branch }, child.label));
+        let nested = format!("{}{}", prefix, if is_last { "    " } else { pipe });
+        render_children(&child.children, &nested, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_render() {
+        let mut root = Node::new("~/.config");
+        let mut source = Node::new("~/dotfiles/config");
+        source.insert_path(Path::new("nvim/init.lua"), |name| name.to_string());
+        source.insert_path(Path::new("nvim/lua/plugins.lua"), |name| format!("{} (broken)", name));
+        source.insert_path(Path::new("starship.toml"), |name| name.to_string());
+        root.push(source);
+        root.push(Node::new("~/work/config"));
+
+        assert_eq!(root.render(), vec![
+            "~/.config",
+            "├── ~/dotfiles/config",
+            "│   ├── nvim/",
+            "│   │   ├── init.lua",
+            "│   │   └── lua/",
+            "│   │       └── plugins.lua (broken)",
+            "│   └── starship.toml",
+            "└── ~/work/config",
+        ]);
+    }
+}
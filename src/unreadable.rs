@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::error::{DotlinkError, Result};
+use crate::strict;
+
+/*
+ * Directories a scan was not allowed to read
+ * Scanners (status, list, orphans, audit, clean, and the walks behind linking)
+ * skip a subtree they cannot read rather than stop, but note it here; the command then
+ * warns how many were skipped, so a denied directory does not simply vanish from the
+ * output. A directory whose listing breaks off partway is noted the same way. With
+ * `--fail-on-unreadable` the command fails instead.
+ */
+static SKIPPED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+static FAIL: OnceLock<bool> = OnceLock::new();
+
+/// Directories listed in the warning; the rest are counted
+const LISTED: usize = 5;
+
+fn skipped_set() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    SKIPPED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Fail commands that skipped an unreadable directory (`--fail-on-unreadable`).
+pub fn set_fail(enabled: bool) {
+    let _ = FAIL.set(enabled);
+}
+
+/// Entries of a directory being scanned; an entry that cannot be read notes the directory.
+pub struct Entries {
+    dir: PathBuf,
+    inner: fs::ReadDir,
+}
+
+impl Iterator for Entries {
+    type Item = fs::DirEntry;
+
+    fn next(&mut self) -> Option<fs::DirEntry> {
+        loop {
+            match self.inner.next()? {
+                Ok(entry) => return Some(entry),
+                Err(e) => {
+                    crate::debug!("could not read an entry of {}: {}", self.dir.display(), e);
+                    skipped_set().insert(self.dir.clone());
+                }
+            }
+        }
+    }
+}
+
+/// `fs::read_dir` for scanners: None if the directory cannot be read, noting it if access was denied.
+pub fn read_dir(dir: &Path) -> Option<Entries> {
+    match fs::read_dir(dir) {
+        Ok(inner) => Some(Entries { dir: dir.to_path_buf(), inner }),
+        Err(e) => {
+            if e.kind() == ErrorKind::PermissionDenied {
+                crate::debug!("skipped unreadable directory {}", dir.display());
+                skipped_set().insert(dir.to_path_buf());
+            }
+            None
+        }
+    }
+}
+
+/// Directories skipped so far.
+pub fn skipped() -> Vec<PathBuf> {
+    skipped_set().iter().cloned().collect()
+}
+
+/// Warn about the directories skipped so far, once; an error with `--fail-on-unreadable`.
+pub fn report() -> Result<()> {
+    let skipped = std::mem::take(&mut *skipped_set());
+    if skipped.is_empty() {
+        return Ok(());
+    }
+    let mut listed: Vec<String> = skipped.iter().take(LISTED).map(|p| p.display().to_string()).collect();
+    if skipped.len() > LISTED {
+        listed.push(format!("... and {} more", skipped.len() - LISTED));
+    }
+    if FAIL.get().copied().unwrap_or(false) {
+        return Err(DotlinkError::Unreadable { count: skipped.len(), paths: listed.join("\n  ") });
+    }
+    let noun = if skipped.len() == 1 { "directory" } else { "directories" };
+    strict::warn(format!("skipped {} unreadable {}:\n  {}", skipped.len(), noun, listed.join("\n  ")));
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_dir_notes_denied() {
+        let temp = TempDir::new().unwrap();
+        let locked = temp.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads it anyway
+        if fs::read_dir(&locked).is_ok() {
+            return;
+        }
+
+        assert!(read_dir(&locked).is_none());
+        assert!(read_dir(&temp.path().join("missing")).is_none());
+        assert!(read_dir(temp.path()).is_some());
+        let skipped = skipped();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(skipped.contains(&locked));
+        assert!(!skipped.contains(&temp.path().join("missing")));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error::{DotlinkError, Result};
+use crate::ownership;
+use crate::state;
+use crate::yaml;
+
+const VARS_FILE: &str = "vars.yaml";
+
+/// Per-machine template variables, stored in the state directory
+pub type Vars = BTreeMap<String, String>;
+
+fn vars_path() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(VARS_FILE))
+}
+
+pub fn load() -> Result<Vars> {
+    let path = vars_path()?;
+    if !path.exists() {
+        return Ok(Vars::new());
+    }
+
+    let content = fs::read_to_string(&path)?;
+    yaml::from_str(&content).map_err(DotlinkError::StateError)
+}
+
+pub fn save(vars: &Vars) -> Result<()> {
+    let path = vars_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content = serde_yaml::to_string(vars)
+        .map_err(|e| DotlinkError::StateError(e.to_string()))?;
+    fs::write(&path, content)?;
+    ownership::fix_written(&path);
+    Ok(())
+}
+
+/// Variable names must be identifiers so templates can reference them unambiguously.
+pub fn validate_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(DotlinkError::InvalidVariableName(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("email").is_ok());
+        assert!(validate_name("_git_user2").is_ok());
+        assert!(validate_name("2fast").is_err());
+        assert!(validate_name("with-dash").is_err());
+        assert!(validate_name("").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{DotlinkError, Result};
+use crate::ignore;
+use crate::ownership;
+use crate::precedence;
+use crate::renames;
+use crate::state;
+
+const LOG_FILE: &str = "watch.log";
+
+/// Debounce rounds before acting on changes that keep arriving
+const MAX_DEBOUNCE_ROUNDS: usize = 10;
+
+/*
+ * Watch mode decision log
+ * Every decision (change received, debounce window, action taken) is appended as one
+ * JSON object per line so watch runs can be inspected and asserted on in scripts.
+ */
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum LogEvent<'a> {
+    Received { source: &'a Path, target: &'a Path, path: &'a Path, kind: &'static str },
+    Unindexed { source: &'a Path, target: &'a Path },
+    Debounce { window_ms: u64, round: usize },
+    Action { action: &'static str, source: &'a Path, target: &'a Path, result: &'static str, message: Option<String> },
+    Idle,
+}
+
+pub struct WatchLog {
+    path: PathBuf,
+}
+
+impl WatchLog {
+    /// Log to `path`, or to `watch.log` in the state directory.
+    pub fn open(path: Option<PathBuf>) -> Result<Self> {
+        let path = match path {
+            Some(p) => p,
+            None => state::state_dir()?.join(LOG_FILE),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(WatchLog { path })
+    }
+
+    fn write(&self, event: LogEvent) {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let Ok(mut value) = serde_json::to_value(&event) else { return };
+        value["ts"] = serde_json::json!(ts);
+
+        let existed = self.path.exists();
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            let _ = writeln!(file, "{value}");
+        }
+        if !existed {
+            ownership::fix_written(&self.path);
+        }
+    }
+}
+
+/// A (target, source) pair whose source gained or lost files since it was last linked
+#[derive(PartialEq)]
+pub struct Pending {
+    pub target: PathBuf,
+    pub source: PathBuf,
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    /// No snapshot exists yet, so the pair is relinked to create one
+    pub unindexed: bool,
+}
+
+pub fn detect(config: &Config) -> Vec<Pending> {
+    let mut pending = Vec::new();
+    for (target, sources) in &config.targets {
+        if !target.exists() {
+            continue;
+        }
+        for source in sources.iter().filter(|s| s.exists()) {
+            let ignore = ignore::patterns_for(config, target, source);
+            let entry = match renames::changes_since_snapshot(source, &ignore) {
+                Some((added, removed)) if added.is_empty() && removed.is_empty() => continue,
+                Some((added, removed)) => Pending {
+                    target: target.to_path_buf(),
+                    source: source.clone(),
+                    added,
+                    removed,
+                    unindexed: false,
+                },
+                None => Pending {
+                    target: target.to_path_buf(),
+                    source: source.clone(),
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    unindexed: true,
+                },
+            };
+            pending.push(entry);
+        }
+    }
+    pending
+}
+
+fn log_received(log: &WatchLog, pending: &[Pending]) {
+    for p in pending {
+        if p.unindexed {
+            log.write(LogEvent::Unindexed { source: &p.source, target: &p.target });
+        }
+        for path in &p.added {
+            log.write(LogEvent::Received { source: &p.source, target: &p.target, path, kind: "added" });
+        }
+        for path in &p.removed {
+            log.write(LogEvent::Received { source: &p.source, target: &p.target, path, kind: "removed" });
+        }
+    }
+}
+
+/*
+ * Detect pending changes and wait until they settle
+ * Changes are re-scanned after each debounce window until two scans agree, so a burst
+ * of edits (e.g. a checkout) results in a single relink.
+ */
+fn settle(config: &Config, debounce: Duration, log: &WatchLog) -> Vec<Pending> {
+    let mut pending = detect(config);
+    if pending.is_empty() {
+        return pending;
+    }
+    log_received(log, &pending);
+
+    for round in 1..=MAX_DEBOUNCE_ROUNDS {
+        if debounce.is_zero() {
+            break;
+        }
+        log.write(LogEvent::Debounce { window_ms: debounce.as_millis() as u64, round });
+        thread::sleep(debounce);
+
+        let rescanned = detect(config);
+        if rescanned == pending {
+            break;
+        }
+        log_received(log, &rescanned);
+        pending = rescanned;
+    }
+    pending
+}
+
+/// Process pending changes once and exit. Returns the number of relinked pairs.
+pub fn run_once(debounce: Duration, log: &WatchLog) -> Result<usize> {
+    let relinked = process(debounce, log)?;
+    if relinked.is_none() {
+        log.write(LogEvent::Idle);
+    }
+    Ok(relinked.unwrap_or(0))
+}
+
+type Events = notify::Result<Event>;
+
+/*
+ * Keep processing changes until interrupted
+ * Sources are watched recursively and the config file's directory one level deep; a
+ * notification that can change which files a source has (or touches the config) triggers
+ * a pass. The watch is renewed after each pass so newly registered sources are covered.
+ * Where notifications are unavailable (e.g. inotify watches exhausted), sources are
+ * checked every `interval` instead.
+ */
+pub fn run(interval: Duration, debounce: Duration, log: &WatchLog) -> Result<()> {
+    let config_path = Config::config_path()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut polling = false;
+    process(debounce, log)?;
+    loop {
+        let watcher = match watch(&Config::load()?, &config_path, &sender) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                if !polling {
+                    crate::warn!("file notifications unavailable, checking every {}ms: {}", interval.as_millis(), e);
+                    polling = true;
+                }
+                None
+            }
+        };
+        match watcher {
+            Some(_watcher) => {
+                if !wait(&receiver, &config_path) {
+                    return Ok(());
+                }
+            }
+            None => thread::sleep(interval),
+        }
+        process(debounce, log)?;
+        // Anything that arrived during the pass was already seen by its final scan
+        while receiver.try_recv().is_ok() {}
+    }
+}
+
+/// Block until a relevant notification arrives; false once the watcher has gone away.
+fn wait(receiver: &Receiver<Events>, config_path: &Path) -> bool {
+    loop {
+        match receiver.recv() {
+            Ok(Ok(event)) if is_relevant(&event, config_path) => return true,
+            Ok(Ok(_)) => {}
+            // A lost event (e.g. a queue overflow) is treated as a change
+            Ok(Err(e)) => {
+                crate::warn!("{}", e);
+                return true;
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Whether an event can change a source's files: a file created, removed or renamed, or
+/// any change to the config file. Content edits are left to the links that already exist.
+fn is_relevant(event: &Event, config_path: &Path) -> bool {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => true,
+        EventKind::Any | EventKind::Other => true,
+        _ => event.paths.iter().any(|p| p == config_path),
+    }
+}
+
+fn watch(config: &Config, config_path: &Path, sender: &Sender<Events>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(sender.clone()).map_err(watch_error)?;
+    if let Some(dir) = config_path.parent().filter(|d| d.is_dir()) {
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(watch_error)?;
+    }
+    for source in config.targets.values().flatten().filter(|s| s.is_dir()) {
+        watcher.watch(source, RecursiveMode::Recursive).map_err(watch_error)?;
+    }
+    Ok(watcher)
+}
+
+fn watch_error(e: notify::Error) -> DotlinkError {
+    DotlinkError::IoError(io::Error::other(e))
+}
+
+/// Relink every pair with settled changes. Returns None if nothing was pending.
+fn process(debounce: Duration, log: &WatchLog) -> Result<Option<usize>> {
+    let config = Config::load()?;
+    let pending = settle(&config, debounce, log);
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    // As `amu update`: links at paths a higher-priority source now wins make way for it
+    let mut released: Vec<&PathBuf> = Vec::new();
+    for p in &pending {
+        if !released.contains(&&p.target) && p.target.exists() {
+            precedence::release(&config, &p.target);
+            released.push(&p.target);
+        }
+    }
+
+    let mut relinked = 0;
+    for p in &pending {
+        let mut ignore = ignore::patterns_for(&config, &p.target, &p.source);
+        ignore.extend(precedence::skip_patterns(&precedence::contested_in(&config, &p.target, &p.source)));
+        match crate::link::restow_source(&config, &p.source, &p.target, &ignore) {
+            Ok(_) => {
+                log.write(LogEvent::Action {
+                    action: "restow",
+                    source: &p.source,
+                    target: &p.target,
+                    result: "ok",
+                    message: None,
+                });
+                println!(
+                    "Restowed: {} -> {} ({} added, {} removed)",
+                    crate::config::abbreviate_path(&p.source),
+                    crate::config::abbreviate_path(&p.target),
+                    p.added.len(),
+                    p.removed.len()
+                );
+                relinked += 1;
+            }
+            Err(e) => {
+                log.write(LogEvent::Action {
+                    action: "restow",
+                    source: &p.source,
+                    target: &p.target,
+                    result: "error",
+                    message: Some(e.to_string()),
+                });
+                crate::error!("failed to restow {}: {}", crate::config::abbreviate_path(&p.source), e);
+            }
+        }
+    }
+    Ok(Some(relinked))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange};
+
+    #[test]
+    fn test_is_relevant() {
+        let config = Path::new("/home/u/.config/amu/config.yaml");
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+
+        assert!(is_relevant(&event(EventKind::Create(CreateKind::File), "/dotfiles/.zshrc"), config));
+        assert!(!is_relevant(&event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), "/dotfiles/.zshrc"), config));
+        assert!(is_relevant(&event(EventKind::Modify(ModifyKind::Data(DataChange::Any)), config.to_str().unwrap()), config));
+    }
+}
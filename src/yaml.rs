@@ -0,0 +1,120 @@
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_yaml::Value;
+
+/*
+ * Guarded YAML parsing for files amu did not necessarily write itself
+ * serde_yaml keeps the last of duplicate keys and follows nesting as deep as its own
+ * recursion limit, so a shared or generated config can silently drop entries. Documents
+ * are checked for size, depth and duplicate keys before they are deserialized.
+ */
+
+/// Largest document accepted, in bytes
+pub const MAX_SIZE: usize = 1024 * 1024;
+
+/// Deepest nesting of mappings and sequences accepted; amu's own files use fewer than ten levels
+pub const MAX_DEPTH: usize = 32;
+
+/// Deserialize `content` after checking it against the limits above.
+pub fn from_str<T: DeserializeOwned>(content: &str) -> Result<T, String> {
+    check(content)?;
+    serde_yaml::from_str(content).map_err(|e| e.to_string())
+}
+
+/// Reject documents that are too large, too deeply nested, or that repeat a key in a mapping.
+pub fn check(content: &str) -> Result<(), String> {
+    if content.len() > MAX_SIZE {
+        return Err(format!("file is {} bytes, larger than the {} byte limit", content.len(), MAX_SIZE));
+    }
+    for document in serde_yaml::Deserializer::from_str(content) {
+        Node { depth: 0 }.deserialize(document).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Walks one node, failing on excessive depth or a repeated mapping key
+struct Node {
+    depth: usize,
+}
+
+impl Node {
+    fn child<E: de::Error>(&self) -> Result<Node, E> {
+        if self.depth >= MAX_DEPTH {
+            return Err(E::custom(format!("nested deeper than {} levels", MAX_DEPTH)));
+        }
+        Ok(Node { depth: self.depth + 1 })
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Node {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Node {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any YAML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> { Ok(()) }
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> { Ok(()) }
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> { Ok(()) }
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> { Ok(()) }
+    fn visit_str<E>(self, _: &str) -> Result<(), E> { Ok(()) }
+    fn visit_unit<E>(self) -> Result<(), E> { Ok(()) }
+    fn visit_none<E>(self) -> Result<(), E> { Ok(()) }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<(), A::Error> {
+        // Tagged values (`!tag value`); the content is checked like any other node
+        use de::VariantAccess;
+        let (_, variant): (Value, _) = data.variant()?;
+        variant.newtype_variant_seed(self.child()?)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq.next_element_seed(self.child()?)?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut keys: Vec<Value> = Vec::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            if keys.contains(&key) {
+                let shown = serde_yaml::to_string(&key).unwrap_or_default();
+                return Err(de::Error::custom(format!("duplicate key {}", shown.trim())));
+            }
+            map.next_value_seed(self.child()?)?;
+            keys.push(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_limits() {
+        assert!(check("targets:\n  /a:\n    - /b\n  /c: [/d]\n").is_ok());
+
+        let error = check("targets:\n  /a: [/b]\n  /a: [/c]\n").unwrap_err();
+        assert!(error.contains("duplicate key /a"), "{}", error);
+
+        let deep = format!("{}x{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+        assert!(check(&deep).unwrap_err().contains("nested deeper"));
+
+        let large = format!("ignore:\n{}", "  - aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\n".repeat(MAX_SIZE / 32 + 1));
+        assert!(check(&large).unwrap_err().contains("byte limit"));
+    }
+}